@@ -0,0 +1,71 @@
+use crate::cpu::BOOTROM_BASE;
+
+#[cfg(test)]
+mod tests;
+
+// The mask ROM the hart resets into, at 0x1000 as on the QEMU virt machine.
+// Its code is assembled at build time: a0 gets the hart id and a1 the
+// device tree address from the pointer block behind it, then it jumps to
+// the start address kept there. A device tree generated for the machine
+// follows for guests that are not given one. The ROM only takes loads and
+// fetches, stores fault.
+//
+//   0x00  auipc t0, 0
+//   0x04  ld    a0, 0x20(t0)
+//   0x08  ld    a1, 0x28(t0)
+//   0x0c  ld    t0, 0x18(t0)
+//   0x10  jr    t0
+//   0x18  start address
+//   0x20  hart id
+//   0x28  device tree address
+//   0x30  device tree, when embedded
+
+pub const ROM_SIZE: u64 = 0xf000;
+
+const START: usize = 0x18;
+const HART_ID: usize = 0x20;
+const DTB: usize = 0x28;
+const FDT: usize = 0x30;
+
+// Where an embedded device tree ends up.
+pub const FDT_ADDR: u64 = BOOTROM_BASE + FDT as u64;
+
+const T0: u32 = 5;
+const A0: u32 = 10;
+const A1: u32 = 11;
+
+const fn auipc(rd: u32, imm: u32) -> u32 {
+    (imm & 0xfffff000) | rd << 7 | 0x17
+}
+
+const fn ld(rd: u32, rs1: u32, off: usize) -> u32 {
+    (off as u32) << 20 | rs1 << 15 | 3 << 12 | rd << 7 | 0x03
+}
+
+const fn jalr(rd: u32, rs1: u32, off: u32) -> u32 {
+    off << 20 | rs1 << 15 | rd << 7 | 0x67
+}
+
+const TRAMPOLINE: [u32; 5] = [
+    auipc(T0, 0),
+    ld(A0, T0, HART_ID),
+    ld(A1, T0, DTB),
+    ld(T0, T0, START),
+    jalr(0, T0, 0),
+];
+
+// The ROM contents for a hart that starts at `start` with `dtb` in a1, the
+// device tree `fdt` embedded at FDT_ADDR unless it is empty. Anything past
+// the image reads as zero.
+pub fn image(start: u64, hart_id: u64, dtb: u64, fdt: &[u8]) -> Vec<u8> {
+    assert!(FDT + fdt.len() <= ROM_SIZE as usize, "device tree of {} bytes does not fit the boot ROM", fdt.len());
+    let mut rom = vec![0; FDT];
+    for (i, insn) in TRAMPOLINE.iter().enumerate() {
+        rom[i * 4..][..4].copy_from_slice(&insn.to_le_bytes());
+    }
+    rom[START..][..8].copy_from_slice(&start.to_le_bytes());
+    rom[HART_ID..][..8].copy_from_slice(&hart_id.to_le_bytes());
+    rom[DTB..][..8].copy_from_slice(&dtb.to_le_bytes());
+    rom.extend_from_slice(fdt);
+    rom
+}
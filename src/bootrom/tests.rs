@@ -0,0 +1,24 @@
+use super::*;
+use crate::decoder::Instruction;
+
+#[test]
+fn trampoline_reads_the_pointer_block() {
+    let rom = image(0x8020_0000, 3, 0x8220_0000, &[]);
+    assert_eq!(rom.len(), FDT);
+    let word = |off: usize| u64::from_le_bytes(rom[off..][..8].try_into().unwrap());
+    assert_eq!((word(START), word(HART_ID), word(DTB)), (0x8020_0000, 3, 0x8220_0000));
+
+    let insn = |i: usize| Instruction::decode(u32::from_le_bytes(rom[i * 4..][..4].try_into().unwrap()));
+    assert!(matches!(insn(0), Instruction::Auipc(u) if u.rd == T0 as u8 && u.imm == 0));
+    for (i, (rd, off)) in [(A0, HART_ID), (A1, DTB), (T0, START)].into_iter().enumerate() {
+        assert!(matches!(insn(i + 1), Instruction::Load(l)
+            if l.rd == rd as u8 && l.rs1 == T0 as u8 && l.funct3 == 3 && l.imm == off as i32));
+    }
+    assert!(matches!(insn(4), Instruction::Jalr(j) if j.rd == 0 && j.rs1 == T0 as u8 && j.imm == 0));
+}
+
+#[test]
+fn device_tree_follows_the_block() {
+    let rom = image(0x8000_0000, 0, FDT_ADDR, b"\xd0\x0d\xfe\xed");
+    assert_eq!(&rom[FDT..], b"\xd0\x0d\xfe\xed");
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use crate::clock::TIMEBASE_FREQ;
+use crate::cpu::Cpu;
+use crate::expr;
+use crate::loader::Symbol;
+
+#[cfg(test)]
+mod tests;
+
+// `--insn-budget <insns>[,window=<ms>]` holds the guest to a number of
+// instructions per virtual second, checked over consecutive windows of
+// virtual time from the first step, one second long unless given. A window
+// that retires more than its share of the budget is reported with the code
+// that ran most in it, by function where the payloads have symbols and by
+// page where they do not. The emulator is not cycle accurate, so this finds
+// paths that are unexpectedly long rather than ones that are slow; with
+// --rtc clock=vm the same run reports the same windows every time.
+
+const PAGE_SHIFT: u64 = 12;
+
+// Sections listed for a window over budget.
+const TOP_SECTIONS: usize = 5;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Settings {
+    // instructions per virtual second
+    pub insns: u64,
+    // ticks of the time CSR
+    pub window: u64,
+}
+
+impl Settings {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut items = spec.split(',');
+        let insns = items.next().and_then(expr::parse_number).filter(|&n| n > 0)
+            .ok_or_else(|| format!("bad budget {:?}", spec))?;
+        let mut settings = Settings { insns, window: TIMEBASE_FREQ };
+        for item in items {
+            match item.split_once('=') {
+                Some(("window", ms)) => {
+                    let ms = expr::parse_number(ms).filter(|&ms| ms > 0)
+                        .ok_or_else(|| format!("bad window {:?}", ms))?;
+                    settings.window = ms.saturating_mul(TIMEBASE_FREQ / 1000);
+                }
+                _ => return Err(format!("bad setting {:?}", item)),
+            }
+        }
+        Ok(settings)
+    }
+
+    // The instructions a window may retire.
+    fn limit(&self) -> u64 {
+        (self.insns as u128 * self.window as u128 / TIMEBASE_FREQ as u128) as u64
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Section {
+    Symbol(usize),
+    Page(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overrun {
+    // ticks since the first step
+    pub start: u64,
+    pub window: u64,
+    pub insns: u64,
+    pub limit: u64,
+    // (name, steps at it), the busiest first
+    pub sections: Vec<(String, u64)>,
+}
+
+pub struct Budget {
+    settings: Settings,
+    symbols: Vec<Symbol>,
+    // time of the first step
+    origin: Option<u64>,
+    start: u64,
+    instret: u64,
+    counts: HashMap<Section, u64>,
+}
+
+impl Budget {
+    pub fn new(settings: Settings, mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|sym| sym.addr);
+        Budget {
+            settings,
+            symbols,
+            origin: None,
+            start: 0,
+            instret: 0,
+            counts: HashMap::new(),
+        }
+    }
+
+    // Counts the instruction about to run, after closing the window that
+    // ended before it.
+    pub fn before_step(&mut self, cpu: &Cpu) -> Option<Overrun> {
+        self.count(cpu.time(), cpu.instret(), cpu.pc())
+    }
+
+    fn count(&mut self, now: u64, instret: u64, pc: u64) -> Option<Overrun> {
+        let origin = *self.origin.get_or_insert_with(|| {
+            self.instret = instret;
+            now
+        });
+        let now = now.saturating_sub(origin);
+        let overrun = if now >= self.start + self.settings.window {
+            self.close(now, instret)
+        } else {
+            None
+        };
+        *self.counts.entry(self.section(pc)).or_default() += 1;
+        overrun
+    }
+
+    fn close(&mut self, now: u64, instret: u64) -> Option<Overrun> {
+        let start = self.start;
+        let insns = instret.saturating_sub(self.instret);
+        let counts = std::mem::take(&mut self.counts);
+        // Windows where the hart slept through are empty.
+        self.start = now - (now - start) % self.settings.window;
+        self.instret = instret;
+
+        let limit = self.settings.limit();
+        if insns <= limit {
+            return None;
+        }
+        let mut sections: Vec<(String, u64)> = counts.into_iter().map(|(sec, n)| (self.name(sec), n)).collect();
+        sections.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        sections.truncate(TOP_SECTIONS);
+        Some(Overrun { start, window: self.settings.window, insns, limit, sections })
+    }
+
+    fn section(&self, pc: u64) -> Section {
+        let i = self.symbols.partition_point(|sym| sym.addr <= pc);
+        match i.checked_sub(1) {
+            Some(i) if pc - self.symbols[i].addr < self.symbols[i].size.max(1) => Section::Symbol(i),
+            _ => Section::Page(pc >> PAGE_SHIFT),
+        }
+    }
+
+    fn name(&self, section: Section) -> String {
+        match section {
+            Section::Symbol(i) => self.symbols[i].name.clone(),
+            Section::Page(page) => format!("page {:#x}", page << PAGE_SHIFT),
+        }
+    }
+}
+
+fn seconds(ticks: u64) -> String {
+    format!("{}.{:03}s", ticks / TIMEBASE_FREQ, ticks % TIMEBASE_FREQ / (TIMEBASE_FREQ / 1000))
+}
+
+pub fn report(o: &Overrun) -> String {
+    let mut out = format!("insn-budget: {} insns in {} from {}, budget {}\n",
+        o.insns, seconds(o.window), seconds(o.start), o.limit);
+    for (name, n) in &o.sections {
+        out.push_str(&format!("  {:>10} {:5.1}%  {}\n", n, *n as f64 * 100.0 / o.insns as f64, name));
+    }
+    out
+}
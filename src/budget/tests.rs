@@ -0,0 +1,59 @@
+use super::*;
+
+fn sym(name: &str, addr: u64, size: u64) -> Symbol {
+    Symbol { name: name.into(), addr, size }
+}
+
+#[test]
+fn settings() {
+    assert_eq!(Settings::parse("1000000"), Ok(Settings { insns: 1_000_000, window: TIMEBASE_FREQ }));
+    let settings = Settings::parse("0x100000,window=10").unwrap();
+    assert_eq!((settings.window, settings.limit()), (TIMEBASE_FREQ / 100, 0x100000 / 100));
+    assert!(Settings::parse("0").is_err());
+    assert!(Settings::parse("1000,window=0").is_err());
+    assert!(Settings::parse("1000,per=1").is_err());
+}
+
+#[test]
+fn over_budget_windows_name_the_busiest_code() {
+    let settings = Settings { insns: 100, window: TIMEBASE_FREQ };
+    let mut budget = Budget::new(settings, vec![sym("memset", 0x8000_0100, 0x40), sym("_entry", 0x8000_0000, 0x10)]);
+    let origin = 5 * TIMEBASE_FREQ;
+    let mut instret = 1000;
+    // 60 in memset and 20 past its end, under budget
+    for i in 0..80 {
+        let pc = if i < 60 { 0x8000_0100 + i % 16 * 4 } else { 0x8000_0200 };
+        assert_eq!(budget.count(origin + i, instret, pc), None);
+        instret += 1;
+    }
+    // the next second: 150 steps, 120 of them in _entry
+    for i in 0..150 {
+        let pc = if i < 120 { 0x8000_0000 } else { 0x8000_0104 };
+        assert_eq!(budget.count(origin + TIMEBASE_FREQ + i, instret, pc), None);
+        instret += 1;
+    }
+    // the hart slept a while before the next step
+    let overrun = budget.count(origin + 4 * TIMEBASE_FREQ + 7, instret, 0x8000_0000).unwrap();
+    assert_eq!(overrun, Overrun {
+        start: TIMEBASE_FREQ,
+        window: TIMEBASE_FREQ,
+        insns: 150,
+        limit: 100,
+        sections: vec![("_entry".into(), 120), ("memset".into(), 30)],
+    });
+    assert_eq!(report(&overrun), concat!(
+        "insn-budget: 150 insns in 1.000s from 1.000s, budget 100\n",
+        "         120  80.0%  _entry\n",
+        "          30  20.0%  memset\n",
+    ));
+    assert_eq!(budget.start, 4 * TIMEBASE_FREQ);
+}
+
+#[test]
+fn code_without_symbols_goes_by_page() {
+    let mut budget = Budget::new(Settings { insns: 1, window: 10 }, vec![sym("f", 0x1000, 8)]);
+    budget.count(0, 0, 0x1008);
+    budget.count(1, 1, 0x1ffc);
+    let overrun = budget.count(10, 2, 0x1000).unwrap();
+    assert_eq!(overrun.sections, [("page 0x1000".to_string(), 2)]);
+}
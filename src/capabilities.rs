@@ -0,0 +1,77 @@
+use crate::cpu::DEVICES;
+use crate::gdb;
+use crate::isa::Isa;
+use crate::snapshot;
+
+// Bumped when a field of the JSON output changes meaning or goes away, new
+// fields may be added without a bump.
+const SCHEMA: u32 = 1;
+
+const MACHINES: &[&str] = &["virt"];
+// `text` goes to stdout without --trace-file, `jsonl` is written with it.
+const TRACE_FORMATS: &[&str] = &["text", "jsonl"];
+const FEATURES: &[&str] = &[
+    "gdb", "semihosting", "monitor", "script", "strace", "wx-monitor", "watch", "config-reload",
+    "checkpoint", "load-elf", "fetch-defaults", "host-mem", "vlen", "fuzz-features", "selftest",
+    "console-filter", "misaligned", "compare-report", "guest-trace", "max-host-mem", "trace-priv",
+    "console-encoding", "console-log", "assert", "rtc", "insn-budget",
+    "dump-dtb", "dump-memory-map", "irq-latency", "insn-mix",
+];
+
+// What this build supports, for test frameworks that adapt to it.
+pub struct Capabilities {
+    pub version: &'static str,
+    pub isa: Isa,
+    pub machines: &'static [&'static str],
+    pub devices: &'static [(&'static str, &'static str, u64, u64)],
+    pub trace_formats: &'static [&'static str],
+    pub gdb: Vec<&'static str>,
+    pub snapshot_version: u32,
+    pub features: &'static [&'static str],
+}
+
+fn json_strs<S: AsRef<str>>(items: &[S]) -> String {
+    let items: Vec<String> = items.iter().map(|s| format!("\"{}\"", s.as_ref())).collect();
+    format!("[{}]", items.join(","))
+}
+
+impl Capabilities {
+    pub fn current() -> Self {
+        Capabilities {
+            version: env!("CARGO_PKG_VERSION"),
+            isa: Isa::default(),
+            machines: MACHINES,
+            devices: DEVICES,
+            trace_formats: TRACE_FORMATS,
+            gdb: gdb::SUPPORTED.split(';').collect(),
+            snapshot_version: snapshot::VERSION,
+            features: FEATURES,
+        }
+    }
+
+    // One object on one line, addresses are hex strings like in traces.
+    pub fn to_json(&self) -> String {
+        let devices: Vec<String> = self.devices.iter().map(|(name, compatible, base, size)| {
+            format!(r#"{{"name":"{}","compatible":"{}","base":"{:#x}","size":"{:#x}"}}"#,
+                name, compatible, base, size)
+        }).collect();
+        format!(concat!(r#"{{"schema":{},"version":"{}","isa":"{}","extensions":{},"machines":{},"#,
+            r#""devices":[{}],"trace_formats":{},"gdb":{},"snapshot_version":{},"features":{}}}"#),
+            SCHEMA, self.version, self.isa, json_strs(&self.isa.extensions()), json_strs(self.machines),
+            devices.join(","), json_strs(self.trace_formats), json_strs(&self.gdb), self.snapshot_version,
+            json_strs(self.features))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("nrv64emu {}\nisa: {}\nmachines: {}\n", self.version, self.isa,
+            self.machines.join(" "));
+        out.push_str("devices:\n");
+        for (name, compatible, base, size) in self.devices {
+            out.push_str(&format!("  {:<8} {:#010x} {:#10x} {}\n", name, base, size, compatible));
+        }
+        out.push_str(&format!("trace formats: {}\ngdb: {}\nsnapshot version: {}\nfeatures: {}\n",
+            self.trace_formats.join(" "), self.gdb.join(" "), self.snapshot_version,
+            self.features.join(" ")));
+        out
+    }
+}
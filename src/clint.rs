@@ -0,0 +1,91 @@
+use crate::snapshot;
+
+// SiFive CLINT as found on the QEMU virt machine, for the one hart: msip,
+// whose bit 0 is the machine software interrupt, the machine timer compare
+// that OpenSBI programs and mtime, the hart's clock.
+
+pub const MMIO_SIZE: u64 = 0x10000;
+
+const MSIP: u64 = 0x0;
+const MTIMECMP: u64 = 0x4000;
+const MTIME: u64 = 0xbff8;
+
+pub struct Clint {
+    msip: bool,
+    mtimecmp: u64,
+}
+
+// Merges a 4- or 8-byte write at offset into the 64-bit register at base.
+fn merge(reg: u64, base: u64, offset: u64, size: usize, val: u64) -> u64 {
+    match (offset - base, size) {
+        (0, 8) => val,
+        (4, _) => (reg & 0xffff_ffff) | (val << 32),
+        _ => (reg & !0xffff_ffff) | (val & 0xffff_ffff),
+    }
+}
+
+impl Clint {
+    pub fn new() -> Self {
+        Clint { msip: false, mtimecmp: u64::MAX }
+    }
+
+    pub fn reset(&mut self) {
+        self.msip = false;
+        self.mtimecmp = u64::MAX;
+    }
+
+    pub fn msip(&self) -> bool {
+        self.msip
+    }
+
+    pub fn mtimecmp(&self) -> u64 {
+        self.mtimecmp
+    }
+
+    // Aligned 4- and 8-byte accesses, `now` is mtime.
+    pub fn load(&self, offset: u64, size: usize, now: u64) -> Option<u64> {
+        if !(size == 4 || size == 8) || !offset.is_multiple_of(size as u64) {
+            return None;
+        }
+        let (reg, base) = match offset {
+            MSIP..=0x3 => (self.msip as u64, MSIP),
+            MTIMECMP..=0x4007 => (self.mtimecmp, MTIMECMP),
+            MTIME..=0xbfff => (now, MTIME),
+            _ => (0, offset),
+        };
+        Some(match (offset - base, size) {
+            (0, 8) => reg,
+            (4, _) => reg >> 32,
+            _ => reg & 0xffff_ffff,
+        })
+    }
+
+    // Writes to mtime are dropped, the clock is the host's.
+    pub fn store(&mut self, offset: u64, size: usize, val: u64) -> bool {
+        if !(size == 4 || size == 8) || !offset.is_multiple_of(size as u64) {
+            return false;
+        }
+        if offset == MSIP {
+            self.msip = val & 1 != 0;
+        }
+        if (MTIMECMP..MTIMECMP + 8).contains(&offset) {
+            self.mtimecmp = merge(self.mtimecmp, MTIMECMP, offset, size, val);
+        }
+        true
+    }
+
+    pub fn registers(&self) -> Vec<(&'static str, u64)> {
+        vec![("msip", self.msip as u64), ("mtimecmp", self.mtimecmp)]
+    }
+
+    pub fn save(&self, w: &mut snapshot::Writer) {
+        w.u8(self.msip as u8);
+        w.u64(self.mtimecmp);
+    }
+
+    pub fn restore(&mut self, r: &mut snapshot::Reader) -> Result<(), String> {
+        self.msip = r.u8()? != 0;
+        self.mtimecmp = r.u64()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(test)]
+mod tests;
+
+// The time CSR and mtime tick at 10 MHz.
+pub const TIMEBASE_FREQ: u64 = 10_000_000;
+
+const NANOS_PER_TICK: u64 = 1_000_000_000 / TIMEBASE_FREQ;
+
+// Nominal length of a step of the vm clock, 100 MHz.
+const VM_NANOS_PER_STEP: f64 = 10.0;
+
+// What virtual time follows: the host clock, or the hart's steps (retired
+// instructions and steps waiting in WFI) so that a run reads the same
+// times every time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Source {
+    #[default]
+    Host,
+    Vm,
+}
+
+// `--rtc [base=<date>|now][,clock=host|vm]`, the date as seconds since the
+// Unix epoch and None for the host's.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct RtcSettings {
+    pub base: Option<u64>,
+    pub source: Source,
+}
+
+impl RtcSettings {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut settings = RtcSettings::default();
+        for item in spec.split(',') {
+            match item.split_once('=') {
+                Some(("base", "now")) => settings.base = None,
+                Some(("base", date)) => settings.base = Some(parse_date(date)?),
+                Some(("clock", "host")) => settings.source = Source::Host,
+                Some(("clock", "vm")) => settings.source = Source::Vm,
+                _ => return Err(format!("bad setting {:?}", item)),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+// `YYYY-MM-DDTHH:MM:SS` in UTC, a trailing Z allowed, as seconds since the
+// Unix epoch.
+pub fn parse_date(s: &str) -> Result<u64, String> {
+    let bad = || format!("bad date {:?}, expected YYYY-MM-DDTHH:MM:SS", s);
+    let (date, time) = s.strip_suffix('Z').unwrap_or(s).split_once('T').ok_or_else(bad)?;
+    let fields = |s: &str, sep| -> Option<[u64; 3]> {
+        s.split(sep).map(|f| f.parse().ok()).collect::<Option<Vec<_>>>()?.try_into().ok()
+    };
+    let (Some([y, mo, d]), Some([h, mi, sec])) = (fields(date, '-'), fields(time, ':')) else {
+        return Err(bad());
+    };
+    if y < 1970 || !(1..=12).contains(&mo) || !(1..=31).contains(&d) || h > 23 || mi > 59 || sec > 59 {
+        return Err(bad());
+    }
+    Ok(days_from_civil(y, mo, d) * 86400 + h * 3600 + mi * 60 + sec)
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(y: u64, m: u64, d: u64) -> u64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// Virtual time, decoupled from the host clock by a scale factor so guests
+// can be fast-forwarded through sleeps or slowed down for debugging. Time
+// is in ticks since the Unix epoch, the host's unless set. Methods take the
+// hart's steps for the vm clock.
+pub struct Clock {
+    start: Instant,
+    base: u64,
+    // steps at base
+    base_steps: u64,
+    scale: f64,
+    skipped: u64,
+    source: Source,
+}
+
+impl Clock {
+    pub fn new(scale: f64) -> Self {
+        let since_epoch = SystemTime::UNIX_EPOCH.elapsed().unwrap();
+        Clock {
+            start: Instant::now(),
+            base: (since_epoch.as_nanos() / NANOS_PER_TICK as u128) as u64,
+            base_steps: 0,
+            scale,
+            skipped: 0,
+            source: Source::Host,
+        }
+    }
+
+    pub fn set_source(&mut self, source: Source) {
+        self.source = source;
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    pub fn now(&self, steps: u64) -> u64 {
+        let ns = match self.source {
+            Source::Host => self.start.elapsed().as_nanos() as f64,
+            Source::Vm => steps.saturating_sub(self.base_steps) as f64 * VM_NANOS_PER_STEP,
+        };
+        self.base + (ns * self.scale) as u64 / NANOS_PER_TICK + self.skipped
+    }
+
+    // Fast-forwards virtual time, used to skip idle periods.
+    pub fn skip_to(&mut self, deadline: u64, steps: u64) {
+        let now = self.now(steps);
+        if deadline > now {
+            self.skipped += deadline - now;
+        }
+    }
+
+    // The host time `ticks` of virtual time take at the current scale.
+    pub fn host_duration(&self, ticks: u64) -> Duration {
+        Duration::try_from_secs_f64(ticks as f64 * NANOS_PER_TICK as f64 / self.scale / 1e9).unwrap_or(Duration::MAX)
+    }
+
+    // Continues from a saved time, e.g. when resuming a snapshot.
+    pub fn set_now(&mut self, ticks: u64, steps: u64) {
+        self.start = Instant::now();
+        self.base = ticks;
+        self.base_steps = steps;
+        self.skipped = 0;
+    }
+}
@@ -0,0 +1,31 @@
+use super::*;
+
+#[test]
+fn parses_dates() {
+    assert_eq!(parse_date("1970-01-01T00:00:00"), Ok(0));
+    assert_eq!(parse_date("2020-01-01T00:00:00Z"), Ok(1577836800));
+    assert_eq!(parse_date("2024-02-29T12:34:56"), Ok(1709210096));
+    assert!(parse_date("2020-13-01T00:00:00").is_err());
+    assert!(parse_date("2020-01-01").is_err());
+    assert!(parse_date("2020-01-01T00:00").is_err());
+}
+
+#[test]
+fn parses_rtc_settings() {
+    assert_eq!(RtcSettings::parse("base=2020-01-01T00:00:00,clock=vm"),
+        Ok(RtcSettings { base: Some(1577836800), source: Source::Vm }));
+    assert_eq!(RtcSettings::parse("base=now"), Ok(RtcSettings::default()));
+    assert!(RtcSettings::parse("clock=tai").is_err());
+}
+
+// The vm clock depends on nothing but the steps.
+#[test]
+fn vm_clock_follows_steps() {
+    let mut clock = Clock::new(1.0);
+    clock.set_source(Source::Vm);
+    clock.set_now(1000, 50);
+    assert_eq!(clock.now(50), 1000);
+    assert_eq!(clock.now(150), 1010);
+    clock.skip_to(2000, 150);
+    assert_eq!(clock.now(160), 2001);
+}
@@ -0,0 +1,176 @@
+use std::io::BufRead;
+
+use crate::trace::Event;
+
+#[cfg(test)]
+mod tests;
+
+// Offline comparison of two instruction logs, for checking a run against a
+// reference simulator without cosimulating. Each log is either a structured
+// trace of this emulator or a Spike log, with or without --log-commits:
+//
+//   core   0: 0x0000000080000000 (0x00000297) auipc   t0, 0x0
+//   core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000
+//
+// Only executed instructions are compared, by pc and encoding. Other lines,
+// such as traps, are kept as notes and shown in the context of a report.
+
+pub const DEFAULT_CONTEXT: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub pc: u64,
+    pub insn: u32,
+    pub line: usize,
+    // What the log says about the instruction besides pc and encoding.
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Log {
+    pub steps: Vec<Step>,
+    // Lines that are not instructions, by the index of the step they follow.
+    pub notes: Vec<(usize, String)>,
+}
+
+// Parses one line of either format, Ok(None) for lines to keep as notes.
+pub fn parse_line(line: &str) -> Result<Option<(u64, u32, String)>, String> {
+    let line = line.trim();
+    if line.starts_with('{') {
+        return match Event::from_json(line)? {
+            Event::Insn { pc, insn, .. } => Ok(Some((pc, insn, String::new()))),
+            _ => Ok(None),
+        };
+    }
+
+    let Some((_, rest)) = line.strip_prefix("core").and_then(|l| l.split_once(':')) else {
+        return Ok(None);
+    };
+    let mut words = rest.split_whitespace().peekable();
+    // the privilege level of --log-commits
+    if words.peek().is_some_and(|w| w.len() == 1) {
+        words.next();
+    }
+    let pc = words.next().and_then(|w| w.strip_prefix("0x"));
+    let insn = words.next().and_then(|w| w.strip_prefix("(0x")).and_then(|w| w.strip_suffix(')'));
+    let (Some(pc), Some(insn)) = (pc, insn) else {
+        return Ok(None);
+    };
+    let pc = u64::from_str_radix(pc, 16).map_err(|_| format!("bad pc {:?}", pc))?;
+    let insn = u32::from_str_radix(insn, 16).map_err(|_| format!("bad instruction {:?}", insn))?;
+    Ok(Some((pc, insn, words.collect::<Vec<_>>().join(" "))))
+}
+
+pub fn read(input: impl BufRead) -> Result<Log, String> {
+    let mut log = Log::default();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_line(&line).map_err(|e| format!("line {}: {}", i + 1, e))? {
+            Some((pc, insn, detail)) => log.steps.push(Step { pc, insn, line: i + 1, detail }),
+            None => log.notes.push((log.steps.len(), line.trim().to_string())),
+        }
+    }
+    Ok(log)
+}
+
+// Compressed instructions are logged as 16 bits by Spike and with the
+// following halfword by the structured trace.
+fn same_insn(a: u32, b: u32) -> bool {
+    if a & 3 != 3 || b & 3 != 3 {
+        a & 0xffff == b & 0xffff
+    } else {
+        a == b
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Same(usize),
+    // One log ends early, after the given number of matching steps.
+    Ended { short: usize, steps: usize },
+    Diverged(usize),
+}
+
+// Compares the logs from the first step of each at `start`, or from their
+// beginning. Returns the outcome and the offsets both logs were aligned at.
+pub fn compare(a: &Log, b: &Log, start: Option<u64>) -> (Outcome, usize, usize) {
+    let align = |log: &Log| match start {
+        Some(pc) => log.steps.iter().position(|s| s.pc == pc).unwrap_or(log.steps.len()),
+        None => 0,
+    };
+    let (off_a, off_b) = (align(a), align(b));
+    let (sa, sb) = (&a.steps[off_a..], &b.steps[off_b..]);
+    let outcome = match sa.iter().zip(sb).position(|(x, y)| x.pc != y.pc || !same_insn(x.insn, y.insn)) {
+        Some(i) => Outcome::Diverged(i),
+        None if sa.len() == sb.len() => Outcome::Same(sa.len()),
+        None if sa.len() < sb.len() => Outcome::Ended { short: 0, steps: sa.len() },
+        None => Outcome::Ended { short: 1, steps: sb.len() },
+    };
+    (outcome, off_a, off_b)
+}
+
+fn format_step(step: &Step) -> String {
+    let detail = if step.detail.is_empty() { String::new() } else { format!(" {}", step.detail) };
+    format!("line {:>8}: pc={:#010x} insn={:#010x}{}", step.line, step.pc, step.insn, detail)
+}
+
+// The steps around index i of a log, with its notes in between.
+fn context(log: &Log, off: usize, i: usize, lines: usize) -> String {
+    let first = (off + i).saturating_sub(lines).max(off);
+    let last = (off + i + lines + 1).min(log.steps.len());
+    let mut out = String::new();
+    for idx in first..last {
+        for (_, note) in log.notes.iter().filter(|(at, _)| *at == idx && idx > first) {
+            out.push_str(&format!("                 {}\n", note));
+        }
+        let mark = if idx == off + i { ">" } else { " " };
+        out.push_str(&format!("  {} {}\n", mark, format_step(&log.steps[idx])));
+    }
+    out
+}
+
+pub fn report(names: [&str; 2], a: &Log, b: &Log, start: Option<u64>, lines: usize) -> (String, bool) {
+    let (outcome, off_a, off_b) = compare(a, b, start);
+    let mut out = format!("compare: {} ({} instructions) vs {} ({} instructions)\n",
+        names[0], a.steps.len(), names[1], b.steps.len());
+    if let Some(pc) = start {
+        out.push_str(&format!("aligned at pc {:#x}: {} from step {}, {} from step {}\n",
+            pc, names[0], off_a, names[1], off_b));
+    }
+    match outcome {
+        Outcome::Same(steps) => {
+            out.push_str(&format!("no divergence in {} instructions\n", steps));
+            (out, true)
+        }
+        Outcome::Ended { short, steps } => {
+            out.push_str(&format!("{} ends after {} matching instructions\n", names[short], steps));
+            (out, false)
+        }
+        Outcome::Diverged(i) => {
+            out.push_str(&format!("first divergence after {} matching instructions\n\n", i));
+            for (name, log, off) in [(names[0], a, off_a), (names[1], b, off_b)] {
+                out.push_str(&format!("{}:\n{}", name, context(log, off, i, lines)));
+            }
+            (out, false)
+        }
+    }
+}
+
+// `compare-report <a> <b>`: exits with 1 when the logs differ.
+pub fn run(paths: [&str; 2], start: Option<u64>, lines: usize) {
+    let logs: Vec<Log> = paths.iter().map(|path| {
+        let file = std::fs::File::open(path).map(std::io::BufReader::new).map_err(|e| e.to_string());
+        file.and_then(read).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        })
+    }).collect();
+    let (text, same) = report(paths, &logs[0], &logs[1], start, lines);
+    print!("{}", text);
+    if !same {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,46 @@
+use super::*;
+
+const SPIKE: &str = "\
+core   0: 0x0000000000001000 (0x00000297) auipc   t0, 0x0
+core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000
+core   0: exception trap_illegal_instruction, epc 0x0000000080000004
+core   0: 3 0x0000000080000010 (0x4501) x10 0x0000000000000000
+";
+
+fn trace(steps: &[(u64, u32)]) -> Log {
+    let text: String = steps.iter().enumerate()
+        .map(|(n, &(pc, insn))| Event::Insn { n: n as u64, pc, insn, privl: 3 }.to_json() + "\n")
+        .collect();
+    read(text.as_bytes()).unwrap()
+}
+
+#[test]
+fn reads_spike_logs() {
+    let log = read(SPIKE.as_bytes()).unwrap();
+    let steps: Vec<(u64, u32)> = log.steps.iter().map(|s| (s.pc, s.insn)).collect();
+    assert_eq!(steps, [(0x1000, 0x297), (0x8000_0000, 0x297), (0x8000_0010, 0x4501)]);
+    assert_eq!(log.steps[1].detail, "x5 0x0000000080000000");
+    assert_eq!(log.notes.len(), 1);
+    assert_eq!(log.notes[0].0, 2);
+}
+
+#[test]
+fn aligns_and_matches_compressed_instructions() {
+    let spike = read(SPIKE.as_bytes()).unwrap();
+    let ours = trace(&[(0x8000_0000, 0x297), (0x8000_0010, 0x1234_4501)]);
+    assert_eq!(compare(&ours, &spike, Some(0x8000_0000)), (Outcome::Same(2), 0, 1));
+    assert_eq!(compare(&ours, &spike, None).0, Outcome::Diverged(0));
+}
+
+#[test]
+fn reports_the_first_divergence() {
+    let a = trace(&[(0x100, 0x13), (0x104, 0x13), (0x108, 0x13)]);
+    let b = trace(&[(0x100, 0x13), (0x104, 0x73), (0x108, 0x13)]);
+    let (text, same) = report(["a", "b"], &a, &b, None, 1);
+    assert!(!same);
+    assert!(text.contains("after 1 matching"), "{}", text);
+    assert!(text.contains("> line        2: pc=0x00000104 insn=0x00000073"), "{}", text);
+
+    let short = trace(&[(0x100, 0x13)]);
+    assert_eq!(compare(&a, &short, None).0, Outcome::Ended { short: 1, steps: 1 });
+}
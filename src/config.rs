@@ -0,0 +1,136 @@
+use std::time::SystemTime;
+
+use crate::console::FilterKind;
+use crate::cpu::Cpu;
+use crate::expr;
+use crate::trace::Trace;
+use crate::uart::SerialScript;
+
+// Settings that can be changed while the guest runs, one per line and named
+// like the command line flag without the dashes:
+//
+//   trace-start 0x80001000
+//   trace-insns 1000
+//   trace-file boot.trace
+//   serial-input console.txt
+//   serial-record none
+//   console-filter strip-ansi,timestamp
+//
+// Lines starting with '#' are ignored. Settings missing from the file keep
+// their current value and `none` turns a trace bound, the trace file, the
+// serial recording or the console filters off.
+#[derive(Debug, Default)]
+pub struct Config {
+    trace_start: Option<Option<u64>>,
+    trace_stop: Option<Option<u64>>,
+    trace_insns: Option<Option<u64>>,
+    trace_file: Option<Option<String>>,
+    serial_input: Option<String>,
+    serial_record: Option<Option<String>>,
+    console_filter: Option<Vec<FilterKind>>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Config::default();
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, val) = line.split_once(char::is_whitespace)
+                .ok_or_else(|| format!("line {}: expected <setting> <value>", lineno + 1))?;
+            let val = val.trim();
+            let path = || (val != "none").then(|| val.to_string());
+            let number = || match val {
+                "none" => Ok(None),
+                _ => expr::parse_number(val).map(Some)
+                    .ok_or_else(|| format!("line {}: bad number {:?}", lineno + 1, val)),
+            };
+
+            match key {
+                "trace-start" => config.trace_start = Some(number()?),
+                "trace-stop" => config.trace_stop = Some(number()?),
+                "trace-insns" => config.trace_insns = Some(number()?),
+                "trace-file" => config.trace_file = Some(path()),
+                "serial-input" => config.serial_input = Some(val.to_string()),
+                "serial-record" => config.serial_record = Some(path()),
+                "console-filter" if val == "none" => config.console_filter = Some(Vec::new()),
+                "console-filter" => config.console_filter = Some(FilterKind::parse_list(val)
+                    .map_err(|e| format!("line {}: {}", lineno + 1, e))?),
+                _ => return Err(format!("line {}: unknown setting {:?}", lineno + 1, key)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    // Files are opened before anything changes so a bad path leaves the
+    // running configuration alone.
+    pub fn apply(&self, cpu: &mut Cpu, trace: &mut Trace) -> Result<(), String> {
+        let create = |path: &String| std::fs::File::create(path)
+            .map_err(|e| format!("{}: {}", path, e));
+        let trace_file = self.trace_file.as_ref().map(|p| p.as_ref().map(create).transpose())
+            .transpose()?;
+        let serial_record = self.serial_record.as_ref().map(|p| p.as_ref().map(create).transpose())
+            .transpose()?;
+        let serial_input = self.serial_input.as_ref().map(|path| {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            SerialScript::parse(&text).map_err(|e| format!("{}: {}", path, e))
+        }).transpose()?;
+
+        let (mut start, mut stop, mut insns) = trace.window();
+        start = self.trace_start.unwrap_or(start);
+        stop = self.trace_stop.unwrap_or(stop);
+        insns = self.trace_insns.unwrap_or(insns);
+        trace.set_window(start, stop, insns);
+        match trace_file {
+            Some(Some(file)) => trace.set_output(file),
+            Some(None) => trace.clear_output(),
+            None => {}
+        }
+
+        let now = cpu.instret();
+        let uart = cpu.uart_mut();
+        if let Some(script) = serial_input {
+            uart.set_script(script, now);
+        }
+        match serial_record {
+            Some(Some(file)) => uart.set_record(file),
+            Some(None) => uart.clear_record(),
+            None => {}
+        }
+        if let Some(filters) = &self.console_filter {
+            uart.set_console_filters(filters);
+        }
+
+        Ok(())
+    }
+}
+
+// Re-applies a config file when it changes on disk or a reload is requested.
+pub struct Reloader {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl Reloader {
+    pub fn new(path: String) -> Self {
+        Reloader { path, modified: None }
+    }
+
+    fn mtime(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+
+    pub fn changed(&self) -> bool {
+        self.mtime() != self.modified
+    }
+
+    pub fn reload(&mut self, cpu: &mut Cpu, trace: &mut Trace) -> Result<(), String> {
+        self.modified = self.mtime();
+        let text = std::fs::read_to_string(&self.path).map_err(|e| format!("{}: {}", self.path, e))?;
+        let config = Config::parse(&text).map_err(|e| format!("{}: {}", self.path, e))?;
+        config.apply(cpu, trace)
+    }
+}
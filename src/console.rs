@@ -0,0 +1,274 @@
+use std::io::Write;
+use std::time::Instant;
+
+#[cfg(test)]
+mod tests;
+
+// Console filters rewrite what the guest sends to a serial backend before it
+// reaches the host: `--console-filter strip-ansi,timestamp` for CI logs,
+// `safe-ansi` for output from untrusted guests or `hexdump` for binary
+// protocols. Filters run in the order given, each one seeing the previous
+// one's output. The result is then encoded for the host terminal. The raw
+// bytes can also be logged to a file, ahead of any of this.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    StripAnsi,
+    SafeAnsi,
+    Timestamp,
+    Hexdump,
+}
+
+impl FilterKind {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "strip-ansi" => Some(FilterKind::StripAnsi),
+            "safe-ansi" => Some(FilterKind::SafeAnsi),
+            "timestamp" => Some(FilterKind::Timestamp),
+            "hexdump" => Some(FilterKind::Hexdump),
+            _ => None,
+        }
+    }
+
+    // A comma separated list as taken by --console-filter.
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, String> {
+        list.split(',')
+            .map(|name| Self::parse(name).ok_or_else(|| format!("unknown console filter {:?}", name)))
+            .collect()
+    }
+}
+
+// How the filtered bytes reach the host. Utf8 passes through what is valid
+// UTF-8, assembled across writes, and replaces the rest with U+FFFD so stray
+// bytes cannot form C1 controls. Latin1 shows every byte as the code point
+// of the same value, but for the C1 controls which are replaced as well.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Latin1,
+}
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "utf8" => Some(Encoding::Utf8),
+            "latin1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+}
+
+const REPLACEMENT: &str = "\u{fffd}";
+
+// Length of the UTF-8 sequence a byte starts, None for continuation bytes
+// and bytes that never start one.
+fn utf8_len(b: u8) -> Option<usize> {
+    match b {
+        0x00..=0x7f => Some(1),
+        0xc2..=0xdf => Some(2),
+        0xe0..=0xef => Some(3),
+        0xf0..=0xf4 => Some(4),
+        _ => None,
+    }
+}
+
+// Longest escape sequence safe-ansi holds back before giving up on it.
+const MAX_CSI: usize = 32;
+
+enum Filter {
+    StripAnsi(Escape),
+    // The sequence so far while in a CSI.
+    SafeAnsi(Escape, Vec<u8>),
+    // The start of the console and whether the next byte begins a line.
+    Timestamp(Instant, bool),
+    // Bytes so far and the printable form of the current line.
+    Hexdump(u64, String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    Esc,
+    // CSI, ESC [ up to a final byte in 0x40..=0x7e
+    Csi,
+    // OSC, ESC ] up to BEL or ST (ESC \), and for safe-ansi the other
+    // control strings: DCS, SOS, PM and APC
+    Osc,
+    OscEsc,
+}
+
+impl Filter {
+    fn new(kind: FilterKind) -> Self {
+        match kind {
+            FilterKind::StripAnsi => Filter::StripAnsi(Escape::None),
+            FilterKind::SafeAnsi => Filter::SafeAnsi(Escape::None, Vec::new()),
+            FilterKind::Timestamp => Filter::Timestamp(Instant::now(), true),
+            FilterKind::Hexdump => Filter::Hexdump(0, String::new()),
+        }
+    }
+
+    fn apply(&mut self, b: u8, out: &mut Vec<u8>) {
+        match self {
+            Filter::StripAnsi(state) => {
+                *state = match (*state, b) {
+                    (Escape::None, 0x1b) => Escape::Esc,
+                    (Escape::None, _) => {
+                        out.push(b);
+                        Escape::None
+                    }
+                    (Escape::Esc, b'[') => Escape::Csi,
+                    (Escape::Esc, b']') => Escape::Osc,
+                    (Escape::Esc, _) => Escape::None,
+                    (Escape::Csi, 0x40..=0x7e) => Escape::None,
+                    (Escape::Csi, _) => Escape::Csi,
+                    (Escape::Osc | Escape::OscEsc, 0x07) => Escape::None,
+                    (Escape::OscEsc, b'\\') => Escape::None,
+                    (Escape::Osc | Escape::OscEsc, 0x1b) => Escape::OscEsc,
+                    (Escape::Osc | Escape::OscEsc, _) => Escape::Osc,
+                };
+            }
+            // Keeps text, colors and cursor movement. Drops control strings,
+            // which can set the window title or clipboard, escapes other
+            // than CSI, CSIs that make the terminal answer on the guest's
+            // input and control characters a console has no use for.
+            Filter::SafeAnsi(state, csi) => {
+                *state = match (*state, b) {
+                    (Escape::None, 0x1b) => Escape::Esc,
+                    (Escape::None, 0x07..=0x0a | 0x0d | 0x20..=0x7e | 0x80..) => {
+                        out.push(b);
+                        Escape::None
+                    }
+                    (Escape::None, _) => Escape::None,
+                    (Escape::Esc, b'[') => {
+                        csi.clear();
+                        csi.extend_from_slice(b"\x1b[");
+                        Escape::Csi
+                    }
+                    (Escape::Esc, b']' | b'P' | b'X' | b'^' | b'_') => Escape::Osc,
+                    (Escape::Esc, _) => Escape::None,
+                    (Escape::Csi, 0x40..=0x7e) => {
+                        if !matches!(b, b'c' | b'n') {
+                            out.extend_from_slice(csi);
+                            out.push(b);
+                        }
+                        Escape::None
+                    }
+                    (Escape::Csi, 0x20..=0x3f) if csi.len() < MAX_CSI => {
+                        csi.push(b);
+                        Escape::Csi
+                    }
+                    (Escape::Csi, _) => Escape::None,
+                    (Escape::Osc | Escape::OscEsc, 0x07) => Escape::None,
+                    (Escape::OscEsc, b'\\') => Escape::None,
+                    (Escape::Osc | Escape::OscEsc, 0x1b) => Escape::OscEsc,
+                    (Escape::Osc | Escape::OscEsc, _) => Escape::Osc,
+                };
+            }
+            Filter::Timestamp(start, line_start) => {
+                if *line_start {
+                    let t = start.elapsed();
+                    out.extend(format!("[{:5}.{:06}] ", t.as_secs(), t.subsec_micros()).bytes());
+                }
+                out.push(b);
+                *line_start = b == b'\n';
+            }
+            // Lines of 16 bytes as `offset  hex bytes  |ascii|`, streamed so
+            // nothing waits for a line to fill.
+            Filter::Hexdump(count, ascii) => {
+                let col = *count % 16;
+                if col == 0 {
+                    out.extend(format!("{:08x} ", count).bytes());
+                }
+                if col == 8 {
+                    out.push(b' ');
+                }
+                out.extend(format!(" {:02x}", b).bytes());
+                ascii.push(if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' });
+                *count += 1;
+                if col == 15 {
+                    out.extend(format!("  |{}|\n", ascii).bytes());
+                    ascii.clear();
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Console {
+    filters: Vec<Filter>,
+    encoding: Encoding,
+    // The start of a UTF-8 sequence still missing bytes.
+    partial: Vec<u8>,
+    log: Option<std::fs::File>,
+}
+
+impl Console {
+    pub fn set_filters(&mut self, kinds: &[FilterKind]) {
+        self.filters = kinds.iter().map(|kind| Filter::new(*kind)).collect();
+    }
+
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+        self.partial.clear();
+    }
+
+    // Logs the guest's bytes as they are, before filters and encoding.
+    pub fn set_log(&mut self, file: std::fs::File) {
+        self.log = Some(file);
+    }
+
+    fn encode(&mut self, b: u8, out: &mut Vec<u8>) {
+        if self.encoding == Encoding::Latin1 {
+            match b {
+                0x80..=0x9f => out.extend(REPLACEMENT.bytes()),
+                _ => out.extend((b as char).encode_utf8(&mut [0; 4]).bytes()),
+            }
+            return;
+        }
+        if !self.partial.is_empty() {
+            if b & 0xc0 == 0x80 {
+                self.partial.push(b);
+                if self.partial.len() == utf8_len(self.partial[0]).unwrap_or(0) {
+                    match std::str::from_utf8(&self.partial) {
+                        Ok(c) => out.extend(c.bytes()),
+                        Err(_) => out.extend(REPLACEMENT.bytes()),
+                    }
+                    self.partial.clear();
+                }
+                return;
+            }
+            // cut short by a byte that is not a continuation
+            out.extend(REPLACEMENT.bytes());
+            self.partial.clear();
+        }
+        match utf8_len(b) {
+            Some(1) => out.push(b),
+            Some(_) => self.partial.push(b),
+            None => out.extend(REPLACEMENT.bytes()),
+        }
+    }
+
+    pub fn write(&mut self, b: u8) {
+        if let Some(log) = &mut self.log
+            && log.write_all(&[b]).is_err()
+        {
+            eprintln!("console: failed to write console log");
+            self.log = None;
+        }
+        let mut bytes = vec![b];
+        for filter in &mut self.filters {
+            let mut out = Vec::new();
+            for b in bytes {
+                filter.apply(b, &mut out);
+            }
+            bytes = out;
+        }
+        let mut out = Vec::new();
+        for b in bytes {
+            self.encode(b, &mut out);
+        }
+        let _ = std::io::stdout().write_all(&out);
+    }
+}
@@ -0,0 +1,39 @@
+use super::*;
+
+fn encode(encoding: Encoding, bytes: &[u8]) -> String {
+    let mut console = Console::default();
+    console.set_encoding(encoding);
+    let mut out = Vec::new();
+    for b in bytes {
+        console.encode(*b, &mut out);
+    }
+    String::from_utf8(out).unwrap()
+}
+
+fn filter(kind: FilterKind, bytes: &[u8]) -> Vec<u8> {
+    let mut filter = Filter::new(kind);
+    let mut out = Vec::new();
+    for b in bytes {
+        filter.apply(*b, &mut out);
+    }
+    out
+}
+
+#[test]
+fn utf8_is_assembled() {
+    assert_eq!(encode(Encoding::Utf8, "héllo → ✓ 🦀\n".as_bytes()), "héllo → ✓ 🦀\n");
+    // a stray continuation, a cut short sequence, a C1 control and an
+    // encoded surrogate
+    assert_eq!(encode(Encoding::Utf8, b"\x80a\xe2\x86b\x9b[c\xed\xa0\x80"), "\u{fffd}a\u{fffd}b\u{fffd}[c\u{fffd}");
+}
+
+#[test]
+fn latin1_maps_bytes() {
+    assert_eq!(encode(Encoding::Latin1, b"caf\xe9 \x9b"), "café \u{fffd}");
+}
+
+#[test]
+fn safe_ansi_keeps_colors_and_drops_control_strings() {
+    let input = b"\x1b[1;31mred\x1b[0m\x1b]0;title\x07\x1bP+q\x1b\\\x1b[6n\x1bcok\x00\r\n";
+    assert_eq!(filter(FilterKind::SafeAnsi, input), b"\x1b[1;31mred\x1b[0mok\r\n");
+}
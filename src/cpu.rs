@@ -1,42 +1,208 @@
+use std::time::Duration;
+
+use crate::bootrom;
+use crate::clint::{self, Clint};
+use crate::clock::{Clock, Source, TIMEBASE_FREQ};
+use crate::crypto;
 use crate::decoder::Instruction;
+use crate::featfuzz::Choices;
+use crate::fpu;
+use crate::hosttime::{Account, HostTime};
+use crate::isa::Isa;
+use crate::memuse::{MemUse, Owner};
+use crate::pagecache::{Access, PageCache};
+use crate::plic::{self, Plic};
+use crate::rtc::{self, Rtc};
+use crate::snapshot;
+use crate::syscon::{PowerEvent, Syscon};
+use crate::uart::Uart;
+use crate::vector::{self, Vector};
+use crate::virtio::{self, Transport, rng::Rng};
+use crate::wx::WxMonitor;
+
+mod csr;
+mod exec;
+mod mmu;
+mod mstatus;
+#[cfg(test)]
+mod tests;
+mod trap;
+
+use csr::CsrFile;
+use mstatus::*;
+pub use trap::{Exception, MemFault, Trap};
+
+pub const BOOTROM_BASE: u64 = 0x00001000;
+pub const CLINT_BASE: u64 = 0x02000000;
+pub const PLIC_BASE: u64 = 0x0C000000;
+pub const SYSCON_BASE: u64 = 0x00100000;
+pub const SYSCON_SIZE: u64 = 0x1000;
+pub const RTC_BASE: u64 = 0x00101000;
+pub const UART_BASE: u64 = 0x10000000;
+pub const UART_SIZE: u64 = 0x100;
+pub const RAM_BASE: u64 = 0x80000000;
+pub const RAM_SIZE: u64 = 128 * 1024 * 1024;
 
-const CLINT_BASE: u64 = 0x02000000;
-const PLIC_BASE: u64 = 0x0C000000;
-const UART_BASE: u64 = 0x10000000;
-const UART_SIZE: u64 = 0x100;
+// The bus as (name, compatible, base, size), reported by `capabilities`.
+pub const DEVICES: &[(&str, &str, u64, u64)] = &[
+    ("bootrom", "rom", BOOTROM_BASE, bootrom::ROM_SIZE),
+    ("syscon", "sifive,test0", SYSCON_BASE, SYSCON_SIZE),
+    ("rtc", "google,goldfish-rtc", RTC_BASE, rtc::MMIO_SIZE),
+    ("clint", "riscv,clint0", CLINT_BASE, clint::MMIO_SIZE),
+    ("plic", "riscv,plic0", PLIC_BASE, plic::MMIO_SIZE),
+    ("uart", "ns16550a", UART_BASE, UART_SIZE),
+    ("virtio-rng", "virtio,mmio", VIRTIO_RNG_BASE, virtio::MMIO_SIZE),
+    ("ram", "memory", RAM_BASE, RAM_SIZE),
+];
+#[allow(dead_code)]
 const VIRTIO_DISK_BASE: u64 = 0x10001000;
+#[allow(dead_code)]
 const VIRTIO_NET_BASE: u64 = 0x10001000; //XXX: enough distance?
+pub const VIRTIO_RNG_BASE: u64 = 0x10008000;
+// PLIC sources of the devices.
+pub const UART_IRQ: u32 = 10;
+pub const VIRTIO_RNG_IRQ: u32 = 8;
+pub const RTC_IRQ: u32 = 11;
+
+// Longest host sleep of a hart waiting in WFI.
+const IDLE_SLICE: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MmioAccess {
+    pub addr: u64,
+    pub write: bool,
+    pub value: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TrapEvent {
+    pub cause: u64,
+    pub tval: u64,
+    pub epc: u64,
+}
+
+// Requests of the guest to the tracer, written to the nrvtrace and nrvmark
+// CSRs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuestTrace {
+    Start,
+    Stop,
+    Marker(String),
+}
+
+// Sees every instruction go through the stages of step(): the raw bits at
+// pc, what they decode to and where execution left pc, which is the trap
+// vector when it trapped. Fetch faults and interrupts are not instructions
+// and skip the observer. The seam for timing and cache models.
+pub trait Observer {
+    fn fetched(&mut self, _pc: u64, _bits: u32) {}
+    fn decoded(&mut self, _pc: u64, _insn: &Instruction) {}
+    fn executed(&mut self, _pc: u64, _insn: &Instruction, _next_pc: u64) {}
+}
 
-fn rtc_time() -> u64 {
-    let ts = std::time::SystemTime::UNIX_EPOCH.elapsed().unwrap();
-    (ts.as_nanos() / 100) as u64
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Watchpoint {
+    kind: WatchKind,
+    addr: u64,
+    len: u64,
 }
 
 pub struct Cpu {
     ram_base: u64,
     ram: Vec<u8>, //TODO: bus abstraction
+    rom: Vec<u8>,
+    uart: Uart,
+    syscon: Syscon,
+    clint: Clint,
+    plic: Plic,
+    rtc: Rtc,
+    virtio_rng: Transport<Rng>,
+    clock: Clock,
+    idle_skip: bool,
+    // In WFI until an interrupt is pending, and the steps passed there,
+    // which count towards the time of serial input scripts.
+    waiting: bool,
+    idle_steps: u64,
+    // Set by the debugger for a single step that should not be taken over
+    // by an interrupt or a timer firing, timers then see the time they
+    // were frozen at.
+    irqs_masked: bool,
+    timers_frozen: Option<u64>,
+    emulate_misaligned: bool,
+    host_time: HostTime,
+    mem_use: MemUse,
+    fusion: bool,
+    fused_pc: Option<u64>,
+    // bits of the instruction step() fetched, for the mtval of an illegal one
+    insn_bits: u32,
+    observer: Option<Box<dyn Observer>>,
+    pause_yield: bool,
+    pages: PageCache,
+    fuzz: Choices,
+    csrs: CsrFile,
 
     // Interal state
     pc: u64,
     regs: [u64; 32],
+    fregs: [u64; 32], // narrower values are NaN-boxed
+    fcsr: u64,
+    vector: Vector,
 
     privl: u8,
+    instret: u64,
 
     pmpcfg: [u64; 4],
     pmpaddr: [u64; 64],
 
     mstatus: u64,
     misa: u64,
+    isa: Isa,
     medeleg: u64,
     mideleg: u64,
     mie: u64,
+    mip: u64,    // software-writable bits
+    mip_hw: u64, // lines driven by CLINT/PLIC/Sstc
+    mip_injected: u64, // lines raised by the monitor, apart from the devices
+    // Set when mip, mie, mstatus or the privilege may have unmasked an
+    // interrupt, the next step then looks for one to take.
+    check_irqs: bool,
     mtvec: u64,
     mcounteren: u64,
     menvcfg: u64,
     mepc: u64,
+    mcause: u64,
+    mtval: u64,
 
+    stvec: u64,
+    scounteren: u64,
+    sscratch: u64,
+    sepc: u64,
+    scause: u64,
+    stval: u64,
     satp: u64,
     stimecmp: u64,
+
+    reservation: Option<u64>,
+
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<(WatchKind, u64)>,
+
+    wx: Option<WxMonitor>,
+
+    semihosting: bool,
+    semihost_call: Option<(u64, u64)>,
+
+    mmio_access: Option<MmioAccess>,
+    trap_event: Option<TrapEvent>,
+    irq_event: Option<(u64, bool)>,
+    guest_trace: Option<GuestTrace>,
 }
 
 impl std::fmt::Debug for Cpu {
@@ -44,541 +210,1537 @@ impl std::fmt::Debug for Cpu {
         f.debug_struct("Cpu")
             .field("pc", &self.pc)
             .field("regs", &self.regs)
+            .field("fregs", &self.fregs)
             .finish()
     }
 }
 
-const MISA_RV64G: u64 = (2 << 62) // XLEN=64
+// The remaining extension bits come from the configured Isa.
+const MISA_BASE: u64 = (2 << 62) // XLEN=64
     | (1<<18)   // S
-    | (1<<20)   // U
-    | (1<<8)    // I
-    | (1<<12)   // M
-    | (1<<0)    // A
-    // | (1<<5)    // F
-    // | (1<<3)    // D
-    | 1<<2;   // C
+    | 1<<20;    // U
+
+// slli x0, x0, 0x1f; ebreak; srai x0, x0, 7
+const SEMIHOST_PRE: u32 = 0x01f01013;
+const SEMIHOST_POST: u32 = 0x40705013;
+
+
+const MIP_SSIP: u64 = 1 << 1;
+const MIP_MSIP: u64 = 1 << 3;
+const MIP_STIP: u64 = 1 << 5;
+const MIP_MTIP: u64 = 1 << 7;
+const MIP_SEIP: u64 = 1 << 9;
+const MIP_MEIP: u64 = 1 << 11;
+const MIE_MASK: u64 = MIP_SSIP | MIP_MSIP | MIP_STIP | MIP_MTIP | MIP_SEIP | MIP_MEIP;
+// Only S-level interrupts can be delegated, and all exceptions but ECALLs
+// from M-mode and the reserved causes.
+const MIDELEG_MASK: u64 = MIP_SSIP | MIP_STIP | MIP_SEIP;
+const MEDELEG_MASK: u64 = 0xb3ff;
+
+// Sets or clears the mask bits of lines, whether any changed.
+fn set_line(lines: &mut u64, mask: u64, level: bool) -> bool {
+    let old = *lines;
+    if level {
+        *lines |= mask & MIE_MASK;
+    } else {
+        *lines &= !mask;
+    }
+    *lines != old
+}
+
+pub fn irq_mask(name: &str) -> Option<u64> {
+    match name {
+        "ssip" => Some(MIP_SSIP),
+        "msip" => Some(MIP_MSIP),
+        "stip" => Some(MIP_STIP),
+        "mtip" => Some(MIP_MTIP),
+        "seip" => Some(MIP_SEIP),
+        "meip" => Some(MIP_MEIP),
+        _ => None,
+    }
+}
+
+const MENVCFG_CBIE: u64 = 3 << 4;
+const MENVCFG_CBCFE: u64 = 1 << 6;
+const MENVCFG_CBZE: u64 = 1 << 7;
+const MENVCFG_STCE: u64 = 1 << 63;
+// cycle, time and instret in mcounteren and scounteren, the hpmcounters
+// do not exist and their bits read as zero.
+const MCOUNTEREN_CY: u64 = 1 << 0;
+const MCOUNTEREN_TM: u64 = 1 << 1;
+const MCOUNTEREN_IR: u64 = 1 << 2;
+const COUNTEREN_MASK: u64 = MCOUNTEREN_CY | MCOUNTEREN_TM | MCOUNTEREN_IR;
+
+
+// LR reserves an aligned block, any store overlapping it breaks the
+// reservation.
+const RESERVATION_SIZE: u64 = 64;
 
-const SSTATUS_MASK: u64 = 0x30000de122;
+// Cache block size of the Zicbom/Zicboz operations, in the device tree.
+pub const CACHE_BLOCK_SIZE: u64 = 64;
 
+// Granularity of RAM in snapshots.
+pub const SNAPSHOT_PAGE: usize = 4096;
+
+const MARKER_LEN: u64 = 256;
+
+// Whether two ranges overlap, without overflowing for guest addresses at the
+// top of the address space.
+fn overlaps(a: u64, a_len: u64, b: u64, b_len: u64) -> bool {
+    a.wrapping_sub(b) < b_len || b.wrapping_sub(a) < a_len
+}
+
+// The value an AMO stores. Word, halfword and byte operands are
+// sign-extended, which keeps both their signed and unsigned order.
+fn amo(insn: Instruction, old: u64, src: u64) -> u64 {
+    match insn {
+        Instruction::Amoswapw(_) | Instruction::Amoswapd(_) | Instruction::Amoswaph(_) | Instruction::Amoswapb(_) => src,
+        Instruction::Amoaddw(_) | Instruction::Amoaddd(_) | Instruction::Amoaddh(_) | Instruction::Amoaddb(_) => old.wrapping_add(src),
+        Instruction::Amoxorw(_) | Instruction::Amoxord(_) | Instruction::Amoxorh(_) | Instruction::Amoxorb(_) => old ^ src,
+        Instruction::Amoandw(_) | Instruction::Amoandd(_) | Instruction::Amoandh(_) | Instruction::Amoandb(_) => old & src,
+        Instruction::Amoorw(_) | Instruction::Amoord(_) | Instruction::Amoorh(_) | Instruction::Amoorb(_) => old | src,
+        Instruction::Amominw(_) | Instruction::Amomind(_) | Instruction::Amominh(_) | Instruction::Amominb(_) => (old as i64).min(src as i64) as u64,
+        Instruction::Amomaxw(_) | Instruction::Amomaxd(_) | Instruction::Amomaxh(_) | Instruction::Amomaxb(_) => (old as i64).max(src as i64) as u64,
+        Instruction::Amominuw(_) | Instruction::Amominud(_) | Instruction::Amominuh(_) | Instruction::Amominub(_) => old.min(src),
+        Instruction::Amomaxuw(_) | Instruction::Amomaxud(_) | Instruction::Amomaxuh(_) | Instruction::Amomaxub(_) => old.max(src),
+        _ => unreachable!("{:X?} is not an AMO", insn),
+    }
+}
 
 impl Cpu {
     pub fn new() -> Self {
-        let mut ram = Vec::new();
-        ram.resize(128 * 1024 * 1024, 0x00);
+        let ram = vec![0x00; RAM_SIZE as usize];
+        let mut mem_use = MemUse::default();
+        mem_use.set(Owner::Ram, ram.len());
 
         Cpu {
-            ram_base: 0x80000000,
-            ram: ram,
+            ram_base: RAM_BASE,
+            rom: bootrom::image(RAM_BASE, 0, 0, &[]),
+            ram,
+            uart: Uart::new(),
+            syscon: Syscon::new(),
+            clint: Clint::new(),
+            plic: Plic::new(),
+            rtc: Rtc::new(),
+            virtio_rng: Transport::default(),
+            clock: Clock::new(1.0),
+            idle_skip: false,
+            waiting: false,
+            idle_steps: 0,
+            irqs_masked: false,
+            timers_frozen: None,
+            emulate_misaligned: false,
+            host_time: HostTime::default(),
+            mem_use,
+            fusion: false,
+            fused_pc: None,
+            insn_bits: 0,
+            observer: None,
+            pause_yield: false,
+            pages: PageCache::default(),
+            fuzz: Choices::default(),
+            csrs: CsrFile::new(),
 
-            pc: 0x80000000,
+            pc: BOOTROM_BASE,
             regs: [0; 32],
+            fregs: [0; 32],
+            fcsr: 0,
+            vector: Vector::new(vector::DEFAULT_VLEN),
 
             privl: 3,
+            instret: 0,
 
             pmpcfg: [0; 4],
             pmpaddr: [0; 64],
 
-            mstatus: 0,
-            misa: 0,
+            mstatus: MSTATUS_RESET,
+            misa: MISA_BASE | Isa::default().misa_extensions(),
+            isa: Isa::default(),
             medeleg: 0,
             mideleg: 0,
             mie: 0,
+            mip: 0,
+            mip_hw: 0,
+            mip_injected: 0,
+            check_irqs: false,
             mtvec: 0,
             mcounteren: 0,
             menvcfg: 0,
             mepc: 0,
+            mcause: 0,
+            mtval: 0,
 
+            stvec: 0,
+            scounteren: 0,
+            sscratch: 0,
+            sepc: 0,
+            scause: 0,
+            stval: 0,
             satp: 0,
             stimecmp: 0,
+
+            reservation: None,
+
+            watchpoints: Vec::new(),
+            watch_hit: None,
+
+            wx: None,
+
+            semihosting: false,
+            semihost_call: None,
+
+            mmio_access: None,
+            trap_event: None,
+            irq_event: None,
+            guest_trace: None,
         }
     }
 
-    pub fn load_bytes(&mut self, offset: u64, bytes: &[u8]) {
-        let ram_off = (offset - self.ram_base) as usize;
-        self.ram[ram_off..][..bytes.len()].copy_from_slice(bytes);
-    }
-
-    fn read_csr(&mut self, csr: u32) -> u64 {
-        match csr {
-            0x100 => self.mstatus & SSTATUS_MASK,
-            0x104 => self.mie & self.mideleg, // sie
-            0x14D => self.stimecmp,
-            0x180 => self.satp,
-            0x300 => self.mstatus,
-            0x301 => self.misa,
-            0x302 => self.medeleg,
-            0x303 => self.mideleg,
-            0x304 => self.mie,
-            0x305 => self.mtvec,
-            0x306 => self.mcounteren,
-            0x30a => self.menvcfg,
-            0x3a0..=0x3a3 => self.pmpcfg[(csr & 0x0f) as usize],
-
-            0x3b0..=0x3ff => self.pmpaddr[(csr & 0x3f) as usize],
-            0x341 => self.mepc,
-            0xC01 => rtc_time(),
-            0xF14 => 0, // mhartid
-            _ => unimplemented!("csr {:03X} read, cause exception!", csr),
-        }
-    }
-
-    fn write_csr(&mut self, csr: u32, val: u64) -> bool {
-        match csr {
-            0x100 => {
-                self.mstatus &= !SSTATUS_MASK;
-                self.mstatus |= val & SSTATUS_MASK;
-            }
-            0x104 => {
-                let mask = self.mideleg;
-                self.mie = (val & mask) | (self.mie & !mask);
+    // Warm reset: hart and device state return to their reset values while
+    // RAM contents and host-side attachments are kept.
+    pub fn reset(&mut self) {
+        self.pc = BOOTROM_BASE;
+        self.waiting = false;
+        self.regs = [0; 32];
+        self.fregs = [0; 32];
+        self.fcsr = 0;
+        self.vector.reset();
+        self.privl = 3;
+
+        self.pmpcfg = [0; 4];
+        self.pmpaddr = [0; 64];
+
+        self.mstatus = MSTATUS_RESET;
+        self.misa = MISA_BASE | self.isa.misa_extensions();
+        self.medeleg = 0;
+        self.mideleg = 0;
+        self.mie = 0;
+        self.mip = 0;
+        self.mip_hw = 0;
+        self.mip_injected = 0;
+        self.check_irqs = false;
+        self.mtvec = 0;
+        self.mcounteren = 0;
+        self.menvcfg = 0;
+        self.mepc = 0;
+        self.mcause = 0;
+        self.mtval = 0;
+
+        self.stvec = 0;
+        self.scounteren = 0;
+        self.sscratch = 0;
+        self.sepc = 0;
+        self.scause = 0;
+        self.stval = 0;
+        self.satp = 0;
+        self.stimecmp = 0;
+
+        self.reservation = None;
+        self.pages.flush();
+
+        self.uart.reset();
+        self.clint.reset();
+        self.virtio_rng.reset();
+        self.rtc.reset();
+        self.plic.reset();
+        self.update_device_irqs();
+    }
+
+    // Guest-visible state in snapshot order, see snapshot.rs. RAM is saved
+    // as its non-zero pages.
+    pub fn save(&self, w: &mut snapshot::Writer) {
+        w.bytes(self.isa.to_string().as_bytes());
+        w.u64(self.pc);
+        w.u64s(&self.regs);
+        w.u64s(&self.fregs);
+        w.u64(self.fcsr);
+        self.vector.save(w);
+        w.u8(self.privl);
+        w.u64(self.instret);
+
+        w.u64s(&self.pmpcfg);
+        w.u64s(&self.pmpaddr);
+
+        w.u64s(&[self.mstatus, self.misa, self.medeleg, self.mideleg, self.mie, self.mip,
+            self.mip_hw, self.mtvec, self.mcounteren, self.menvcfg, self.mepc, self.mcause,
+            self.mtval, self.stvec, self.sscratch, self.sepc, self.scause, self.stval, self.satp,
+            self.stimecmp, self.scounteren]);
+        w.opt_u64(self.reservation);
+
+        w.u64(self.time());
+        self.uart.save(w);
+        self.clint.save(w);
+        self.plic.save(w);
+        self.rtc.save(w);
+        self.virtio_rng.save(w);
+
+        let pages = self.ram.chunks(SNAPSHOT_PAGE).enumerate()
+            .filter(|(_, page)| page.iter().any(|b| *b != 0));
+        for (i, page) in pages {
+            w.u64(i as u64);
+            w.bytes(page);
+        }
+        w.u64(u64::MAX);
+    }
+
+    pub fn restore(&mut self, r: &mut snapshot::Reader) -> Result<(), String> {
+        let isa = String::from_utf8_lossy(r.bytes()?).into_owned();
+        self.isa = Isa::parse(&isa)?;
+        self.pc = r.u64()?;
+        r.u64s(&mut self.regs)?;
+        r.u64s(&mut self.fregs)?;
+        self.fcsr = r.u64()?;
+        self.vector.restore(r)?;
+        self.privl = r.u8()?;
+        self.instret = r.u64()?;
+
+        r.u64s(&mut self.pmpcfg)?;
+        r.u64s(&mut self.pmpaddr)?;
+
+        let mut csrs = [0; 21];
+        r.u64s(&mut csrs)?;
+        [self.mstatus, self.misa, self.medeleg, self.mideleg, self.mie, self.mip,
+            self.mip_hw, self.mtvec, self.mcounteren, self.menvcfg, self.mepc, self.mcause,
+            self.mtval, self.stvec, self.sscratch, self.sepc, self.scause, self.stval, self.satp,
+            self.stimecmp, self.scounteren] = csrs;
+        self.reservation = r.opt_u64()?;
+        self.pages.flush();
+        self.check_irqs = true;
+
+        self.clock.set_now(r.u64()?, self.steps());
+        self.uart.restore(r)?;
+        self.clint.restore(r)?;
+        self.plic.restore(r)?;
+        self.rtc.restore(r)?;
+        self.virtio_rng.restore(r)?;
+        self.update_device_irqs();
+
+        self.ram.fill(0);
+        loop {
+            let i = r.u64()?;
+            if i == u64::MAX {
+                break;
             }
-            0x14D => { self.stimecmp = val; }
-            0x180 => { self.satp = val; println!("satp: {:#018X}", self.satp); } //TODO
-            0x300 => { self.mstatus = val; }
-            0x302 => { self.medeleg = val; }
-            0x303 => { self.mideleg = val; }
-            0x304 => { self.mie = val; }
-            0x306 => { self.mcounteren = val; }
-            0x30a => { self.menvcfg = val; }
-            0x341 => { self.mepc = val; }
-            0x3a0..=0x3a3 => { self.pmpcfg[(csr & 0x0f) as usize] = val; }
-            0x3b0..=0x3ff => { self.pmpaddr[(csr & 0x3f) as usize] = val; }
-            _ => unimplemented!("csr {:03X} write, cause exception!", csr),
+            let page = r.bytes()?;
+            let dest = (i as usize).checked_mul(SNAPSHOT_PAGE)
+                .and_then(|off| self.ram.get_mut(off..off + page.len()))
+                .ok_or("snapshot RAM does not fit this machine")?;
+            dest.copy_from_slice(page);
+        }
+        Ok(())
+    }
+
+    // Drives an interrupt line from a device model.
+    fn set_irq_line(&mut self, mask: u64, level: bool) {
+        if set_line(&mut self.mip_hw, mask, level) {
+            self.irq_event = Some((mask, level));
+            self.check_irqs = true;
+        }
+    }
+
+    // Raises or clears an interrupt from the monitor. Injected lines are
+    // kept apart from the device ones, so a device updating its line does
+    // not drop them, until `inject clear` or a reset.
+    pub fn inject_irq(&mut self, mask: u64, level: bool) {
+        if set_line(&mut self.mip_injected, mask, level) {
+            self.irq_event = Some((mask, level));
+            self.check_irqs = true;
+        }
+    }
+
+    // Pulls host input into the devices, run by the scheduler.
+    pub fn poll_io(&mut self) {
+        let start = self.host_time.start();
+        self.uart.tick(self.steps());
+        self.host_time.stop(Account::Uart, start);
+        self.update_device_irqs();
+    }
+
+    // Drives the PLIC sources from the device lines, with the numbering of
+    // the QEMU virt machine, and the external interrupts from the PLIC.
+    fn update_device_irqs(&mut self) {
+        self.rtc.update(self.time_ns());
+        self.plic.set_level(UART_IRQ, self.uart.interrupt());
+        self.plic.set_level(VIRTIO_RNG_IRQ, self.virtio_rng.interrupt());
+        self.plic.set_level(RTC_IRQ, self.rtc.interrupt());
+        self.update_plic();
+    }
+
+    fn update_plic(&mut self) {
+        self.set_irq_line(MIP_MEIP, self.plic.output(0));
+        self.set_irq_line(MIP_SEIP, self.plic.output(1));
+    }
+
+    // Reset or poweroff requested by the guest through the syscon device.
+    pub fn take_power_event(&mut self) -> Option<PowerEvent> {
+        self.syscon.take_event()
+    }
+
+    // See bootrom::image.
+    pub fn set_boot_rom(&mut self, image: Vec<u8>) {
+        self.rom = image;
+    }
+
+    pub fn load_bytes(&mut self, offset: u64, bytes: &[u8]) {
+        assert!(self.debug_write_mem(offset, bytes), "{:#x} is outside RAM", offset);
+    }
+
+    pub fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    pub fn reg(&self, idx: usize) -> u64 {
+        self.regs[idx]
+    }
+
+    pub fn set_pc(&mut self, pc: u64) {
+        self.pc = pc;
+    }
+
+    pub fn set_reg(&mut self, idx: usize, val: u64) {
+        if idx != 0 {
+            self.regs[idx] = val;
+        }
+    }
+
+    pub fn privilege(&self) -> u8 {
+        self.privl
+    }
+
+    // The privilege of loads and stores, see mstatus::data_privilege.
+    pub fn data_privilege(&self) -> u8 {
+        mstatus::data_privilege(self.mstatus, self.privl)
+    }
+
+    pub fn satp(&self) -> u64 {
+        self.satp
+    }
+
+    pub fn instret(&self) -> u64 {
+        self.instret
+    }
+
+    // Retired instructions and steps waiting in WFI.
+    fn steps(&self) -> u64 {
+        self.instret + self.idle_steps
+    }
+
+    // The time CSR and mtime.
+    pub fn time(&self) -> u64 {
+        self.clock.now(self.steps())
+    }
+
+    // The clock as the RTC counts it.
+    fn time_ns(&self) -> u64 {
+        self.time().wrapping_mul(1_000_000_000 / TIMEBASE_FREQ)
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    pub fn set_isa(&mut self, isa: Isa) {
+        self.misa = MISA_BASE | isa.misa_extensions();
+        self.isa = isa;
+    }
+
+    // Applies the choices of --fuzz-features, after set_clock.
+    pub fn set_fuzz(&mut self, fuzz: Choices) {
+        if let Some(time) = fuzz.time_start {
+            self.clock.set_now(time, self.steps());
+        }
+        self.fuzz = fuzz;
+    }
+
+    // Resets the vector state, which is sized by VLEN.
+    pub fn set_vlen(&mut self, vlen: usize) {
+        self.vector = Vector::new(vlen);
+    }
+
+    pub fn set_idle_skip(&mut self, enabled: bool) {
+        self.idle_skip = enabled;
+    }
+
+    // In WFI with nothing to wake it yet, step() would not execute.
+    pub fn is_waiting(&self) -> bool {
+        self.waiting && !self.interrupt_pending()
+    }
+
+    // Passes `steps` of a hart waiting in WFI. The host sleeps until the
+    // next timer deadline or host input, at most IDLE_SLICE so the other
+    // work of the main loop still runs, or with idle-skip or the vm clock
+    // virtual time jumps to the deadline instead.
+    pub fn idle(&mut self, steps: u64) {
+        self.idle_steps += steps;
+        let deadline = self.next_timer_deadline();
+        let skip = self.idle_skip || self.clock.source() == Source::Vm;
+        if skip && let Some(deadline) = deadline {
+            self.clock.skip_to(deadline, self.steps());
+        } else {
+            let until = deadline.map_or(IDLE_SLICE, |d| self.clock.host_duration(d.saturating_sub(self.time())));
+            let start = self.host_time.start();
+            self.uart.wait_input(until.min(IDLE_SLICE));
+            self.host_time.stop(Account::Idle, start);
+        }
+        self.update_timers();
+    }
+
+    // Pending interrupts stay pending until unmasked.
+    pub fn set_irqs_masked(&mut self, masked: bool) {
+        self.irqs_masked = masked;
+    }
+
+    pub fn set_timers_frozen(&mut self, frozen: bool) {
+        self.timers_frozen = frozen.then(|| self.time());
+    }
+
+    // Misaligned loads and stores succeed instead of trapping, as on
+    // hardware that handles them or firmware that emulates them.
+    pub fn set_misaligned_emulation(&mut self, enabled: bool) {
+        self.emulate_misaligned = enabled;
+    }
+
+    // Register state of every device model, keyed by device name.
+    pub fn device_registers(&self) -> Vec<(&'static str, Vec<(&'static str, u64)>)> {
+        vec![
+            ("uart", self.uart.registers()),
+            ("clint", self.clint.registers()),
+            ("plic", self.plic.registers()),
+            ("rtc", self.rtc.registers()),
+            ("virtio-rng", self.virtio_rng.registers()),
+        ]
+    }
+
+    // Lets PAUSE in guest spin loops give the host CPU away.
+    pub fn set_pause_yield(&mut self, enabled: bool) {
+        self.pause_yield = enabled;
+    }
+
+    pub fn set_fusion(&mut self, enabled: bool) {
+        self.fusion = enabled;
+    }
+
+    // Fusion is off while an observer is installed, so it sees every
+    // instruction of a pair as its own step.
+    pub fn set_observer(&mut self, observer: Option<Box<dyn Observer>>) {
+        self.observer = observer;
+    }
+
+    // The pc of the second instruction of the pair the last step fused.
+    pub fn take_fused_pc(&mut self) -> Option<u64> {
+        self.fused_pc.take()
+    }
+
+    pub fn host_time(&self) -> &HostTime {
+        &self.host_time
+    }
+
+    pub fn host_time_mut(&mut self) -> &mut HostTime {
+        &mut self.host_time
+    }
+
+    pub fn mem_use(&self) -> &MemUse {
+        &self.mem_use
+    }
+
+    pub fn mem_use_mut(&mut self) -> &mut MemUse {
+        &mut self.mem_use
+    }
+
+    // RAM pages holding anything but zeros, what a snapshot has to store.
+    pub fn ram_pages_in_use(&self) -> usize {
+        self.ram.chunks(SNAPSHOT_PAGE).filter(|page| page.iter().any(|b| *b != 0)).count()
+    }
+
+    pub fn uart_mut(&mut self) -> &mut Uart {
+        &mut self.uart
+    }
+
+    // The CSRs of the hart's configuration, regardless of privilege.
+    pub fn csr_name(&self, num: u32) -> Option<String> {
+        match self.csrs.get(num) {
+            Some(csr) if (csr.present)(self) => self.csrs.name(num),
+            _ => None,
+        }
+    }
+
+    pub fn debug_read_csr(&self, num: u32) -> Option<u64> {
+        match self.csrs.get(num) {
+            Some(csr) if (csr.present)(self) => Some((csr.read)(self, num)),
+            _ => None,
         }
+    }
 
+    // Writes like CSRRW but regardless of privilege. False for CSRs the hart
+    // does not have and read-only ones.
+    pub fn debug_write_csr(&mut self, num: u32, val: u64) -> bool {
+        let csr = match self.csrs.get(num) {
+            Some(csr) if (csr.present)(self) => *csr,
+            _ => return false,
+        };
+        let Some(store) = csr.write else {
+            return false;
+        };
+        store(self, num, val);
+        self.csr_written(&csr);
         true
     }
 
-    fn uart_store_u8(&mut self, address: u64, value: u8) -> bool {
-        match address {
-            0 => { print!("{}", value as char); }
-            _ => {}
+    // Raw FP register bits, narrower values NaN-boxed.
+    pub fn freg(&self, idx: usize) -> u64 {
+        self.fregs[idx]
+    }
+
+    pub fn set_freg(&mut self, idx: usize, val: u64) {
+        self.fregs[idx] = val;
+        self.mstatus |= MSTATUS_FS | MSTATUS_SD;
+    }
+
+    // Vector register idx, VLEN bits little-endian.
+    pub fn vreg(&self, idx: usize) -> &[u8] {
+        self.vector.reg(idx)
+    }
+
+    pub fn set_vreg(&mut self, idx: usize, bytes: &[u8]) {
+        self.vector.reg_mut(idx).copy_from_slice(bytes);
+        self.mstatus |= MSTATUS_VS | MSTATUS_SD;
+    }
+
+    pub fn vlen(&self) -> usize {
+        self.vector.vlen()
+    }
+
+    pub fn isa(&self) -> &Isa {
+        &self.isa
+    }
+
+    // Whether an instruction the decoder knows has an execute handler.
+    #[cfg(test)]
+    pub fn executes(insn: &Instruction) -> bool {
+        exec::handler(insn) as usize != Cpu::exec_invalid as exec::Handler as usize
+    }
+
+    // None when the instruction at pc cannot be fetched.
+    pub fn peek_insn(&self) -> Option<Instruction> {
+        let (address, _) = self.walk(Access::Fetch, self.pc).ok()?;
+        self.fetch_physical(address).ok().map(|bits| self.decode_insn(bits))
+    }
+
+    // Debugger accesses only see RAM and never trigger MMIO side effects.
+    pub fn debug_read_u8(&self, address: u64) -> Option<u8> {
+        if (BOOTROM_BASE..BOOTROM_BASE+bootrom::ROM_SIZE).contains(&address) {
+            return self.rom_load(address, 1).ok().map(|v| v as u8);
         }
+        let ram_off = address.checked_sub(self.ram_base)? as usize;
+        self.ram.get(ram_off).copied()
+    }
 
+    // Writes from outside the hart: the debugger, scripts, the loader and
+    // device DMA. Like a store they break an overlapping LR reservation and
+    // drop cached fetches, so attaching a debugger does not change what the
+    // guest sees. False, with nothing written, unless it lies entirely in RAM.
+    pub fn debug_write_mem(&mut self, address: u64, bytes: &[u8]) -> bool {
+        let Some(start) = address.checked_sub(self.ram_base) else {
+            return false;
+        };
+        let end = (start as usize).checked_add(bytes.len());
+        let Some(ram) = end.and_then(|end| self.ram.get_mut(start as usize..end)) else {
+            return false;
+        };
+        ram.copy_from_slice(bytes);
+        self.check_reservation(address, bytes.len() as u64);
+        self.invalidate_fetch_caches();
         true
     }
 
-    fn uart_load_u8(&mut self, address: u64) -> Option<u8> {
-        match address {
-            0x05 => Some(0x60),
-            _ => None,
+    pub fn debug_read_u32(&self, address: u64) -> Option<u32> {
+        let mut bytes = [0u8; 4];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = self.debug_read_u8(address.wrapping_add(i as u64))?;
+        }
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    pub fn debug_read_u64(&self, address: u64) -> Option<u64> {
+        let lo = self.debug_read_u32(address)? as u64;
+        let hi = self.debug_read_u32(address.wrapping_add(4))? as u64;
+        Some(lo | hi << 32)
+    }
+
+    // For host-side setup of the RAM mapping, see hostmem.rs.
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    // A range of RAM for the debugger, None unless it lies entirely in RAM.
+    pub fn debug_ram(&self, address: u64, len: u64) -> Option<&[u8]> {
+        let start = address.checked_sub(self.ram_base)? as usize;
+        self.ram.get(start..start.checked_add(len as usize)?)
+    }
+
+    pub fn set_semihosting(&mut self, enabled: bool) {
+        self.semihosting = enabled;
+    }
+
+    // A pending semihosting call as (operation, parameter), the hart does not
+    // advance until it is completed.
+    pub fn take_semihost_call(&mut self) -> Option<(u64, u64)> {
+        self.semihost_call.take()
+    }
+
+    pub fn complete_semihost_call(&mut self, ret: u64) {
+        self.regs[10] = ret;
+        self.pc += 4;
+    }
+
+    fn is_semihost_sequence(&self, pc: u64) -> bool {
+        self.debug_read_u32(pc.wrapping_sub(4)) == Some(SEMIHOST_PRE)
+            && self.debug_read_u32(pc.wrapping_add(4)) == Some(SEMIHOST_POST)
+    }
+
+    pub fn add_watchpoint(&mut self, kind: WatchKind, addr: u64, len: u64) {
+        self.watchpoints.push(Watchpoint { kind, addr, len });
+    }
+
+    pub fn remove_watchpoint(&mut self, kind: WatchKind, addr: u64, len: u64) -> bool {
+        let wp = Watchpoint { kind, addr, len };
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|w| *w != wp);
+        self.watchpoints.len() != before
+    }
+
+    pub fn take_watch_hit(&mut self) -> Option<(WatchKind, u64)> {
+        self.watch_hit.take()
+    }
+
+    // The device access made by the last step, if any.
+    pub fn take_mmio_access(&mut self) -> Option<MmioAccess> {
+        self.mmio_access.take()
+    }
+
+    pub fn take_trap_event(&mut self) -> Option<TrapEvent> {
+        self.trap_event.take()
+    }
+
+    // The trap of the last step, until the tracer takes it.
+    pub fn trap_event(&self) -> Option<TrapEvent> {
+        self.trap_event
+    }
+
+    pub fn take_guest_trace(&mut self) -> Option<GuestTrace> {
+        self.guest_trace.take()
+    }
+
+    // A NUL-terminated guest string of at most MARKER_LEN bytes, for markers.
+    fn read_guest_str(&mut self, addr: u64) -> String {
+        let bytes: Vec<u8> = (0..MARKER_LEN)
+            .map_while(|i| self.ram_offset(Access::Load, addr.wrapping_add(i)).ok().map(|off| self.ram[off]))
+            .take_while(|b| *b != 0)
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    // Where the interrupts of the machine come from: each source, whether it
+    // is raised and the mip bits of the interrupts that enter its handler.
+    // The device lines go through the PLIC to MEI and SEI.
+    pub fn irq_sources(&self) -> [(&'static str, bool, u64); 6] {
+        let external = MIP_MEIP | MIP_SEIP;
+        [
+            ("msip", self.mip_hw & MIP_MSIP != 0, MIP_MSIP),
+            ("mtimer", self.mip_hw & MIP_MTIP != 0, MIP_MTIP),
+            ("stimer", self.mip_hw & MIP_STIP != 0, MIP_STIP),
+            ("uart", self.uart.interrupt(), external),
+            ("virtio-rng", self.virtio_rng.interrupt(), external),
+            ("rtc", self.rtc.interrupt(), external),
+        ]
+    }
+
+    // The last interrupt line that changed level.
+    pub fn take_irq_event(&mut self) -> Option<(u64, bool)> {
+        self.irq_event.take()
+    }
+
+    pub fn set_wx_monitor(&mut self, enabled: bool) {
+        self.wx = enabled.then(WxMonitor::new);
+    }
+
+    // Return addresses from ra and the frame pointer chain, innermost first.
+    pub fn backtrace(&self, depth: usize) -> Vec<u64> {
+        let mut frames = Vec::new();
+        if self.regs[1] != 0 {
+            frames.push(self.regs[1]);
+        }
+        let mut fp = self.regs[8];
+        while frames.len() < depth {
+            let (Some(ra), Some(prev)) = (self.debug_read_u64(fp.wrapping_sub(8)),
+                self.debug_read_u64(fp.wrapping_sub(16))) else {
+                break;
+            };
+            if ra == 0 {
+                break;
+            }
+            if frames.last() != Some(&ra) {
+                frames.push(ra);
+            }
+            // the stack grows down
+            if prev <= fp {
+                break;
+            }
+            fp = prev;
+        }
+        frames
+    }
+
+    fn report_wx(&self, violation: crate::wx::Violation, addr: u64) {
+        let frames: Vec<String> = self.backtrace(16).iter().map(|ra| format!("{:#x}", ra)).collect();
+        eprintln!("wx: {} {:#x} pc={:#x} backtrace: {}", violation.describe(), addr, self.pc,
+            frames.join(" "));
+    }
+
+    // The reservation holds the physical block, the one stores and writes
+    // from outside the hart break. Called after the load succeeded.
+    fn reserve(&mut self, address: u64) {
+        self.reservation = self.translate(Access::Load, address).ok().map(|paddr| paddr & !(RESERVATION_SIZE - 1));
+    }
+
+    // address is physical.
+    fn check_reservation(&mut self, address: u64, len: u64) {
+        if let Some(base) = self.reservation
+            && overlaps(address, len, base, RESERVATION_SIZE)
+        {
+            self.reservation = None;
+        }
+    }
+
+    // Watchpoints and the W^X monitor only see stores that happened, not
+    // ones that faulted.
+    fn stored(&mut self, address: u64, len: u64) {
+        self.check_watch(address, len, true);
+        self.check_wx_write(address);
+    }
+
+    fn check_wx_write(&mut self, address: u64) {
+        if let Some(violation) = self.wx.as_mut().and_then(|wx| wx.on_write(address)) {
+            self.report_wx(violation, address);
+        }
+    }
+
+    fn check_watch(&mut self, address: u64, len: u64, write: bool) {
+        for wp in &self.watchpoints {
+            let kind_matches = match wp.kind {
+                WatchKind::Write => write,
+                WatchKind::Read => !write,
+                WatchKind::Access => true,
+            };
+            // Reports the first accessed byte under the watchpoint, which is
+            // the access address unless the access starts below it.
+            if kind_matches && overlaps(address, len, wp.addr, wp.len) {
+                self.watch_hit = Some((wp.kind, address.max(wp.addr)));
+            }
+        }
+    }
+
+    // SEIP reads as the OR of the software bit and the PLIC line, injected
+    // lines read as raised.
+    fn read_mip(&self) -> u64 {
+        self.mip | self.mip_hw | self.mip_injected
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        self.read_mip() & self.mie != 0
+    }
+
+    // The time compares and the lines they drive: CLINT mtimecmp always,
+    // stimecmp with Sstc enabled.
+    fn timer_compares(&self) -> [Option<(u64, u64)>; 2] {
+        [Some((self.clint.mtimecmp(), MIP_MTIP)),
+            (self.menvcfg & MENVCFG_STCE != 0).then_some((self.stimecmp, MIP_STIP))]
+    }
+
+    // The earliest time compare a sleeping hart could be woken by, of
+    // those whose interrupt is enabled.
+    fn next_timer_deadline(&self) -> Option<u64> {
+        self.timer_compares().into_iter().flatten()
+            .filter(|(_, line)| self.mie & line != 0)
+            .map(|(deadline, _)| deadline)
+            .min()
+    }
+
+    // Drives the timer interrupt lines from the clock. A line stays high
+    // until its compare is written, so this returns the ticks until the next
+    // deadline still ahead, None when there is none.
+    pub fn update_timers(&mut self) -> Option<u64> {
+        let now = self.timers_frozen.unwrap_or_else(|| self.time());
+        let mut next = None;
+        for (deadline, line) in self.timer_compares().into_iter().flatten() {
+            self.set_irq_line(line, now >= deadline);
+            if deadline > now {
+                next = Some(next.map_or(deadline - now, |n: u64| n.min(deadline - now)));
+            }
         }
+        if self.menvcfg & MENVCFG_STCE == 0 {
+            self.set_irq_line(MIP_STIP, false);
+        }
+        next
+    }
+
+    // The interrupt the hart takes next: M-level ones unless masked by
+    // MIE in M-mode, delegated ones only below M-mode and unless masked by
+    // SIE in S-mode. Ordered by the priority of the privileged spec.
+    fn pending_interrupt(&self) -> Option<u64> {
+        let pending = self.read_mip() & self.mie;
+        let m_enabled = self.privl < 3 || self.mstatus & MSTATUS_MIE != 0;
+        let s_enabled = self.privl == 0 || (self.privl == 1 && self.mstatus & MSTATUS_SIE != 0);
+        let irqs = match (pending & !self.mideleg, pending & self.mideleg) {
+            (m, _) if m != 0 && m_enabled => m,
+            (_, s) if s != 0 && s_enabled => s,
+            _ => return None,
+        };
+        [11, 3, 7, 9, 1, 5].into_iter().find(|irq| irqs >> irq & 1 != 0)
     }
 
-    fn store_u8(&mut self, address: u64, value: u8) -> bool {
+    // CSRRW/CSRRS/CSRRC: returns the CSR's value and, when write is set,
+    // stores op of the read-modify-write value. None when the CSR does not
+    // exist or the hart may not access it this way.
+    fn csr_insn(&mut self, num: u32, write: bool, op: impl FnOnce(u64) -> u64) -> Option<u64> {
+        let csr = match self.csrs.get(num) {
+            Some(csr) if (csr.present)(self) => *csr,
+            _ => return None,
+        };
+        if self.privl < csr::privilege(num) || !(csr.enabled)(self) || (write && csr.write.is_none()) {
+            return None;
+        }
+        let val = (csr.read)(self, num);
+        if let (true, Some(store)) = (write, csr.write) {
+            let old = csr.read_rmw.map_or(val, |read| read(self, num));
+            store(self, num, op(old));
+            self.csr_written(&csr);
+        }
+        Some(val)
+    }
+
+    // Side effects of a write to csr.
+    fn csr_written(&mut self, csr: &csr::Csr) {
+        if csr.effects & csr::FLUSH_PAGES != 0 {
+            self.pages.flush();
+        }
+        self.check_irqs = true;
+        if csr.effects & csr::FS_DIRTY != 0 {
+            self.mstatus |= MSTATUS_FS | MSTATUS_SD;
+        }
+        if csr.effects & csr::VS_DIRTY != 0 {
+            self.mstatus |= MSTATUS_VS | MSTATUS_SD;
+        }
+    }
+
+    fn freg_s(&self, idx: u8) -> f32 {
+        fpu::unbox_s(self.fregs[idx as usize])
+    }
+
+    // Any write to the FP state marks it dirty for the OS' context switch.
+    fn set_freg_s(&mut self, idx: u8, val: f32) {
+        self.fregs[idx as usize] = fpu::box_s(val);
+        self.mstatus |= MSTATUS_FS | MSTATUS_SD;
+    }
+
+    // Halves are widened to f64 exactly, see fpu::half_to_f64.
+    fn freg_h(&self, idx: u8) -> f64 {
+        fpu::half_to_f64(fpu::unbox_h(self.fregs[idx as usize]))
+    }
+
+    fn set_freg_h(&mut self, idx: u8, val: u16) {
+        self.fregs[idx as usize] = fpu::box_h(val);
+        self.mstatus |= MSTATUS_FS | MSTATUS_SD;
+    }
+
+    fn freg_d(&self, idx: u8) -> f64 {
+        f64::from_bits(self.fregs[idx as usize])
+    }
+
+    fn set_freg_d(&mut self, idx: u8, val: f64) {
+        self.fregs[idx as usize] = val.to_bits();
+        self.mstatus |= MSTATUS_FS | MSTATUS_SD;
+    }
+
+    // Resolves the dynamic rounding mode, reserved modes are illegal.
+    fn rounding_mode(&self, rm: u8) -> Option<u8> {
+        let rm = if rm == 7 { (self.fcsr >> 5) as u8 & 7 } else { rm };
+        (rm <= fpu::RM_RMM).then_some(rm)
+    }
+
+    fn set_fflags(&mut self, flags: u8) {
+        if flags != 0 {
+            self.fcsr |= flags as u64;
+            self.mstatus |= MSTATUS_FS | MSTATUS_SD;
+        }
+    }
+
+    // RAM offset of an access to a virtual address, for the paths that
+    // only reach RAM.
+    fn ram_offset(&mut self, access: Access, address: u64) -> Result<usize, MemFault> {
+        let paddr = self.translate(access, address)?;
+        self.ram_index(paddr)
+    }
+
+    fn ram_index(&self, paddr: u64) -> Result<usize, MemFault> {
+        if paddr < self.ram_base || paddr >= (self.ram_base + self.ram.len() as u64) {
+            return Err(MemFault::Access);
+        }
+        Ok((paddr - self.ram_base) as usize)
+    }
+
+    // Misaligned RAM accesses are emulated a byte at a time when enabled, an
+    // access crossing into a page without permission faults as a whole.
+    // MMIO registers only take aligned accesses.
+    fn load_misaligned(&mut self, address: u64, size: usize) -> Result<u64, MemFault> {
+        if !self.emulate_misaligned {
+            return Err(MemFault::Misaligned);
+        }
+        let mut bytes = [0; 8];
+        for (i, byte) in bytes[..size].iter_mut().enumerate() {
+            let ram_off = self.ram_offset(Access::Load, address.wrapping_add(i as u64))?;
+            *byte = self.ram[ram_off];
+        }
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn store_misaligned(&mut self, address: u64, bytes: &[u8]) -> Result<(), MemFault> {
+        if !self.emulate_misaligned {
+            return Err(MemFault::Misaligned);
+        }
+        let mut offsets = [0; 8];
+        for (i, off) in offsets[..bytes.len()].iter_mut().enumerate() {
+            *off = self.ram_offset(Access::Store, address.wrapping_add(i as u64))?;
+        }
+        for (off, byte) in offsets.iter().zip(bytes) {
+            self.ram[*off] = *byte;
+            self.check_reservation(self.ram_base + *off as u64, 1);
+        }
+        Ok(())
+    }
+
+    fn store_u8(&mut self, address: u64, value: u8) -> Result<(), MemFault> {
+        self.store_u8_unwatched(address, value).inspect(|_| self.stored(address, 1))
+    }
+
+    fn store_u8_unwatched(&mut self, address: u64, value: u8) -> Result<(), MemFault> {
+        let address = self.translate(Access::Store, address)?;
+        self.check_reservation(address, 1);
+
         // bounds
-        //TODO: handle MMIO
         if (UART_BASE..UART_BASE+UART_SIZE).contains(&address) {
-            return self.uart_store_u8(address - UART_BASE, value);
+            self.mmio_access = Some(MmioAccess { addr: address, write: true, value: value as u64 });
+            let start = self.host_time.start();
+            let ok = self.uart.store_u8(address - UART_BASE, value);
+            self.host_time.stop(Account::Uart, start);
+            self.update_device_irqs();
+            return ok.then_some(()).ok_or(MemFault::Access);
         }
 
-        if address < self.ram_base || address >= (self.ram_base + self.ram.len() as u64) {
-            return false;
+        if (VIRTIO_RNG_BASE..VIRTIO_RNG_BASE+virtio::MMIO_SIZE).contains(&address) {
+            return self.virtio_store(address, 1, value as u32);
         }
-        
-        let ram_off = (address - self.ram_base) as usize;
+
+        let ram_off = self.ram_index(address)?;
         self.ram[ram_off..][..1].copy_from_slice(&value.to_le_bytes());
 
-        true
+        Ok(())
+    }
+    fn store_u16(&mut self, address: u64, value: u16) -> Result<(), MemFault> {
+        self.store_u16_unwatched(address, value).inspect(|_| self.stored(address, 2))
     }
-    fn store_u16(&mut self, address: u64, value: u16) -> bool { unimplemented!("store_u16") }
-    fn store_u32(&mut self, address: u64, value: u32) -> bool {
+
+    fn store_u16_unwatched(&mut self, address: u64, value: u16) -> Result<(), MemFault> {
         // alignment
-        if address % 4 != 0 {
-            return false;
+        if !address.is_multiple_of(2) {
+            return self.store_misaligned(address, &value.to_le_bytes());
+        }
+
+        let address = self.translate(Access::Store, address)?;
+        self.check_reservation(address, 2);
+
+        if (VIRTIO_RNG_BASE..VIRTIO_RNG_BASE+virtio::MMIO_SIZE).contains(&address) {
+            return self.virtio_store(address, 2, value as u32);
         }
 
-        // bounds
         //TODO: handle MMIO
-        if address < self.ram_base || address >= (self.ram_base + self.ram.len() as u64) {
-            return false;
+        let ram_off = self.ram_index(address)?;
+        self.ram[ram_off..][..2].copy_from_slice(&value.to_le_bytes());
+
+        Ok(())
+    }
+    fn store_u32(&mut self, address: u64, value: u32) -> Result<(), MemFault> {
+        self.store_u32_unwatched(address, value).inspect(|_| self.stored(address, 4))
+    }
+
+    fn store_u32_unwatched(&mut self, address: u64, value: u32) -> Result<(), MemFault> {
+        // alignment
+        if !address.is_multiple_of(4) {
+            return self.store_misaligned(address, &value.to_le_bytes());
+        }
+
+        let address = self.translate(Access::Store, address)?;
+        self.check_reservation(address, 4);
+
+        if (SYSCON_BASE..SYSCON_BASE+SYSCON_SIZE).contains(&address) {
+            self.mmio_access = Some(MmioAccess { addr: address, write: true, value: value as u64 });
+            let start = self.host_time.start();
+            let ok = self.syscon.store_u32(address - SYSCON_BASE, value);
+            self.host_time.stop(Account::Syscon, start);
+            return ok.then_some(()).ok_or(MemFault::Access);
+        }
+        if (CLINT_BASE..CLINT_BASE+clint::MMIO_SIZE).contains(&address) {
+            return self.clint_store(address, 4, value as u64);
+        }
+        if (PLIC_BASE..PLIC_BASE+plic::MMIO_SIZE).contains(&address) {
+            return self.plic_store(address, value);
+        }
+        if (RTC_BASE..RTC_BASE+rtc::MMIO_SIZE).contains(&address) {
+            return self.rtc_store(address, value);
+        }
+        if (VIRTIO_RNG_BASE..VIRTIO_RNG_BASE+virtio::MMIO_SIZE).contains(&address) {
+            return self.virtio_store(address, 4, value);
         }
 
-        let ram_off = (address - self.ram_base) as usize;
+        //TODO: handle MMIO
+        let ram_off = self.ram_index(address)?;
         self.ram[ram_off..][..4].copy_from_slice(&value.to_le_bytes());
 
-        true
+        Ok(())
+    }
 
+    fn store_u64(&mut self, address: u64, value: u64) -> Result<(), MemFault> {
+        self.store_u64_unwatched(address, value).inspect(|_| self.stored(address, 8))
     }
 
-    fn store_u64(&mut self, address: u64, value: u64) -> bool{
+    fn store_u64_unwatched(&mut self, address: u64, value: u64) -> Result<(), MemFault> {
         // alignment
-        if address % 8 != 0 {
-            return false;
+        if !address.is_multiple_of(8) {
+            return self.store_misaligned(address, &value.to_le_bytes());
         }
 
-        // bounds
-        //TODO: handle MMIO
-        if address < self.ram_base || address >= (self.ram_base + self.ram.len() as u64) {
-            return false;
+        let address = self.translate(Access::Store, address)?;
+        self.check_reservation(address, 8);
+
+        if (CLINT_BASE..CLINT_BASE+clint::MMIO_SIZE).contains(&address) {
+            return self.clint_store(address, 8, value);
         }
 
-        let ram_off = (address - self.ram_base) as usize;
+        //TODO: handle MMIO
+        let ram_off = self.ram_index(address)?;
         self.ram[ram_off..][..8].copy_from_slice(&value.to_le_bytes());
 
-        true
+        Ok(())
+    }
+
+    fn clint_load(&mut self, address: u64, size: usize) -> Result<u64, MemFault> {
+        let value = self.clint.load(address - CLINT_BASE, size, self.time());
+        if let Some(value) = value {
+            self.mmio_access = Some(MmioAccess { addr: address, write: false, value });
+        }
+        value.ok_or(MemFault::Access)
+    }
+
+    // A write to msip or mtimecmp moves MSIP or MTIP right away.
+    fn clint_store(&mut self, address: u64, size: usize, value: u64) -> Result<(), MemFault> {
+        self.mmio_access = Some(MmioAccess { addr: address, write: true, value });
+        if !self.clint.store(address - CLINT_BASE, size, value) {
+            return Err(MemFault::Access);
+        }
+        self.set_irq_line(MIP_MSIP, self.clint.msip());
+        self.update_timers();
+        Ok(())
+    }
+
+    // A claim takes the source off the line until it is completed.
+    fn plic_load(&mut self, address: u64) -> Result<u32, MemFault> {
+        let value = self.plic.load(address - PLIC_BASE, 4);
+        if let Some(value) = value {
+            self.mmio_access = Some(MmioAccess { addr: address, write: false, value: value as u64 });
+        }
+        self.update_plic();
+        value.ok_or(MemFault::Access)
+    }
+
+    fn plic_store(&mut self, address: u64, value: u32) -> Result<(), MemFault> {
+        self.mmio_access = Some(MmioAccess { addr: address, write: true, value: value as u64 });
+        if !self.plic.store(address - PLIC_BASE, 4, value) {
+            return Err(MemFault::Access);
+        }
+        self.update_plic();
+        Ok(())
+    }
+
+    fn rtc_load(&mut self, address: u64) -> Result<u32, MemFault> {
+        let value = self.rtc.load(address - RTC_BASE, 4, self.time_ns());
+        if let Some(value) = value {
+            self.mmio_access = Some(MmioAccess { addr: address, write: false, value: value as u64 });
+        }
+        value.ok_or(MemFault::Access)
+    }
+
+    fn rtc_store(&mut self, address: u64, value: u32) -> Result<(), MemFault> {
+        self.mmio_access = Some(MmioAccess { addr: address, write: true, value: value as u64 });
+        if !self.rtc.store(address - RTC_BASE, 4, value, self.time_ns()) {
+            return Err(MemFault::Access);
+        }
+        self.update_device_irqs();
+        Ok(())
+    }
+
+    fn virtio_load(&mut self, address: u64, size: usize) -> Result<u32, MemFault> {
+        let start = self.host_time.start();
+        let value = self.virtio_rng.load(address - VIRTIO_RNG_BASE, size);
+        self.host_time.stop(Account::Virtio, start);
+        if let Some(value) = value {
+            self.mmio_access = Some(MmioAccess { addr: address, write: false, value: value as u64 });
+        }
+        value.ok_or(MemFault::Access)
     }
 
-    fn load_u8(&mut self, address: u64) -> Option<u8> {
+    // The device DMAs into guest RAM through the Cpu, so it is taken out
+    // for the duration of the access.
+    fn virtio_store(&mut self, address: u64, size: usize, value: u32) -> Result<(), MemFault> {
+        self.mmio_access = Some(MmioAccess { addr: address, write: true, value: value as u64 });
+        let start = self.host_time.start();
+        let mut dev = std::mem::take(&mut self.virtio_rng);
+        let ok = dev.store(address - VIRTIO_RNG_BASE, size, value, self);
+        self.virtio_rng = dev;
+        self.host_time.stop(Account::Virtio, start);
+        self.update_device_irqs();
+        ok.then_some(()).ok_or(MemFault::Access)
+    }
+
+    // Loads of the boot ROM, aligned ones of any size. Stores there find
+    // neither a device nor RAM and fault.
+    fn rom_load(&self, address: u64, size: usize) -> Result<u64, MemFault> {
+        let off = (address - BOOTROM_BASE) as usize;
+        let mut bytes = [0; 8];
+        for (i, byte) in bytes[..size].iter_mut().enumerate() {
+            *byte = self.rom.get(off + i).copied().unwrap_or(0);
+        }
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn load_u8(&mut self, address: u64) -> Result<u8, MemFault> {
+        self.check_watch(address, 1, false);
+
+        let address = self.translate(Access::Load, address)?;
+
         if (UART_BASE..UART_BASE+UART_SIZE).contains(&address) {
-            return self.uart_load_u8(address - UART_BASE);
+            let start = self.host_time.start();
+            let value = self.uart.load_u8(address - UART_BASE);
+            self.host_time.stop(Account::Uart, start);
+            self.update_device_irqs();
+            if let Some(value) = value {
+                self.mmio_access = Some(MmioAccess { addr: address, write: false, value: value as u64 });
+            }
+            return value.ok_or(MemFault::Access);
         }
 
-        // bounds
-        //TODO: handle MMIO
-        if address < self.ram_base || address >= (self.ram_base + self.ram.len() as u64) {
-            return None;
+        if (VIRTIO_RNG_BASE..VIRTIO_RNG_BASE+virtio::MMIO_SIZE).contains(&address) {
+            return self.virtio_load(address, 1).map(|v| v as u8);
+        }
+
+        if (BOOTROM_BASE..BOOTROM_BASE+bootrom::ROM_SIZE).contains(&address) {
+            return self.rom_load(address, 1).map(|v| v as u8);
         }
 
-        let ram_off = (address - self.ram_base) as usize;
-        Some(u8::from_le_bytes(self.ram[ram_off..][..1].try_into().unwrap()))
+        //TODO: handle MMIO
+        let ram_off = self.ram_index(address)?;
+        Ok(u8::from_le_bytes(self.ram[ram_off..][..1].try_into().unwrap()))
     }
-    fn load_u16(&mut self, address: u64) -> Option<u16> { unimplemented!("load_u16") }
-    fn load_u32(&mut self, address: u64) -> Option<u32> {
+    fn load_u16(&mut self, address: u64) -> Result<u16, MemFault> {
+        self.check_watch(address, 2, false);
+
         // alignment
-        if address % 4 != 0 {
-            return None;
+        if !address.is_multiple_of(2) {
+            return self.load_misaligned(address, 2).map(|v| v as u16);
         }
 
-        // bounds
-        //TODO: handle MMIO
-        if address < self.ram_base || address >= (self.ram_base + self.ram.len() as u64) {
-            return None;
+        let address = self.translate(Access::Load, address)?;
+
+        if (VIRTIO_RNG_BASE..VIRTIO_RNG_BASE+virtio::MMIO_SIZE).contains(&address) {
+            return self.virtio_load(address, 2).map(|v| v as u16);
         }
 
-        let ram_off = (address - self.ram_base) as usize;
-        Some(u32::from_le_bytes(self.ram[ram_off..][..4].try_into().unwrap()))
+        if (BOOTROM_BASE..BOOTROM_BASE+bootrom::ROM_SIZE).contains(&address) {
+            return self.rom_load(address, 2).map(|v| v as u16);
+        }
 
+        //TODO: handle MMIO
+        let ram_off = self.ram_index(address)?;
+        Ok(u16::from_le_bytes(self.ram[ram_off..][..2].try_into().unwrap()))
     }
+    fn load_u32(&mut self, address: u64) -> Result<u32, MemFault> {
+        self.check_watch(address, 4, false);
 
-    fn load_u64(&mut self, address: u64) -> Option<u64> {
         // alignment
-        if address % 8 != 0 {
-            return None;
+        if !address.is_multiple_of(4) {
+            return self.load_misaligned(address, 4).map(|v| v as u32);
+        }
+
+        let address = self.translate(Access::Load, address)?;
+
+        if (CLINT_BASE..CLINT_BASE+clint::MMIO_SIZE).contains(&address) {
+            return self.clint_load(address, 4).map(|v| v as u32);
+        }
+        if (PLIC_BASE..PLIC_BASE+plic::MMIO_SIZE).contains(&address) {
+            return self.plic_load(address);
+        }
+        if (RTC_BASE..RTC_BASE+rtc::MMIO_SIZE).contains(&address) {
+            return self.rtc_load(address);
+        }
+        if (VIRTIO_RNG_BASE..VIRTIO_RNG_BASE+virtio::MMIO_SIZE).contains(&address) {
+            return self.virtio_load(address, 4);
+        }
+
+        if (BOOTROM_BASE..BOOTROM_BASE+bootrom::ROM_SIZE).contains(&address) {
+            return self.rom_load(address, 4).map(|v| v as u32);
         }
 
-        // bounds
         //TODO: handle MMIO
-        if address < self.ram_base || address >= (self.ram_base + self.ram.len() as u64) {
-            return None;
+        let ram_off = self.ram_index(address)?;
+        Ok(u32::from_le_bytes(self.ram[ram_off..][..4].try_into().unwrap()))
+    }
+
+    fn load_u64(&mut self, address: u64) -> Result<u64, MemFault> {
+        self.check_watch(address, 8, false);
+
+        // alignment
+        if !address.is_multiple_of(8) {
+            return self.load_misaligned(address, 8);
+        }
+
+        let address = self.translate(Access::Load, address)?;
+
+        if (CLINT_BASE..CLINT_BASE+clint::MMIO_SIZE).contains(&address) {
+            return self.clint_load(address, 8);
         }
 
-        let ram_off = (address - self.ram_base) as usize;
-        Some(u64::from_le_bytes(self.ram[ram_off..][..8].try_into().unwrap()))
+        if (BOOTROM_BASE..BOOTROM_BASE+bootrom::ROM_SIZE).contains(&address) {
+            return self.rom_load(address, 8);
+        }
+
+        //TODO: handle MMIO
+        let ram_off = self.ram_index(address)?;
+        Ok(u64::from_le_bytes(self.ram[ram_off..][..8].try_into().unwrap()))
     }
 
     pub fn step(&mut self) {
         debug_assert!(self.regs[0] == 0);
-        debug_assert!(self.pc != 0x80000AEC);
-
-        println!("{:#010X}", self.pc);
 
-        let insn = self.fetch_and_decode_insn(self.pc);
-        match insn {
-            Instruction::Auipc(u) => {
-                self.regs[u.rd as usize] = self.pc.wrapping_add(u.imm as u64);
-                self.pc += 4;
-            }
-            Instruction::Lui(u) => {
-                if u.rd != 0 {
-                    self.regs[u.rd as usize] = u.imm as i64 as u64;
-                }
-                self.pc += 4;
-            }
-            Instruction::Addi(i) => {
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = self.regs[i.rs1 as usize].wrapping_add(i.imm as u64);
-                }
-                self.pc += 4;
-            }
-            Instruction::Addiw(i) => {
-                if i.rd != 0 {
-                    let res = self.regs[i.rs1 as usize].wrapping_add_signed(i.imm as i64) as i64;
-                    self.regs[i.rd as usize] = ((res << 32) >> 32) as u64; //XXX: is there
-                                                                                    // a better
-                                                                                    // way?
-                }
-                self.pc += 4;
-            }
-            Instruction::Andi(i) => {
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = self.regs[i.rs1 as usize] & i.imm as u64;
-                }
-                self.pc += 4;
-            }
-            Instruction::Ori(i) => {
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = self.regs[i.rs1 as usize] | i.imm as u64;
-                }
-                self.pc += 4;
-            }
-            Instruction::Slli(i) => {
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = self.regs[i.rs1 as usize] << (i.imm as u64 & 0x1F);
-                }
-                self.pc += 4;
-            }
-            Instruction::Srli(i) => {
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = self.regs[i.rs1 as usize] >> (i.imm as u64 & 0x1F);
-                }
-                self.pc += 4;
+        if self.waiting {
+            if !self.interrupt_pending() {
+                return;
             }
-            Instruction::Slti(i) => {
-                let val = self.regs[i.rs1 as usize] as i64;
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = if val < i.imm as i64 { 1 } else { 0 };
-                }
-                self.pc += 4;
-            }
-            Instruction::Sltiu(i) => {
-                let val = self.regs[i.rs1 as usize];
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = if val < i.imm as u64 { 1 } else { 0 };
-                }
-                self.pc += 4;
-            }
-            Instruction::Slt(r) => {
-                let val1 = self.regs[r.rs1 as usize] as i64;
-                let val2 = self.regs[r.rs2 as usize] as i64;
-                if r.rd != 0 {
-                    self.regs[r.rd as usize] = if val1 < val2 { 1 } else { 0 };
-                }
-                self.pc += 4;
-            }
-            Instruction::Sltu(r) => {
-                let val1 = self.regs[r.rs1 as usize];
-                let val2 = self.regs[r.rs2 as usize];
-                if r.rd != 0 {
-                    self.regs[r.rd as usize] = if val1 < val2 { 1 } else { 0 };
-                }
-                self.pc += 4;
-            }
-            Instruction::Csrrw(i) => {
-                let csrid = i.imm as u32 & 0xfff;
-                let val = self.regs[i.rs1 as usize];
-                let csr = self.read_csr(csrid);
-                self.write_csr(csrid, val);
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = csr;
-                }
-                self.pc += 4;
-            }
-            Instruction::Csrrs(i) => {
-                let csrid = i.imm as u32 & 0xfff;
-                let val = self.regs[i.rs1 as usize];
-                let csr = self.read_csr(csrid);
-                if val != 0 {
-                    self.write_csr(csrid, val | csr);
-                }
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = csr;
-                }
-                self.pc += 4;
-            }
-            Instruction::Csrrc(i) => {
-                let csrid = i.imm as u32 & 0xfff;
-                let val = self.regs[i.rs1 as usize];
-                let csr = self.read_csr(csrid);
-                if val != 0 {
-                    self.write_csr(csrid, val & !csr);
-                }
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = csr;
-                }
-                self.pc += 4;
-            }
-            Instruction::Add(r) => {
-                let opa = self.regs[r.rs1 as usize];
-                let opb = self.regs[r.rs2 as usize];
-                self.regs[r.rd as usize] = opa.wrapping_add(opb);
-                self.pc += 4;
-            }
-            Instruction::Sub(r) => {
-                let opa = self.regs[r.rs1 as usize];
-                let opb = self.regs[r.rs2 as usize];
-                self.regs[r.rd as usize] = opa.wrapping_sub(opb);
-                self.pc += 4;
-            }
-            Instruction::And(r) => {
-                let opa = self.regs[r.rs1 as usize];
-                let opb = self.regs[r.rs2 as usize];
-                self.regs[r.rd as usize] = opa & opb;
-                self.pc += 4;
-            }
-            Instruction::Or(r) => {
-                let opa = self.regs[r.rs1 as usize];
-                let opb = self.regs[r.rs2 as usize];
-                self.regs[r.rd as usize] = opa | opb;
-                self.pc += 4;
-            }
-            Instruction::Xor(r) => {
-                let opa = self.regs[r.rs1 as usize];
-                let opb = self.regs[r.rs2 as usize];
-                self.regs[r.rd as usize] = opa ^ opb;
-                self.pc += 4;
-            }
-            Instruction::Mul(r) => {
-                let opa = self.regs[r.rs1 as usize];
-                let opb = self.regs[r.rs2 as usize];
-                self.regs[r.rd as usize] = opa * opb;
-                self.pc += 4;
-            }
-            Instruction::Mulh(r) => {
-                let opa = self.regs[r.rs1 as usize];
-                let opb = self.regs[r.rs2 as usize];
-                self.regs[r.rd as usize] = ((opa as u128 * opb as u128) >> 64) as u64;
-                self.pc += 4;
-            }
-            Instruction::Jal(j) => {
-                if j.rd != 0 {
-                    self.regs[j.rd as usize] = self.pc + 4;
-                }
+            self.waiting = false;
+        }
 
-                let target = self.pc.wrapping_add_signed(j.imm as i64);
-                self.pc = target;
+        if self.check_irqs && !self.irqs_masked {
+            self.check_irqs = false;
+            if let Some(irq) = self.pending_interrupt() {
+                self.take_trap(Trap::Interrupt(irq));
+                return;
             }
-            Instruction::Jalr(i) => {
-                let val = self.pc + 4;
-                let target = self.regs[i.rs1 as usize].wrapping_add_signed(i.imm as i64) & !1;
-                self.pc = target;
+        }
 
-                if i.rd != 0 {
-                    self.regs[i.rd as usize] = val;
-                }
-            }
-            Instruction::Store(s) => {
-                // size
-                let addr = self.regs[s.rs1 as usize].wrapping_add_signed(s.imm as i64);
-                let val = self.regs[s.rs2 as usize];
-                let retired = match s.funct3 {
-                    0 => self.store_u8(addr, val as u8),
-                    1 => self.store_u16(addr, val as u16),
-                    2 => self.store_u32(addr, val as u32),
-                    3 => self.store_u64(addr, val),
-                    _ => unimplemented!("pc={:08X} {:X?}", self.pc, insn),
-                };
+        if let Some(violation) = self.wx.as_mut().and_then(|wx| wx.on_exec(self.pc)) {
+            self.report_wx(violation, self.pc);
+        }
 
-                if retired {
-                    self.pc += 4;
-                } else {
-                    unimplemented!("store exception pc={:08X} addr={:#010X?} val={:#X?}", self.pc, addr, val);
-                }
-            }
-            Instruction::Load(i) => {
-                let addr = self.regs[i.rs1 as usize].wrapping_add_signed(i.imm as i64);
-                let val = match i.funct3 {
-                    0 => self.load_u8(addr).map(|x| x as i8 as i64),
-                    1 => self.load_u16(addr).map(|x| x as i8 as i64),
-                    2 => self.load_u32(addr).map(|x| x as i8 as i64),
-                    3 => self.load_u64(addr).map(|x| x as i64),
-                    4 => self.load_u8(addr).map(|x| x as i64),
-                    5 => self.load_u16(addr).map(|x| x as i64),
-                    _ => unimplemented!("pc={:08X} {:X?}", self.pc, insn),
-                };
+        let pc = self.pc;
+        let bits = match self.fetch(pc) {
+            Ok(bits) => bits,
+            Err(fault) => return self.take_trap(Trap::Exception(fault.exception(Access::Fetch), pc)),
+        };
+        self.insn_bits = bits;
+        if let Some(observer) = &mut self.observer {
+            observer.fetched(pc, bits);
+        }
 
-                if let Some(val) = val {
-                    self.regs[i.rd as usize] = val as u64;
-                    self.pc += 4;
-                } else {
-                    unimplemented!("load exception pc={:08X} addr={:#010X?}", self.pc, addr);
-                }
-            }
-            Instruction::Mret(i) => {
-                let mpp = (self.mstatus >> 11) & 3;
-                let mpie = (self.mstatus >> 7) & 1;
+        let insn = self.decode_insn(bits);
+        if let Some(observer) = &mut self.observer {
+            observer.decoded(pc, &insn);
+        }
 
-                self.mstatus &= !(1 << mpp);
-                self.mstatus |= mpie << mpp;
+        // Fused pairs skip the stages of the second instruction, so they are
+        // off while observed.
+        if self.fusion && self.observer.is_none() && self.step_fused(insn) {
+            return;
+        }
+        self.execute(insn);
+        if let Some(observer) = &mut self.observer {
+            observer.executed(pc, &insn, self.pc);
+        }
+    }
 
-                // set MPIE
-                self.mstatus |= 1 << 7;
+    // Counts and runs one decoded instruction at pc.
+    fn execute(&mut self, insn: Instruction) {
+        self.instret += 1;
+        exec::handler(&insn)(self, insn);
+    }
 
-                self.mstatus &= !(3 << 11);
-                self.privl = mpp as u8;
-                self.pc = self.mepc;
+    // Executes `first` and the instruction after it as one step when they form
+    // one of the common pairs below, with the same result as two steps. The
+    // second instruction has to be in the same page so W^X checks stay exact.
+    fn step_fused(&mut self, first: Instruction) -> bool {
+        if !matches!(first, Instruction::Lui(_) | Instruction::Auipc(_) | Instruction::Slli(_)
+            | Instruction::Slt(_) | Instruction::Sltu(_) | Instruction::Slti(_) | Instruction::Sltiu(_))
+        {
+            return false;
+        }
+        let next_pc = self.pc + 4;
+        if next_pc.is_multiple_of(4096) {
+            return false;
+        }
 
+        let Ok(second) = self.fetch_and_decode_insn(next_pc) else {
+            return false;
+        };
+        match (first, second) {
+            // lui+addi: 32-bit constants
+            (Instruction::Lui(u), Instruction::Addi(i)) if u.rd != 0 && i.rd == u.rd && i.rs1 == u.rd => {
+                self.regs[u.rd as usize] = (u.imm as i64 as u64).wrapping_add(i.imm as u64);
+                self.pc += 8;
             }
-            Instruction::Beq(b) => {
-                let cond = self.regs[b.rs1 as usize] == self.regs[b.rs2 as usize];
-                if cond {
-                    let target = self.pc.wrapping_add_signed(b.imm as i64);
-                    self.pc = target;
-                } else {
-                    self.pc += 4;
-                }
-            }
-            Instruction::Bne(b) => {
-                let cond = self.regs[b.rs1 as usize] != self.regs[b.rs2 as usize];
-                if cond {
-                    let target = self.pc.wrapping_add_signed(b.imm as i64);
-                    self.pc = target;
-                } else {
-                    self.pc += 4;
+            // auipc+jalr: far calls and jumps
+            (Instruction::Auipc(u), Instruction::Jalr(i)) if u.rd != 0 && i.rs1 == u.rd => {
+                // A misaligned target traps at the jalr, leave it to step().
+                let base = self.pc.wrapping_add(u.imm as u64);
+                let target = base.wrapping_add_signed(i.imm as i64) & !1;
+                if !target.is_multiple_of(4) {
+                    return false;
                 }
-            }
-            Instruction::Blt(b) => {
-                let cond = (self.regs[b.rs1 as usize] as i64) < self.regs[b.rs2 as usize] as i64;
-                if cond {
-                    let target = self.pc.wrapping_add_signed(b.imm as i64);
-                    self.pc = target;
-                } else {
-                    self.pc += 4;
+                self.regs[u.rd as usize] = base;
+                if i.rd != 0 {
+                    self.regs[i.rd as usize] = self.pc + 8;
                 }
+                self.pc = target;
             }
-            Instruction::Bge(b) => {
-                let cond = (self.regs[b.rs1 as usize] as i64) >= self.regs[b.rs2 as usize] as i64;
-                if cond {
-                    let target = self.pc.wrapping_add_signed(b.imm as i64);
-                    self.pc = target;
-                } else {
-                    self.pc += 4;
-                }
+            // slli+add: indexing
+            (Instruction::Slli(i), Instruction::Add(r)) if i.rd != 0 && (r.rs1 == i.rd || r.rs2 == i.rd) => {
+                self.regs[i.rd as usize] = self.regs[i.rs1 as usize] << i.shamt;
+                self.set_reg(r.rd as usize, self.regs[r.rs1 as usize].wrapping_add(self.regs[r.rs2 as usize]));
+                self.pc += 8;
             }
-            Instruction::Bltu(b) => {
-                let cond = self.regs[b.rs1 as usize] < self.regs[b.rs2 as usize];
-                if cond {
-                    let target = self.pc.wrapping_add_signed(b.imm as i64);
-                    self.pc = target;
-                } else {
-                    self.pc += 4;
+            // set-less-than+beqz/bnez: compare and branch
+            (cmp @ (Instruction::Slt(_) | Instruction::Sltu(_) | Instruction::Slti(_) | Instruction::Sltiu(_)),
+                branch @ (Instruction::Beq(b) | Instruction::Bne(b))) => {
+                let (rd, lt) = match cmp {
+                    Instruction::Slt(r) => (r.rd, (self.regs[r.rs1 as usize] as i64) < self.regs[r.rs2 as usize] as i64),
+                    Instruction::Sltu(r) => (r.rd, self.regs[r.rs1 as usize] < self.regs[r.rs2 as usize]),
+                    Instruction::Slti(i) => (i.rd, (self.regs[i.rs1 as usize] as i64) < i.imm as i64),
+                    Instruction::Sltiu(i) => (i.rd, self.regs[i.rs1 as usize] < i.imm as u64),
+                    _ => unreachable!(),
+                };
+                if rd == 0 || !((b.rs1 == rd && b.rs2 == 0) || (b.rs1 == 0 && b.rs2 == rd)) {
+                    return false;
                 }
-            }
-            Instruction::Bgeu(b) => {
-                let cond = self.regs[b.rs1 as usize] >= self.regs[b.rs2 as usize];
-                if cond {
-                    let target = self.pc.wrapping_add_signed(b.imm as i64);
-                    self.pc = target;
-                } else {
-                    self.pc += 4;
+                let taken = lt == matches!(branch, Instruction::Bne(_));
+                let target = next_pc.wrapping_add_signed(b.imm as i64);
+                if taken && !target.is_multiple_of(4) {
+                    return false;
                 }
+                self.regs[rd as usize] = lt as u64;
+                self.pc = match taken {
+                    true => target,
+                    false => self.pc + 8,
+                };
             }
-            Instruction::Amoswapw(r) => {
-                let addr = self.regs[r.rs1 as usize];
-                let val = self.regs[r.rs2 as usize];
-                let memval = self.load_u32(addr).unwrap_or_else(|| unimplemented!("load exception pc={:08X} addr={:#010X?}", self.pc, addr));
-                if !self.store_u32(addr, val as u32) {
-                        unimplemented!("store exception pc={:08X} addr={:#010X?}", self.pc, addr);
-                }
+            _ => return false,
+        }
+        self.instret += 2;
+        self.fused_pc = Some(next_pc);
+        true
+    }
 
-                if r.rd != 0 {
-                    self.regs[r.rd as usize] = ((memval as i64) << 32 >> 32) as u64;
-                }
-                self.pc += 4;
-            }
-            Instruction::Fence => {
-                self.pc += 4;
-            }
-            _ => unimplemented!("pc={:08X} {:X?}", self.pc, insn),
+    // FENCE.I makes earlier stores visible to instruction fetch. Fetch decodes
+    // straight from RAM so there is nothing to drop yet, any cache of decoded
+    // instructions or fetch translations has to be flushed here.
+    fn invalidate_fetch_caches(&mut self) {}
+
+    fn fetch(&mut self, address: u64) -> Result<u32, MemFault> {
+        let address = self.translate(Access::Fetch, address)?;
+        self.fetch_physical(address)
+    }
+
+    fn fetch_physical(&self, address: u64) -> Result<u32, MemFault> {
+        if (BOOTROM_BASE..BOOTROM_BASE+bootrom::ROM_SIZE).contains(&address) {
+            return self.rom_load(address, 4).map(|v| v as u32);
         }
+        let ram_off = self.ram_index(address)?;
+        let bytes = self.ram.get(ram_off..ram_off + 4).ok_or(MemFault::Access)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
     }
 
-    fn fetch_and_decode_insn(&self, address: u64) -> Instruction {
-        let ram_off = (address - self.ram_base) as usize;
-        let bytes = &self.ram[ram_off..][..4];
+    fn fetch_and_decode_insn(&mut self, address: u64) -> Result<Instruction, MemFault> {
+        self.fetch(address).map(|instruction| self.decode_insn(instruction))
+    }
 
-        let instruction = u32::from_le_bytes(bytes.try_into().unwrap());
+    // Instructions of extensions the hart lacks decode as invalid.
+    fn decode_insn(&self, instruction: u32) -> Instruction {
+        let insn = Instruction::decode(instruction);
+        let ext = match insn {
+            Instruction::Aes64ks1i(_) | Instruction::Aes64ks2(_) if self.isa.has("zknd") => "zknd",
+            _ => insn.extension(),
+        };
+        if !self.isa.has(ext) {
+            return Instruction::Invalid(instruction);
+        }
+        // Conversions between halves and doubles need both.
+        if matches!(insn, Instruction::FcvtDH(_) | Instruction::FcvtHD(_)) && !self.isa.has("d") {
+            return Instruction::Invalid(instruction);
+        }
+        // Byte and halfword compare-and-swap need Zacas as well.
+        if matches!(insn, Instruction::AmocasB(_) | Instruction::AmocasH(_)) && !self.isa.has("zacas") {
+            return Instruction::Invalid(instruction);
+        }
+        if insn.is_hint(instruction) {
+            return Instruction::Hint;
+        }
+        insn
+    }
+}
+
+impl vector::Hart for Cpu {
+    fn xreg(&self, idx: u8) -> u64 {
+        self.regs[idx as usize]
+    }
+
+    fn set_xreg(&mut self, idx: u8, val: u64) {
+        self.set_reg(idx as usize, val);
+    }
+
+    fn load(&mut self, addr: u64, size: usize) -> Result<u64, MemFault> {
+        match size {
+            1 => self.load_u8(addr).map(u64::from),
+            2 => self.load_u16(addr).map(u64::from),
+            4 => self.load_u32(addr).map(u64::from),
+            _ => self.load_u64(addr),
+        }
+    }
+
+    fn store(&mut self, addr: u64, size: usize, val: u64) -> Result<(), MemFault> {
+        match size {
+            1 => self.store_u8(addr, val as u8),
+            2 => self.store_u16(addr, val as u16),
+            4 => self.store_u32(addr, val as u32),
+            _ => self.store_u64(addr, val),
+        }
+    }
+}
+
+impl virtio::Memory for Cpu {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> bool {
+        let Some(bytes) = self.debug_ram(addr, buf.len() as u64) else {
+            return false;
+        };
+        buf.copy_from_slice(bytes);
+        true
+    }
 
-        Instruction::decode(instruction)
+    fn write(&mut self, addr: u64, data: &[u8]) -> bool {
+        self.debug_write_mem(addr, data)
     }
 }
@@ -0,0 +1,280 @@
+use super::*;
+
+// The CSR file: every CSR is a Csr entry keyed by number, with its read and
+// WARL write behaviour and the side effects a write has on the rest of the
+// hart. Who may access a CSR comes from the number itself, bits 9:8 are the
+// lowest privilege level and 0b11 in bits 11:10 marks it read-only.
+
+// Side effects of a successful write.
+pub const FLUSH_PAGES: u8 = 1 << 0; // changes what an access may touch
+pub const FS_DIRTY: u8 = 1 << 1;
+pub const VS_DIRTY: u8 = 1 << 2;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Csr {
+    pub name: &'static str,
+    // First and last number, a range for the pmp arrays.
+    pub first: u32,
+    pub last: u32,
+    // Whether the CSR exists in the hart's configuration.
+    pub present: fn(&Cpu) -> bool,
+    // Whether the hart may access it at its current level, for CSRs a
+    // higher level enables beyond what the number says.
+    pub enabled: fn(&Cpu) -> bool,
+    pub read: fn(&Cpu, u32) -> u64,
+    // The value CSRRS/CSRRC modify, when it differs from what is read.
+    pub read_rmw: Option<fn(&Cpu, u32) -> u64>,
+    // Stores the legal part of a written value, None for read-only CSRs.
+    pub write: Option<fn(&mut Cpu, u32, u64)>,
+    pub effects: u8,
+}
+
+// The lowest privilege level that may access the CSR.
+pub fn privilege(num: u32) -> u8 {
+    ((num >> 8) & 3) as u8
+}
+
+pub fn read_only(num: u32) -> bool {
+    (num >> 10) & 3 == 3
+}
+
+const NONE: u8 = 0xff;
+
+pub struct CsrFile {
+    index: Vec<u8>,
+    csrs: Vec<Csr>,
+}
+
+impl CsrFile {
+    pub fn new() -> Self {
+        let mut file = CsrFile { index: vec![NONE; 4096], csrs: Vec::new() };
+        for csr in STANDARD {
+            file.add(*csr);
+        }
+        file
+    }
+
+    // Adds or replaces the CSRs in csr's range, for custom CSRs.
+    pub fn add(&mut self, csr: Csr) {
+        assert!(csr.first <= csr.last && csr.last < 4096, "csr {} out of range", csr.name);
+        assert!(self.csrs.len() < NONE as usize, "csr file full");
+        if csr.write.is_some() && read_only(csr.first) {
+            panic!("csr {} is writable but numbered read-only", csr.name);
+        }
+        let idx = self.csrs.len() as u8;
+        self.csrs.push(csr);
+        for num in csr.first..=csr.last {
+            self.index[num as usize] = idx;
+        }
+    }
+
+    // The name of a single CSR, ranges append the index: pmpaddr3.
+    pub fn name(&self, num: u32) -> Option<String> {
+        let csr = self.get(num)?;
+        if csr.first == csr.last {
+            Some(csr.name.to_string())
+        } else {
+            Some(format!("{}{}", csr.name, num - csr.first))
+        }
+    }
+
+    pub fn get(&self, num: u32) -> Option<&Csr> {
+        match self.index.get(num as usize) {
+            Some(&idx) if idx != NONE => Some(&self.csrs[idx as usize]),
+            _ => None,
+        }
+    }
+}
+
+impl Default for CsrFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn always(_: &Cpu) -> bool {
+    true
+}
+
+fn has_v(cpu: &Cpu) -> bool {
+    cpu.isa.has("v")
+}
+
+fn has_sstc(cpu: &Cpu) -> bool {
+    cpu.isa.has("sstc")
+}
+
+// Below M-mode stimecmp needs menvcfg.STCE and mcounteren.TM.
+fn stimecmp_enabled(cpu: &Cpu) -> bool {
+    cpu.privl == 3 || (cpu.menvcfg & MENVCFG_STCE != 0 && cpu.mcounteren & MCOUNTEREN_TM != 0)
+}
+
+// The F CSRs are off with mstatus.FS.
+fn fs_enabled(cpu: &Cpu) -> bool {
+    cpu.mstatus & MSTATUS_FS != 0
+}
+
+// TVM keeps S-mode off satp.
+fn satp_enabled(cpu: &Cpu) -> bool {
+    cpu.privl != 1 || cpu.mstatus & MSTATUS_TVM == 0
+}
+
+// cycle, time and instret below M-mode need their bit in mcounteren, in
+// U-mode in scounteren as well.
+fn counter_enabled(cpu: &Cpu, bit: u64) -> bool {
+    match cpu.privl {
+        3 => true,
+        1 => cpu.mcounteren & bit != 0,
+        _ => cpu.mcounteren & cpu.scounteren & bit != 0,
+    }
+}
+
+const fn csr(name: &'static str, num: u32, read: fn(&Cpu, u32) -> u64,
+             write: Option<fn(&mut Cpu, u32, u64)>, effects: u8) -> Csr {
+    Csr { name, first: num, last: num, present: always, enabled: always, read, read_rmw: None, write, effects }
+}
+
+const STANDARD: &[Csr] = &[
+    Csr {
+        enabled: fs_enabled,
+        ..csr("fflags", 0x001, |cpu, _| cpu.fcsr & 0x1f,
+            Some(|cpu, _, val| cpu.fcsr = (cpu.fcsr & !0x1f) | (val & 0x1f)), FS_DIRTY)
+    },
+    Csr {
+        enabled: fs_enabled,
+        ..csr("frm", 0x002, |cpu, _| (cpu.fcsr >> 5) & 7,
+            Some(|cpu, _, val| cpu.fcsr = (cpu.fcsr & 0x1f) | ((val & 7) << 5)), FS_DIRTY)
+    },
+    Csr {
+        enabled: fs_enabled,
+        ..csr("fcsr", 0x003, |cpu, _| cpu.fcsr, Some(|cpu, _, val| cpu.fcsr = val & 0xff), FS_DIRTY)
+    },
+    Csr { present: has_v, ..csr("vstart", 0x008, read_vector, Some(write_vector), VS_DIRTY) },
+    Csr { present: has_v, ..csr("vxsat", 0x009, read_vector, Some(write_vector), VS_DIRTY) },
+    Csr { present: has_v, ..csr("vxrm", 0x00A, read_vector, Some(write_vector), VS_DIRTY) },
+    Csr { present: has_v, ..csr("vcsr", 0x00F, read_vector, Some(write_vector), VS_DIRTY) },
+    csr("sstatus", 0x100, |cpu, _| cpu.mstatus & SSTATUS_MASK,
+        Some(|cpu, _, val| cpu.mstatus = mstatus::write_sstatus(cpu.mstatus, val, &cpu.isa)),
+        FLUSH_PAGES),
+    csr("sie", 0x104, |cpu, _| cpu.mie & cpu.mideleg,
+        Some(|cpu, _, val| {
+            let mask = cpu.mideleg;
+            cpu.mie = (val & mask) | (cpu.mie & !mask);
+        }), 0),
+    // only SSIP is writable through sip
+    csr("sip", 0x144, |cpu, _| cpu.read_mip() & cpu.mideleg,
+        Some(|cpu, _, val| {
+            let mask = cpu.mideleg & MIP_SSIP;
+            cpu.mip = (val & mask) | (cpu.mip & !mask);
+        }), 0),
+    // only direct and vectored modes
+    csr("stvec", 0x105, |cpu, _| cpu.stvec,
+        Some(|cpu, _, val| {
+            if val & 3 < 2 {
+                cpu.stvec = val;
+            }
+        }), 0),
+    csr("scounteren", 0x106, |cpu, _| cpu.scounteren,
+        Some(|cpu, _, val| cpu.scounteren = val & COUNTEREN_MASK), 0),
+    csr("sscratch", 0x140, |cpu, _| cpu.sscratch, Some(|cpu, _, val| cpu.sscratch = val), 0),
+    csr("sepc", 0x141, |cpu, _| cpu.sepc, Some(|cpu, _, val| cpu.sepc = val & !1), 0),
+    csr("scause", 0x142, |cpu, _| cpu.scause, Some(|cpu, _, val| cpu.scause = val), 0),
+    csr("stval", 0x143, |cpu, _| cpu.stval, Some(|cpu, _, val| cpu.stval = val), 0),
+    Csr {
+        present: has_sstc,
+        enabled: stimecmp_enabled,
+        ..csr("stimecmp", 0x14D, |cpu, _| cpu.stimecmp,
+            Some(|cpu, _, val| {
+                cpu.stimecmp = val;
+                cpu.update_timers();
+            }), 0)
+    },
+    // WARL, a write of a mode other than Bare or Sv39 is ignored. There are
+    // no ASIDs, the field reads as zero.
+    Csr {
+        enabled: satp_enabled,
+        ..csr("satp", 0x180, |cpu, _| cpu.satp,
+            Some(|cpu, _, val| {
+                let mode = val >> mmu::SATP_MODE_SHIFT;
+                if mode == mmu::SATP_MODE_BARE || mode == mmu::SATP_MODE_SV39 {
+                    cpu.satp = val & (mode << mmu::SATP_MODE_SHIFT | mmu::SATP_PPN);
+                }
+            }), FLUSH_PAGES)
+    },
+    // MPRV, SUM and MXR change what an access may touch.
+    csr("mstatus", 0x300, |cpu, _| cpu.mstatus,
+        Some(|cpu, _, val| cpu.mstatus = mstatus::write(cpu.mstatus, val, &cpu.isa)), FLUSH_PAGES),
+    // WARL, the extensions are fixed at startup
+    csr("misa", 0x301, |cpu, _| if cpu.fuzz.misa_zero { 0 } else { cpu.misa }, Some(|_, _, _| {}), 0),
+    csr("medeleg", 0x302, |cpu, _| cpu.medeleg, Some(|cpu, _, val| cpu.medeleg = val & MEDELEG_MASK), 0),
+    csr("mideleg", 0x303, |cpu, _| cpu.mideleg, Some(|cpu, _, val| cpu.mideleg = val & MIDELEG_MASK), 0),
+    csr("mie", 0x304, |cpu, _| cpu.mie, Some(|cpu, _, val| cpu.mie = val & MIE_MASK), 0),
+    // only direct and vectored modes
+    csr("mtvec", 0x305, |cpu, _| cpu.mtvec,
+        Some(|cpu, _, val| {
+            if val & 3 < 2 {
+                cpu.mtvec = if cpu.fuzz.mtvec_direct_only { val & !3 } else { val };
+            }
+        }), 0),
+    csr("mcounteren", 0x306, |cpu, _| cpu.mcounteren, Some(|cpu, _, val| cpu.mcounteren = val & COUNTEREN_MASK), 0),
+    csr("menvcfg", 0x30a, |cpu, _| cpu.menvcfg,
+        Some(|cpu, _, val| {
+            cpu.menvcfg = if has_sstc(cpu) { val } else { val & !MENVCFG_STCE };
+            cpu.update_timers();
+        }), 0),
+    csr("mepc", 0x341, |cpu, _| cpu.mepc, Some(|cpu, _, val| cpu.mepc = val), 0),
+    csr("mcause", 0x342, |cpu, _| cpu.mcause, Some(|cpu, _, val| cpu.mcause = val), 0),
+    csr("mtval", 0x343, |cpu, _| cpu.mtval, Some(|cpu, _, val| cpu.mtval = val), 0),
+    // Only the software-writable SEIP bit participates in a CSRRS/CSRRC of
+    // mip. With Sstc enabled STIP reflects stimecmp and is read-only.
+    Csr {
+        read_rmw: Some(|cpu, _| cpu.mip | ((cpu.mip_hw | cpu.mip_injected) & !MIP_SEIP)),
+        ..csr("mip", 0x344, |cpu, _| cpu.read_mip(),
+            Some(|cpu, _, val| {
+                let mut mask = MIP_SSIP | MIP_STIP | MIP_SEIP;
+                if cpu.menvcfg & MENVCFG_STCE != 0 {
+                    mask &= !MIP_STIP;
+                }
+                cpu.mip = (val & mask) | (cpu.mip & !mask);
+            }), 0)
+    },
+    Csr {
+        last: 0x3a3,
+        ..csr("pmpcfg", 0x3a0, |cpu, num| cpu.pmpcfg[(num & 0x0f) as usize],
+            Some(|cpu, num, val| cpu.pmpcfg[(num & 0x0f) as usize] = val), FLUSH_PAGES)
+    },
+    Csr {
+        last: 0x3ef,
+        ..csr("pmpaddr", 0x3b0, |cpu, num| cpu.pmpaddr[(num - 0x3b0) as usize],
+            Some(|cpu, num, val| cpu.pmpaddr[(num - 0x3b0) as usize] = val), FLUSH_PAGES)
+    },
+    // Guest-controlled tracing, usable from U-mode: writing nrvtrace starts
+    // (non-zero) or stops (zero) the trace, nrvmark takes the address of a
+    // marker string to record.
+    csr("nrvtrace", 0x8C0, |_, _| 0,
+        Some(|cpu, _, val| cpu.guest_trace = Some(if val != 0 { GuestTrace::Start } else { GuestTrace::Stop })), 0),
+    csr("nrvmark", 0x8C1, |_, _| 0,
+        Some(|cpu, _, val| cpu.guest_trace = Some(GuestTrace::Marker(cpu.read_guest_str(val)))), 0),
+    // Not cycle accurate: a cycle is a step, retiring or waiting in WFI.
+    Csr {
+        enabled: |cpu| counter_enabled(cpu, MCOUNTEREN_CY),
+        ..csr("cycle", 0xC00, |cpu, _| cpu.steps(), None, 0)
+    },
+    Csr { enabled: |cpu| counter_enabled(cpu, MCOUNTEREN_TM), ..csr("time", 0xC01, |cpu, _| cpu.time(), None, 0) },
+    Csr {
+        enabled: |cpu| counter_enabled(cpu, MCOUNTEREN_IR),
+        ..csr("instret", 0xC02, |cpu, _| cpu.instret, None, 0)
+    },
+    Csr { present: has_v, ..csr("vl", 0xC20, read_vector, None, 0) },
+    Csr { present: has_v, ..csr("vtype", 0xC21, read_vector, None, 0) },
+    Csr { present: has_v, ..csr("vlenb", 0xC22, read_vector, None, 0) },
+    csr("mhartid", 0xF14, |_, _| 0, None, 0),
+];
+
+fn read_vector(cpu: &Cpu, num: u32) -> u64 {
+    cpu.vector.read_csr(num).unwrap_or(0)
+}
+
+fn write_vector(cpu: &mut Cpu, num: u32, val: u64) {
+    cpu.vector.write_csr(num, val);
+}
@@ -0,0 +1,1614 @@
+use super::*;
+
+// The execute stage. Each handler runs one group of instructions that share
+// operands and checks and `handler` is the table from instruction to handler.
+// A handler gets an instruction that has been fetched, decoded and counted
+// and leaves pc at the next one or takes the trap it raised.
+
+pub(super) type Handler = fn(&mut Cpu, Instruction);
+
+pub(super) fn handler(insn: &Instruction) -> Handler {
+    match insn {
+        Instruction::Auipc(_) | Instruction::Lui(_) | Instruction::Addi(_) | Instruction::Addiw(_)
+            | Instruction::Slliw(_) | Instruction::Srliw(_) | Instruction::Sraiw(_) | Instruction::Andi(_)
+            | Instruction::Ori(_) | Instruction::Xori(_) | Instruction::Slli(_) | Instruction::Srli(_) | Instruction::Srai(_)
+            | Instruction::Slti(_) | Instruction::Sltiu(_) | Instruction::Slt(_) | Instruction::Sltu(_)
+            | Instruction::Add(_) | Instruction::Sub(_) | Instruction::And(_) | Instruction::Or(_)
+            | Instruction::Xor(_) | Instruction::Sll(_) | Instruction::Srl(_) | Instruction::Sra(_)
+            | Instruction::Addw(_) | Instruction::Subw(_) | Instruction::Sllw(_)
+            | Instruction::Srlw(_) | Instruction::Sraw(_) => Cpu::exec_alu,
+        Instruction::Csrrw(_) | Instruction::Csrrs(_) | Instruction::Csrrc(_) | Instruction::Csrrwi(_)
+            | Instruction::Csrrsi(_) | Instruction::Csrrci(_) => Cpu::exec_csr,
+        Instruction::Mul(_) | Instruction::Mulh(_) | Instruction::Mulhsu(_) | Instruction::Mulhu(_)
+            | Instruction::Div(_) | Instruction::Divu(_) | Instruction::Rem(_) | Instruction::Remu(_)
+            | Instruction::Mulw(_) | Instruction::Divw(_) | Instruction::Divuw(_) | Instruction::Remw(_)
+            | Instruction::Remuw(_) => Cpu::exec_muldiv,
+        Instruction::Sh1add(_) | Instruction::Sh2add(_) | Instruction::Sh3add(_) | Instruction::AddUw(_)
+            | Instruction::Sh1addUw(_) | Instruction::Sh2addUw(_) | Instruction::Sh3addUw(_)
+            | Instruction::SlliUw(_) | Instruction::Andn(_) | Instruction::Orn(_) | Instruction::Xnor(_)
+            | Instruction::Max(_) | Instruction::Maxu(_) | Instruction::Min(_) | Instruction::Minu(_)
+            | Instruction::Rol(_) | Instruction::Ror(_) | Instruction::Rolw(_) | Instruction::Rorw(_)
+            | Instruction::ZextH(_) | Instruction::Clz(_) | Instruction::Clzw(_) | Instruction::Ctz(_)
+            | Instruction::Ctzw(_) | Instruction::Cpop(_) | Instruction::Cpopw(_) | Instruction::SextB(_)
+            | Instruction::SextH(_) | Instruction::Rori(_) | Instruction::Roriw(_) | Instruction::OrcB(_)
+            | Instruction::Rev8(_) | Instruction::Clmul(_) | Instruction::Clmulh(_) | Instruction::Clmulr(_)
+            | Instruction::Bclr(_) | Instruction::Bext(_) | Instruction::Binv(_) | Instruction::Bset(_)
+            | Instruction::Bclri(_) | Instruction::Bexti(_) | Instruction::Binvi(_) | Instruction::Bseti(_)
+            | Instruction::CzeroEqz(_) | Instruction::CzeroNez(_) => Cpu::exec_bitmanip,
+        Instruction::Aes64es(_) | Instruction::Aes64esm(_) | Instruction::Aes64ds(_) | Instruction::Aes64dsm(_)
+            | Instruction::Aes64ks2(_) | Instruction::Aes64im(_) | Instruction::Aes64ks1i(_)
+            | Instruction::Sha256sig0(_) | Instruction::Sha256sig1(_) | Instruction::Sha256sum0(_)
+            | Instruction::Sha256sum1(_) | Instruction::Sha512sig0(_) | Instruction::Sha512sig1(_)
+            | Instruction::Sha512sum0(_) | Instruction::Sha512sum1(_) => Cpu::exec_crypto,
+        Instruction::Jal(_) | Instruction::Jalr(_) | Instruction::Beq(_) | Instruction::Bne(_) | Instruction::Blt(_)
+            | Instruction::Bge(_) | Instruction::Bltu(_) | Instruction::Bgeu(_) => Cpu::exec_jump,
+        Instruction::Store(_) | Instruction::Load(_) => Cpu::exec_memory,
+        Instruction::Mret(_) | Instruction::Sret(_) | Instruction::Fence | Instruction::Pause | Instruction::Hint
+            | Instruction::FenceI | Instruction::SfenceVma(_) | Instruction::CboClean(_) | Instruction::CboFlush(_)
+            | Instruction::CboInval(_) | Instruction::CboZero(_) | Instruction::Wfi(_) | Instruction::Ecall(_)
+            | Instruction::Ebreak(_) => Cpu::exec_system,
+        Instruction::LrW(_) | Instruction::LrD(_) | Instruction::ScW(_) | Instruction::ScD(_)
+            | Instruction::Amoswapw(_) | Instruction::Amoaddw(_) | Instruction::Amoxorw(_) | Instruction::Amoandw(_)
+            | Instruction::Amoorw(_) | Instruction::Amominw(_) | Instruction::Amomaxw(_) | Instruction::Amominuw(_)
+            | Instruction::Amomaxuw(_) | Instruction::Amoswapd(_) | Instruction::Amoaddd(_)
+            | Instruction::Amoxord(_) | Instruction::Amoandd(_) | Instruction::Amoord(_) | Instruction::Amomind(_)
+            | Instruction::Amomaxd(_) | Instruction::Amominud(_) | Instruction::Amomaxud(_)
+            | Instruction::Amoswapb(_) | Instruction::Amoaddb(_) | Instruction::Amoxorb(_) | Instruction::Amoandb(_)
+            | Instruction::Amoorb(_) | Instruction::Amominb(_) | Instruction::Amomaxb(_) | Instruction::Amominub(_)
+            | Instruction::Amomaxub(_) | Instruction::Amoswaph(_) | Instruction::Amoaddh(_)
+            | Instruction::Amoxorh(_) | Instruction::Amoandh(_) | Instruction::Amoorh(_) | Instruction::Amominh(_)
+            | Instruction::Amomaxh(_) | Instruction::Amominuh(_) | Instruction::Amomaxuh(_)
+            | Instruction::AmocasW(_) | Instruction::AmocasD(_) | Instruction::AmocasB(_) | Instruction::AmocasH(_)
+            | Instruction::AmocasQ(_) => Cpu::exec_atomic,
+        Instruction::Flw(_) | Instruction::Fsw(_) | Instruction::FmaddS(_) | Instruction::FmsubS(_)
+            | Instruction::FnmsubS(_) | Instruction::FnmaddS(_) | Instruction::FaddS(_) | Instruction::FsubS(_)
+            | Instruction::FmulS(_) | Instruction::FdivS(_) | Instruction::FsqrtS(_) | Instruction::FsgnjS(_)
+            | Instruction::FsgnjnS(_) | Instruction::FsgnjxS(_) | Instruction::FminS(_) | Instruction::FmaxS(_)
+            | Instruction::FcvtWS(_) | Instruction::FcvtWuS(_) | Instruction::FcvtLS(_) | Instruction::FcvtLuS(_)
+            | Instruction::FcvtSW(_) | Instruction::FcvtSWu(_) | Instruction::FcvtSL(_) | Instruction::FcvtSLu(_)
+            | Instruction::FmvXW(_) | Instruction::FmvWX(_) | Instruction::FeqS(_) | Instruction::FltS(_)
+            | Instruction::FleS(_) | Instruction::FclassS(_) => Cpu::exec_float_s,
+        Instruction::Fld(_) | Instruction::Fsd(_) | Instruction::FmaddD(_) | Instruction::FmsubD(_)
+            | Instruction::FnmsubD(_) | Instruction::FnmaddD(_) | Instruction::FaddD(_) | Instruction::FsubD(_)
+            | Instruction::FmulD(_) | Instruction::FdivD(_) | Instruction::FsqrtD(_) | Instruction::FsgnjD(_)
+            | Instruction::FsgnjnD(_) | Instruction::FsgnjxD(_) | Instruction::FminD(_) | Instruction::FmaxD(_)
+            | Instruction::FcvtSD(_) | Instruction::FcvtDS(_) | Instruction::FcvtWD(_) | Instruction::FcvtWuD(_)
+            | Instruction::FcvtLD(_) | Instruction::FcvtLuD(_) | Instruction::FcvtDW(_) | Instruction::FcvtDWu(_)
+            | Instruction::FcvtDL(_) | Instruction::FcvtDLu(_) | Instruction::FmvXD(_) | Instruction::FmvDX(_)
+            | Instruction::FeqD(_) | Instruction::FltD(_) | Instruction::FleD(_) | Instruction::FclassD(_)
+            => Cpu::exec_float_d,
+        Instruction::Flh(_) | Instruction::Fsh(_) | Instruction::FmaddH(_) | Instruction::FmsubH(_)
+            | Instruction::FnmsubH(_) | Instruction::FnmaddH(_) | Instruction::FaddH(_) | Instruction::FsubH(_)
+            | Instruction::FmulH(_) | Instruction::FdivH(_) | Instruction::FsqrtH(_) | Instruction::FsgnjH(_)
+            | Instruction::FsgnjnH(_) | Instruction::FsgnjxH(_) | Instruction::FminH(_) | Instruction::FmaxH(_)
+            | Instruction::FcvtSH(_) | Instruction::FcvtDH(_) | Instruction::FcvtHS(_) | Instruction::FcvtHD(_)
+            | Instruction::FcvtWH(_) | Instruction::FcvtWuH(_) | Instruction::FcvtLH(_) | Instruction::FcvtLuH(_)
+            | Instruction::FcvtHW(_) | Instruction::FcvtHWu(_) | Instruction::FcvtHL(_) | Instruction::FcvtHLu(_)
+            | Instruction::FmvXH(_) | Instruction::FmvHX(_) | Instruction::FeqH(_) | Instruction::FltH(_)
+            | Instruction::FleH(_) | Instruction::FclassH(_) => Cpu::exec_float_h,
+        Instruction::Vsetvli(_) | Instruction::Vsetivli(_) | Instruction::Vsetvl(_) | Instruction::Vload(_)
+            | Instruction::Vstore(_) | Instruction::Varith(_) => Cpu::exec_vector,
+        _ => Cpu::exec_invalid,
+    }
+}
+
+impl Cpu {
+    // Base integer arithmetic, logic, shifts and compares.
+    fn exec_alu(&mut self, insn: Instruction) {
+        match insn {
+            Instruction::Auipc(u) => {
+                self.set_reg(u.rd as usize, self.pc.wrapping_add(u.imm as u64));
+                self.pc += 4;
+            }
+            Instruction::Lui(u) => {
+                if u.rd != 0 {
+                    self.regs[u.rd as usize] = u.imm as i64 as u64;
+                }
+                self.pc += 4;
+            }
+            Instruction::Addi(i) => {
+                if i.rd != 0 {
+                    self.regs[i.rd as usize] = self.regs[i.rs1 as usize].wrapping_add(i.imm as u64);
+                }
+                self.pc += 4;
+            }
+            Instruction::Addiw(i) => {
+                if i.rd != 0 {
+                    let res = self.regs[i.rs1 as usize].wrapping_add_signed(i.imm as i64) as i64;
+                    self.regs[i.rd as usize] = ((res << 32) >> 32) as u64; //XXX: is there
+                                                                                    // a better
+                                                                                    // way?
+                }
+                self.pc += 4;
+            }
+            Instruction::Slliw(i) => {
+                let val = (self.regs[i.rs1 as usize] as u32) << i.shamt;
+                self.set_reg(i.rd as usize, val as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::Srliw(i) => {
+                let val = (self.regs[i.rs1 as usize] as u32) >> i.shamt;
+                self.set_reg(i.rd as usize, val as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::Sraiw(i) => {
+                let val = (self.regs[i.rs1 as usize] as i32) >> i.shamt;
+                self.set_reg(i.rd as usize, val as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::Andi(i) => {
+                if i.rd != 0 {
+                    self.regs[i.rd as usize] = self.regs[i.rs1 as usize] & i.imm as u64;
+                }
+                self.pc += 4;
+            }
+            Instruction::Ori(i) => {
+                if i.rd != 0 {
+                    self.regs[i.rd as usize] = self.regs[i.rs1 as usize] | i.imm as u64;
+                }
+                self.pc += 4;
+            }
+            Instruction::Xori(i) => {
+                self.set_reg(i.rd as usize, self.regs[i.rs1 as usize] ^ i.imm as u64);
+                self.pc += 4;
+            }
+            Instruction::Slli(i) => {
+                if i.rd != 0 {
+                    self.regs[i.rd as usize] = self.regs[i.rs1 as usize] << i.shamt;
+                }
+                self.pc += 4;
+            }
+            Instruction::Srli(i) => {
+                if i.rd != 0 {
+                    self.regs[i.rd as usize] = self.regs[i.rs1 as usize] >> i.shamt;
+                }
+                self.pc += 4;
+            }
+            Instruction::Srai(i) => {
+                if i.rd != 0 {
+                    let val = self.regs[i.rs1 as usize] as i64;
+                    self.regs[i.rd as usize] = (val >> i.shamt) as u64;
+                }
+                self.pc += 4;
+            }
+            Instruction::Slti(i) => {
+                let val = self.regs[i.rs1 as usize] as i64;
+                if i.rd != 0 {
+                    self.regs[i.rd as usize] = if val < i.imm as i64 { 1 } else { 0 };
+                }
+                self.pc += 4;
+            }
+            Instruction::Sltiu(i) => {
+                let val = self.regs[i.rs1 as usize];
+                if i.rd != 0 {
+                    self.regs[i.rd as usize] = if val < i.imm as u64 { 1 } else { 0 };
+                }
+                self.pc += 4;
+            }
+            Instruction::Slt(r) => {
+                let val1 = self.regs[r.rs1 as usize] as i64;
+                let val2 = self.regs[r.rs2 as usize] as i64;
+                if r.rd != 0 {
+                    self.regs[r.rd as usize] = if val1 < val2 { 1 } else { 0 };
+                }
+                self.pc += 4;
+            }
+            Instruction::Sltu(r) => {
+                let val1 = self.regs[r.rs1 as usize];
+                let val2 = self.regs[r.rs2 as usize];
+                if r.rd != 0 {
+                    self.regs[r.rd as usize] = if val1 < val2 { 1 } else { 0 };
+                }
+                self.pc += 4;
+            }
+            Instruction::Add(r) => {
+                let opa = self.regs[r.rs1 as usize];
+                let opb = self.regs[r.rs2 as usize];
+                self.set_reg(r.rd as usize, opa.wrapping_add(opb));
+                self.pc += 4;
+            }
+            Instruction::Sub(r) => {
+                let opa = self.regs[r.rs1 as usize];
+                let opb = self.regs[r.rs2 as usize];
+                self.set_reg(r.rd as usize, opa.wrapping_sub(opb));
+                self.pc += 4;
+            }
+            Instruction::And(r) => {
+                let opa = self.regs[r.rs1 as usize];
+                let opb = self.regs[r.rs2 as usize];
+                self.set_reg(r.rd as usize, opa & opb);
+                self.pc += 4;
+            }
+            Instruction::Or(r) => {
+                let opa = self.regs[r.rs1 as usize];
+                let opb = self.regs[r.rs2 as usize];
+                self.set_reg(r.rd as usize, opa | opb);
+                self.pc += 4;
+            }
+            Instruction::Xor(r) => {
+                let opa = self.regs[r.rs1 as usize];
+                let opb = self.regs[r.rs2 as usize];
+                self.set_reg(r.rd as usize, opa ^ opb);
+                self.pc += 4;
+            }
+            // Shifts only use the low six bits of rs2.
+            Instruction::Sll(r) => {
+                let val = self.regs[r.rs1 as usize] << (self.regs[r.rs2 as usize] & 0x3F);
+                self.set_reg(r.rd as usize, val);
+                self.pc += 4;
+            }
+            Instruction::Srl(r) => {
+                let val = self.regs[r.rs1 as usize] >> (self.regs[r.rs2 as usize] & 0x3F);
+                self.set_reg(r.rd as usize, val);
+                self.pc += 4;
+            }
+            Instruction::Sra(r) => {
+                let val = (self.regs[r.rs1 as usize] as i64) >> (self.regs[r.rs2 as usize] & 0x3F);
+                self.set_reg(r.rd as usize, val as u64);
+                self.pc += 4;
+            }
+            Instruction::Addw(r) => {
+                let opa = self.regs[r.rs1 as usize] as i32;
+                let opb = self.regs[r.rs2 as usize] as i32;
+                self.set_reg(r.rd as usize, opa.wrapping_add(opb) as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::Subw(r) => {
+                let opa = self.regs[r.rs1 as usize] as i32;
+                let opb = self.regs[r.rs2 as usize] as i32;
+                self.set_reg(r.rd as usize, opa.wrapping_sub(opb) as i64 as u64);
+                self.pc += 4;
+            }
+            // Word shifts only use the low five bits of rs2.
+            Instruction::Sllw(r) => {
+                let val = (self.regs[r.rs1 as usize] as u32) << (self.regs[r.rs2 as usize] & 0x1F);
+                self.set_reg(r.rd as usize, val as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::Srlw(r) => {
+                let val = (self.regs[r.rs1 as usize] as u32) >> (self.regs[r.rs2 as usize] & 0x1F);
+                self.set_reg(r.rd as usize, val as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::Sraw(r) => {
+                let val = (self.regs[r.rs1 as usize] as i32) >> (self.regs[r.rs2 as usize] & 0x1F);
+                self.set_reg(r.rd as usize, val as i64 as u64);
+                self.pc += 4;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Zicsr: CSR reads and writes.
+    fn exec_csr(&mut self, insn: Instruction) {
+        match insn {
+            // The immediate forms take a 5-bit zimm in the rs1 field. CSRRS/CSRRC
+            // with rs1 = x0 or zimm = 0 do not write, so they have no write side
+            // effects and work on read-only CSRs.
+            Instruction::Csrrw(i) | Instruction::Csrrs(i) | Instruction::Csrrc(i)
+                | Instruction::Csrrwi(i) | Instruction::Csrrsi(i) | Instruction::Csrrci(i) => {
+                let csrid = i.imm as u32 & 0xfff;
+                let val = match insn {
+                    Instruction::Csrrwi(_) | Instruction::Csrrsi(_) | Instruction::Csrrci(_) => i.rs1 as u64,
+                    _ => self.regs[i.rs1 as usize],
+                };
+                let res = match insn {
+                    Instruction::Csrrw(_) | Instruction::Csrrwi(_) => self.csr_insn(csrid, true, |_| val),
+                    Instruction::Csrrs(_) | Instruction::Csrrsi(_) => self.csr_insn(csrid, i.rs1 != 0, |old| old | val),
+                    _ => self.csr_insn(csrid, i.rs1 != 0, |old| old & !val),
+                };
+                if let Some(csr) = res {
+                    if i.rd != 0 {
+                        self.regs[i.rd as usize] = csr;
+                    }
+                    self.pc += 4;
+                } else {
+                    self.illegal_insn();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // M: multiplication and division.
+    fn exec_muldiv(&mut self, insn: Instruction) {
+        match insn {
+            Instruction::Mul(r) => {
+                let opa = self.regs[r.rs1 as usize];
+                let opb = self.regs[r.rs2 as usize];
+                self.set_reg(r.rd as usize, opa.wrapping_mul(opb));
+                self.pc += 4;
+            }
+            Instruction::Mulh(r) => {
+                let opa = self.regs[r.rs1 as usize] as i64 as i128;
+                let opb = self.regs[r.rs2 as usize] as i64 as i128;
+                self.set_reg(r.rd as usize, ((opa * opb) >> 64) as u64);
+                self.pc += 4;
+            }
+            Instruction::Mulhsu(r) => {
+                let opa = self.regs[r.rs1 as usize] as i64 as i128;
+                let opb = self.regs[r.rs2 as usize] as i128;
+                self.set_reg(r.rd as usize, ((opa * opb) >> 64) as u64);
+                self.pc += 4;
+            }
+            Instruction::Mulhu(r) => {
+                let opa = self.regs[r.rs1 as usize] as u128;
+                let opb = self.regs[r.rs2 as usize] as u128;
+                self.set_reg(r.rd as usize, ((opa * opb) >> 64) as u64);
+                self.pc += 4;
+            }
+            // Division never traps: x/0 is all ones, x%0 is x, and the
+            // signed overflow MIN/-1 gives MIN with a remainder of 0.
+            Instruction::Div(r) => {
+                let opa = self.regs[r.rs1 as usize] as i64;
+                let opb = self.regs[r.rs2 as usize] as i64;
+                let val = if opb == 0 { -1 } else { opa.wrapping_div(opb) };
+                self.set_reg(r.rd as usize, val as u64);
+                self.pc += 4;
+            }
+            Instruction::Divu(r) => {
+                let opa = self.regs[r.rs1 as usize];
+                let opb = self.regs[r.rs2 as usize];
+                self.set_reg(r.rd as usize, opa.checked_div(opb).unwrap_or(u64::MAX));
+                self.pc += 4;
+            }
+            Instruction::Rem(r) => {
+                let opa = self.regs[r.rs1 as usize] as i64;
+                let opb = self.regs[r.rs2 as usize] as i64;
+                let val = if opb == 0 { opa } else { opa.wrapping_rem(opb) };
+                self.set_reg(r.rd as usize, val as u64);
+                self.pc += 4;
+            }
+            Instruction::Remu(r) => {
+                let opa = self.regs[r.rs1 as usize];
+                let opb = self.regs[r.rs2 as usize];
+                self.set_reg(r.rd as usize, opa.checked_rem(opb).unwrap_or(opa));
+                self.pc += 4;
+            }
+            Instruction::Mulw(r) => {
+                let opa = self.regs[r.rs1 as usize] as i32;
+                let opb = self.regs[r.rs2 as usize] as i32;
+                self.set_reg(r.rd as usize, opa.wrapping_mul(opb) as i64 as u64);
+                self.pc += 4;
+            }
+            // Word variants follow the same rules on the low 32 bits and
+            // sign-extend the 32-bit result, unsigned ones included.
+            Instruction::Divw(r) => {
+                let opa = self.regs[r.rs1 as usize] as i32;
+                let opb = self.regs[r.rs2 as usize] as i32;
+                let val = if opb == 0 { -1 } else { opa.wrapping_div(opb) };
+                self.set_reg(r.rd as usize, val as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::Divuw(r) => {
+                let opa = self.regs[r.rs1 as usize] as u32;
+                let opb = self.regs[r.rs2 as usize] as u32;
+                let val = opa.checked_div(opb).unwrap_or(u32::MAX);
+                self.set_reg(r.rd as usize, val as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::Remw(r) => {
+                let opa = self.regs[r.rs1 as usize] as i32;
+                let opb = self.regs[r.rs2 as usize] as i32;
+                let val = if opb == 0 { opa } else { opa.wrapping_rem(opb) };
+                self.set_reg(r.rd as usize, val as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::Remuw(r) => {
+                let opa = self.regs[r.rs1 as usize] as u32;
+                let opb = self.regs[r.rs2 as usize] as u32;
+                let val = opa.checked_rem(opb).unwrap_or(opa);
+                self.set_reg(r.rd as usize, val as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Zba, Zbb, Zbc, Zbs and Zicond.
+    fn exec_bitmanip(&mut self, insn: Instruction) {
+        match insn {
+            Instruction::Sh1add(r) | Instruction::Sh2add(r) | Instruction::Sh3add(r) => {
+                let shift = match insn {
+                    Instruction::Sh1add(_) => 1,
+                    Instruction::Sh2add(_) => 2,
+                    _ => 3,
+                };
+                let val = (self.regs[r.rs1 as usize] << shift).wrapping_add(self.regs[r.rs2 as usize]);
+                self.set_reg(r.rd as usize, val);
+                self.pc += 4;
+            }
+            // The .uw forms zero-extend the low word of rs1 first.
+            Instruction::AddUw(r) | Instruction::Sh1addUw(r) | Instruction::Sh2addUw(r) | Instruction::Sh3addUw(r) => {
+                let shift = match insn {
+                    Instruction::AddUw(_) => 0,
+                    Instruction::Sh1addUw(_) => 1,
+                    Instruction::Sh2addUw(_) => 2,
+                    _ => 3,
+                };
+                let base = self.regs[r.rs1 as usize] as u32 as u64;
+                self.set_reg(r.rd as usize, (base << shift).wrapping_add(self.regs[r.rs2 as usize]));
+                self.pc += 4;
+            }
+            Instruction::SlliUw(i) => {
+                let val = (self.regs[i.rs1 as usize] as u32 as u64) << (i.imm & 0x3F);
+                self.set_reg(i.rd as usize, val);
+                self.pc += 4;
+            }
+            Instruction::Andn(r) | Instruction::Orn(r) | Instruction::Xnor(r) | Instruction::Max(r)
+                | Instruction::Maxu(r) | Instruction::Min(r) | Instruction::Minu(r) | Instruction::Rol(r)
+                | Instruction::Ror(r) | Instruction::Rolw(r) | Instruction::Rorw(r) => {
+                let opa = self.regs[r.rs1 as usize];
+                let opb = self.regs[r.rs2 as usize];
+                let val = match insn {
+                    Instruction::Andn(_) => opa & !opb,
+                    Instruction::Orn(_) => opa | !opb,
+                    Instruction::Xnor(_) => !(opa ^ opb),
+                    Instruction::Max(_) => (opa as i64).max(opb as i64) as u64,
+                    Instruction::Maxu(_) => opa.max(opb),
+                    Instruction::Min(_) => (opa as i64).min(opb as i64) as u64,
+                    Instruction::Minu(_) => opa.min(opb),
+                    Instruction::Rol(_) => opa.rotate_left(opb as u32 & 0x3F),
+                    Instruction::Ror(_) => opa.rotate_right(opb as u32 & 0x3F),
+                    Instruction::Rolw(_) => (opa as u32).rotate_left(opb as u32 & 0x1F) as i32 as i64 as u64,
+                    _ => (opa as u32).rotate_right(opb as u32 & 0x1F) as i32 as i64 as u64,
+                };
+                self.set_reg(r.rd as usize, val);
+                self.pc += 4;
+            }
+            Instruction::ZextH(r) => {
+                self.set_reg(r.rd as usize, self.regs[r.rs1 as usize] as u16 as u64);
+                self.pc += 4;
+            }
+            Instruction::Clz(i) | Instruction::Clzw(i) | Instruction::Ctz(i) | Instruction::Ctzw(i)
+                | Instruction::Cpop(i) | Instruction::Cpopw(i) | Instruction::SextB(i) | Instruction::SextH(i)
+                | Instruction::Rori(i) | Instruction::Roriw(i) | Instruction::OrcB(i) | Instruction::Rev8(i) => {
+                let opa = self.regs[i.rs1 as usize];
+                let val = match insn {
+                    Instruction::Clz(_) => opa.leading_zeros() as u64,
+                    Instruction::Clzw(_) => (opa as u32).leading_zeros() as u64,
+                    Instruction::Ctz(_) => opa.trailing_zeros() as u64,
+                    Instruction::Ctzw(_) => (opa as u32).trailing_zeros() as u64,
+                    Instruction::Cpop(_) => opa.count_ones() as u64,
+                    Instruction::Cpopw(_) => (opa as u32).count_ones() as u64,
+                    Instruction::SextB(_) => opa as i8 as i64 as u64,
+                    Instruction::SextH(_) => opa as i16 as i64 as u64,
+                    Instruction::Rori(_) => opa.rotate_right(i.imm as u32 & 0x3F),
+                    Instruction::Roriw(_) => (opa as u32).rotate_right(i.imm as u32 & 0x1F) as i32 as i64 as u64,
+                    // Every non-zero byte becomes 0xff.
+                    Instruction::OrcB(_) => {
+                        let bytes = opa.to_le_bytes().map(|b| if b != 0 { 0xFF } else { 0 });
+                        u64::from_le_bytes(bytes)
+                    }
+                    _ => opa.swap_bytes(),
+                };
+                self.set_reg(i.rd as usize, val);
+                self.pc += 4;
+            }
+            // clmul is the low half of the 128-bit carry-less product, clmulh
+            // the high half and clmulr bits 126..63.
+            Instruction::Clmul(r) | Instruction::Clmulh(r) | Instruction::Clmulr(r) => {
+                let opa = self.regs[r.rs1 as usize] as u128;
+                let opb = self.regs[r.rs2 as usize];
+                let product = (0..64).filter(|i| opb >> i & 1 != 0).fold(0u128, |acc, i| acc ^ opa << i);
+                let val = match insn {
+                    Instruction::Clmul(_) => product as u64,
+                    Instruction::Clmulh(_) => (product >> 64) as u64,
+                    _ => (product >> 63) as u64,
+                };
+                self.set_reg(r.rd as usize, val);
+                self.pc += 4;
+            }
+            // The bit index is the low six bits of rs2 or the immediate.
+            Instruction::Bclr(_) | Instruction::Bext(_) | Instruction::Binv(_) | Instruction::Bset(_)
+                | Instruction::Bclri(_) | Instruction::Bexti(_) | Instruction::Binvi(_) | Instruction::Bseti(_) => {
+                let (rd, opa, index) = match insn {
+                    Instruction::Bclr(r) | Instruction::Bext(r) | Instruction::Binv(r) | Instruction::Bset(r) =>
+                        (r.rd, self.regs[r.rs1 as usize], self.regs[r.rs2 as usize]),
+                    Instruction::Bclri(i) | Instruction::Bexti(i) | Instruction::Binvi(i) | Instruction::Bseti(i) =>
+                        (i.rd, self.regs[i.rs1 as usize], i.imm as u64),
+                    _ => unreachable!(),
+                };
+                let bit = 1u64 << (index & 0x3F);
+                let val = match insn {
+                    Instruction::Bclr(_) | Instruction::Bclri(_) => opa & !bit,
+                    Instruction::Bext(_) | Instruction::Bexti(_) => (opa & bit != 0) as u64,
+                    Instruction::Binv(_) | Instruction::Binvi(_) => opa ^ bit,
+                    _ => opa | bit,
+                };
+                self.set_reg(rd as usize, val);
+                self.pc += 4;
+            }
+            Instruction::CzeroEqz(r) | Instruction::CzeroNez(r) => {
+                let cond = self.regs[r.rs2 as usize] != 0;
+                let keep = matches!(insn, Instruction::CzeroEqz(_)) == cond;
+                let val = if keep { self.regs[r.rs1 as usize] } else { 0 };
+                self.set_reg(r.rd as usize, val);
+                self.pc += 4;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Zknd, Zkne and Zknh: AES and SHA-2 steps.
+    fn exec_crypto(&mut self, insn: Instruction) {
+        match insn {
+            Instruction::Aes64es(r) | Instruction::Aes64esm(r) | Instruction::Aes64ds(r)
+                | Instruction::Aes64dsm(r) | Instruction::Aes64ks2(r) => {
+                let (opa, opb) = (self.regs[r.rs1 as usize], self.regs[r.rs2 as usize]);
+                let val = match insn {
+                    Instruction::Aes64es(_) => crypto::aes64es(opa, opb),
+                    Instruction::Aes64esm(_) => crypto::aes64esm(opa, opb),
+                    Instruction::Aes64ds(_) => crypto::aes64ds(opa, opb),
+                    Instruction::Aes64dsm(_) => crypto::aes64dsm(opa, opb),
+                    _ => crypto::aes64ks2(opa, opb),
+                };
+                self.set_reg(r.rd as usize, val);
+                self.pc += 4;
+            }
+            Instruction::Aes64im(i) | Instruction::Aes64ks1i(i) | Instruction::Sha256sig0(i)
+                | Instruction::Sha256sig1(i) | Instruction::Sha256sum0(i) | Instruction::Sha256sum1(i)
+                | Instruction::Sha512sig0(i) | Instruction::Sha512sig1(i) | Instruction::Sha512sum0(i)
+                | Instruction::Sha512sum1(i) => {
+                let opa = self.regs[i.rs1 as usize];
+                let val = match insn {
+                    Instruction::Aes64im(_) => crypto::aes64im(opa),
+                    Instruction::Aes64ks1i(_) => crypto::aes64ks1i(opa, i.imm as u32 & 0xF),
+                    Instruction::Sha256sig0(_) => crypto::sha256sig0(opa),
+                    Instruction::Sha256sig1(_) => crypto::sha256sig1(opa),
+                    Instruction::Sha256sum0(_) => crypto::sha256sum0(opa),
+                    Instruction::Sha256sum1(_) => crypto::sha256sum1(opa),
+                    Instruction::Sha512sig0(_) => crypto::sha512sig0(opa),
+                    Instruction::Sha512sig1(_) => crypto::sha512sig1(opa),
+                    Instruction::Sha512sum0(_) => crypto::sha512sum0(opa),
+                    _ => crypto::sha512sum1(opa),
+                };
+                self.set_reg(i.rd as usize, val);
+                self.pc += 4;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Jumps and branches.
+    fn exec_jump(&mut self, insn: Instruction) {
+        match insn {
+            Instruction::Jal(j) => {
+                let target = self.pc.wrapping_add_signed(j.imm as i64);
+                if self.misaligned_target(target) {
+                    return;
+                }
+                self.set_reg(j.rd as usize, self.pc + 4);
+                self.pc = target;
+            }
+            Instruction::Jalr(i) => {
+                let target = self.regs[i.rs1 as usize].wrapping_add_signed(i.imm as i64) & !1;
+                if self.misaligned_target(target) {
+                    return;
+                }
+                self.set_reg(i.rd as usize, self.pc + 4);
+                self.pc = target;
+            }
+            Instruction::Beq(b) => self.branch(self.regs[b.rs1 as usize] == self.regs[b.rs2 as usize], b.imm),
+            Instruction::Bne(b) => self.branch(self.regs[b.rs1 as usize] != self.regs[b.rs2 as usize], b.imm),
+            Instruction::Blt(b) => {
+                self.branch((self.regs[b.rs1 as usize] as i64) < self.regs[b.rs2 as usize] as i64, b.imm)
+            }
+            Instruction::Bge(b) => {
+                self.branch((self.regs[b.rs1 as usize] as i64) >= self.regs[b.rs2 as usize] as i64, b.imm)
+            }
+            Instruction::Bltu(b) => self.branch(self.regs[b.rs1 as usize] < self.regs[b.rs2 as usize], b.imm),
+            Instruction::Bgeu(b) => self.branch(self.regs[b.rs1 as usize] >= self.regs[b.rs2 as usize], b.imm),
+            _ => unreachable!(),
+        }
+    }
+
+    fn branch(&mut self, taken: bool, imm: i32) {
+        if !taken {
+            self.pc += 4;
+            return;
+        }
+        let target = self.pc.wrapping_add_signed(imm as i64);
+        if !self.misaligned_target(target) {
+            self.pc = target;
+        }
+    }
+
+    // Integer loads and stores.
+    fn exec_memory(&mut self, insn: Instruction) {
+        match insn {
+            Instruction::Store(s) => {
+                // size
+                let addr = self.regs[s.rs1 as usize].wrapping_add_signed(s.imm as i64);
+                let val = self.regs[s.rs2 as usize];
+                let retired = match s.funct3 {
+                    0 => self.store_u8(addr, val as u8),
+                    1 => self.store_u16(addr, val as u16),
+                    2 => self.store_u32(addr, val as u32),
+                    3 => self.store_u64(addr, val),
+                    _ => return self.illegal_insn(),
+                };
+
+                match retired {
+                    Ok(()) => self.pc += 4,
+                    Err(fault) => self.mem_fault(Access::Store, fault, addr),
+                }
+            }
+            Instruction::Load(i) => {
+                let addr = self.regs[i.rs1 as usize].wrapping_add_signed(i.imm as i64);
+                let val = match i.funct3 {
+                    0 => self.load_u8(addr).map(|x| x as i8 as i64),
+                    1 => self.load_u16(addr).map(|x| x as i16 as i64),
+                    2 => self.load_u32(addr).map(|x| x as i32 as i64),
+                    3 => self.load_u64(addr).map(|x| x as i64),
+                    4 => self.load_u8(addr).map(|x| x as i64),
+                    5 => self.load_u16(addr).map(|x| x as i64),
+                    6 => self.load_u32(addr).map(|x| x as i64),
+                    _ => return self.illegal_insn(),
+                };
+
+                match val {
+                    Ok(val) => {
+                        self.set_reg(i.rd as usize, val as u64);
+                        self.pc += 4;
+                    }
+                    Err(fault) => self.mem_fault(Access::Load, fault, addr),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Privileged instructions, fences, hints and cache-block operations.
+    fn exec_system(&mut self, insn: Instruction) {
+        match insn {
+            Instruction::Mret(_) => {
+                if self.privl < 3 {
+                    return self.illegal_insn();
+                }
+                (self.mstatus, self.privl) = mstatus::mret(self.mstatus);
+                self.pages.flush();
+                self.check_irqs = true;
+                self.pc = self.mepc;
+            }
+            Instruction::Sret(_) => {
+                // Below S-mode SRET is illegal, in S-mode too with TSR set.
+                if self.privl == 0 || (self.privl == 1 && self.mstatus & MSTATUS_TSR != 0) {
+                    return self.illegal_insn();
+                }
+                (self.mstatus, self.privl) = mstatus::sret(self.mstatus);
+                self.pages.flush();
+                self.check_irqs = true;
+                self.pc = self.sepc;
+            }
+            Instruction::Fence => {
+                self.pc += 4;
+            }
+            Instruction::Pause => {
+                if self.pause_yield {
+                    std::thread::yield_now();
+                }
+                self.pc += 4;
+            }
+            Instruction::Hint => {
+                self.pc += 4;
+            }
+            Instruction::FenceI => {
+                self.invalidate_fetch_caches();
+                self.pc += 4;
+            }
+            Instruction::SfenceVma(_) => {
+                // Illegal in U-mode, in S-mode too with TVM set.
+                if self.privl == 0 || (self.privl == 1 && self.mstatus & MSTATUS_TVM != 0) {
+                    return self.illegal_insn();
+                }
+                self.pages.flush();
+                self.pc += 4;
+            }
+            // There are no caches, so only the menvcfg checks remain of clean,
+            // flush and inval. Below M-mode they are illegal unless enabled,
+            // senvcfg is not implemented so U-mode follows menvcfg alone.
+            Instruction::CboClean(_) | Instruction::CboFlush(_) | Instruction::CboInval(_) => {
+                let enable = match insn {
+                    Instruction::CboInval(_) => MENVCFG_CBIE,
+                    _ => MENVCFG_CBCFE,
+                };
+                if self.privl < 3 && self.menvcfg & enable == 0 {
+                    self.illegal_insn();
+                } else {
+                    self.pc += 4;
+                }
+            }
+            Instruction::CboZero(i) => {
+                let addr = self.regs[i.rs1 as usize];
+                let block = addr & !(CACHE_BLOCK_SIZE - 1);
+                if self.privl < 3 && self.menvcfg & MENVCFG_CBZE == 0 {
+                    self.illegal_insn();
+                } else if let Err(fault) = self.ram_offset(Access::Store, block) {
+                    // checked up front so a fault leaves the block untouched
+                    self.mem_fault(Access::Store, fault, addr);
+                } else {
+                    for off in (0..CACHE_BLOCK_SIZE).step_by(8) {
+                        let _ = self.store_u64(block + off, 0);
+                    }
+                    self.pc += 4;
+                }
+            }
+            Instruction::Wfi(_) => {
+                // Below M-mode WFI traps with TW set, in U-mode always as
+                // S-mode is there to wait instead.
+                if (self.privl == 1 && self.mstatus & MSTATUS_TW != 0) || self.privl == 0 {
+                    self.illegal_insn();
+                } else {
+                    self.pc += 4;
+                    self.waiting = !self.interrupt_pending();
+                }
+            }
+            Instruction::Ecall(_) => {
+                let exception = match self.privl {
+                    0 => Exception::EcallU,
+                    1 => Exception::EcallS,
+                    _ => Exception::EcallM,
+                };
+                self.raise(exception, 0);
+            }
+            Instruction::Ebreak(_) => {
+                if self.semihosting && self.is_semihost_sequence(self.pc) {
+                    self.semihost_call = Some((self.regs[10], self.regs[11]));
+                } else {
+                    self.raise(Exception::Breakpoint, self.pc);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // A, Zacas and Zabha.
+    fn exec_atomic(&mut self, insn: Instruction) {
+        match insn {
+            // A single hart executes in program order, so every atomic
+            // already satisfies its aq/rl constraints.
+            // LR/SC are never emulated when misaligned, the reservation
+            // could not cover them.
+            Instruction::LrW(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if !addr.is_multiple_of(4) {
+                    self.raise(Exception::LoadMisaligned, addr);
+                    return;
+                }
+                let val = match self.load_u32(addr) {
+                    Ok(val) => val,
+                    Err(fault) => return self.mem_fault(Access::Load, fault, addr),
+                };
+                self.reserve(addr);
+                self.set_reg(r.rd as usize, val as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::LrD(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if !addr.is_multiple_of(8) {
+                    self.raise(Exception::LoadMisaligned, addr);
+                    return;
+                }
+                let val = match self.load_u64(addr) {
+                    Ok(val) => val,
+                    Err(fault) => return self.mem_fault(Access::Load, fault, addr),
+                };
+                self.reserve(addr);
+                self.set_reg(r.rd as usize, val);
+                self.pc += 4;
+            }
+            Instruction::ScW(r) | Instruction::ScD(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                let val = self.regs[r.rs2 as usize];
+                let size = if matches!(insn, Instruction::ScW(_)) { 4 } else { 8 };
+                if !addr.is_multiple_of(size) {
+                    self.raise(Exception::StoreMisaligned, addr);
+                    return;
+                }
+                let paddr = match self.translate(Access::Store, addr) {
+                    Ok(paddr) => paddr,
+                    Err(fault) => return self.mem_fault(Access::Store, fault, addr),
+                };
+                let reserved = self.reservation.take() == Some(paddr & !(RESERVATION_SIZE - 1));
+                if reserved {
+                    let stored = match insn {
+                        Instruction::ScW(_) => self.store_u32(addr, val as u32),
+                        _ => self.store_u64(addr, val),
+                    };
+                    if let Err(fault) = stored {
+                        return self.mem_fault(Access::Store, fault, addr);
+                    }
+                }
+                self.set_reg(r.rd as usize, !reserved as u64);
+                self.pc += 4;
+            }
+            Instruction::Amoswapw(r) | Instruction::Amoaddw(r) | Instruction::Amoxorw(r) | Instruction::Amoandw(r) | Instruction::Amoorw(r) | Instruction::Amominw(r) | Instruction::Amomaxw(r) | Instruction::Amominuw(r) | Instruction::Amomaxuw(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if self.amo_fault(addr, 4) {
+                    return;
+                }
+                let src = self.regs[r.rs2 as usize] as i32 as i64 as u64;
+                let old = match self.load_u32(addr) {
+                    Ok(old) => old,
+                    Err(fault) => return self.mem_fault(Access::Store, fault, addr),
+                };
+                let old = old as i32 as i64 as u64;
+                if let Err(fault) = self.store_u32(addr, amo(insn, old, src) as u32) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.set_reg(r.rd as usize, old);
+                self.pc += 4;
+            }
+            Instruction::Amoswapd(r) | Instruction::Amoaddd(r) | Instruction::Amoxord(r) | Instruction::Amoandd(r) | Instruction::Amoord(r) | Instruction::Amomind(r) | Instruction::Amomaxd(r) | Instruction::Amominud(r) | Instruction::Amomaxud(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if self.amo_fault(addr, 8) {
+                    return;
+                }
+                let src = self.regs[r.rs2 as usize];
+                let old = match self.load_u64(addr) {
+                    Ok(old) => old,
+                    Err(fault) => return self.mem_fault(Access::Store, fault, addr),
+                };
+                if let Err(fault) = self.store_u64(addr, amo(insn, old, src)) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.set_reg(r.rd as usize, old);
+                self.pc += 4;
+            }
+            Instruction::Amoswapb(r) | Instruction::Amoaddb(r) | Instruction::Amoxorb(r) | Instruction::Amoandb(r) | Instruction::Amoorb(r) | Instruction::Amominb(r) | Instruction::Amomaxb(r) | Instruction::Amominub(r) | Instruction::Amomaxub(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if self.amo_fault(addr, 1) {
+                    return;
+                }
+                let src = self.regs[r.rs2 as usize] as i8 as i64 as u64;
+                let old = match self.load_u8(addr) {
+                    Ok(old) => old,
+                    Err(fault) => return self.mem_fault(Access::Store, fault, addr),
+                };
+                let old = old as i8 as i64 as u64;
+                if let Err(fault) = self.store_u8(addr, amo(insn, old, src) as u8) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.set_reg(r.rd as usize, old);
+                self.pc += 4;
+            }
+            Instruction::Amoswaph(r) | Instruction::Amoaddh(r) | Instruction::Amoxorh(r) | Instruction::Amoandh(r) | Instruction::Amoorh(r) | Instruction::Amominh(r) | Instruction::Amomaxh(r) | Instruction::Amominuh(r) | Instruction::Amomaxuh(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if self.amo_fault(addr, 2) {
+                    return;
+                }
+                let src = self.regs[r.rs2 as usize] as i16 as i64 as u64;
+                let old = match self.load_u16(addr) {
+                    Ok(old) => old,
+                    Err(fault) => return self.mem_fault(Access::Store, fault, addr),
+                };
+                let old = old as i16 as i64 as u64;
+                if let Err(fault) = self.store_u16(addr, amo(insn, old, src) as u16) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.set_reg(r.rd as usize, old);
+                self.pc += 4;
+            }
+            // rd holds the expected value and receives the old one, the store
+            // only happens on a match.
+            Instruction::AmocasW(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if self.amo_fault(addr, 4) {
+                    return;
+                }
+                let old = match self.load_u32(addr) {
+                    Ok(old) => old,
+                    Err(fault) => return self.mem_fault(Access::Store, fault, addr),
+                };
+                if old == self.regs[r.rd as usize] as u32 && let Err(fault) = self.store_u32(addr, self.regs[r.rs2 as usize] as u32) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.set_reg(r.rd as usize, old as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::AmocasD(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if self.amo_fault(addr, 8) {
+                    return;
+                }
+                let old = match self.load_u64(addr) {
+                    Ok(old) => old,
+                    Err(fault) => return self.mem_fault(Access::Store, fault, addr),
+                };
+                if old == self.regs[r.rd as usize] && let Err(fault) = self.store_u64(addr, self.regs[r.rs2 as usize]) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.set_reg(r.rd as usize, old);
+                self.pc += 4;
+            }
+            Instruction::AmocasB(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if self.amo_fault(addr, 1) {
+                    return;
+                }
+                let old = match self.load_u8(addr) {
+                    Ok(old) => old,
+                    Err(fault) => return self.mem_fault(Access::Store, fault, addr),
+                };
+                if old == self.regs[r.rd as usize] as u8 && let Err(fault) = self.store_u8(addr, self.regs[r.rs2 as usize] as u8) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.set_reg(r.rd as usize, old as i8 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::AmocasH(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if self.amo_fault(addr, 2) {
+                    return;
+                }
+                let old = match self.load_u16(addr) {
+                    Ok(old) => old,
+                    Err(fault) => return self.mem_fault(Access::Store, fault, addr),
+                };
+                if old == self.regs[r.rd as usize] as u16 && let Err(fault) = self.store_u16(addr, self.regs[r.rs2 as usize] as u16) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.set_reg(r.rd as usize, old as i16 as i64 as u64);
+                self.pc += 4;
+            }
+            // Operands are register pairs, low half in the even register. A
+            // pair starting at x0 reads as zero and is not written.
+            Instruction::AmocasQ(r) => {
+                let addr = self.regs[r.rs1 as usize];
+                if self.amo_fault(addr, 16) {
+                    return;
+                }
+                let pair = |reg: u8| match reg {
+                    0 => (0, 0),
+                    _ => (self.regs[reg as usize], self.regs[reg as usize + 1]),
+                };
+                let (expected, (lo, hi)) = (pair(r.rd), pair(r.rs2));
+                let old = self.load_u64(addr).and_then(|lo| Ok((lo, self.load_u64(addr + 8)?)));
+                let (old_lo, old_hi) = match old {
+                    Ok(old) => old,
+                    Err(fault) => return self.mem_fault(Access::Store, fault, addr),
+                };
+                if (old_lo, old_hi) == expected
+                    && let Err(fault) = self.store_u64(addr, lo).and_then(|()| self.store_u64(addr + 8, hi))
+                {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                if r.rd != 0 {
+                    self.set_reg(r.rd as usize, old_lo);
+                    self.set_reg(r.rd as usize + 1, old_hi);
+                }
+                self.pc += 4;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // F: single precision.
+    fn exec_float_s(&mut self, insn: Instruction) {
+        // All of F is illegal while mstatus.FS is off.
+        if self.mstatus & MSTATUS_FS == 0 {
+            return self.illegal_insn();
+        }
+        match insn {
+            Instruction::Flw(i) => {
+                let addr = self.regs[i.rs1 as usize].wrapping_add_signed(i.imm as i64);
+                let val = match self.load_u32(addr) {
+                    Ok(val) => val,
+                    Err(fault) => return self.mem_fault(Access::Load, fault, addr),
+                };
+                self.set_freg_s(i.rd, f32::from_bits(val));
+                self.pc += 4;
+            }
+            Instruction::Fsw(s) => {
+                let addr = self.regs[s.rs1 as usize].wrapping_add_signed(s.imm as i64);
+                let val = self.fregs[s.rs2 as usize] as u32;
+                if let Err(fault) = self.store_u32(addr, val) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.pc += 4;
+            }
+            Instruction::FmaddS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (a, b, c) = (self.freg_s(r.rs1), self.freg_s(r.rs2), self.freg_s(r.rs3));
+                let (res, flags) = fpu::fma(a, b, c, rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FmsubS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (a, b, c) = (self.freg_s(r.rs1), self.freg_s(r.rs2), self.freg_s(r.rs3));
+                let (res, flags) = fpu::fma(a, b, -c, rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FnmsubS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (a, b, c) = (self.freg_s(r.rs1), self.freg_s(r.rs2), self.freg_s(r.rs3));
+                let (res, flags) = fpu::fma(-a, b, c, rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FnmaddS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (a, b, c) = (self.freg_s(r.rs1), self.freg_s(r.rs2), self.freg_s(r.rs3));
+                let (res, flags) = fpu::fma(-a, b, -c, rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FaddS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::add(self.freg_s(r.rs1), self.freg_s(r.rs2), rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FsubS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::sub(self.freg_s(r.rs1), self.freg_s(r.rs2), rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FmulS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::mul(self.freg_s(r.rs1), self.freg_s(r.rs2), rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FdivS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::div(self.freg_s(r.rs1), self.freg_s(r.rs2), rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FsqrtS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::sqrt(self.freg_s(r.rs1), rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FsgnjS(r) | Instruction::FsgnjnS(r) | Instruction::FsgnjxS(r) => {
+                let a = self.freg_s(r.rs1).to_bits();
+                let b = self.freg_s(r.rs2).to_bits();
+                let sign: u32 = 0x80000000;
+                let res = match insn {
+                    Instruction::FsgnjS(_) => (a & !sign) | (b & sign),
+                    Instruction::FsgnjnS(_) => (a & !sign) | (!b & sign),
+                    _ => a ^ (b & sign),
+                };
+                self.set_freg_s(r.rd, f32::from_bits(res));
+                self.pc += 4;
+            }
+            Instruction::FminS(r) => {
+                let (res, flags) = fpu::min(self.freg_s(r.rs1), self.freg_s(r.rs2));
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FmaxS(r) => {
+                let (res, flags) = fpu::max(self.freg_s(r.rs1), self.freg_s(r.rs2));
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtWS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::to_int(self.freg_s(r.rs1) as f64, rm, i32::MIN as i128, i32::MAX as i128);
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::FcvtWuS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::to_int(self.freg_s(r.rs1) as f64, rm, 0, u32::MAX as i128);
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u32 as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::FcvtLS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::to_int(self.freg_s(r.rs1) as f64, rm, i64::MIN as i128, i64::MAX as i128);
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FcvtLuS(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::to_int(self.freg_s(r.rs1) as f64, rm, 0, u64::MAX as i128);
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FcvtSW(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::from_int(self.regs[r.rs1 as usize] as i32 as i128, rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtSWu(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::from_int(self.regs[r.rs1 as usize] as u32 as i128, rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtSL(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::from_int(self.regs[r.rs1 as usize] as i64 as i128, rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtSLu(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::from_int(self.regs[r.rs1 as usize] as i128, rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FmvXW(r) => {
+                let val = self.fregs[r.rs1 as usize] as u32;
+                self.set_reg(r.rd as usize, val as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::FmvWX(r) => {
+                let val = self.regs[r.rs1 as usize] as u32;
+                self.set_freg_s(r.rd, f32::from_bits(val));
+                self.pc += 4;
+            }
+            Instruction::FeqS(r) => {
+                let (res, flags) = fpu::eq(self.freg_s(r.rs1), self.freg_s(r.rs2));
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FltS(r) => {
+                let (res, flags) = fpu::lt(self.freg_s(r.rs1), self.freg_s(r.rs2));
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FleS(r) => {
+                let (res, flags) = fpu::le(self.freg_s(r.rs1), self.freg_s(r.rs2));
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FclassS(r) => {
+                let res = fpu::class(self.freg_s(r.rs1));
+                self.set_reg(r.rd as usize, res);
+                self.pc += 4;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // D: double precision.
+    fn exec_float_d(&mut self, insn: Instruction) {
+        // All of D is illegal while mstatus.FS is off.
+        if self.mstatus & MSTATUS_FS == 0 {
+            return self.illegal_insn();
+        }
+        match insn {
+            Instruction::Fld(i) => {
+                let addr = self.regs[i.rs1 as usize].wrapping_add_signed(i.imm as i64);
+                let val = match self.load_u64(addr) {
+                    Ok(val) => val,
+                    Err(fault) => return self.mem_fault(Access::Load, fault, addr),
+                };
+                self.set_freg_d(i.rd, f64::from_bits(val));
+                self.pc += 4;
+            }
+            Instruction::Fsd(s) => {
+                let addr = self.regs[s.rs1 as usize].wrapping_add_signed(s.imm as i64);
+                let val = self.fregs[s.rs2 as usize];
+                if let Err(fault) = self.store_u64(addr, val) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.pc += 4;
+            }
+            Instruction::FmaddD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (a, b, c) = (self.freg_d(r.rs1), self.freg_d(r.rs2), self.freg_d(r.rs3));
+                let (res, flags) = fpu::fma(a, b, c, rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FmsubD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (a, b, c) = (self.freg_d(r.rs1), self.freg_d(r.rs2), self.freg_d(r.rs3));
+                let (res, flags) = fpu::fma(a, b, -c, rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FnmsubD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (a, b, c) = (self.freg_d(r.rs1), self.freg_d(r.rs2), self.freg_d(r.rs3));
+                let (res, flags) = fpu::fma(-a, b, c, rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FnmaddD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (a, b, c) = (self.freg_d(r.rs1), self.freg_d(r.rs2), self.freg_d(r.rs3));
+                let (res, flags) = fpu::fma(-a, b, -c, rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FaddD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::add(self.freg_d(r.rs1), self.freg_d(r.rs2), rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FsubD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::sub(self.freg_d(r.rs1), self.freg_d(r.rs2), rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FmulD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::mul(self.freg_d(r.rs1), self.freg_d(r.rs2), rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FdivD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::div(self.freg_d(r.rs1), self.freg_d(r.rs2), rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FsqrtD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::sqrt(self.freg_d(r.rs1), rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FsgnjD(r) | Instruction::FsgnjnD(r) | Instruction::FsgnjxD(r) => {
+                let a = self.freg_d(r.rs1).to_bits();
+                let b = self.freg_d(r.rs2).to_bits();
+                let sign: u64 = 1 << 63;
+                let res = match insn {
+                    Instruction::FsgnjD(_) => (a & !sign) | (b & sign),
+                    Instruction::FsgnjnD(_) => (a & !sign) | (!b & sign),
+                    _ => a ^ (b & sign),
+                };
+                self.set_freg_d(r.rd, f64::from_bits(res));
+                self.pc += 4;
+            }
+            Instruction::FminD(r) => {
+                let (res, flags) = fpu::min(self.freg_d(r.rs1), self.freg_d(r.rs2));
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FmaxD(r) => {
+                let (res, flags) = fpu::max(self.freg_d(r.rs1), self.freg_d(r.rs2));
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtSD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::narrow(self.freg_d(r.rs1), rm);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtDS(r) => {
+                let (res, flags) = fpu::widen(self.freg_s(r.rs1));
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtWD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::to_int(self.freg_d(r.rs1), rm, i32::MIN as i128, i32::MAX as i128);
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::FcvtWuD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::to_int(self.freg_d(r.rs1), rm, 0, u32::MAX as i128);
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u32 as i32 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::FcvtLD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::to_int(self.freg_d(r.rs1), rm, i64::MIN as i128, i64::MAX as i128);
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FcvtLuD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::to_int(self.freg_d(r.rs1), rm, 0, u64::MAX as i128);
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FcvtDW(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::from_int(self.regs[r.rs1 as usize] as i32 as i128, rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtDWu(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::from_int(self.regs[r.rs1 as usize] as u32 as i128, rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtDL(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::from_int(self.regs[r.rs1 as usize] as i64 as i128, rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtDLu(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::from_int(self.regs[r.rs1 as usize] as i128, rm);
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FmvXD(r) => {
+                let val = self.fregs[r.rs1 as usize];
+                self.set_reg(r.rd as usize, val);
+                self.pc += 4;
+            }
+            Instruction::FmvDX(r) => {
+                let val = self.regs[r.rs1 as usize];
+                self.set_freg_d(r.rd, f64::from_bits(val));
+                self.pc += 4;
+            }
+            Instruction::FeqD(r) => {
+                let (res, flags) = fpu::eq(self.freg_d(r.rs1), self.freg_d(r.rs2));
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FltD(r) => {
+                let (res, flags) = fpu::lt(self.freg_d(r.rs1), self.freg_d(r.rs2));
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FleD(r) => {
+                let (res, flags) = fpu::le(self.freg_d(r.rs1), self.freg_d(r.rs2));
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FclassD(r) => {
+                let res = fpu::class(self.freg_d(r.rs1));
+                self.set_reg(r.rd as usize, res);
+                self.pc += 4;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Zfh: half precision.
+    fn exec_float_h(&mut self, insn: Instruction) {
+        // All of Zfh is illegal while mstatus.FS is off.
+        if self.mstatus & MSTATUS_FS == 0 {
+            return self.illegal_insn();
+        }
+        match insn {
+            Instruction::Flh(i) => {
+                let addr = self.regs[i.rs1 as usize].wrapping_add_signed(i.imm as i64);
+                let val = match self.load_u16(addr) {
+                    Ok(val) => val,
+                    Err(fault) => return self.mem_fault(Access::Load, fault, addr),
+                };
+                self.set_freg_h(i.rd, val);
+                self.pc += 4;
+            }
+            Instruction::Fsh(s) => {
+                let addr = self.regs[s.rs1 as usize].wrapping_add_signed(s.imm as i64);
+                let val = self.fregs[s.rs2 as usize] as u16;
+                if let Err(fault) = self.store_u16(addr, val) {
+                    return self.mem_fault(Access::Store, fault, addr);
+                }
+                self.pc += 4;
+            }
+            Instruction::FmaddH(r) | Instruction::FmsubH(r) | Instruction::FnmsubH(r) | Instruction::FnmaddH(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (a, b, c) = (self.freg_h(r.rs1), self.freg_h(r.rs2), self.freg_h(r.rs3));
+                let (res, flags) = match insn {
+                    Instruction::FmaddH(_) => fpu::fma_h(a, b, c, rm),
+                    Instruction::FmsubH(_) => fpu::fma_h(a, b, -c, rm),
+                    Instruction::FnmsubH(_) => fpu::fma_h(-a, b, c, rm),
+                    _ => fpu::fma_h(-a, b, -c, rm),
+                };
+                self.set_fflags(flags);
+                self.set_freg_h(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FaddH(r) | Instruction::FsubH(r) | Instruction::FmulH(r) | Instruction::FdivH(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (a, b) = (self.freg_h(r.rs1), self.freg_h(r.rs2));
+                let (res, flags) = match insn {
+                    Instruction::FaddH(_) => fpu::add_h(a, b, rm),
+                    Instruction::FsubH(_) => fpu::sub_h(a, b, rm),
+                    Instruction::FmulH(_) => fpu::mul_h(a, b, rm),
+                    _ => fpu::div_h(a, b, rm),
+                };
+                self.set_fflags(flags);
+                self.set_freg_h(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FsqrtH(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (res, flags) = fpu::sqrt_h(self.freg_h(r.rs1), rm);
+                self.set_fflags(flags);
+                self.set_freg_h(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FsgnjH(r) | Instruction::FsgnjnH(r) | Instruction::FsgnjxH(r) => {
+                let a = fpu::unbox_h(self.fregs[r.rs1 as usize]);
+                let b = fpu::unbox_h(self.fregs[r.rs2 as usize]);
+                let sign: u16 = 0x8000;
+                let res = match insn {
+                    Instruction::FsgnjH(_) => (a & !sign) | (b & sign),
+                    Instruction::FsgnjnH(_) => (a & !sign) | (!b & sign),
+                    _ => a ^ (b & sign),
+                };
+                self.set_freg_h(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FminH(r) | Instruction::FmaxH(r) => {
+                let (a, b) = (self.freg_h(r.rs1), self.freg_h(r.rs2));
+                let (res, flags) = match insn {
+                    Instruction::FminH(_) => fpu::min_h(a, b),
+                    _ => fpu::max_h(a, b),
+                };
+                self.set_fflags(flags);
+                self.set_freg_h(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtSH(r) => {
+                let (res, flags) = fpu::widen_h(fpu::unbox_h(self.fregs[r.rs1 as usize]));
+                // exact, narrow only carries over the canonical NaN
+                let (res, _) = fpu::narrow(res, 0);
+                self.set_fflags(flags);
+                self.set_freg_s(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtDH(r) => {
+                let (res, flags) = fpu::widen_h(fpu::unbox_h(self.fregs[r.rs1 as usize]));
+                self.set_fflags(flags);
+                self.set_freg_d(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtHS(r) | Instruction::FcvtHD(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let src = match insn {
+                    Instruction::FcvtHS(_) => self.freg_s(r.rs1) as f64,
+                    _ => self.freg_d(r.rs1),
+                };
+                let (res, flags) = fpu::narrow_h(src, rm);
+                self.set_fflags(flags);
+                self.set_freg_h(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtWH(r) | Instruction::FcvtWuH(r) | Instruction::FcvtLH(r) | Instruction::FcvtLuH(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let (min, max) = match insn {
+                    Instruction::FcvtWH(_) => (i32::MIN as i128, i32::MAX as i128),
+                    Instruction::FcvtWuH(_) => (0, u32::MAX as i128),
+                    Instruction::FcvtLH(_) => (i64::MIN as i128, i64::MAX as i128),
+                    _ => (0, u64::MAX as i128),
+                };
+                let (res, flags) = fpu::to_int(self.freg_h(r.rs1), rm, min, max);
+                // 32-bit results are sign-extended, unsigned ones too
+                let res = match insn {
+                    Instruction::FcvtWH(_) | Instruction::FcvtWuH(_) => res as u32 as i32 as i64 as u64,
+                    _ => res as u64,
+                };
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res);
+                self.pc += 4;
+            }
+            Instruction::FcvtHW(r) | Instruction::FcvtHWu(r) | Instruction::FcvtHL(r) | Instruction::FcvtHLu(r) => {
+                let Some(rm) = self.rounding_mode(r.funct3) else { return self.illegal_insn() };
+                let src = self.regs[r.rs1 as usize];
+                let src = match insn {
+                    Instruction::FcvtHW(_) => src as i32 as i128,
+                    Instruction::FcvtHWu(_) => src as u32 as i128,
+                    Instruction::FcvtHL(_) => src as i64 as i128,
+                    _ => src as i128,
+                };
+                let (res, flags) = fpu::from_int_h(src, rm);
+                self.set_fflags(flags);
+                self.set_freg_h(r.rd, res);
+                self.pc += 4;
+            }
+            Instruction::FmvXH(r) => {
+                let val = self.fregs[r.rs1 as usize] as u16;
+                self.set_reg(r.rd as usize, val as i16 as i64 as u64);
+                self.pc += 4;
+            }
+            Instruction::FmvHX(r) => {
+                let val = self.regs[r.rs1 as usize] as u16;
+                self.set_freg_h(r.rd, val);
+                self.pc += 4;
+            }
+            Instruction::FeqH(r) | Instruction::FltH(r) | Instruction::FleH(r) => {
+                let (a, b) = (self.freg_h(r.rs1), self.freg_h(r.rs2));
+                let (res, flags) = match insn {
+                    Instruction::FeqH(_) => fpu::eq(a, b),
+                    Instruction::FltH(_) => fpu::lt(a, b),
+                    _ => fpu::le(a, b),
+                };
+                self.set_fflags(flags);
+                self.set_reg(r.rd as usize, res as u64);
+                self.pc += 4;
+            }
+            Instruction::FclassH(r) => {
+                let res = fpu::class_h(fpu::unbox_h(self.fregs[r.rs1 as usize]));
+                self.set_reg(r.rd as usize, res);
+                self.pc += 4;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // V, run by the vector unit.
+    fn exec_vector(&mut self, insn: Instruction) {
+        match insn {
+            // All of V is illegal while mstatus.VS is off.
+            Instruction::Vsetvli(_) | Instruction::Vsetivli(_) | Instruction::Vsetvl(_)
+                | Instruction::Vload(_) | Instruction::Vstore(_) | Instruction::Varith(_) => {
+                let mut vector = std::mem::take(&mut self.vector);
+                let ok = self.mstatus & MSTATUS_VS != 0 && vector.exec(insn, self);
+                let fault = vector.take_fault();
+                self.vector = vector;
+                if let Some((store, addr, fault)) = fault {
+                    // elements before the faulting one are done
+                    self.mstatus |= MSTATUS_VS | MSTATUS_SD;
+                    self.mem_fault(if store { Access::Store } else { Access::Load }, fault, addr);
+                } else if ok {
+                    self.mstatus |= MSTATUS_VS | MSTATUS_SD;
+                    self.pc += 4;
+                } else {
+                    self.illegal_insn();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Everything the hart does not implement.
+    pub(super) fn exec_invalid(&mut self, insn: Instruction) {
+        match insn {
+            Instruction::Invalid(bits) => self.raise(Exception::IllegalInsn, bits as u64),
+            _ => self.illegal_insn(),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use super::*;
+
+// Sv39 translation. Fetches translate at the current privilege, loads and
+// stores at data_privilege() so MPRV reaches them, and M-mode is bare either
+// way. The walk sets A and D itself rather than faulting for software to do
+// it, xv6 never sets them. The page cache holds the last translation of each
+// access class until something flushes it.
+
+pub const SATP_MODE_SHIFT: u32 = 60;
+pub const SATP_MODE_BARE: u64 = 0;
+pub const SATP_MODE_SV39: u64 = 8;
+pub const SATP_PPN: u64 = (1 << 44) - 1;
+
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+// N, PBMT and the reserved bits, the hart has none of them.
+const PTE_RESERVED: u64 = 0x3ff << 54;
+const PTE_PPN_SHIFT: u32 = 10;
+
+const LEVELS: u32 = 3;
+const VA_BITS: u32 = 39;
+
+impl Cpu {
+    // Physical address of an access, from the page cache when it hits.
+    pub(super) fn translate(&mut self, access: Access, address: u64) -> Result<u64, MemFault> {
+        if let Some(paddr) = self.pages.lookup(access, address) {
+            return Ok(paddr);
+        }
+        let (paddr, update) = self.walk(access, address)?;
+        if let Some((pte_off, pte)) = update {
+            self.ram[pte_off..][..8].copy_from_slice(&pte.to_le_bytes());
+        }
+        self.pages.fill(access, address, paddr);
+        Ok(paddr)
+    }
+
+    // Walks the page table without touching it: the physical address and,
+    // when the access sets A or D, the RAM offset of the leaf and its new
+    // value. A PTE outside RAM is an access fault.
+    pub(super) fn walk(&self, access: Access, address: u64) -> Result<(u64, Option<(usize, u64)>), MemFault> {
+        let privl = match access {
+            Access::Fetch => self.privl,
+            Access::Load | Access::Store => self.data_privilege(),
+        };
+        if privl == 3 || self.satp >> SATP_MODE_SHIFT == SATP_MODE_BARE {
+            return Ok((address, None));
+        }
+
+        // Bits 63:39 have to copy bit 38.
+        let shift = 64 - VA_BITS;
+        if ((address << shift) as i64 >> shift) as u64 != address {
+            return Err(MemFault::Page);
+        }
+
+        let mut table = (self.satp & SATP_PPN) << 12;
+        for level in (0..LEVELS).rev() {
+            let pte_off = self.ram_index(table + (address >> (12 + 9 * level) & 0x1ff) * 8)?;
+            let pte = u64::from_le_bytes(self.ram[pte_off..][..8].try_into().unwrap());
+            if pte & PTE_V == 0 || pte & (PTE_R | PTE_W) == PTE_W || pte & PTE_RESERVED != 0 {
+                return Err(MemFault::Page);
+            }
+            let ppn = pte >> PTE_PPN_SHIFT;
+            if pte & (PTE_R | PTE_X) == 0 {
+                table = ppn << 12;
+                continue;
+            }
+
+            // A superpage maps an aligned range.
+            if !self.pte_permits(access, privl, pte) || ppn & ((1 << (9 * level)) - 1) != 0 {
+                return Err(MemFault::Page);
+            }
+            let offset = address & ((1 << (12 + 9 * level)) - 1);
+            let flags = if access == Access::Store { PTE_A | PTE_D } else { PTE_A };
+            let update = (pte & flags != flags).then_some((pte_off, pte | flags));
+            return Ok(((ppn << 12) | offset, update));
+        }
+        Err(MemFault::Page)
+    }
+
+    // U-mode only reaches U pages. S-mode reaches them with SUM, for loads
+    // and stores only. MXR makes executable pages readable.
+    fn pte_permits(&self, access: Access, privl: u8, pte: u64) -> bool {
+        let user = pte & PTE_U != 0;
+        let privl_ok = match privl {
+            0 => user,
+            _ => !user || (access != Access::Fetch && self.mstatus & MSTATUS_SUM != 0),
+        };
+        let readable = pte & PTE_R != 0 || (pte & PTE_X != 0 && self.mstatus & MSTATUS_MXR != 0);
+        privl_ok && match access {
+            Access::Fetch => pte & PTE_X != 0,
+            Access::Load => readable,
+            Access::Store => pte & PTE_W != 0,
+        }
+    }
+}
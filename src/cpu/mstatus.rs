@@ -0,0 +1,115 @@
+use crate::isa::Isa;
+
+#[cfg(test)]
+mod tests;
+
+// mstatus field by field as the privileged spec defines it. The hart keeps
+// the raw value, these are the rules for changing it: what a write leaves
+// of the WARL fields, the read-only ones and the transitions of traps and
+// xRET. sstatus is the SSTATUS_MASK view of the same value.
+
+pub const MSTATUS_SIE: u64 = 1 << 1;
+pub const MSTATUS_MIE: u64 = 1 << 3;
+pub const MSTATUS_SPIE: u64 = 1 << 5;
+pub const MSTATUS_UBE: u64 = 1 << 6;
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+pub const MSTATUS_SPP: u64 = 1 << 8;
+pub const MSTATUS_VS: u64 = 3 << 9;
+pub const MSTATUS_MPP: u64 = 3 << 11;
+pub const MSTATUS_FS: u64 = 3 << 13;
+pub const MSTATUS_XS: u64 = 3 << 15;
+pub const MSTATUS_MPRV: u64 = 1 << 17;
+pub const MSTATUS_SUM: u64 = 1 << 18;
+pub const MSTATUS_MXR: u64 = 1 << 19;
+pub const MSTATUS_TVM: u64 = 1 << 20;
+pub const MSTATUS_TW: u64 = 1 << 21;
+pub const MSTATUS_TSR: u64 = 1 << 22;
+pub const MSTATUS_UXL: u64 = 3 << 32;
+pub const MSTATUS_SXL: u64 = 3 << 34;
+pub const MSTATUS_SD: u64 = 1 << 63;
+
+const MPP_SHIFT: u32 = 11;
+const SPP_SHIFT: u32 = 8;
+// XLEN of U- and S-mode, fixed at 64. The hart is little-endian only, so
+// UBE, SBE and MBE read as zero.
+const XL_64: u64 = 2;
+
+// UXL = SXL = 64, everything else clear.
+pub const MSTATUS_RESET: u64 = (MSTATUS_UXL | MSTATUS_SXL) / 3 * XL_64;
+
+const WRITABLE: u64 = MSTATUS_SIE | MSTATUS_MIE | MSTATUS_SPIE | MSTATUS_MPIE | MSTATUS_SPP | MSTATUS_VS
+    | MSTATUS_MPP | MSTATUS_FS | MSTATUS_MPRV | MSTATUS_SUM | MSTATUS_MXR | MSTATUS_TVM | MSTATUS_TW | MSTATUS_TSR;
+pub const SSTATUS_MASK: u64 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_UBE | MSTATUS_SPP | MSTATUS_VS | MSTATUS_FS
+    | MSTATUS_XS | MSTATUS_SUM | MSTATUS_MXR | MSTATUS_UXL | MSTATUS_SD;
+
+// SD summarizes whether any of FS, VS and XS is dirty.
+fn with_sd(val: u64) -> u64 {
+    let dirty = [MSTATUS_FS, MSTATUS_VS, MSTATUS_XS].iter().any(|field| val & field == *field);
+    if dirty { val | MSTATUS_SD } else { val & !MSTATUS_SD }
+}
+
+// What a write of val to mstatus leaves. Read-only fields keep their value,
+// FS and VS stay off without F and V and MPP keeps its value when written
+// the reserved level 2.
+pub fn write(old: u64, val: u64, isa: &Isa) -> u64 {
+    let mut mask = WRITABLE;
+    if !isa.has("f") {
+        mask &= !MSTATUS_FS;
+    }
+    if !isa.has("v") {
+        mask &= !MSTATUS_VS;
+    }
+    let mut new = (old & !mask) | (val & mask);
+    if (new & MSTATUS_MPP) >> MPP_SHIFT == 2 {
+        new = (new & !MSTATUS_MPP) | (old & MSTATUS_MPP);
+    }
+    with_sd(new)
+}
+
+// A write of val to sstatus, which reaches only its fields of mstatus.
+pub fn write_sstatus(old: u64, val: u64, isa: &Isa) -> u64 {
+    write(old, (old & !SSTATUS_MASK) | (val & SSTATUS_MASK), isa)
+}
+
+// Entering M-mode from privl: MPIE takes MIE, MIE clears and MPP records
+// where the trap came from.
+pub fn trap_to_m(val: u64, privl: u8) -> u64 {
+    let mpie = if val & MSTATUS_MIE != 0 { MSTATUS_MPIE } else { 0 };
+    (val & !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP)) | mpie | (privl as u64) << MPP_SHIFT
+}
+
+// Entering S-mode from privl, below M-mode, the same with the S fields.
+pub fn trap_to_s(val: u64, privl: u8) -> u64 {
+    let spie = if val & MSTATUS_SIE != 0 { MSTATUS_SPIE } else { 0 };
+    (val & !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP)) | spie | (privl as u64) << SPP_SHIFT
+}
+
+// The privilege loads and stores are made at, for permissions and
+// translation: MPP's in M-mode with MPRV set. Fetches are always made at the
+// current privilege.
+pub fn data_privilege(val: u64, privl: u8) -> u8 {
+    if privl == 3 && val & MSTATUS_MPRV != 0 {
+        ((val & MSTATUS_MPP) >> MPP_SHIFT) as u8
+    } else {
+        privl
+    }
+}
+
+// MRET: back to MPP with MIE from MPIE, MPIE set and MPP to U-mode. MPRV
+// clears when leaving M-mode.
+pub fn mret(val: u64) -> (u64, u8) {
+    let privl = ((val & MSTATUS_MPP) >> MPP_SHIFT) as u8;
+    let mie = if val & MSTATUS_MPIE != 0 { MSTATUS_MIE } else { 0 };
+    let mprv = if privl == 3 { val & MSTATUS_MPRV } else { 0 };
+    let val = (val & !(MSTATUS_MIE | MSTATUS_MPP | MSTATUS_MPRV)) | mie | MSTATUS_MPIE | mprv;
+    (val, privl)
+}
+
+// SRET: back to SPP with SIE from SPIE, SPIE set and SPP to U-mode, which
+// always leaves M-mode behind.
+pub fn sret(val: u64) -> (u64, u8) {
+    let privl = ((val & MSTATUS_SPP) >> SPP_SHIFT) as u8;
+    let sie = if val & MSTATUS_SPIE != 0 { MSTATUS_SIE } else { 0 };
+    let val = (val & !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPRV)) | sie | MSTATUS_SPIE;
+    (val, privl)
+}
@@ -0,0 +1,76 @@
+use super::*;
+
+fn isa(s: &str) -> Isa {
+    Isa::parse(s).unwrap()
+}
+
+#[test]
+fn reset_value() {
+    assert_eq!(MSTATUS_RESET, 0xa_0000_0000);
+    assert_eq!(MSTATUS_RESET & SSTATUS_MASK, 0x2_0000_0000);
+}
+
+#[test]
+fn read_only_fields_keep_their_value() {
+    let rv64ima = isa("rv64ima");
+    let val = write(MSTATUS_RESET, !0, &rv64ima);
+    assert_eq!(val & (MSTATUS_UXL | MSTATUS_SXL), MSTATUS_RESET);
+    assert_eq!(val & (MSTATUS_UBE | MSTATUS_XS | MSTATUS_FS | MSTATUS_VS | MSTATUS_SD), 0);
+    assert_eq!(write(val, 0, &rv64ima), MSTATUS_RESET);
+    // SD follows the dirty states, it cannot be written
+    assert_eq!(write(MSTATUS_RESET, MSTATUS_SD, &rv64ima), MSTATUS_RESET);
+}
+
+#[test]
+fn fs_vs_and_sd() {
+    let rv64gv = isa("rv64gv");
+    assert_eq!(write(0, MSTATUS_FS, &rv64gv), MSTATUS_FS | MSTATUS_SD);
+    assert_eq!(write(0, MSTATUS_VS, &rv64gv), MSTATUS_VS | MSTATUS_SD);
+    assert_eq!(write(0, 1 << 13, &rv64gv), 1 << 13);
+    assert_eq!(write_sstatus(MSTATUS_FS | MSTATUS_SD, 0, &rv64gv), 0);
+}
+
+#[test]
+fn mpp_is_warl() {
+    let rv64g = isa("rv64g");
+    let s_mode = 1 << 11;
+    assert_eq!(write(s_mode, MSTATUS_MPP, &rv64g), MSTATUS_MPP);
+    assert_eq!(write(s_mode, 2 << 11, &rv64g), s_mode);
+}
+
+#[test]
+fn sstatus_reaches_only_its_fields() {
+    let rv64g = isa("rv64g");
+    let val = write_sstatus(MSTATUS_RESET | MSTATUS_MIE, !0, &rv64g);
+    let s_fields = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_FS | MSTATUS_SUM | MSTATUS_MXR;
+    assert_eq!(val, MSTATUS_RESET | MSTATUS_MIE | s_fields | MSTATUS_SD);
+}
+
+#[test]
+fn traps_and_returns() {
+    // from S-mode with SIE set into S-mode and back
+    let val = trap_to_s(MSTATUS_SIE, 1);
+    assert_eq!(val, MSTATUS_SPIE | MSTATUS_SPP);
+    assert_eq!(sret(val), (MSTATUS_SIE | MSTATUS_SPIE, 1));
+
+    // from U-mode into M-mode and back
+    let val = trap_to_m(MSTATUS_MIE | MSTATUS_SIE | MSTATUS_MPP, 0);
+    assert_eq!(val, MSTATUS_MPIE | MSTATUS_SIE);
+    assert_eq!(mret(val), (MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_SIE, 0));
+
+    // MPRV survives a return to M-mode only
+    assert_eq!(mret(MSTATUS_MPRV | MSTATUS_MPP), (MSTATUS_MPRV | MSTATUS_MPIE, 3));
+    assert_eq!(mret(MSTATUS_MPRV | 1 << 11), (MSTATUS_MPIE, 1));
+    assert_eq!(sret(MSTATUS_MPRV | MSTATUS_SPP), (MSTATUS_SPIE, 1));
+}
+
+#[test]
+fn mprv_moves_data_accesses_to_mpp() {
+    let s_mode = 1 << MPP_SHIFT;
+    assert_eq!(data_privilege(MSTATUS_MPRV | s_mode, 3), 1);
+    assert_eq!(data_privilege(MSTATUS_MPRV, 3), 0);
+    assert_eq!(data_privilege(s_mode, 3), 3);
+    // only M-mode accesses are affected
+    assert_eq!(data_privilege(MSTATUS_MPRV | MSTATUS_MPP, 1), 1);
+    assert_eq!(data_privilege(MSTATUS_MPRV | s_mode, 0), 0);
+}
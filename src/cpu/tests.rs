@@ -0,0 +1,954 @@
+use super::*;
+use crate::uart::SerialScript;
+
+const TVEC: u64 = RAM_BASE + 0x1000;
+const DATA: u64 = RAM_BASE + 0x2000;
+
+fn i_type(op: u32, rd: u32, funct3: u32, rs1: u32, imm: i32) -> u32 {
+    ((imm as u32 & 0xfff) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | op
+}
+
+fn s_type(funct3: u32, rs1: u32, rs2: u32, imm: i32) -> u32 {
+    let imm = imm as u32 & 0xfff;
+    ((imm >> 5) << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | ((imm & 0x1f) << 7) | 0x23
+}
+
+fn amo(funct5: u32, funct3: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    (funct5 << 27) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | 0x2f
+}
+
+// A hart about to execute insn with x1 = addr, x2..x5 = a pattern and a
+// known doubleword at DATA.
+fn hart(insn: u32, addr: u64) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.pc = RAM_BASE;
+    cpu.load_bytes(RAM_BASE, &insn.to_le_bytes());
+    cpu.load_bytes(DATA, &0x1122_3344_5566_7788u64.to_le_bytes());
+    cpu.mtvec = TVEC;
+    cpu.set_reg(1, addr);
+    for reg in 2..=5 {
+        cpu.set_reg(reg, 0xdead_0000 + reg as u64);
+    }
+    cpu
+}
+
+// Steps and checks the instruction trapped with nothing but the trap CSRs
+// changed: epc at the instruction, registers, memory and instret as before.
+fn assert_faults(mut cpu: Cpu, exception: Exception, tval: u64) {
+    let regs = cpu.regs;
+    let data = cpu.debug_read_u64(DATA);
+    cpu.step();
+    assert_eq!(cpu.pc, TVEC);
+    assert_eq!(cpu.mepc, RAM_BASE);
+    assert_eq!(cpu.mcause, exception as u64);
+    assert_eq!(cpu.mtval, tval);
+    assert_eq!(cpu.regs, regs);
+    assert_eq!(cpu.debug_read_u64(DATA), data);
+    assert_eq!(cpu.instret, 0);
+}
+
+#[test]
+fn load_retires() {
+    let mut cpu = hart(i_type(0x03, 3, 3, 1, 0), DATA);
+    cpu.step();
+    assert_eq!(cpu.pc, RAM_BASE + 4);
+    assert_eq!(cpu.reg(3), 0x1122_3344_5566_7788);
+    assert_eq!(cpu.instret, 1);
+}
+
+#[test]
+fn misaligned_load() {
+    assert_faults(hart(i_type(0x03, 3, 3, 1, 4), DATA), Exception::LoadMisaligned, DATA + 4);
+}
+
+#[test]
+fn load_access_fault() {
+    assert_faults(hart(i_type(0x03, 3, 2, 1, 0), 0x100), Exception::LoadAccess, 0x100);
+}
+
+#[test]
+fn misaligned_store() {
+    assert_faults(hart(s_type(3, 1, 2, 2), DATA), Exception::StoreMisaligned, DATA + 2);
+}
+
+#[test]
+fn store_access_fault() {
+    assert_faults(hart(s_type(2, 1, 2, 0), 0x100), Exception::StoreAccess, 0x100);
+}
+
+#[test]
+fn emulated_misaligned_load_and_store() {
+    let mut cpu = hart(i_type(0x03, 3, 3, 1, 3), DATA);
+    cpu.set_misaligned_emulation(true);
+    cpu.step();
+    assert_eq!(cpu.reg(3), 0x1122_3344_5566_7788 >> 24);
+
+    // sd x2, 5(x1)
+    let mut cpu = hart(s_type(3, 1, 2, 5), DATA);
+    cpu.set_misaligned_emulation(true);
+    cpu.step();
+    assert_eq!(cpu.pc, RAM_BASE + 4);
+    assert_eq!(cpu.debug_read_u64(DATA), Some(0xad00_0244_5566_7788));
+    assert_eq!(cpu.debug_read_u64(DATA + 8), Some(0xde));
+}
+
+#[test]
+fn emulated_misaligned_access_faults_as_a_whole() {
+    // ld x3, 0(x1) with the last bytes beyond RAM
+    let mut cpu = hart(i_type(0x03, 3, 3, 1, 0), RAM_BASE + RAM_SIZE - 4);
+    cpu.set_misaligned_emulation(true);
+    assert_faults(cpu, Exception::LoadAccess, RAM_BASE + RAM_SIZE - 4);
+
+    // LR is never emulated
+    let mut cpu = hart(amo(0x02, 3, 3, 1, 0), DATA + 4);
+    cpu.set_misaligned_emulation(true);
+    assert_faults(cpu, Exception::LoadMisaligned, DATA + 4);
+}
+
+#[test]
+fn misaligned_amo() {
+    // amoadd.w x3, x2, (x1)
+    assert_faults(hart(amo(0x00, 2, 3, 1, 2), DATA + 2), Exception::StoreMisaligned, DATA + 2);
+}
+
+#[test]
+fn amo_access_fault_is_a_store_fault() {
+    // amoswap.d x3, x2, (x1)
+    assert_faults(hart(amo(0x01, 3, 3, 1, 2), 0x100), Exception::StoreAccess, 0x100);
+}
+
+#[test]
+fn lr_faults_as_a_load() {
+    // lr.d x3, (x1)
+    assert_faults(hart(amo(0x02, 3, 3, 1, 0), DATA + 4), Exception::LoadMisaligned, DATA + 4);
+}
+
+#[test]
+fn sc_fault_keeps_rd() {
+    // sc.d x3, x2, (x1) holding a reservation outside RAM
+    let mut cpu = hart(amo(0x03, 3, 3, 1, 2), 0x100);
+    cpu.reservation = Some(0x100);
+    assert_faults(cpu, Exception::StoreAccess, 0x100);
+}
+
+#[test]
+fn misaligned_amocas_q_stores_nothing() {
+    // amocas.q x2, x4, (x1) whose expected pair matches the memory at an
+    // 8-byte aligned address
+    let mut cpu = hart(amo(0x05, 4, 2, 1, 4), DATA - 8);
+    cpu.set_reg(2, 0);
+    cpu.set_reg(3, 0x1122_3344_5566_7788);
+    assert_faults(cpu, Exception::StoreMisaligned, DATA - 8);
+}
+
+#[test]
+fn shifts_use_six_bit_shamt() {
+    // slli x3, x1, 32; srli x3, x1, 63; srai x3, x1, 40
+    for (insn, val) in [(0x02009193, 0x8000_0001_0000_0000), (0x03f0d193, 1), (0x4280d193, 0xffff_ffff_ff80_0000)] {
+        let mut cpu = hart(insn, 0x8000_0000_8000_0001);
+        cpu.step();
+        assert_eq!(cpu.regs[3], val, "{:#010x}", insn);
+    }
+}
+
+fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | 0x33
+}
+
+#[test]
+fn xori_and_register_shifts() {
+    // xori x3, x1, -1
+    let mut cpu = hart(i_type(0x13, 3, 4, 1, -1), 0x00ff);
+    cpu.step();
+    assert_eq!(cpu.reg(3), !0x00ff);
+
+    // sll, srl and sra x3, x1, x2 only use the low six bits of x2
+    for (funct7, funct3, expected) in [(0x00, 1, 0x8000_0000_0000_0000), (0x00, 5, 1), (0x20, 5, !0)] {
+        let mut cpu = hart(r_type(funct7, 2, 1, funct3, 3), if funct3 == 1 { 1 } else { 1 << 63 });
+        cpu.set_reg(2, 0x7f);
+        cpu.step();
+        assert_eq!((cpu.reg(3), cpu.pc), (expected, RAM_BASE + 4), "{:#x} {}", funct7, funct3);
+    }
+}
+
+#[test]
+fn alu_results_never_reach_x0() {
+    // add, sub, and, or, xor x0, x1, x2 and auipc x0, 1
+    let insns = [r_type(0, 2, 1, 0, 0), r_type(0x20, 2, 1, 0, 0), r_type(0, 2, 1, 7, 0), r_type(0, 2, 1, 6, 0),
+        r_type(0, 2, 1, 4, 0), 0x00001017];
+    for insn in insns {
+        let mut cpu = hart(insn, 0x1234);
+        cpu.step();
+        assert_eq!((cpu.reg(0), cpu.pc), (0, RAM_BASE + 4), "{:#010x}", insn);
+    }
+}
+
+#[test]
+fn loads_never_reach_x0() {
+    // lb, lh, lw, ld, lbu, lhu, lwu x0, 0(x1)
+    for funct3 in 0..=6 {
+        let mut cpu = hart(i_type(0x03, 0, funct3, 1, 0), DATA);
+        cpu.step();
+        assert_eq!((cpu.reg(0), cpu.pc), (0, RAM_BASE + 4), "funct3 {}", funct3);
+    }
+}
+
+#[test]
+fn reserved_shift_traps() {
+    // srai x3, x1, 3 with funct6 0x11
+    let insn = 0x4430d193;
+    assert_faults(hart(insn, 0), Exception::IllegalInsn, insn as u64);
+}
+
+#[test]
+fn unknown_csr_traps() {
+    // csrrs x3, 0x7ff, x0
+    let insn = 0x7ff021f3;
+    assert_faults(hart(insn, 0), Exception::IllegalInsn, insn as u64);
+}
+
+#[test]
+fn ebreak_traps() {
+    assert_faults(hart(0x00100073, 0), Exception::Breakpoint, RAM_BASE);
+}
+
+// Without C a target off a 4-byte boundary traps at the jump, rd untouched.
+#[test]
+fn misaligned_jump_targets_trap() {
+    // jal x1, 6; jalr x3, 2(x1); beq x0, x0, 6
+    let jumps = [(0x006000ef, RAM_BASE + 6), (i_type(0x67, 3, 0, 1, 2), DATA + 2), (0x00000363, RAM_BASE + 6)];
+    for (insn, tval) in jumps {
+        assert_faults(hart(insn, DATA), Exception::InsnMisaligned, tval);
+    }
+
+    // bne x0, x0, 6 is not taken
+    let mut cpu = hart(0x00001363, 0);
+    cpu.step();
+    assert_eq!(cpu.pc, RAM_BASE + 4);
+}
+
+#[test]
+fn lh_sign_extends() {
+    // lh x3, 0(x1)
+    let mut cpu = hart(i_type(0x03, 3, 1, 1, 0), DATA);
+    cpu.step();
+    assert_eq!(cpu.reg(3), 0x7788);
+    cpu.load_bytes(DATA, &0x8000u16.to_le_bytes());
+    cpu.pc = RAM_BASE;
+    cpu.step();
+    assert_eq!(cpu.reg(3), 0xffff_ffff_ffff_8000);
+}
+
+#[test]
+fn lw_sign_extends() {
+    // lw x3, 0(x1)
+    let mut cpu = hart(i_type(0x03, 3, 2, 1, 0), DATA);
+    cpu.step();
+    assert_eq!(cpu.reg(3), 0x5566_7788);
+    cpu.load_bytes(DATA, &0x8000_0000u32.to_le_bytes());
+    cpu.pc = RAM_BASE;
+    cpu.step();
+    assert_eq!(cpu.reg(3), 0xffff_ffff_8000_0000);
+}
+
+#[test]
+fn lwu_zero_extends() {
+    // lwu x3, 4(x1)
+    let mut cpu = hart(i_type(0x03, 3, 6, 1, 4), DATA);
+    cpu.step();
+    assert_eq!(cpu.reg(3), 0x1122_3344);
+    cpu.load_bytes(DATA + 4, &0x8000_0000u32.to_le_bytes());
+    cpu.pc = RAM_BASE;
+    cpu.step();
+    assert_eq!(cpu.reg(3), 0x8000_0000);
+}
+
+#[test]
+fn csr_names() {
+    let cpu = Cpu::new();
+    assert_eq!(cpu.csr_name(0x342).as_deref(), Some("mcause"));
+    assert_eq!(cpu.csr_name(0x3b3).as_deref(), Some("pmpaddr3"));
+    assert_eq!(cpu.csr_name(0x7ff), None);
+    assert_eq!(crate::registers::csr_number(&cpu, "pmpaddr3"), Some(0x3b3));
+    assert_eq!(crate::registers::csr_number(&cpu, "0x342"), Some(0x342));
+}
+
+#[test]
+fn supervisor_csrs_shadow_machine_ones() {
+    // csrw mideleg, x2; csrw sie, x3; csrw stvec, x4; csrw sepc, x5
+    let mut cpu = hart(0x30311073, 0);
+    for (i, insn) in [0x10419073u32, 0x10521073, 0x14129073].iter().enumerate() {
+        cpu.load_bytes(RAM_BASE + 4 * (i as u64 + 1), &insn.to_le_bytes());
+    }
+    cpu.set_reg(2, u64::MAX);
+    cpu.set_reg(3, MIE_MASK);
+    cpu.set_reg(4, TVEC + 1);
+    cpu.set_reg(5, DATA + 3);
+    for _ in 0..4 {
+        cpu.step();
+    }
+    assert_eq!(cpu.mideleg, MIP_SSIP | MIP_STIP | MIP_SEIP);
+    assert_eq!(cpu.mie, MIP_SSIP | MIP_STIP | MIP_SEIP);
+    assert_eq!(cpu.stvec, TVEC + 1);
+    assert_eq!(cpu.sepc, DATA + 2);
+    assert_eq!(cpu.instret, 4);
+}
+
+#[test]
+fn delegated_exception_enters_s_mode() {
+    let insn = 0x4430d193;
+    let mut cpu = hart(insn, 0);
+    cpu.medeleg = 1 << Exception::IllegalInsn as u64;
+    cpu.stvec = DATA;
+    cpu.mstatus = MSTATUS_SIE;
+    cpu.privl = 1;
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.privl), (DATA, 1));
+    assert_eq!((cpu.sepc, cpu.scause, cpu.stval), (RAM_BASE, Exception::IllegalInsn as u64, insn as u64));
+    assert_eq!(cpu.mstatus, MSTATUS_SPIE | MSTATUS_SPP);
+    assert_eq!(cpu.mcause, 0);
+
+    // M-mode keeps its traps
+    let mut cpu = hart(insn, 0);
+    cpu.medeleg = 1 << Exception::IllegalInsn as u64;
+    assert_faults(cpu, Exception::IllegalInsn, insn as u64);
+}
+
+// SRET back to U-mode restores SIE from SPIE, and is illegal in U-mode or
+// under TSR.
+#[test]
+fn sret_returns_to_spp() {
+    let sret = 0x10200073;
+    let mut cpu = hart(sret, 0);
+    cpu.privl = 1;
+    cpu.sepc = DATA;
+    cpu.mstatus = MSTATUS_SPIE;
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.privl, cpu.mstatus), (DATA, 0, MSTATUS_SIE | MSTATUS_SPIE));
+
+    let mut cpu = hart(sret, 0);
+    cpu.sepc = DATA;
+    cpu.mstatus = MSTATUS_SIE | MSTATUS_SPP;
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.privl, cpu.mstatus), (DATA, 1, MSTATUS_SPIE));
+
+    let mut cpu = hart(sret, 0);
+    cpu.privl = 0;
+    assert_faults(cpu, Exception::IllegalInsn, sret as u64);
+    let mut cpu = hart(sret, 0);
+    cpu.privl = 1;
+    cpu.mstatus = MSTATUS_TSR;
+    assert_faults(cpu, Exception::IllegalInsn, sret as u64);
+}
+
+// MRET is illegal below M-mode.
+#[test]
+fn mret_needs_m_mode() {
+    let mret = 0x30200073;
+    for privl in [0, 1] {
+        let mut cpu = hart(mret, 0);
+        cpu.privl = privl;
+        cpu.mepc = DATA;
+        cpu.mstatus = MSTATUS_MPP;
+        assert_faults(cpu, Exception::IllegalInsn, mret as u64);
+    }
+}
+
+// With TVM set S-mode may neither touch satp nor run SFENCE.VMA, U-mode
+// never runs SFENCE.VMA.
+#[test]
+fn tvm_traps_satp_and_sfence_vma() {
+    // sfence.vma; csrr x3, satp
+    for insn in [0x12000073, 0x180021f3] {
+        let mut cpu = hart(insn, 0);
+        cpu.privl = 1;
+        cpu.step();
+        assert_eq!(cpu.pc, RAM_BASE + 4, "{:#010x}", insn);
+
+        let mut cpu = hart(insn, 0);
+        cpu.privl = 1;
+        cpu.mstatus = MSTATUS_TVM;
+        assert_faults(cpu, Exception::IllegalInsn, insn as u64);
+    }
+    let mut cpu = hart(0x12000073, 0);
+    cpu.privl = 0;
+    assert_faults(cpu, Exception::IllegalInsn, 0x12000073);
+}
+
+// With mstatus.FS off the FP instructions and CSRs trap.
+#[test]
+fn fp_needs_fs() {
+    // fadd.s, fadd.d, fadd.h f1, f2, f3; flw f1, 0(x1); csrr x3, fflags/frm/fcsr
+    let insns = [0x003100d3, 0x023100d3, 0x043100d3, i_type(0x07, 1, 2, 1, 0), 0x001021f3, 0x002021f3,
+                 0x003021f3];
+    for insn in insns {
+        let mut cpu = hart(insn, DATA);
+        cpu.mstatus |= MSTATUS_FS / 3;
+        cpu.step();
+        assert_eq!(cpu.pc, RAM_BASE + 4, "{:#010x}", insn);
+
+        assert_faults(hart(insn, DATA), Exception::IllegalInsn, insn as u64);
+    }
+}
+
+#[test]
+fn expired_stimecmp_interrupts_s_mode() {
+    let mut cpu = hart(0x00000013, 0);
+    cpu.menvcfg = MENVCFG_STCE;
+    cpu.mideleg = MIP_STIP;
+    cpu.mie = MIP_STIP;
+    cpu.stvec = DATA;
+    cpu.privl = 1;
+    cpu.update_timers();
+    assert_ne!(cpu.read_mip() & MIP_STIP, 0);
+    // masked by SIE
+    cpu.step();
+    assert_eq!(cpu.pc, RAM_BASE + 4);
+
+    cpu.mstatus = MSTATUS_SIE;
+    cpu.check_irqs = true;
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.sepc, cpu.scause), (DATA, RAM_BASE + 4, (1 << 63) | 5));
+    assert_eq!(cpu.instret, 1);
+}
+
+#[test]
+fn counters_are_enabled_per_level() {
+    // csrr x3, cycle / time / instret
+    for (insn, bit) in [(0xc00021f3u32, MCOUNTEREN_CY), (0xc01021f3, MCOUNTEREN_TM), (0xc02021f3, MCOUNTEREN_IR)] {
+        let mut cpu = hart(insn, 0);
+        cpu.step();
+        assert_eq!((cpu.pc, cpu.instret), (RAM_BASE + 4, 1));
+
+        let mut cpu = hart(insn, 0);
+        cpu.privl = 1;
+        cpu.mcounteren = COUNTEREN_MASK & !bit;
+        assert_faults(cpu, Exception::IllegalInsn, insn as u64);
+        let mut cpu = hart(insn, 0);
+        cpu.privl = 1;
+        cpu.mcounteren = bit;
+        cpu.step();
+        assert_eq!(cpu.pc, RAM_BASE + 4);
+
+        // U-mode needs the bit in both
+        let mut cpu = hart(insn, 0);
+        cpu.privl = 0;
+        cpu.mcounteren = bit;
+        assert_faults(cpu, Exception::IllegalInsn, insn as u64);
+        let mut cpu = hart(insn, 0);
+        cpu.privl = 0;
+        cpu.scounteren = bit;
+        assert_faults(cpu, Exception::IllegalInsn, insn as u64);
+        let mut cpu = hart(insn, 0);
+        cpu.privl = 0;
+        cpu.mcounteren = bit;
+        cpu.scounteren = bit;
+        cpu.step();
+        assert_eq!(cpu.pc, RAM_BASE + 4);
+    }
+
+    let mut cpu = hart(0xc02021f3, 0);
+    cpu.instret = 41;
+    cpu.step();
+    // the count includes the csrr, retired as it executes
+    assert_eq!(cpu.reg(3), 42);
+    assert!(cpu.debug_write_csr(0x306, u64::MAX) && cpu.debug_write_csr(0x106, u64::MAX));
+    assert_eq!((cpu.mcounteren, cpu.scounteren), (COUNTEREN_MASK, COUNTEREN_MASK));
+}
+
+#[test]
+fn stimecmp_is_enabled_by_stce_and_tm() {
+    // csrr x3, stimecmp
+    let insn = 0x14d021f3;
+    let mut cpu = hart(insn, 0);
+    cpu.privl = 1;
+    cpu.menvcfg = MENVCFG_STCE;
+    assert_faults(cpu, Exception::IllegalInsn, insn as u64);
+    let mut cpu = hart(insn, 0);
+    cpu.privl = 1;
+    cpu.mcounteren = MCOUNTEREN_TM;
+    assert_faults(cpu, Exception::IllegalInsn, insn as u64);
+
+    let mut cpu = hart(insn, 0);
+    cpu.privl = 1;
+    cpu.menvcfg = MENVCFG_STCE;
+    cpu.mcounteren = MCOUNTEREN_TM;
+    cpu.stimecmp = 42;
+    cpu.step();
+    assert_eq!(cpu.reg(3), 42);
+
+    // without Sstc neither stimecmp nor STCE exist
+    let mut cpu = hart(insn, 0);
+    cpu.set_isa(Isa::parse("rv64imafd").unwrap());
+    assert!(cpu.debug_write_csr(0x30a, MENVCFG_STCE));
+    assert_eq!(cpu.menvcfg, 0);
+    assert_faults(cpu, Exception::IllegalInsn, insn as u64);
+}
+
+#[test]
+fn clint_mtimecmp_interrupts_m_mode() {
+    let mut cpu = hart(0x00000013, 0);
+    cpu.mie = MIP_MTIP;
+    cpu.mtvec = DATA;
+    let now = cpu.load_u64(CLINT_BASE + 0xbff8).unwrap();
+    cpu.store_u32(CLINT_BASE + 0x4004, (now >> 32) as u32 + 1).unwrap();
+    assert_eq!(cpu.load_u64(CLINT_BASE + 0x4000), Ok(((now >> 32) + 1) << 32 | 0xffff_ffff));
+    assert_eq!(cpu.read_mip() & MIP_MTIP, 0);
+
+    cpu.store_u64(CLINT_BASE + 0x4000, now).unwrap();
+    assert_ne!(cpu.read_mip() & MIP_MTIP, 0);
+    // masked by MIE
+    cpu.step();
+    assert_eq!(cpu.pc, RAM_BASE + 4);
+
+    cpu.mstatus = MSTATUS_MIE;
+    cpu.check_irqs = true;
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.mepc, cpu.mcause), (DATA, RAM_BASE + 4, (1 << 63) | 7));
+
+    // a later compare takes the line down again
+    cpu.store_u64(CLINT_BASE + 0x4000, u64::MAX).unwrap();
+    assert_eq!(cpu.read_mip() & MIP_MTIP, 0);
+    assert_eq!(cpu.store_u16(CLINT_BASE + 0x4000, 0), Err(MemFault::Access));
+}
+
+#[test]
+fn software_interrupts() {
+    let mut cpu = hart(0x00000013, 0);
+    cpu.mie = MIP_MSIP | MIP_SSIP;
+    cpu.mtvec = DATA;
+    cpu.mstatus = MSTATUS_MIE;
+    cpu.store_u32(CLINT_BASE, 3).unwrap();
+    assert_eq!(cpu.load_u32(CLINT_BASE), Ok(1));
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.mepc, cpu.mcause), (DATA, RAM_BASE, (1 << 63) | 3));
+    cpu.store_u32(CLINT_BASE, 0).unwrap();
+    assert_eq!(cpu.read_mip() & MIP_MSIP, 0);
+
+    // csrs sip, x2 from S-mode
+    let mut cpu = hart(0x14412073, 0);
+    cpu.load_bytes(RAM_BASE + 4, &0x00000013u32.to_le_bytes());
+    cpu.set_reg(2, MIP_SSIP);
+    cpu.mideleg = MIP_SSIP;
+    cpu.mie = MIP_SSIP;
+    cpu.stvec = DATA;
+    cpu.privl = 1;
+    cpu.mstatus = MSTATUS_SIE;
+    cpu.step();
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.sepc, cpu.scause), (DATA, RAM_BASE + 4, (1 << 63) | 1));
+}
+
+// UART receive through the PLIC: SEIP delegated to S-mode, then the same
+// source routed to the M-mode context.
+#[test]
+fn external_interrupts() {
+    let claim_s = PLIC_BASE + 0x201004;
+    let mut cpu = hart(0x00000013, 0);
+    cpu.load_bytes(RAM_BASE + 4, &0x00000013u32.to_le_bytes());
+    cpu.store_u32(PLIC_BASE + 4 * UART_IRQ as u64, 1).unwrap();
+    cpu.store_u32(PLIC_BASE + 0x2080, 1 << UART_IRQ).unwrap();
+    cpu.store_u8(UART_BASE + 1, 1).unwrap();
+    cpu.mideleg = MIP_SEIP;
+    cpu.mie = MIP_SEIP | MIP_MEIP;
+    cpu.stvec = DATA;
+    cpu.privl = 1;
+    cpu.mstatus = MSTATUS_SIE;
+
+    cpu.uart.set_script(SerialScript::parse("0 a").unwrap(), 0);
+    cpu.poll_io();
+    assert_eq!(cpu.read_mip() & (MIP_SEIP | MIP_MEIP), MIP_SEIP);
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.sepc, cpu.scause), (DATA, RAM_BASE, (1 << 63) | 9));
+
+    // claiming drops the line, completing with data left raises it again
+    assert_eq!(cpu.load_u32(claim_s), Ok(UART_IRQ));
+    assert_eq!(cpu.read_mip() & MIP_SEIP, 0);
+    cpu.store_u32(claim_s, UART_IRQ).unwrap();
+    assert_eq!(cpu.read_mip() & MIP_SEIP, MIP_SEIP);
+    assert_eq!(cpu.load_u8(UART_BASE), Ok(b'a'));
+    assert_eq!(cpu.read_mip() & MIP_SEIP, 0);
+
+    cpu.store_u32(PLIC_BASE + 0x2000, 1 << UART_IRQ).unwrap();
+    cpu.uart.set_script(SerialScript::parse("0 b").unwrap(), 0);
+    cpu.poll_io();
+    cpu.pc = RAM_BASE;
+    cpu.mtvec = TVEC;
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.mepc, cpu.mcause), (TVEC, RAM_BASE, (1 << 63) | 11));
+}
+
+// WFI holds the hart until an interrupt is pending, enabled globally or not.
+#[test]
+fn wfi_waits_for_an_interrupt() {
+    let mut cpu = hart(0x10500073, 0);
+    cpu.load_bytes(RAM_BASE + 4, &0x00000013u32.to_le_bytes());
+    cpu.mie = MIP_MSIP;
+    cpu.step();
+    assert!(cpu.is_waiting());
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.instret), (RAM_BASE + 4, 1));
+
+    cpu.store_u32(CLINT_BASE, 1).unwrap();
+    assert!(!cpu.is_waiting());
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.instret), (RAM_BASE + 8, 2));
+
+    let mut cpu = hart(0x10500073, 0);
+    cpu.privl = 1;
+    cpu.mstatus = MSTATUS_TW;
+    assert_faults(cpu, Exception::IllegalInsn, 0x10500073);
+    let mut cpu = hart(0x10500073, 0);
+    cpu.privl = 0;
+    assert_faults(cpu, Exception::IllegalInsn, 0x10500073);
+}
+
+// A debugger single step with interrupts masked and timers frozen.
+#[test]
+fn masked_step_leaves_interrupts_pending() {
+    let mut cpu = hart(0x00000013, 0);
+    cpu.load_bytes(RAM_BASE + 4, &0x00000013u32.to_le_bytes());
+    cpu.menvcfg = MENVCFG_STCE;
+    cpu.mie = MIP_STIP;
+    cpu.mstatus = MSTATUS_MIE;
+    cpu.set_timers_frozen(true);
+    cpu.stimecmp = cpu.timers_frozen.unwrap() + 1;
+    assert_eq!(cpu.update_timers(), Some(1));
+
+    cpu.set_irq_line(MIP_STIP, true);
+    cpu.set_irqs_masked(true);
+    cpu.step();
+    assert_eq!(cpu.pc, RAM_BASE + 4);
+
+    cpu.set_irqs_masked(false);
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.mepc, cpu.mcause), (TVEC, RAM_BASE + 4, (1 << 63) | 5));
+}
+
+// Device updates leave the lines the monitor raised alone.
+#[test]
+fn injected_interrupts_outlive_device_updates() {
+    let mut cpu = hart(0x00000013, 0);
+    cpu.inject_irq(MIP_MEIP | MIP_MTIP | MIP_MSIP, true);
+    cpu.update_device_irqs();
+    cpu.update_timers();
+    cpu.clint_store(CLINT_BASE, 4, 0).unwrap();
+    assert_eq!(cpu.read_mip(), MIP_MEIP | MIP_MTIP | MIP_MSIP);
+
+    cpu.inject_irq(MIP_MTIP, false);
+    assert_eq!(cpu.read_mip(), MIP_MEIP | MIP_MSIP);
+}
+
+// mtval of an illegal instruction holds its bits wherever they came from.
+#[test]
+fn illegal_insn_mtval_holds_the_fetched_bits() {
+    // a load with the reserved funct3 7
+    let insn = i_type(0x03, 3, 7, 1, 0);
+    let mut cpu = hart(0, 0);
+    cpu.set_boot_rom(insn.to_le_bytes().to_vec());
+    cpu.pc = BOOTROM_BASE;
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.mcause, cpu.mtval), (TVEC, Exception::IllegalInsn as u64, insn as u64));
+
+    // and in S-mode through a page mapping it somewhere else
+    let mut cpu = hart(insn, 0);
+    map_page(&mut cpu, VA, RAM_BASE, PTE_X);
+    cpu.pc = VA;
+    cpu.privl = 1;
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.mepc, cpu.mtval), (TVEC, VA, insn as u64));
+}
+
+#[test]
+fn ecall_cause_follows_privilege() {
+    assert_faults(hart(0x00000073, 0), Exception::EcallM, 0);
+
+    let mut cpu = hart(0x00000073, 0);
+    cpu.privl = 0;
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.mcause, cpu.privl), (TVEC, Exception::EcallU as u64, 3));
+    assert_eq!(cpu.mstatus & MSTATUS_MPP, 0);
+}
+
+#[test]
+fn guest_trace_csrs() {
+    // csrw nrvmark, x1; csrw nrvtrace, x0
+    let mut cpu = hart(0x8c109073, DATA);
+    cpu.load_bytes(RAM_BASE + 4, &0x8c001073u32.to_le_bytes());
+    cpu.load_bytes(DATA, b"boot, done\0");
+    cpu.privl = 0;
+    cpu.step();
+    assert_eq!(cpu.take_guest_trace(), Some(GuestTrace::Marker("boot, done".into())));
+    cpu.step();
+    assert_eq!(cpu.take_guest_trace(), Some(GuestTrace::Stop));
+}
+
+#[test]
+fn undecodable_instruction_traps() {
+    assert_faults(hart(0xffff_ffff, 0), Exception::IllegalInsn, 0xffff_ffff);
+}
+
+#[test]
+fn fetch_access_fault() {
+    let mut cpu = hart(0x00000013, 0);
+    cpu.pc = 0x100;
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.mepc), (TVEC, 0x100));
+    assert_eq!((cpu.mcause, cpu.mtval), (Exception::InsnAccess as u64, 0x100));
+}
+
+#[test]
+fn page_faults_follow_the_access_class() {
+    for (access, exception) in [
+        (Access::Fetch, Exception::InsnPageFault),
+        (Access::Load, Exception::LoadPageFault),
+        (Access::Store, Exception::StorePageFault),
+    ] {
+        assert_eq!(MemFault::Page.exception(access), exception);
+    }
+
+    // delegated with the virtual address in stval
+    let mut cpu = hart(0x00000013, 0);
+    cpu.medeleg = 1 << Exception::LoadPageFault as u64;
+    cpu.stvec = DATA;
+    cpu.privl = 1;
+    cpu.instret = 1;
+    cpu.mem_fault(Access::Load, MemFault::Page, 0x4000_1234);
+    assert_eq!((cpu.pc, cpu.scause, cpu.stval), (DATA, Exception::LoadPageFault as u64, 0x4000_1234));
+    assert_eq!(cpu.mcause, 0);
+}
+
+// Probing drivers touch holes in the bus, up to the top of the address
+// space. Loads, stores and fetches there are access faults.
+#[test]
+fn absent_devices_fault() {
+    for addr in [0, CLINT_BASE + clint::MMIO_SIZE, 0x0c00_0000, UART_BASE + 0x10, RAM_BASE + RAM_SIZE, u64::MAX - 7] {
+        // ld x3, 0(x1)
+        let mut cpu = hart(i_type(0x03, 3, 3, 1, 0), addr);
+        cpu.add_watchpoint(WatchKind::Access, u64::MAX - 3, 4);
+        assert_faults(cpu, Exception::LoadAccess, addr);
+
+        // sd x2, 0(x1) holding a reservation of the same block
+        let mut cpu = hart(s_type(3, 1, 2, 0), addr);
+        cpu.reservation = Some(addr & !(RESERVATION_SIZE - 1));
+        assert_faults(cpu, Exception::StoreAccess, addr);
+
+        let mut cpu = hart(0x00000013, 0);
+        cpu.pc = addr;
+        cpu.step();
+        assert_eq!((cpu.pc, cpu.mcause, cpu.mtval), (TVEC, Exception::InsnAccess as u64, addr));
+    }
+}
+
+// Watchpoint hits report the address the guest accessed.
+#[test]
+fn watch_hit_reports_the_access() {
+    // ld x3, 8(x1)
+    let mut cpu = hart(i_type(0x03, 3, 3, 1, 8), DATA);
+    cpu.add_watchpoint(WatchKind::Read, DATA, 16);
+    cpu.step();
+    assert_eq!(cpu.take_watch_hit(), Some((WatchKind::Read, DATA + 8)));
+
+    // sd x2, 0(x1) over the start of a watched word
+    let mut cpu = hart(s_type(3, 1, 2, 0), DATA);
+    cpu.add_watchpoint(WatchKind::Write, DATA + 4, 4);
+    cpu.step();
+    assert_eq!(cpu.take_watch_hit(), Some((WatchKind::Write, DATA + 4)));
+}
+
+// A store that faults is no watch hit and no write for the W^X monitor.
+#[test]
+fn faulting_store_is_not_watched() {
+    // sd x2, 0(x1) outside RAM and misaligned
+    for addr in [0x100, DATA + 4] {
+        let mut cpu = hart(s_type(3, 1, 2, 0), addr);
+        cpu.add_watchpoint(WatchKind::Write, addr, 8);
+        cpu.set_wx_monitor(true);
+        cpu.step();
+        assert_eq!(cpu.pc, TVEC);
+        assert_eq!(cpu.take_watch_hit(), None);
+        assert_eq!(cpu.wx.as_mut().unwrap().on_exec(addr), None);
+    }
+}
+
+#[test]
+fn execute_runs_a_decoded_instruction() {
+    // addi x3, x2, -5, with nothing in memory at pc
+    let mut cpu = hart(0, 0);
+    cpu.execute(Instruction::decode(i_type(0x13, 3, 0, 2, -5)));
+    assert_eq!((cpu.pc, cpu.reg(3), cpu.instret), (RAM_BASE + 4, 0xdead_0002 - 5, 1));
+
+    cpu.execute(Instruction::decode(0x00100073));
+    assert_eq!((cpu.pc, cpu.mepc, cpu.mcause), (TVEC, RAM_BASE + 4, Exception::Breakpoint as u64));
+}
+
+#[test]
+fn fused_slli_add_writes_only_nonzero_rd() {
+    // slli x3, x2, 2; add x0, x3, x4
+    let mut cpu = hart(i_type(0x13, 3, 1, 2, 2), 0);
+    cpu.load_bytes(RAM_BASE + 4, &r_type(0, 4, 3, 0, 0).to_le_bytes());
+    cpu.set_fusion(true);
+    // the add decodes as a hint today, fused or not x0 has to stay zero
+    while cpu.pc != RAM_BASE + 8 {
+        cpu.step();
+    }
+    assert_eq!((cpu.reg(0), cpu.reg(3), cpu.instret), (0, 0xdead_0002 << 2, 2));
+
+    // slli x3, x2, 2; add x5, x3, x4
+    let mut cpu = hart(i_type(0x13, 3, 1, 2, 2), 0);
+    cpu.load_bytes(RAM_BASE + 4, &r_type(0, 4, 3, 0, 5).to_le_bytes());
+    cpu.set_fusion(true);
+    cpu.step();
+    assert_eq!(cpu.take_fused_pc(), Some(RAM_BASE + 4));
+    assert_eq!((cpu.pc, cpu.reg(5)), (RAM_BASE + 8, (0xdead_0002 << 2) + 0xdead_0004));
+}
+
+#[test]
+fn fused_auipc_jalr_misaligned_target_traps_at_jalr() {
+    // auipc x6, 0; jalr x1, 2(x6)
+    let mut cpu = hart(0x00000317, 0);
+    cpu.load_bytes(RAM_BASE + 4, &i_type(0x67, 1, 0, 6, 2).to_le_bytes());
+    cpu.set_fusion(true);
+    cpu.step();
+    assert_eq!(cpu.pc, RAM_BASE + 4);
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.mepc, cpu.mtval), (TVEC, RAM_BASE + 4, RAM_BASE + 2));
+    assert_eq!(cpu.mcause, Exception::InsnMisaligned as u64);
+    assert_eq!((cpu.reg(1), cpu.reg(6)), (0, RAM_BASE));
+}
+
+struct Stages(std::rc::Rc<std::cell::RefCell<Vec<String>>>);
+
+impl Observer for Stages {
+    fn fetched(&mut self, pc: u64, bits: u32) {
+        self.0.borrow_mut().push(format!("fetch {:#x} {:#010x}", pc, bits));
+    }
+
+    fn decoded(&mut self, _pc: u64, insn: &Instruction) {
+        let name = format!("{:?}", insn);
+        self.0.borrow_mut().push(format!("decode {}", &name[..name.find('(').unwrap_or(name.len())]));
+    }
+
+    fn executed(&mut self, pc: u64, _insn: &Instruction, next_pc: u64) {
+        self.0.borrow_mut().push(format!("execute {:#x} -> {:#x}", pc, next_pc));
+    }
+}
+
+#[test]
+fn observer_sees_every_stage() {
+    // lui x3, 1; addi x3, x3, 1, a pair that would fuse; ebreak
+    let mut cpu = hart(0x000011b7, 0);
+    cpu.load_bytes(RAM_BASE + 4, &i_type(0x13, 3, 0, 3, 1).to_le_bytes());
+    cpu.load_bytes(RAM_BASE + 8, &0x00100073u32.to_le_bytes());
+    cpu.set_fusion(true);
+    cpu.mtvec = 0x100;
+    let log = std::rc::Rc::default();
+    cpu.set_observer(Some(Box::new(Stages(std::rc::Rc::clone(&log)))));
+    for _ in 0..3 {
+        cpu.step();
+    }
+    // a fetch fault at the vector is not an instruction
+    cpu.step();
+    assert_eq!(cpu.mcause, Exception::InsnAccess as u64);
+
+    assert_eq!(cpu.reg(3), 0x1001);
+    assert_eq!(cpu.take_fused_pc(), None);
+    assert_eq!(*log.borrow(), [
+        "fetch 0x80000000 0x000011b7", "decode Lui", "execute 0x80000000 -> 0x80000004",
+        "fetch 0x80000004 0x00118193", "decode Addi", "execute 0x80000004 -> 0x80000008",
+        "fetch 0x80000008 0x00100073", "decode Ebreak", "execute 0x80000008 -> 0x100",
+    ]);
+}
+
+#[test]
+fn reset_boots_through_the_rom() {
+    let mut cpu = Cpu::new();
+    cpu.set_boot_rom(bootrom::image(DATA, 0, bootrom::FDT_ADDR, &[0xd0, 0x0d, 0xfe, 0xed]));
+    cpu.reset();
+    assert_eq!(cpu.pc, BOOTROM_BASE);
+    for _ in 0..5 {
+        cpu.step();
+    }
+    assert_eq!((cpu.pc, cpu.reg(10), cpu.reg(11)), (DATA, 0, bootrom::FDT_ADDR));
+    assert_eq!(cpu.debug_read_u32(bootrom::FDT_ADDR), Some(0xedfe0dd0));
+
+    // sd x2, 0(x1) into the ROM
+    assert_faults(hart(s_type(3, 1, 2, 0), bootrom::FDT_ADDR), Exception::StoreAccess, bootrom::FDT_ADDR);
+}
+
+// Maps the 4 KiB page at va to pa through a three-level Sv39 table at
+// RAM_BASE + 0x10000 and points satp at it.
+fn map_page(cpu: &mut Cpu, va: u64, pa: u64, flags: u64) {
+    let tables = [RAM_BASE + 0x10000, RAM_BASE + 0x11000, RAM_BASE + 0x12000];
+    for level in 0..2 {
+        let pte = (tables[level + 1] >> 12) << 10 | 1;
+        cpu.load_bytes(tables[level] + (va >> (30 - 9 * level) & 0x1ff) * 8, &pte.to_le_bytes());
+    }
+    let leaf = (pa >> 12) << 10 | flags | 1;
+    cpu.load_bytes(tables[2] + (va >> 12 & 0x1ff) * 8, &leaf.to_le_bytes());
+    cpu.satp = 8 << 60 | tables[0] >> 12;
+}
+
+const VA: u64 = 0x4000_5000;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_A: u64 = 1 << 6;
+// loads and stores at S, MPP = 1
+const MPRV_S: u64 = MSTATUS_MPRV | (MSTATUS_MPP / 3);
+
+#[test]
+fn mprv_loads_translate_at_mpp() {
+    let mut cpu = hart(i_type(0x03, 3, 3, 1, 0), VA);
+    map_page(&mut cpu, VA, DATA, PTE_R);
+    cpu.mstatus = MPRV_S;
+    cpu.step();
+    assert_eq!(cpu.reg(3), 0x1122_3344_5566_7788);
+    // the walk set A in the leaf
+    let leaf = cpu.debug_read_u64(RAM_BASE + 0x12000 + (VA >> 12 & 0x1ff) * 8).unwrap();
+    assert_ne!(leaf & PTE_A, 0);
+}
+
+#[test]
+fn store_to_read_only_page_faults() {
+    let mut cpu = hart(s_type(3, 1, 2, 0), VA);
+    map_page(&mut cpu, VA, DATA, PTE_R);
+    cpu.mstatus = MPRV_S;
+    assert_faults(cpu, Exception::StorePageFault, VA);
+}
+
+#[test]
+fn unmapped_and_non_canonical_loads_fault() {
+    for addr in [VA + 0x1000, 1 << 39] {
+        let mut cpu = hart(i_type(0x03, 3, 3, 1, 0), addr);
+        map_page(&mut cpu, VA, DATA, PTE_R);
+        cpu.mstatus = MPRV_S;
+        assert_faults(cpu, Exception::LoadPageFault, addr);
+    }
+}
+
+// The reservation is physical: a debugger write to the frame behind the
+// reserved page breaks it.
+#[test]
+fn reservation_follows_the_physical_address() {
+    // lr.d x3, (x1); sc.d x3, x2, (x1)
+    let mut cpu = hart(amo(0x02, 3, 3, 1, 0), VA + 8);
+    cpu.load_bytes(RAM_BASE + 4, &amo(0x03, 3, 3, 1, 2).to_le_bytes());
+    map_page(&mut cpu, VA, DATA, PTE_R | PTE_W);
+    cpu.mstatus = MPRV_S;
+    cpu.step();
+    assert_eq!(cpu.reservation, Some(DATA));
+
+    cpu.debug_write_mem(DATA + 8, &[0; 8]);
+    assert_eq!(cpu.reservation, None);
+    cpu.step();
+    assert_eq!(cpu.reg(3), 1);
+}
+
+#[test]
+fn m_mode_ignores_satp() {
+    let mut cpu = hart(i_type(0x03, 3, 3, 1, 0), DATA);
+    map_page(&mut cpu, VA, DATA, PTE_R);
+    cpu.step();
+    assert_eq!(cpu.reg(3), 0x1122_3344_5566_7788);
+}
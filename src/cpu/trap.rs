@@ -0,0 +1,182 @@
+use super::*;
+
+const CAUSE_INTERRUPT: u64 = 1 << 63;
+
+// Synchronous exceptions, numbered by their cause.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Exception {
+    InsnMisaligned = 0,
+    InsnAccess = 1,
+    IllegalInsn = 2,
+    Breakpoint = 3,
+    LoadMisaligned = 4,
+    LoadAccess = 5,
+    StoreMisaligned = 6,
+    StoreAccess = 7,
+    EcallU = 8,
+    EcallS = 9,
+    EcallM = 11,
+    InsnPageFault = 12,
+    LoadPageFault = 13,
+    StorePageFault = 15,
+}
+
+const EXCEPTIONS: [Exception; 14] = [
+    Exception::InsnMisaligned, Exception::InsnAccess, Exception::IllegalInsn, Exception::Breakpoint,
+    Exception::LoadMisaligned, Exception::LoadAccess, Exception::StoreMisaligned, Exception::StoreAccess,
+    Exception::EcallU, Exception::EcallS, Exception::EcallM, Exception::InsnPageFault,
+    Exception::LoadPageFault, Exception::StorePageFault,
+];
+
+impl Exception {
+    pub fn from_cause(cause: u64) -> Option<Self> {
+        EXCEPTIONS.into_iter().find(|e| *e as u64 == cause)
+    }
+}
+
+// Why a memory access failed, raised as the exception of its access class
+// with the virtual address in xtval.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemFault {
+    Misaligned,
+    // No memory or device at the physical address.
+    Access,
+    // Translation found no valid leaf or the leaf does not permit the access.
+    Page,
+}
+
+impl MemFault {
+    pub fn exception(self, access: Access) -> Exception {
+        match (self, access) {
+            (MemFault::Misaligned, Access::Fetch) => Exception::InsnMisaligned,
+            (MemFault::Misaligned, Access::Load) => Exception::LoadMisaligned,
+            (MemFault::Misaligned, Access::Store) => Exception::StoreMisaligned,
+            (MemFault::Access, Access::Fetch) => Exception::InsnAccess,
+            (MemFault::Access, Access::Load) => Exception::LoadAccess,
+            (MemFault::Access, Access::Store) => Exception::StoreAccess,
+            (MemFault::Page, Access::Fetch) => Exception::InsnPageFault,
+            (MemFault::Page, Access::Load) => Exception::LoadPageFault,
+            (MemFault::Page, Access::Store) => Exception::StorePageFault,
+        }
+    }
+}
+
+// A trap as the handler sees it: an exception with its tval, or an
+// interrupt by number.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trap {
+    Exception(Exception, u64),
+    Interrupt(u64),
+}
+
+impl Trap {
+    pub fn cause(self) -> u64 {
+        match self {
+            Trap::Exception(e, _) => e as u64,
+            Trap::Interrupt(irq) => CAUSE_INTERRUPT | irq,
+        }
+    }
+
+    fn tval(self) -> u64 {
+        match self {
+            Trap::Exception(_, tval) => tval,
+            Trap::Interrupt(_) => 0,
+        }
+    }
+}
+
+// The handler address, vectored mode only applies to interrupts.
+fn vector(tvec: u64, trap: Trap) -> u64 {
+    match trap {
+        Trap::Interrupt(irq) if tvec & 1 == 1 => (tvec & !3) + 4 * irq,
+        _ => tvec & !3,
+    }
+}
+
+impl Cpu {
+    // Whether medeleg/mideleg hand the trap to S-mode. Traps taken in M-mode
+    // are never delegated.
+    fn delegated(&self, trap: Trap) -> bool {
+        let deleg = match trap {
+            Trap::Exception(..) => self.medeleg,
+            Trap::Interrupt(_) => self.mideleg,
+        };
+        self.privl <= 1 && deleg >> (trap.cause() & !CAUSE_INTERRUPT) & 1 != 0
+    }
+
+    // Enters the trap handler with the current pc as xepc, in S-mode when
+    // the trap is delegated and in M-mode otherwise.
+    pub fn take_trap(&mut self, trap: Trap) {
+        let (cause, tval) = match trap {
+            Trap::Exception(Exception::IllegalInsn, _) if self.fuzz.illegal_tval_zero => (trap.cause(), 0),
+            _ => (trap.cause(), trap.tval()),
+        };
+        self.trap_event = Some(TrapEvent { cause, tval, epc: self.pc });
+        self.reservation = None;
+
+        if self.delegated(trap) {
+            self.sepc = self.pc;
+            self.scause = cause;
+            self.stval = tval;
+
+            self.mstatus = mstatus::trap_to_s(self.mstatus, self.privl);
+            self.privl = 1;
+            self.pages.flush();
+            self.pc = vector(self.stvec, trap);
+            return;
+        }
+
+        self.mepc = self.pc;
+        self.mcause = cause;
+        self.mtval = tval;
+
+        self.mstatus = mstatus::trap_to_m(self.mstatus, self.privl);
+        self.privl = 3;
+        self.pages.flush();
+
+        self.pc = vector(self.mtvec, trap);
+    }
+
+    // A synchronous exception of the instruction at pc, which then does not
+    // retire. Arms raise it before touching architectural state.
+    pub(super) fn raise(&mut self, exception: Exception, tval: u64) {
+        self.instret -= 1;
+        self.take_trap(Trap::Exception(exception, tval));
+    }
+
+    // The instruction at pc is illegal, mtval holds the bits step() fetched
+    // for it, from RAM or the ROM.
+    pub(super) fn illegal_insn(&mut self) {
+        self.raise(Exception::IllegalInsn, self.insn_bits as u64);
+    }
+
+    // There is no C, so a jump or taken branch to a target that is not 4-byte
+    // aligned traps at the jump, before rd is written.
+    pub(super) fn misaligned_target(&mut self, target: u64) -> bool {
+        if target.is_multiple_of(4) {
+            return false;
+        }
+        self.raise(Exception::InsnMisaligned, target);
+        true
+    }
+
+    // A load, store or AMO at addr faulted, AMOs fault as stores.
+    pub(super) fn mem_fault(&mut self, access: Access, fault: MemFault, addr: u64) {
+        self.raise(fault.exception(access), addr);
+    }
+
+    // AMOs check the whole access before reading, so a faulting one leaves
+    // memory and rd untouched. They raise store/AMO exceptions only.
+    pub(super) fn amo_fault(&mut self, addr: u64, size: u64) -> bool {
+        let fault = if !addr.is_multiple_of(size) {
+            Err(MemFault::Misaligned)
+        } else {
+            self.ram_offset(Access::Load, addr).and_then(|_| self.ram_offset(Access::Store, addr))
+        };
+        let Err(fault) = fault else {
+            return false;
+        };
+        self.mem_fault(Access::Store, fault, addr);
+        true
+    }
+}
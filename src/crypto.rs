@@ -0,0 +1,162 @@
+// Helpers for the scalar cryptography instructions (Zkne, Zknd, Zknh) on
+// RV64. AES operates on half of the 128-bit state at a time: rs1 holds
+// columns 0 and 1, rs2 columns 2 and 3, with byte 0 of a column its row 0.
+
+const SBOX: [u8; 256] = sbox();
+const INV_SBOX: [u8; 256] = inv_sbox();
+
+// Multiplication in GF(2^8) with the AES polynomial x^8+x^4+x^3+x+1.
+const fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut res = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            res ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1b } else { 0 };
+        b >>= 1;
+    }
+    res
+}
+
+// The multiplicative inverse followed by the affine transformation.
+const fn sbox() -> [u8; 256] {
+    let mut table = [0; 256];
+    let mut x = 0;
+    while x < 256 {
+        let mut inv = 0;
+        if x != 0 {
+            // x^254 is the inverse
+            let (mut base, mut exp, mut acc) = (x as u8, 254, 1u8);
+            while exp != 0 {
+                if exp & 1 != 0 {
+                    acc = gf_mul(acc, base);
+                }
+                base = gf_mul(base, base);
+                exp >>= 1;
+            }
+            inv = acc;
+        }
+        table[x] = inv ^ inv.rotate_left(1) ^ inv.rotate_left(2) ^ inv.rotate_left(3)
+            ^ inv.rotate_left(4) ^ 0x63;
+        x += 1;
+    }
+    table
+}
+
+const fn inv_sbox() -> [u8; 256] {
+    let fwd = sbox();
+    let mut table = [0; 256];
+    let mut x = 0;
+    while x < 256 {
+        table[fwd[x] as usize] = x as u8;
+        x += 1;
+    }
+    table
+}
+
+fn sub_bytes(val: u64, table: &[u8; 256]) -> u64 {
+    u64::from_le_bytes(val.to_le_bytes().map(|b| table[b as usize]))
+}
+
+// The low half of ShiftRows (or its inverse) on the state rs2:rs1, row r
+// rotates by r columns.
+fn shift_rows(rs1: u64, rs2: u64, inverse: bool) -> u64 {
+    let state = ((rs2 as u128) << 64 | rs1 as u128).to_le_bytes();
+    let mut out = [0; 8];
+    for (i, byte) in out.iter_mut().enumerate() {
+        let (col, row) = (i / 4, i % 4);
+        let from = if inverse { (col + 4 - row) % 4 } else { (col + row) % 4 };
+        *byte = state[from * 4 + row];
+    }
+    u64::from_le_bytes(out)
+}
+
+// MixColumns applies the circulant matrix of coeffs to every column.
+fn mix_columns(val: u64, coeffs: [u8; 4]) -> u64 {
+    let mut out = [0; 8];
+    for (col, bytes) in val.to_le_bytes().chunks(4).enumerate() {
+        for row in 0..4 {
+            out[col * 4 + row] = (0..4).fold(0, |acc, k| acc ^ gf_mul(coeffs[(k + 4 - row) % 4], bytes[k]));
+        }
+    }
+    u64::from_le_bytes(out)
+}
+
+const MIX: [u8; 4] = [2, 3, 1, 1];
+const INV_MIX: [u8; 4] = [14, 11, 13, 9];
+
+pub fn aes64es(rs1: u64, rs2: u64) -> u64 {
+    sub_bytes(shift_rows(rs1, rs2, false), &SBOX)
+}
+
+pub fn aes64esm(rs1: u64, rs2: u64) -> u64 {
+    mix_columns(aes64es(rs1, rs2), MIX)
+}
+
+pub fn aes64ds(rs1: u64, rs2: u64) -> u64 {
+    sub_bytes(shift_rows(rs1, rs2, true), &INV_SBOX)
+}
+
+pub fn aes64dsm(rs1: u64, rs2: u64) -> u64 {
+    mix_columns(aes64ds(rs1, rs2), INV_MIX)
+}
+
+pub fn aes64im(rs1: u64) -> u64 {
+    mix_columns(rs1, INV_MIX)
+}
+
+// Round numbers above 0xA are reserved, 0xA is the AES-256 step without
+// rotation or round constant.
+pub fn aes64ks1i(rs1: u64, rnum: u32) -> u64 {
+    const RCON: [u32; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+    let word = (rs1 >> 32) as u32;
+    let (word, rcon) = match rnum {
+        0xA => (word, 0),
+        _ => (word.rotate_right(8), RCON[rnum as usize]),
+    };
+    let word = u32::from_le_bytes(word.to_le_bytes().map(|b| SBOX[b as usize])) ^ rcon;
+    (word as u64) << 32 | word as u64
+}
+
+pub fn aes64ks2(rs1: u64, rs2: u64) -> u64 {
+    let w0 = (rs1 >> 32) as u32 ^ rs2 as u32;
+    let w1 = w0 ^ (rs2 >> 32) as u32;
+    (w1 as u64) << 32 | w0 as u64
+}
+
+// The SHA-256 functions work on the low word and sign-extend the result.
+pub fn sha256sig0(x: u64) -> u64 {
+    let x = x as u32;
+    (x.rotate_right(7) ^ x.rotate_right(18) ^ (x >> 3)) as i32 as i64 as u64
+}
+
+pub fn sha256sig1(x: u64) -> u64 {
+    let x = x as u32;
+    (x.rotate_right(17) ^ x.rotate_right(19) ^ (x >> 10)) as i32 as i64 as u64
+}
+
+pub fn sha256sum0(x: u64) -> u64 {
+    let x = x as u32;
+    (x.rotate_right(2) ^ x.rotate_right(13) ^ x.rotate_right(22)) as i32 as i64 as u64
+}
+
+pub fn sha256sum1(x: u64) -> u64 {
+    let x = x as u32;
+    (x.rotate_right(6) ^ x.rotate_right(11) ^ x.rotate_right(25)) as i32 as i64 as u64
+}
+
+pub fn sha512sig0(x: u64) -> u64 {
+    x.rotate_right(1) ^ x.rotate_right(8) ^ (x >> 7)
+}
+
+pub fn sha512sig1(x: u64) -> u64 {
+    x.rotate_right(19) ^ x.rotate_right(61) ^ (x >> 6)
+}
+
+pub fn sha512sum0(x: u64) -> u64 {
+    x.rotate_right(28) ^ x.rotate_right(34) ^ x.rotate_right(39)
+}
+
+pub fn sha512sum1(x: u64) -> u64 {
+    x.rotate_right(14) ^ x.rotate_right(18) ^ x.rotate_right(41)
+}
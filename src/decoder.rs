@@ -1,5 +1,10 @@
+#[cfg(test)]
+mod tests;
+
 #[derive(Debug, Copy, Clone)]
 pub struct RType {
+    // The opcode is decoded for Debug output, dispatch is by variant.
+    #[allow(dead_code)]
     pub opcode: u8,
     pub rd: u8,
     pub funct3: u8,
@@ -28,9 +33,51 @@ impl From<u32> for RType {
     }
 }
 
+// Atomics keep the aq/rl ordering bits that RType folds into funct7.
+#[derive(Debug, Copy, Clone)]
+pub struct AType {
+    #[allow(dead_code)]
+    pub opcode: u8,
+    pub rd: u8,
+    pub funct3: u8,
+    pub rs1: u8,
+    pub rs2: u8,
+    pub funct5: u8,
+    // Every access is sequentially consistent, the bits only name the
+    // instruction.
+    #[allow(dead_code)]
+    pub aq: bool,
+    #[allow(dead_code)]
+    pub rl: bool,
+}
+
+impl From<u32> for AType {
+    fn from(instruction: u32) -> Self {
+        let opcode = (instruction & 0x7F) as u8;
+        let rd = ((instruction >> 7) & 0x1F) as u8;
+        let funct3 = ((instruction >> 12) & 0x07) as u8;
+        let rs1 = ((instruction >> 15) & 0x1F) as u8;
+        let rs2 = ((instruction >> 20) & 0x1F) as u8;
+        let funct5 = (instruction >> 27) as u8;
+        let aq = instruction & (1 << 26) != 0;
+        let rl = instruction & (1 << 25) != 0;
+
+        Self {
+            opcode,
+            rd,
+            funct3,
+            rs1,
+            rs2,
+            funct5,
+            aq,
+            rl,
+        }
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 pub struct IType {
+    #[allow(dead_code)]
     pub opcode: u8,
     pub rd: u8,
     pub funct3: u8,
@@ -56,8 +103,43 @@ impl From<u32> for IType {
     }
 }
 
+// Shifts by an immediate split imm[11:0] into funct6 and a 6-bit shamt. The
+// word shifts take a 5-bit shamt, shamt[5] set is reserved for them.
+#[derive(Debug, Copy, Clone)]
+pub struct ShiftType {
+    #[allow(dead_code)]
+    pub opcode: u8,
+    pub rd: u8,
+    #[allow(dead_code)]
+    pub funct3: u8,
+    pub rs1: u8,
+    pub shamt: u8,
+    pub funct6: u8,
+}
+
+impl From<u32> for ShiftType {
+    fn from(instruction: u32) -> Self {
+        let opcode = (instruction & 0x7F) as u8;
+        let rd = ((instruction >> 7) & 0x1F) as u8;
+        let funct3 = ((instruction >> 12) & 0x07) as u8;
+        let rs1 = ((instruction >> 15) & 0x1F) as u8;
+        let shamt = ((instruction >> 20) & 0x3F) as u8;
+        let funct6 = (instruction >> 26) as u8;
+
+        Self {
+            opcode,
+            rd,
+            funct3,
+            rs1,
+            shamt,
+            funct6,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct SType {
+    #[allow(dead_code)]
     pub opcode: u8,
     pub funct3: u8,
     pub rs1: u8,
@@ -87,6 +169,7 @@ impl From<u32> for SType {
 
 #[derive(Debug, Copy, Clone)]
 pub struct BType {
+    #[allow(dead_code)]
     pub opcode: u8,
     pub funct3: u8,
     pub rs1: u8,
@@ -104,7 +187,7 @@ impl From<u32> for BType {
         let imm = decode_b_imm(instruction);
 
         Self {
-            opcode: opcode as u8,
+            opcode,
             funct3,
             rs1,
             rs2,
@@ -115,6 +198,7 @@ impl From<u32> for BType {
 
 #[derive(Debug, Copy, Clone)]
 pub struct UType {
+    #[allow(dead_code)]
     pub opcode: u8,
     pub rd: u8,
     pub imm: i32,
@@ -135,6 +219,7 @@ impl From<u32> for UType {
 
 #[derive(Debug, Copy, Clone)]
 pub struct JType {
+    #[allow(dead_code)]
     pub opcode: u8,
     pub rd: u8,
     pub imm: i32,
@@ -148,7 +233,77 @@ impl From<u32> for JType {
         Self {
             opcode: opcode as u8,
             rd,
-            imm: decode_i_imm(instruction),
+            imm: decode_j_imm(instruction),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct R4Type {
+    #[allow(dead_code)]
+    pub opcode: u8,
+    pub rd: u8,
+    pub funct3: u8,
+    pub rs1: u8,
+    pub rs2: u8,
+    pub rs3: u8,
+    pub fmt: u8,
+}
+
+impl From<u32> for R4Type {
+    fn from(instruction: u32) -> Self {
+        let opcode = (instruction & 0x7F) as u8;
+        let rd = ((instruction >> 7) & 0x1F) as u8;
+        let funct3 = ((instruction >> 12) & 0x07) as u8;
+        let rs1 = ((instruction >> 15) & 0x1F) as u8;
+        let rs2 = ((instruction >> 20) & 0x1F) as u8;
+        let fmt = ((instruction >> 25) & 0x03) as u8;
+        let rs3 = (instruction >> 27) as u8;
+
+        Self {
+            opcode,
+            rd,
+            funct3,
+            rs1,
+            rs2,
+            rs3,
+            fmt,
+        }
+    }
+}
+
+// Vector loads, stores and arithmetic. The fields are kept raw as their
+// meaning depends on the instruction, vector.rs dispatches on them.
+#[derive(Debug, Copy, Clone)]
+pub struct VType {
+    #[allow(dead_code)]
+    pub opcode: u8,
+    pub vd: u8,     // vs3 of stores
+    pub funct3: u8, // width of loads and stores, operand category of arithmetic
+    pub rs1: u8,    // rs1, vs1 or a 5-bit immediate
+    pub vs2: u8,    // vs2, rs2 (stride) or lumop/sumop
+    pub vm: bool,   // unmasked
+    pub funct6: u8, // nf, mew and mop of loads and stores
+}
+
+impl From<u32> for VType {
+    fn from(instruction: u32) -> Self {
+        let opcode = (instruction & 0x7F) as u8;
+        let vd = ((instruction >> 7) & 0x1F) as u8;
+        let funct3 = ((instruction >> 12) & 0x07) as u8;
+        let rs1 = ((instruction >> 15) & 0x1F) as u8;
+        let vs2 = ((instruction >> 20) & 0x1F) as u8;
+        let vm = instruction & (1 << 25) != 0;
+        let funct6 = (instruction >> 26) as u8;
+
+        Self {
+            opcode,
+            vd,
+            funct3,
+            rs1,
+            vs2,
+            vm,
+            funct6,
         }
     }
 }
@@ -160,25 +315,34 @@ pub enum Instruction {
 
     // Immediates
     Addi(IType),
-    Slli(IType),
+    Slli(ShiftType),
     Slti(IType),
     Sltiu(IType),
-    Srai(IType),
-    Srli(IType),
+    Srai(ShiftType),
+    Srli(ShiftType),
     Xori(IType),
     Ori(IType),
     Andi(IType),
 
     Addiw(IType),
+    Slliw(ShiftType),
+    Srliw(ShiftType),
+    Sraiw(ShiftType),
 
     // CSR
     Csrrw(IType),
     Csrrs(IType),
     Csrrc(IType),
+    Csrrwi(IType),
+    Csrrsi(IType),
+    Csrrci(IType),
 
-    Mret(IType),
-    Sret(IType),
-    Wfi(IType),
+    Ecall(#[allow(dead_code)] IType),
+    Ebreak(#[allow(dead_code)] IType),
+    Mret(#[allow(dead_code)] IType),
+    Sret(#[allow(dead_code)] IType),
+    Wfi(#[allow(dead_code)] IType),
+    SfenceVma(#[allow(dead_code)] IType),
 
     // OP
     Add(RType),
@@ -192,6 +356,13 @@ pub enum Instruction {
     Or(RType),
     And(RType),
 
+    // OP-32
+    Addw(RType),
+    Subw(RType),
+    Sllw(RType),
+    Srlw(RType),
+    Sraw(RType),
+
     // Load/Store
     Load(IType),
     Store(SType),
@@ -199,10 +370,91 @@ pub enum Instruction {
     // M
     Mul(RType),
     Mulh(RType),
+    Mulhsu(RType),
+    Mulhu(RType),
     Div(RType),
     Divu(RType),
     Rem(RType),
     Remu(RType),
+    Mulw(RType),
+    Divw(RType),
+    Divuw(RType),
+    Remw(RType),
+    Remuw(RType),
+
+    // Zba
+    Sh1add(RType),
+    Sh2add(RType),
+    Sh3add(RType),
+    AddUw(RType),
+    Sh1addUw(RType),
+    Sh2addUw(RType),
+    Sh3addUw(RType),
+    SlliUw(IType),
+
+    // Zbb
+    Andn(RType),
+    Orn(RType),
+    Xnor(RType),
+    Clz(IType),
+    Clzw(IType),
+    Ctz(IType),
+    Ctzw(IType),
+    Cpop(IType),
+    Cpopw(IType),
+    Max(RType),
+    Maxu(RType),
+    Min(RType),
+    Minu(RType),
+    SextB(IType),
+    SextH(IType),
+    ZextH(RType),
+    Rol(RType),
+    Rolw(RType),
+    Ror(RType),
+    Rori(IType),
+    Roriw(IType),
+    Rorw(RType),
+    OrcB(IType),
+    Rev8(IType),
+
+    // Zbc
+    Clmul(RType),
+    Clmulh(RType),
+    Clmulr(RType),
+
+    // Zkne, Zknd
+    Aes64es(RType),
+    Aes64esm(RType),
+    Aes64ds(RType),
+    Aes64dsm(RType),
+    Aes64im(IType),
+    Aes64ks1i(IType),
+    Aes64ks2(RType),
+
+    // Zknh
+    Sha256sig0(IType),
+    Sha256sig1(IType),
+    Sha256sum0(IType),
+    Sha256sum1(IType),
+    Sha512sig0(IType),
+    Sha512sig1(IType),
+    Sha512sum0(IType),
+    Sha512sum1(IType),
+
+    // Zbs
+    Bclr(RType),
+    Bclri(IType),
+    Bext(RType),
+    Bexti(IType),
+    Binv(RType),
+    Binvi(IType),
+    Bset(RType),
+    Bseti(IType),
+
+    // Zicond
+    CzeroEqz(RType),
+    CzeroNez(RType),
 
     // Jumps
     Jal(JType),
@@ -217,18 +469,185 @@ pub enum Instruction {
     Bgeu(BType),
 
     // A
-    Amoswapw(RType),
+    LrW(AType),
+    ScW(AType),
+    LrD(AType),
+    ScD(AType),
+    Amoswapw(AType),
+    Amoaddw(AType),
+    Amoxorw(AType),
+    Amoandw(AType),
+    Amoorw(AType),
+    Amominw(AType),
+    Amomaxw(AType),
+    Amominuw(AType),
+    Amomaxuw(AType),
+    Amoswapd(AType),
+    Amoaddd(AType),
+    Amoxord(AType),
+    Amoandd(AType),
+    Amoord(AType),
+    Amomind(AType),
+    Amomaxd(AType),
+    Amominud(AType),
+    Amomaxud(AType),
+
+    // Zacas
+    AmocasW(AType),
+    AmocasD(AType),
+    AmocasQ(AType),
+
+    // Zabha
+    Amoswapb(AType),
+    Amoaddb(AType),
+    Amoxorb(AType),
+    Amoandb(AType),
+    Amoorb(AType),
+    Amominb(AType),
+    Amomaxb(AType),
+    Amominub(AType),
+    Amomaxub(AType),
+    Amoswaph(AType),
+    Amoaddh(AType),
+    Amoxorh(AType),
+    Amoandh(AType),
+    Amoorh(AType),
+    Amominh(AType),
+    Amomaxh(AType),
+    Amominuh(AType),
+    Amomaxuh(AType),
+    AmocasB(AType),
+    AmocasH(AType),
+
+    // F
+    Flw(IType),
+    Fsw(SType),
+    FmaddS(R4Type),
+    FmsubS(R4Type),
+    FnmsubS(R4Type),
+    FnmaddS(R4Type),
+    FaddS(RType),
+    FsubS(RType),
+    FmulS(RType),
+    FdivS(RType),
+    FsqrtS(RType),
+    FsgnjS(RType),
+    FsgnjnS(RType),
+    FsgnjxS(RType),
+    FminS(RType),
+    FmaxS(RType),
+    FcvtWS(RType),
+    FcvtWuS(RType),
+    FcvtLS(RType),
+    FcvtLuS(RType),
+    FcvtSW(RType),
+    FcvtSWu(RType),
+    FcvtSL(RType),
+    FcvtSLu(RType),
+    FmvXW(RType),
+    FmvWX(RType),
+    FeqS(RType),
+    FltS(RType),
+    FleS(RType),
+    FclassS(RType),
+
+    // D
+    Fld(IType),
+    Fsd(SType),
+    FmaddD(R4Type),
+    FmsubD(R4Type),
+    FnmsubD(R4Type),
+    FnmaddD(R4Type),
+    FaddD(RType),
+    FsubD(RType),
+    FmulD(RType),
+    FdivD(RType),
+    FsqrtD(RType),
+    FsgnjD(RType),
+    FsgnjnD(RType),
+    FsgnjxD(RType),
+    FminD(RType),
+    FmaxD(RType),
+    FcvtSD(RType),
+    FcvtDS(RType),
+    FcvtWD(RType),
+    FcvtWuD(RType),
+    FcvtLD(RType),
+    FcvtLuD(RType),
+    FcvtDW(RType),
+    FcvtDWu(RType),
+    FcvtDL(RType),
+    FcvtDLu(RType),
+    FmvXD(RType),
+    FmvDX(RType),
+    FeqD(RType),
+    FltD(RType),
+    FleD(RType),
+    FclassD(RType),
+
+    // Zfh
+    Flh(IType),
+    Fsh(SType),
+    FmaddH(R4Type),
+    FmsubH(R4Type),
+    FnmsubH(R4Type),
+    FnmaddH(R4Type),
+    FaddH(RType),
+    FsubH(RType),
+    FmulH(RType),
+    FdivH(RType),
+    FsqrtH(RType),
+    FsgnjH(RType),
+    FsgnjnH(RType),
+    FsgnjxH(RType),
+    FminH(RType),
+    FmaxH(RType),
+    FcvtSH(RType),
+    FcvtHS(RType),
+    FcvtDH(RType),
+    FcvtHD(RType),
+    FcvtWH(RType),
+    FcvtWuH(RType),
+    FcvtLH(RType),
+    FcvtLuH(RType),
+    FcvtHW(RType),
+    FcvtHWu(RType),
+    FcvtHL(RType),
+    FcvtHLu(RType),
+    FmvXH(RType),
+    FmvHX(RType),
+    FeqH(RType),
+    FltH(RType),
+    FleH(RType),
+    FclassH(RType),
 
     Fence,
+    FenceI,
+    Pause,
+    Hint,
+
+    // Zicbom/Zicboz, rs1 holds the address
+    CboClean(#[allow(dead_code)] IType),
+    CboFlush(#[allow(dead_code)] IType),
+    CboInval(#[allow(dead_code)] IType),
+    CboZero(IType),
+
+    // V, vsetivli keeps the AVL immediate in rs1
+    Vsetvli(IType),
+    Vsetivli(IType),
+    Vsetvl(RType),
+    Vload(VType),
+    Vstore(VType),
+    Varith(VType),
 
     Invalid(u32),
 }
 
 fn decode_u_imm(instruction: u32) -> i32 {
-    (instruction & 0xfffff800) as i32
+    (instruction & 0xfffff000) as i32
 }
 
-fn decode_i_imm(instruction: u32) -> i32 {
+fn decode_j_imm(instruction: u32) -> i32 {
     let insn = instruction as i32;
 
     ((insn & 0x80000000u32 as i32) >> 11)
@@ -250,50 +669,254 @@ fn decode_b_imm(instruction: u32) -> i32 {
 }
 
 impl Instruction {
+    // The extension an instruction belongs to, as named in ISA strings.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Instruction::Mul(_) | Instruction::Mulh(_) | Instruction::Mulhsu(_) |
+            Instruction::Mulhu(_) | Instruction::Div(_) | Instruction::Divu(_) |
+            Instruction::Rem(_) | Instruction::Remu(_) | Instruction::Mulw(_) |
+            Instruction::Divw(_) | Instruction::Divuw(_) | Instruction::Remw(_) |
+            Instruction::Remuw(_) => "m",
+            Instruction::LrW(_) | Instruction::ScW(_) | Instruction::LrD(_) | Instruction::ScD(_) |
+            Instruction::Amoswapw(_) | Instruction::Amoaddw(_) | Instruction::Amoxorw(_) |
+            Instruction::Amoandw(_) | Instruction::Amoorw(_) | Instruction::Amominw(_) |
+            Instruction::Amomaxw(_) | Instruction::Amominuw(_) | Instruction::Amomaxuw(_) |
+            Instruction::Amoswapd(_) | Instruction::Amoaddd(_) | Instruction::Amoxord(_) |
+            Instruction::Amoandd(_) | Instruction::Amoord(_) | Instruction::Amomind(_) |
+            Instruction::Amomaxd(_) | Instruction::Amominud(_) | Instruction::Amomaxud(_) => "a",
+            Instruction::AmocasW(_) | Instruction::AmocasD(_) | Instruction::AmocasQ(_) => "zacas",
+            Instruction::Amoswapb(_) | Instruction::Amoaddb(_) | Instruction::Amoxorb(_) |
+            Instruction::Amoandb(_) | Instruction::Amoorb(_) | Instruction::Amominb(_) |
+            Instruction::Amomaxb(_) | Instruction::Amominub(_) | Instruction::Amomaxub(_) |
+            Instruction::Amoswaph(_) | Instruction::Amoaddh(_) | Instruction::Amoxorh(_) |
+            Instruction::Amoandh(_) | Instruction::Amoorh(_) | Instruction::Amominh(_) |
+            Instruction::Amomaxh(_) | Instruction::Amominuh(_) | Instruction::Amomaxuh(_) |
+            Instruction::AmocasB(_) | Instruction::AmocasH(_) => "zabha",
+            Instruction::Flw(_) | Instruction::Fsw(_) | Instruction::FmaddS(_) |
+            Instruction::FmsubS(_) | Instruction::FnmsubS(_) | Instruction::FnmaddS(_) |
+            Instruction::FaddS(_) | Instruction::FsubS(_) | Instruction::FmulS(_) |
+            Instruction::FdivS(_) | Instruction::FsqrtS(_) | Instruction::FsgnjS(_) |
+            Instruction::FsgnjnS(_) | Instruction::FsgnjxS(_) | Instruction::FminS(_) |
+            Instruction::FmaxS(_) | Instruction::FcvtWS(_) | Instruction::FcvtWuS(_) |
+            Instruction::FcvtLS(_) | Instruction::FcvtLuS(_) | Instruction::FcvtSW(_) |
+            Instruction::FcvtSWu(_) | Instruction::FcvtSL(_) | Instruction::FcvtSLu(_) |
+            Instruction::FmvXW(_) | Instruction::FmvWX(_) | Instruction::FeqS(_) |
+            Instruction::FltS(_) | Instruction::FleS(_) | Instruction::FclassS(_) => "f",
+            Instruction::Fld(_) | Instruction::Fsd(_) | Instruction::FmaddD(_) |
+            Instruction::FmsubD(_) | Instruction::FnmsubD(_) | Instruction::FnmaddD(_) |
+            Instruction::FaddD(_) | Instruction::FsubD(_) | Instruction::FmulD(_) |
+            Instruction::FdivD(_) | Instruction::FsqrtD(_) | Instruction::FsgnjD(_) |
+            Instruction::FsgnjnD(_) | Instruction::FsgnjxD(_) | Instruction::FminD(_) |
+            Instruction::FmaxD(_) | Instruction::FcvtSD(_) | Instruction::FcvtDS(_) |
+            Instruction::FcvtWD(_) | Instruction::FcvtWuD(_) | Instruction::FcvtLD(_) |
+            Instruction::FcvtLuD(_) | Instruction::FcvtDW(_) | Instruction::FcvtDWu(_) |
+            Instruction::FcvtDL(_) | Instruction::FcvtDLu(_) | Instruction::FmvXD(_) |
+            Instruction::FmvDX(_) | Instruction::FeqD(_) | Instruction::FltD(_) |
+            Instruction::FleD(_) | Instruction::FclassD(_) => "d",
+            Instruction::Flh(_) | Instruction::Fsh(_) | Instruction::FmaddH(_) |
+            Instruction::FmsubH(_) | Instruction::FnmsubH(_) | Instruction::FnmaddH(_) |
+            Instruction::FaddH(_) | Instruction::FsubH(_) | Instruction::FmulH(_) |
+            Instruction::FdivH(_) | Instruction::FsqrtH(_) | Instruction::FsgnjH(_) |
+            Instruction::FsgnjnH(_) | Instruction::FsgnjxH(_) | Instruction::FminH(_) |
+            Instruction::FmaxH(_) | Instruction::FcvtSH(_) | Instruction::FcvtHS(_) |
+            Instruction::FcvtDH(_) | Instruction::FcvtHD(_) | Instruction::FcvtWH(_) |
+            Instruction::FcvtWuH(_) | Instruction::FcvtLH(_) | Instruction::FcvtLuH(_) |
+            Instruction::FcvtHW(_) | Instruction::FcvtHWu(_) | Instruction::FcvtHL(_) |
+            Instruction::FcvtHLu(_) | Instruction::FmvXH(_) | Instruction::FmvHX(_) |
+            Instruction::FeqH(_) | Instruction::FltH(_) | Instruction::FleH(_) |
+            Instruction::FclassH(_) => "zfh",
+            Instruction::Vsetvli(_) | Instruction::Vsetivli(_) | Instruction::Vsetvl(_) |
+            Instruction::Vload(_) | Instruction::Vstore(_) | Instruction::Varith(_) => "v",
+            Instruction::Sh1add(_) | Instruction::Sh2add(_) | Instruction::Sh3add(_) |
+            Instruction::AddUw(_) | Instruction::Sh1addUw(_) | Instruction::Sh2addUw(_) |
+            Instruction::Sh3addUw(_) | Instruction::SlliUw(_) => "zba",
+            Instruction::Andn(_) | Instruction::Orn(_) | Instruction::Xnor(_) | Instruction::Clz(_) |
+            Instruction::Clzw(_) | Instruction::Ctz(_) | Instruction::Ctzw(_) | Instruction::Cpop(_) |
+            Instruction::Cpopw(_) | Instruction::Max(_) | Instruction::Maxu(_) | Instruction::Min(_) |
+            Instruction::Minu(_) | Instruction::SextB(_) | Instruction::SextH(_) | Instruction::ZextH(_) |
+            Instruction::Rol(_) | Instruction::Rolw(_) | Instruction::Ror(_) | Instruction::Rori(_) |
+            Instruction::Roriw(_) | Instruction::Rorw(_) | Instruction::OrcB(_) | Instruction::Rev8(_) => "zbb",
+            Instruction::Clmul(_) | Instruction::Clmulh(_) | Instruction::Clmulr(_) => "zbc",
+            // The key schedule is in Zknd too, see Cpu::fetch_and_decode_insn.
+            Instruction::Aes64es(_) | Instruction::Aes64esm(_) | Instruction::Aes64ks1i(_) |
+            Instruction::Aes64ks2(_) => "zkne",
+            Instruction::Aes64ds(_) | Instruction::Aes64dsm(_) | Instruction::Aes64im(_) => "zknd",
+            Instruction::Sha256sig0(_) | Instruction::Sha256sig1(_) | Instruction::Sha256sum0(_) |
+            Instruction::Sha256sum1(_) | Instruction::Sha512sig0(_) | Instruction::Sha512sig1(_) |
+            Instruction::Sha512sum0(_) | Instruction::Sha512sum1(_) => "zknh",
+            Instruction::Bclr(_) | Instruction::Bclri(_) | Instruction::Bext(_) | Instruction::Bexti(_) |
+            Instruction::Binv(_) | Instruction::Binvi(_) | Instruction::Bset(_) | Instruction::Bseti(_) => "zbs",
+            Instruction::CzeroEqz(_) | Instruction::CzeroNez(_) => "zicond",
+            Instruction::CboClean(_) | Instruction::CboFlush(_) | Instruction::CboInval(_) => "zicbom",
+            Instruction::CboZero(_) => "zicboz",
+            _ => "i",
+        }
+    }
+
+    // Integer computations writing x0 are HINTs, among them nop, the
+    // Zihintntl ntl.* (add x0, x0, x2..x5) and Zicbop prefetches (ori x0).
+    pub fn is_hint(&self, instruction: u32) -> bool {
+        let rd = (instruction >> 7) & 0x1F;
+        rd == 0 && matches!(instruction & 0x7F, 0x13 | 0x17 | 0x1B | 0x33 | 0x37 | 0x3B)
+            && !matches!(self, Instruction::Invalid(_))
+    }
+
     pub fn decode(instruction: u32) -> Self {
         let opcode = instruction & 0x7F;
 
         match opcode {
             0x03 => Instruction::Load(IType::from(instruction)),
-            0x0F => Instruction::Fence,
+            0x07 => {
+                let it = IType::from(instruction);
+                match it.funct3 {
+                    1 => Instruction::Flh(it),
+                    2 => Instruction::Flw(it),
+                    3 => Instruction::Fld(it),
+                    0 | 5..=7 => Instruction::Vload(VType::from(instruction)),
+                    _ => Instruction::Invalid(instruction),
+                }
+            }
+            0x0F => {
+                let it = IType::from(instruction);
+                match (it.funct3, it.imm & 0xFFF) {
+                    (2, 0x0) if it.rd == 0 => Instruction::CboInval(it),
+                    (2, 0x1) if it.rd == 0 => Instruction::CboClean(it),
+                    (2, 0x2) if it.rd == 0 => Instruction::CboFlush(it),
+                    (2, 0x4) if it.rd == 0 => Instruction::CboZero(it),
+                    // fence w,0
+                    (0, _) if instruction == 0x0100000F => Instruction::Pause,
+                    (0, _) => Instruction::Fence,
+                    (1, _) => Instruction::FenceI,
+                    _ => Instruction::Invalid(instruction),
+                }
+            }
             0x13 => {
                 let it = IType::from(instruction);
                 match it.funct3 {
                     0 => Instruction::Addi(it),
-                    1 => Instruction::Slli(it),
+                    1 => match it.imm & 0xFFF {
+                        0x600 => Instruction::Clz(it),
+                        0x601 => Instruction::Ctz(it),
+                        0x602 => Instruction::Cpop(it),
+                        0x604 => Instruction::SextB(it),
+                        0x605 => Instruction::SextH(it),
+                        0x100 => Instruction::Sha256sum0(it),
+                        0x101 => Instruction::Sha256sum1(it),
+                        0x102 => Instruction::Sha256sig0(it),
+                        0x103 => Instruction::Sha256sig1(it),
+                        0x104 => Instruction::Sha512sum0(it),
+                        0x105 => Instruction::Sha512sum1(it),
+                        0x106 => Instruction::Sha512sig0(it),
+                        0x107 => Instruction::Sha512sig1(it),
+                        0x300 => Instruction::Aes64im(it),
+                        0x310..=0x31A => Instruction::Aes64ks1i(it),
+                        imm => match imm >> 6 {
+                            0x12 => Instruction::Bclri(it),
+                            0x1A => Instruction::Binvi(it),
+                            0x0A => Instruction::Bseti(it),
+                            0x00 => Instruction::Slli(ShiftType::from(instruction)),
+                            _ => Instruction::Invalid(instruction),
+                        },
+                    },
                     2 => Instruction::Slti(it),
                     3 => Instruction::Sltiu(it),
                     4 => Instruction::Xori(it),
+                    5 if it.imm & 0xFFF == 0x287 => Instruction::OrcB(it),
+                    5 if it.imm & 0xFFF == 0x6B8 => Instruction::Rev8(it),
+                    5 if (it.imm >> 6) & 0x3F == 0x18 => Instruction::Rori(it),
+                    5 if (it.imm >> 6) & 0x3F == 0x12 => Instruction::Bexti(it),
                     5 => {
-                        let imm = it.imm & 0x1F;
-                        let shifttype = it.imm >> 5;
-
-                        if shifttype == 0x20 {
-                            Instruction::Srai(it)
-                        } else {
-                            Instruction::Srli(it)
+                        let st = ShiftType::from(instruction);
+                        match st.funct6 {
+                            0x00 => Instruction::Srli(st),
+                            0x10 => Instruction::Srai(st),
+                            _ => Instruction::Invalid(instruction),
                         }
-                    },
+                    }
                     6 => Instruction::Ori(it),
                     7 => Instruction::Andi(it),
-                    _ => unimplemented!("{:#010X} {:X?}", instruction, it),
+                    _ => Instruction::Invalid(instruction),
                 }
             }
             0x1b => {
                 let it = IType::from(instruction);
-                match it.funct3 {
-                    0 => Instruction::Addiw(it),
-                    _ => unimplemented!("{:#010X} {:X?}", instruction, it),
+                match (it.funct3, it.imm >> 5) {
+                    (0, _) => Instruction::Addiw(it),
+                    (1, 0x00) => Instruction::Slliw(ShiftType::from(instruction)),
+                    (1, 0x04 | 0x05) => Instruction::SlliUw(it),
+                    (1, 0x30) if it.imm & 0x1F == 0 => Instruction::Clzw(it),
+                    (1, 0x30) if it.imm & 0x1F == 1 => Instruction::Ctzw(it),
+                    (1, 0x30) if it.imm & 0x1F == 2 => Instruction::Cpopw(it),
+                    (5, 0x00) => Instruction::Srliw(ShiftType::from(instruction)),
+                    (5, 0x20) => Instruction::Sraiw(ShiftType::from(instruction)),
+                    (5, 0x30) => Instruction::Roriw(it),
+                    _ => Instruction::Invalid(instruction),
                 }
             }
             0x17 => Instruction::Auipc(UType::from(instruction)),
             0x23 => Instruction::Store(SType::from(instruction)),
+            0x27 => {
+                let st = SType::from(instruction);
+                match st.funct3 {
+                    1 => Instruction::Fsh(st),
+                    2 => Instruction::Fsw(st),
+                    3 => Instruction::Fsd(st),
+                    0 | 5..=7 => Instruction::Vstore(VType::from(instruction)),
+                    _ => Instruction::Invalid(instruction),
+                }
+            }
             0x2F => {
-                let rt = RType::from(instruction);
-                
-                match (rt.funct3, rt.funct7 >> 2) {
-                    (2, 1) => Instruction::Amoswapw(rt),
-                    _ => unimplemented!("{:#010X} {:X?}", instruction, rt),
+                let rt = AType::from(instruction);
+
+                match (rt.funct3, rt.funct5) {
+                    (2, 2) if rt.rs2 == 0 => Instruction::LrW(rt),
+                    (2, 3) => Instruction::ScW(rt),
+                    (3, 2) if rt.rs2 == 0 => Instruction::LrD(rt),
+                    (3, 3) => Instruction::ScD(rt),
+                    (2, 0x01) => Instruction::Amoswapw(rt),
+                    (2, 0x00) => Instruction::Amoaddw(rt),
+                    (2, 0x04) => Instruction::Amoxorw(rt),
+                    (2, 0x0C) => Instruction::Amoandw(rt),
+                    (2, 0x08) => Instruction::Amoorw(rt),
+                    (2, 0x10) => Instruction::Amominw(rt),
+                    (2, 0x14) => Instruction::Amomaxw(rt),
+                    (2, 0x18) => Instruction::Amominuw(rt),
+                    (2, 0x1C) => Instruction::Amomaxuw(rt),
+                    (3, 0x01) => Instruction::Amoswapd(rt),
+                    (3, 0x00) => Instruction::Amoaddd(rt),
+                    (3, 0x04) => Instruction::Amoxord(rt),
+                    (3, 0x0C) => Instruction::Amoandd(rt),
+                    (3, 0x08) => Instruction::Amoord(rt),
+                    (3, 0x10) => Instruction::Amomind(rt),
+                    (3, 0x14) => Instruction::Amomaxd(rt),
+                    (3, 0x18) => Instruction::Amominud(rt),
+                    (3, 0x1C) => Instruction::Amomaxud(rt),
+                    (2, 0x05) => Instruction::AmocasW(rt),
+                    (3, 0x05) => Instruction::AmocasD(rt),
+                    // rd and rs2 name even register pairs
+                    (4, 0x05) if rt.rd % 2 == 0 && rt.rs2 % 2 == 0 => Instruction::AmocasQ(rt),
+                    (4, 0x05) => Instruction::Invalid(instruction),
+                    (0, 0x01) => Instruction::Amoswapb(rt),
+                    (0, 0x00) => Instruction::Amoaddb(rt),
+                    (0, 0x04) => Instruction::Amoxorb(rt),
+                    (0, 0x0C) => Instruction::Amoandb(rt),
+                    (0, 0x08) => Instruction::Amoorb(rt),
+                    (0, 0x10) => Instruction::Amominb(rt),
+                    (0, 0x14) => Instruction::Amomaxb(rt),
+                    (0, 0x18) => Instruction::Amominub(rt),
+                    (0, 0x1C) => Instruction::Amomaxub(rt),
+                    (1, 0x01) => Instruction::Amoswaph(rt),
+                    (1, 0x00) => Instruction::Amoaddh(rt),
+                    (1, 0x04) => Instruction::Amoxorh(rt),
+                    (1, 0x0C) => Instruction::Amoandh(rt),
+                    (1, 0x08) => Instruction::Amoorh(rt),
+                    (1, 0x10) => Instruction::Amominh(rt),
+                    (1, 0x14) => Instruction::Amomaxh(rt),
+                    (1, 0x18) => Instruction::Amominuh(rt),
+                    (1, 0x1C) => Instruction::Amomaxuh(rt),
+                    (0, 0x05) => Instruction::AmocasB(rt),
+                    (1, 0x05) => Instruction::AmocasH(rt),
+                    _ => Instruction::Invalid(instruction),
                 }
             }
             0x33 => {
@@ -314,14 +937,190 @@ impl Instruction {
 
                     (0x01, 0x0) => Instruction::Mul(rt),
                     (0x01, 0x1) => Instruction::Mulh(rt),
+                    (0x01, 0x2) => Instruction::Mulhsu(rt),
+                    (0x01, 0x3) => Instruction::Mulhu(rt),
                     (0x01, 0x4) => Instruction::Div(rt),
                     (0x01, 0x5) => Instruction::Divu(rt),
                     (0x01, 0x6) => Instruction::Rem(rt),
                     (0x01, 0x7) => Instruction::Remu(rt),
+
+                    (0x10, 0x2) => Instruction::Sh1add(rt),
+                    (0x10, 0x4) => Instruction::Sh2add(rt),
+                    (0x10, 0x6) => Instruction::Sh3add(rt),
+
+                    (0x20, 0x7) => Instruction::Andn(rt),
+                    (0x20, 0x6) => Instruction::Orn(rt),
+                    (0x20, 0x4) => Instruction::Xnor(rt),
+                    (0x05, 0x4) => Instruction::Min(rt),
+                    (0x05, 0x5) => Instruction::Minu(rt),
+                    (0x05, 0x6) => Instruction::Max(rt),
+                    (0x05, 0x7) => Instruction::Maxu(rt),
+                    (0x30, 0x1) => Instruction::Rol(rt),
+                    (0x30, 0x5) => Instruction::Ror(rt),
+
+                    (0x05, 0x1) => Instruction::Clmul(rt),
+                    (0x05, 0x2) => Instruction::Clmulr(rt),
+                    (0x05, 0x3) => Instruction::Clmulh(rt),
+
+                    (0x19, 0x0) => Instruction::Aes64es(rt),
+                    (0x1B, 0x0) => Instruction::Aes64esm(rt),
+                    (0x1D, 0x0) => Instruction::Aes64ds(rt),
+                    (0x1F, 0x0) => Instruction::Aes64dsm(rt),
+                    (0x3F, 0x0) => Instruction::Aes64ks2(rt),
+
+                    (0x24, 0x1) => Instruction::Bclr(rt),
+                    (0x24, 0x5) => Instruction::Bext(rt),
+                    (0x34, 0x1) => Instruction::Binv(rt),
+                    (0x14, 0x1) => Instruction::Bset(rt),
+
+                    (0x07, 0x5) => Instruction::CzeroEqz(rt),
+                    (0x07, 0x7) => Instruction::CzeroNez(rt),
+                    _ => Instruction::Invalid(instruction),
+                }
+            }
+            0x3B => {
+                let rt = RType::from(instruction);
+
+                match (rt.funct7, rt.funct3) {
+                    (0x00, 0x0) => Instruction::Addw(rt),
+                    (0x00, 0x1) => Instruction::Sllw(rt),
+                    (0x00, 0x5) => Instruction::Srlw(rt),
+
+                    (0x20, 0x0) => Instruction::Subw(rt),
+                    (0x20, 0x5) => Instruction::Sraw(rt),
+
+                    (0x04, 0x0) => Instruction::AddUw(rt),
+                    (0x10, 0x2) => Instruction::Sh1addUw(rt),
+                    (0x10, 0x4) => Instruction::Sh2addUw(rt),
+                    (0x10, 0x6) => Instruction::Sh3addUw(rt),
+
+                    (0x04, 0x4) if rt.rs2 == 0 => Instruction::ZextH(rt),
+                    (0x30, 0x1) => Instruction::Rolw(rt),
+                    (0x30, 0x5) => Instruction::Rorw(rt),
+
+                    (0x01, 0x0) => Instruction::Mulw(rt),
+                    (0x01, 0x4) => Instruction::Divw(rt),
+                    (0x01, 0x5) => Instruction::Divuw(rt),
+                    (0x01, 0x6) => Instruction::Remw(rt),
+                    (0x01, 0x7) => Instruction::Remuw(rt),
                     _ => Instruction::Invalid(instruction),
                 }
             }
             0x37 => Instruction::Lui(UType::from(instruction)),
+            0x43 | 0x47 | 0x4B | 0x4F => {
+                let r4 = R4Type::from(instruction);
+                match (opcode, r4.fmt) {
+                    (0x43, 0) => Instruction::FmaddS(r4),
+                    (0x47, 0) => Instruction::FmsubS(r4),
+                    (0x4B, 0) => Instruction::FnmsubS(r4),
+                    (0x4F, 0) => Instruction::FnmaddS(r4),
+                    (0x43, 1) => Instruction::FmaddD(r4),
+                    (0x47, 1) => Instruction::FmsubD(r4),
+                    (0x4B, 1) => Instruction::FnmsubD(r4),
+                    (0x4F, 1) => Instruction::FnmaddD(r4),
+                    (0x43, 2) => Instruction::FmaddH(r4),
+                    (0x47, 2) => Instruction::FmsubH(r4),
+                    (0x4B, 2) => Instruction::FnmsubH(r4),
+                    (0x4F, 2) => Instruction::FnmaddH(r4),
+                    _ => Instruction::Invalid(instruction),
+                }
+            }
+            0x53 => {
+                let rt = RType::from(instruction);
+
+                match (rt.funct7, rt.funct3, rt.rs2) {
+                    (0x00, _, _) => Instruction::FaddS(rt),
+                    (0x04, _, _) => Instruction::FsubS(rt),
+                    (0x08, _, _) => Instruction::FmulS(rt),
+                    (0x0C, _, _) => Instruction::FdivS(rt),
+                    (0x2C, _, 0) => Instruction::FsqrtS(rt),
+                    (0x10, 0, _) => Instruction::FsgnjS(rt),
+                    (0x10, 1, _) => Instruction::FsgnjnS(rt),
+                    (0x10, 2, _) => Instruction::FsgnjxS(rt),
+                    (0x14, 0, _) => Instruction::FminS(rt),
+                    (0x14, 1, _) => Instruction::FmaxS(rt),
+                    (0x50, 2, _) => Instruction::FeqS(rt),
+                    (0x50, 1, _) => Instruction::FltS(rt),
+                    (0x50, 0, _) => Instruction::FleS(rt),
+                    (0x60, _, 0) => Instruction::FcvtWS(rt),
+                    (0x60, _, 1) => Instruction::FcvtWuS(rt),
+                    (0x60, _, 2) => Instruction::FcvtLS(rt),
+                    (0x60, _, 3) => Instruction::FcvtLuS(rt),
+                    (0x68, _, 0) => Instruction::FcvtSW(rt),
+                    (0x68, _, 1) => Instruction::FcvtSWu(rt),
+                    (0x68, _, 2) => Instruction::FcvtSL(rt),
+                    (0x68, _, 3) => Instruction::FcvtSLu(rt),
+                    (0x70, 0, 0) => Instruction::FmvXW(rt),
+                    (0x70, 1, 0) => Instruction::FclassS(rt),
+                    (0x78, 0, 0) => Instruction::FmvWX(rt),
+
+                    (0x01, _, _) => Instruction::FaddD(rt),
+                    (0x05, _, _) => Instruction::FsubD(rt),
+                    (0x09, _, _) => Instruction::FmulD(rt),
+                    (0x0D, _, _) => Instruction::FdivD(rt),
+                    (0x2D, _, 0) => Instruction::FsqrtD(rt),
+                    (0x11, 0, _) => Instruction::FsgnjD(rt),
+                    (0x11, 1, _) => Instruction::FsgnjnD(rt),
+                    (0x11, 2, _) => Instruction::FsgnjxD(rt),
+                    (0x15, 0, _) => Instruction::FminD(rt),
+                    (0x15, 1, _) => Instruction::FmaxD(rt),
+                    (0x20, _, 1) => Instruction::FcvtSD(rt),
+                    (0x21, _, 0) => Instruction::FcvtDS(rt),
+                    (0x51, 2, _) => Instruction::FeqD(rt),
+                    (0x51, 1, _) => Instruction::FltD(rt),
+                    (0x51, 0, _) => Instruction::FleD(rt),
+                    (0x61, _, 0) => Instruction::FcvtWD(rt),
+                    (0x61, _, 1) => Instruction::FcvtWuD(rt),
+                    (0x61, _, 2) => Instruction::FcvtLD(rt),
+                    (0x61, _, 3) => Instruction::FcvtLuD(rt),
+                    (0x69, _, 0) => Instruction::FcvtDW(rt),
+                    (0x69, _, 1) => Instruction::FcvtDWu(rt),
+                    (0x69, _, 2) => Instruction::FcvtDL(rt),
+                    (0x69, _, 3) => Instruction::FcvtDLu(rt),
+                    (0x71, 0, 0) => Instruction::FmvXD(rt),
+                    (0x71, 1, 0) => Instruction::FclassD(rt),
+                    (0x79, 0, 0) => Instruction::FmvDX(rt),
+
+                    (0x02, _, _) => Instruction::FaddH(rt),
+                    (0x06, _, _) => Instruction::FsubH(rt),
+                    (0x0A, _, _) => Instruction::FmulH(rt),
+                    (0x0E, _, _) => Instruction::FdivH(rt),
+                    (0x2E, _, 0) => Instruction::FsqrtH(rt),
+                    (0x12, 0, _) => Instruction::FsgnjH(rt),
+                    (0x12, 1, _) => Instruction::FsgnjnH(rt),
+                    (0x12, 2, _) => Instruction::FsgnjxH(rt),
+                    (0x16, 0, _) => Instruction::FminH(rt),
+                    (0x16, 1, _) => Instruction::FmaxH(rt),
+                    (0x20, _, 2) => Instruction::FcvtSH(rt),
+                    (0x22, _, 0) => Instruction::FcvtHS(rt),
+                    (0x21, _, 2) => Instruction::FcvtDH(rt),
+                    (0x22, _, 1) => Instruction::FcvtHD(rt),
+                    (0x52, 2, _) => Instruction::FeqH(rt),
+                    (0x52, 1, _) => Instruction::FltH(rt),
+                    (0x52, 0, _) => Instruction::FleH(rt),
+                    (0x62, _, 0) => Instruction::FcvtWH(rt),
+                    (0x62, _, 1) => Instruction::FcvtWuH(rt),
+                    (0x62, _, 2) => Instruction::FcvtLH(rt),
+                    (0x62, _, 3) => Instruction::FcvtLuH(rt),
+                    (0x6A, _, 0) => Instruction::FcvtHW(rt),
+                    (0x6A, _, 1) => Instruction::FcvtHWu(rt),
+                    (0x6A, _, 2) => Instruction::FcvtHL(rt),
+                    (0x6A, _, 3) => Instruction::FcvtHLu(rt),
+                    (0x72, 0, 0) => Instruction::FmvXH(rt),
+                    (0x72, 1, 0) => Instruction::FclassH(rt),
+                    (0x7A, 0, 0) => Instruction::FmvHX(rt),
+                    _ => Instruction::Invalid(instruction),
+                }
+            }
+            0x57 => match (instruction >> 12) & 7 {
+                7 => match instruction >> 30 {
+                    0 | 1 => Instruction::Vsetvli(IType::from(instruction)),
+                    3 => Instruction::Vsetivli(IType::from(instruction)),
+                    _ if instruction >> 25 == 0x40 => Instruction::Vsetvl(RType::from(instruction)),
+                    _ => Instruction::Invalid(instruction),
+                },
+                _ => Instruction::Varith(VType::from(instruction)),
+            },
             0x63 => {
                 let bt = BType::from(instruction);
                 match bt.funct3 {
@@ -331,7 +1130,7 @@ impl Instruction {
                     5 => Instruction::Bge(bt),
                     6 => Instruction::Bltu(bt),
                     7 => Instruction::Bgeu(bt),
-                    _ => unimplemented!("{:#010X} {:X?}", instruction, bt),
+                    _ => Instruction::Invalid(instruction),
                 }
             }
             0x67 => Instruction::Jalr(IType::from(instruction)),
@@ -339,17 +1138,23 @@ impl Instruction {
             0x73 => {
                 let it = IType::from(instruction);
                 match (it.funct3, it.imm) {
+                    (0, 0x000) => Instruction::Ecall(it),
+                    (0, 0x001) => Instruction::Ebreak(it),
                     (0, 0x102) => Instruction::Sret(it),
                     (0, 0x105) => Instruction::Wfi(it),
+                    (0, 0x120..=0x13F) if it.rd == 0 => Instruction::SfenceVma(it),
                     (0, 0x302) => Instruction::Mret(it),
                     (1, _) => Instruction::Csrrw(it),
                     (2, _) => Instruction::Csrrs(it),
                     (3, _) => Instruction::Csrrc(it),
-                    _ => unimplemented!("{:#010X} {:X?}", instruction, it),
+                    (5, _) => Instruction::Csrrwi(it),
+                    (6, _) => Instruction::Csrrsi(it),
+                    (7, _) => Instruction::Csrrci(it),
+                    _ => Instruction::Invalid(instruction),
                 }
             }
 
-            _ => unimplemented!("{:#010X} opcode={:02X}", instruction, opcode),
+            _ => Instruction::Invalid(instruction),
         }
     }
 }
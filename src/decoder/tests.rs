@@ -0,0 +1,226 @@
+use super::*;
+use crate::cpu::Cpu;
+
+// x1, x2 and the shift amount of the official encodings.
+fn shift(insn: u32) -> (u8, u8, u8) {
+    match Instruction::decode(insn) {
+        Instruction::Slli(s) | Instruction::Srli(s) | Instruction::Srai(s)
+            | Instruction::Slliw(s) | Instruction::Srliw(s) | Instruction::Sraiw(s) => (s.rd, s.rs1, s.shamt),
+        insn => panic!("{:X?} is not a shift", insn),
+    }
+}
+
+#[test]
+fn rv64_shift_immediates() {
+    // slli x1, x2, 63
+    assert!(matches!(Instruction::decode(0x03f11093), Instruction::Slli(_)));
+    assert_eq!(shift(0x03f11093), (1, 2, 63));
+    // srli x1, x2, 32
+    assert!(matches!(Instruction::decode(0x02015093), Instruction::Srli(_)));
+    assert_eq!(shift(0x02015093), (1, 2, 32));
+    // srai x1, x2, 63, shamt[5] must not be mistaken for funct6
+    assert!(matches!(Instruction::decode(0x43f15093), Instruction::Srai(_)));
+    assert_eq!(shift(0x43f15093), (1, 2, 63));
+    // srai x1, x2, 1
+    assert_eq!(shift(0x40115093), (1, 2, 1));
+}
+
+#[test]
+fn rv64_word_shift_immediates() {
+    // slliw x1, x2, 31
+    assert!(matches!(Instruction::decode(0x01f1109b), Instruction::Slliw(_)));
+    assert_eq!(shift(0x01f1109b), (1, 2, 31));
+    // srliw x1, x2, 0
+    assert!(matches!(Instruction::decode(0x0001509b), Instruction::Srliw(_)));
+    assert_eq!(shift(0x0001509b), (1, 2, 0));
+    // sraiw x1, x2, 31
+    assert!(matches!(Instruction::decode(0x41f1509b), Instruction::Sraiw(_)));
+    assert_eq!(shift(0x41f1509b), (1, 2, 31));
+}
+
+#[test]
+fn reserved_shift_encodings_are_invalid() {
+    for insn in [
+        0x04311093, // slli with funct6 0x01
+        0x80315093, // srli with funct6 0x20
+        0x44315093, // srai with funct6 0x11
+        0x0201109b, // slliw with shamt[5] set
+        0x0201509b, // srliw with shamt[5] set
+        0x4201509b, // sraiw with shamt[5] set
+    ] {
+        assert!(matches!(Instruction::decode(insn), Instruction::Invalid(bits) if bits == insn), "{:#010x}", insn);
+    }
+}
+
+#[test]
+fn undecodable_encodings_are_invalid() {
+    // an unused major opcode, a reserved funct7 of OP and a reserved branch
+    // funct3
+    for insn in [0xffff_ffff, 0xfe00_00b3, 0x0000_2063] {
+        assert!(matches!(Instruction::decode(insn), Instruction::Invalid(i) if i == insn), "{:#010x}", insn);
+    }
+}
+
+// The riscv-opcodes description of every instruction the decoder claims.
+const OPCODES: &str = include_str!("opcodes");
+
+// Bits hi..lo of the operand fields riscv-opcodes names.
+fn operand_bits(name: &str) -> (u32, u32) {
+    match name {
+        "rd" | "bimm12lo" | "imm12lo" => (11, 7),
+        "rs1" | "zimm" => (19, 15),
+        "rs2" | "shamtw" => (24, 20),
+        "rs3" => (31, 27),
+        "rm" => (14, 12),
+        "imm12" | "csr" => (31, 20),
+        "imm20" | "jimm20" => (31, 12),
+        "bimm12hi" | "imm12hi" => (31, 25),
+        "shamtd" => (25, 20),
+        "aq" => (26, 26),
+        "rl" => (25, 25),
+        "rnum" => (23, 20),
+        "fm" => (31, 28),
+        "pred" => (27, 24),
+        "succ" => (23, 20),
+        _ => panic!("unknown operand {}", name),
+    }
+}
+
+fn bits(insn: u32, hi: u32, lo: u32) -> u32 {
+    (insn >> lo) & (u32::MAX >> (31 - hi + lo))
+}
+
+fn sext(val: u32, width: u32) -> i32 {
+    ((val << (32 - width)) as i32) >> (32 - width)
+}
+
+// The variant an instruction decodes to: the name in CamelCase, all loads and
+// stores share one and so do the size suffixes of the plain AMOs.
+fn variant(name: &str) -> String {
+    if name.starts_with('l') && ["lb", "lh", "lw", "ld", "lbu", "lhu", "lwu"].contains(&name) {
+        return "Load".into();
+    }
+    if ["sb", "sh", "sw", "sd"].contains(&name) {
+        return "Store".into();
+    }
+    let camel = |part: &str| part[..1].to_uppercase() + &part[1..];
+    if name.starts_with("amo") && !name.starts_with("amocas") {
+        return camel(&name.replace('.', ""));
+    }
+    name.split('.').map(camel).collect()
+}
+
+// The operands of an encoding as the spec lays them out, by the name of the
+// field of the decoded instruction holding them.
+fn operands(insn: u32, args: &[&str]) -> Vec<(&'static str, i64)> {
+    let mut fields = Vec::new();
+    for &arg in args {
+        let (hi, lo) = operand_bits(arg);
+        let raw = bits(insn, hi, lo);
+        match arg {
+            "rd" => fields.push(("rd", raw as i64)),
+            "rs1" | "zimm" => fields.push(("rs1", raw as i64)),
+            "rs2" => fields.push(("rs2", raw as i64)),
+            "rs3" => fields.push(("rs3", raw as i64)),
+            "rm" => fields.push(("funct3", raw as i64)),
+            "aq" => fields.push(("aq", raw as i64)),
+            "rl" => fields.push(("rl", raw as i64)),
+            "imm12" => fields.push(("imm", sext(raw, 12) as i64)),
+            "imm20" => fields.push(("imm", (raw << 12) as i32 as i64)),
+            "imm12hi" => fields.push(("imm", sext(raw << 5 | bits(insn, 11, 7), 12) as i64)),
+            "jimm20" => {
+                let imm = bits(insn, 31, 31) << 20 | bits(insn, 19, 12) << 12 | bits(insn, 20, 20) << 11
+                    | bits(insn, 30, 21) << 1;
+                fields.push(("imm", sext(imm, 21) as i64));
+            }
+            "bimm12hi" => {
+                let imm = bits(insn, 31, 31) << 12 | bits(insn, 7, 7) << 11 | bits(insn, 30, 25) << 5
+                    | bits(insn, 11, 8) << 1;
+                fields.push(("imm", sext(imm, 13) as i64));
+            }
+            // in the low bits of imm where the layout has no field of its own
+            "shamtd" | "shamtw" => fields.push(("shamt", raw as i64)),
+            "csr" => fields.push(("imm12", raw as i64)),
+            "rnum" => fields.push(("imm4", raw as i64)),
+            _ => {}
+        }
+    }
+    fields
+}
+
+// A field of the Debug form of a decoded instruction.
+fn debug_field(debug: &str, name: &str) -> Option<i64> {
+    let start = debug.find(&format!(" {}: ", name))? + name.len() + 3;
+    let value = debug[start..].split([',', ' ']).next()?;
+    match value {
+        "true" => Some(1),
+        "false" => Some(0),
+        _ => value.parse().ok(),
+    }
+}
+
+#[test]
+fn decodes_and_executes_every_claimed_instruction() {
+    let mut seed = 0x2545_f491u32;
+    let mut random = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    for line in OPCODES.lines().filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap();
+        let (mut mask, mut value, mut args) = (0u32, 0u32, Vec::new());
+        for word in words {
+            let Some((range, val)) = word.split_once('=') else {
+                args.push(word);
+                continue;
+            };
+            let (hi, lo): (u32, u32) = match range.split_once("..") {
+                Some((hi, lo)) => (hi.parse().unwrap(), lo.parse().unwrap()),
+                None => (range.parse().unwrap(), range.parse().unwrap()),
+            };
+            let val = match val.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).unwrap(),
+                None => val.parse().unwrap(),
+            };
+            let field = (u32::MAX >> (31 - hi + lo)) << lo;
+            mask |= field;
+            value |= (val << lo) & field;
+        }
+
+        for _ in 0..64 {
+            let mut insn = (random() & !mask) | value;
+            // operand values the spec reserves: AES key schedule rounds above
+            // 10 and odd register pairs
+            match name {
+                "aes64ks1i" => insn = (insn & !(0xf << 20)) | (bits(insn, 23, 20) % 11) << 20,
+                "amocas.q" => insn &= !(1 << 7 | 1 << 20),
+                _ => {}
+            }
+            let decoded = Instruction::decode(insn);
+            let debug = format!("{:?}", decoded);
+            let expected = variant(name);
+            assert_eq!(&debug[..debug.find('(').unwrap_or(debug.len())], expected,
+                "{} {:#010x} decodes as {}", name, insn, debug);
+            assert!(Cpu::executes(&decoded), "{} {:#010x} has no handler", name, insn);
+            // the fences carry no operands
+            if !debug.contains('(') {
+                continue;
+            }
+            for (field, want) in operands(insn, &args) {
+                let got = match field {
+                    "imm12" => debug_field(&debug, "imm").map(|imm| imm & 0xfff),
+                    "imm4" => debug_field(&debug, "imm").map(|imm| imm & 0xf),
+                    "shamt" => debug_field(&debug, "shamt")
+                        .or_else(|| debug_field(&debug, "imm").map(|imm| imm & 0x3f)),
+                    "rs2" => debug_field(&debug, "rs2")
+                        .or_else(|| debug_field(&debug, "imm").map(|imm| imm & 0x1f)),
+                    _ => debug_field(&debug, field),
+                };
+                assert_eq!(got, Some(want), "{} {:#010x}: {} of {}", name, insn, field, debug);
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use crate::cpu::Cpu;
+use crate::registers;
+
+// Small expression language over guest state used by hook scripts, watches
+// and invariants:
+//   numbers (decimal or 0x hex), registers (x0..x31, ABI names, pc),
+//   memory reads [addr] (u64), u8[addr], u16[addr], u32[addr], u64[addr],
+//   unary - ! ~, and the C binary operators * / % + - << >> & ^ | and
+//   comparisons, all on wrapping unsigned 64-bit values.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Const(u64),
+    Reg(usize),
+    Pc,
+    Var(String),
+    Mem(u8, Box<Expr>),
+    Unary(char, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+pub struct Env<'a> {
+    pub cpu: &'a Cpu,
+    pub vars: &'a [(&'a str, u64)],
+}
+
+const BINARY_OPS: [&[&str]; 7] = [
+    &["==", "!=", "<=", ">=", "<", ">"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+pub fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => s.replace('_', "").parse().ok(),
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_ws(&mut self) {
+        self.src = self.src.trim_start();
+    }
+
+    fn eat(&mut self, tok: &str) -> bool {
+        self.skip_ws();
+        if self.src.starts_with(tok) {
+            self.src = &self.src[tok.len()..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == BINARY_OPS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        'outer: loop {
+            self.skip_ws();
+            for op in BINARY_OPS[level] {
+                // `<<` and `>>` belong to the shift level
+                if self.src.starts_with(op) && !(op.len() == 1 && self.src[1..].starts_with(*op)
+                    && matches!(*op, "<" | ">"))
+                {
+                    self.src = &self.src[op.len()..];
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        for op in ['-', '!', '~'] {
+            if self.eat(&op.to_string()) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        self.skip_ws();
+        if self.eat("(") {
+            let e = self.binary(0)?;
+            if !self.eat(")") {
+                return Err("expected ')'".into());
+            }
+            return Ok(e);
+        }
+        if self.eat("[") {
+            return self.deref(8);
+        }
+
+        let len = self.src.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.src.len());
+        if len == 0 {
+            return Err(format!("unexpected {:?}", self.src));
+        }
+        let word = &self.src[..len];
+        self.src = &self.src[len..];
+
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            return parse_number(word).map(Expr::Const)
+                .ok_or_else(|| format!("bad number {:?}", word));
+        }
+
+        let width = match word {
+            "u8" => Some(1),
+            "u16" => Some(2),
+            "u32" => Some(4),
+            "u64" => Some(8),
+            _ => None,
+        };
+        if let Some(width) = width {
+            if !self.eat("[") {
+                return Err(format!("expected '[' after {}", word));
+            }
+            return self.deref(width);
+        }
+
+        if word == "pc" {
+            return Ok(Expr::Pc);
+        }
+        match registers::index(word) {
+            Some(idx) => Ok(Expr::Reg(idx)),
+            None => Ok(Expr::Var(word.to_string())),
+        }
+    }
+
+    fn deref(&mut self, width: u8) -> Result<Expr, String> {
+        let addr = self.binary(0)?;
+        if !self.eat("]") {
+            return Err("expected ']'".into());
+        }
+        Ok(Expr::Mem(width, Box::new(addr)))
+    }
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut parser = Parser { src };
+        let expr = parser.binary(0)?;
+        parser.skip_ws();
+        if !parser.src.is_empty() {
+            return Err(format!("trailing input {:?}", parser.src));
+        }
+        Ok(expr)
+    }
+
+    // The variables the expression uses, for callers that provide them.
+    pub fn vars(&self) -> Vec<&str> {
+        match self {
+            Expr::Var(name) => vec![name.as_str()],
+            Expr::Mem(_, e) | Expr::Unary(_, e) => e.vars(),
+            Expr::Binary(_, lhs, rhs) => [lhs.vars(), rhs.vars()].concat(),
+            Expr::Const(_) | Expr::Reg(_) | Expr::Pc => Vec::new(),
+        }
+    }
+
+    // None if the expression touches memory outside of RAM or an unknown
+    // variable.
+    pub fn eval(&self, env: &Env) -> Option<u64> {
+        Some(match self {
+            Expr::Const(c) => *c,
+            Expr::Reg(idx) => env.cpu.reg(*idx),
+            Expr::Pc => env.cpu.pc(),
+            Expr::Var(name) => env.vars.iter().find(|(n, _)| n == name)?.1,
+            Expr::Mem(width, addr) => {
+                let addr = addr.eval(env)?;
+                let mut val = 0u64;
+                for i in 0..*width as u64 {
+                    val |= (env.cpu.debug_read_u8(addr.wrapping_add(i))? as u64) << (i * 8);
+                }
+                val
+            }
+            Expr::Unary(op, e) => {
+                let v = e.eval(env)?;
+                match op {
+                    '-' => v.wrapping_neg(),
+                    '!' => (v == 0) as u64,
+                    _ => !v,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (a, b) = (lhs.eval(env)?, rhs.eval(env)?);
+                match *op {
+                    "==" => (a == b) as u64,
+                    "!=" => (a != b) as u64,
+                    "<=" => (a <= b) as u64,
+                    ">=" => (a >= b) as u64,
+                    "<" => (a < b) as u64,
+                    ">" => (a > b) as u64,
+                    "|" => a | b,
+                    "^" => a ^ b,
+                    "&" => a & b,
+                    "<<" => a.wrapping_shl(b as u32),
+                    ">>" => a.wrapping_shr(b as u32),
+                    "+" => a.wrapping_add(b),
+                    "-" => a.wrapping_sub(b),
+                    "*" => a.wrapping_mul(b),
+                    "/" => a.checked_div(b)?,
+                    _ => a.checked_rem(b)?,
+                }
+            }
+        })
+    }
+}
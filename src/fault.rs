@@ -0,0 +1,85 @@
+// Deterministic fault injection for device models. Each fault kind fires
+// with a configured probability drawn from a seeded PRNG so runs can be
+// reproduced exactly.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fault {
+    DropTx,
+    DropRx,
+    CorruptRx,
+}
+
+impl Fault {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "drop-tx" => Some(Fault::DropTx),
+            "drop-rx" => Some(Fault::DropRx),
+            "corrupt-rx" => Some(Fault::CorruptRx),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fault::DropTx => "drop-tx",
+            Fault::DropRx => "drop-rx",
+            Fault::CorruptRx => "corrupt-rx",
+        }
+    }
+}
+
+const DEFAULT_SEED: u64 = 0x9E3779B97F4A7C15;
+
+pub struct FaultInjector {
+    state: u64,
+    rates: Vec<(Fault, f64)>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        FaultInjector {
+            state: DEFAULT_SEED,
+            rates: Vec::new(),
+        }
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        // xorshift must not start from zero
+        self.state = if seed == 0 { DEFAULT_SEED } else { seed };
+    }
+
+    pub fn set_rate(&mut self, fault: Fault, rate: f64) {
+        self.rates.retain(|(f, _)| *f != fault);
+        if rate > 0.0 {
+            self.rates.push((fault, rate.min(1.0)));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.rates.clear();
+    }
+
+    pub fn rates(&self) -> &[(Fault, f64)] {
+        &self.rates
+    }
+
+    pub fn fires(&mut self, fault: Fault) -> bool {
+        let Some(&(_, rate)) = self.rates.iter().find(|(f, _)| *f == fault) else {
+            return false;
+        };
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64 <= rate
+    }
+
+    // The generator position, seed() with it to continue from here.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+}
@@ -0,0 +1,327 @@
+use crate::clock::TIMEBASE_FREQ;
+use crate::clint;
+use crate::plic;
+use crate::rtc;
+use crate::cpu::{CACHE_BLOCK_SIZE, CLINT_BASE, PLIC_BASE, RAM_BASE, RAM_SIZE, RTC_BASE, RTC_IRQ, SYSCON_BASE, SYSCON_SIZE,
+    UART_BASE, UART_IRQ, UART_SIZE, VIRTIO_RNG_BASE, VIRTIO_RNG_IRQ};
+use crate::virtio;
+use crate::isa::Isa;
+
+// Flattened device tree writer, enough for the tree of this machine. Layout
+// per the devicetree spec v0.4: header, empty memory reservation map,
+// structure block, strings block.
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const HEADER_SIZE: usize = 40;
+
+const UART_PHANDLE: u32 = 1;
+const SYSCON_PHANDLE: u32 = 2;
+const CPU_INTC_PHANDLE: u32 = 3;
+const PLIC_PHANDLE: u32 = 4;
+
+struct Fdt {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+}
+
+impl Fdt {
+    fn new() -> Self {
+        Fdt { structure: Vec::new(), strings: Vec::new() }
+    }
+
+    fn token(&mut self, token: u32) {
+        self.structure.extend_from_slice(&token.to_be_bytes());
+    }
+
+    fn pad(&mut self) {
+        while !self.structure.len().is_multiple_of(4) {
+            self.structure.push(0);
+        }
+    }
+
+    fn begin_node(&mut self, name: &str) {
+        self.token(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+    }
+
+    fn end_node(&mut self) {
+        self.token(FDT_END_NODE);
+    }
+
+    fn prop(&mut self, name: &str, value: &[u8]) {
+        let needle: Vec<u8> = name.bytes().chain([0]).collect();
+        let off = match self.strings.windows(needle.len()).position(|w| w == needle.as_slice()) {
+            Some(off) => off,
+            None => {
+                self.strings.extend_from_slice(&needle);
+                self.strings.len() - needle.len()
+            }
+        };
+        self.token(FDT_PROP);
+        self.token(value.len() as u32);
+        self.token(off as u32);
+        self.structure.extend_from_slice(value);
+        self.pad();
+    }
+
+    fn prop_empty(&mut self, name: &str) {
+        self.prop(name, &[]);
+    }
+
+    fn prop_u32(&mut self, name: &str, val: u32) {
+        self.prop(name, &val.to_be_bytes());
+    }
+
+    fn prop_u32s(&mut self, name: &str, vals: &[u32]) {
+        let bytes: Vec<u8> = vals.iter().flat_map(|v| v.to_be_bytes()).collect();
+        self.prop(name, &bytes);
+    }
+
+    // Cells of a reg or ranges property, two per value with
+    // #address-cells = #size-cells = 2.
+    fn prop_u64s(&mut self, name: &str, vals: &[u64]) {
+        let bytes: Vec<u8> = vals.iter().flat_map(|v| v.to_be_bytes()).collect();
+        self.prop(name, &bytes);
+    }
+
+    fn prop_str(&mut self, name: &str, val: &str) {
+        self.prop_strs(name, &[val]);
+    }
+
+    fn prop_strs(&mut self, name: &str, vals: &[&str]) {
+        let bytes: Vec<u8> = vals.iter().flat_map(|v| v.bytes().chain([0])).collect();
+        self.prop(name, &bytes);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.token(FDT_END);
+
+        let rsvmap_off = HEADER_SIZE;
+        let struct_off = rsvmap_off + 16; // one terminating all-zero entry
+        let strings_off = struct_off + self.structure.len();
+        let total = strings_off + self.strings.len();
+
+        let mut blob = Vec::with_capacity(total);
+        for field in [
+            FDT_MAGIC,
+            total as u32,
+            struct_off as u32,
+            strings_off as u32,
+            rsvmap_off as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            0, // boot_cpuid_phys
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ] {
+            blob.extend_from_slice(&field.to_be_bytes());
+        }
+        blob.extend_from_slice(&[0; 16]);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+}
+
+// Device tree of the emulated machine, modelled on QEMU's virt board so the
+// generic OpenSBI platform and Linux drivers bind to the devices.
+pub fn virt_dtb(isa: &Isa) -> Vec<u8> {
+    let mut fdt = Fdt::new();
+    fdt.begin_node("");
+    fdt.prop_u32("#address-cells", 2);
+    fdt.prop_u32("#size-cells", 2);
+    fdt.prop_str("compatible", "riscv-virtio");
+    fdt.prop_str("model", "nrv64emu,virt");
+
+    fdt.begin_node("chosen");
+    fdt.prop_str("stdout-path", &format!("/soc/serial@{:x}", UART_BASE));
+    fdt.end_node();
+
+    fdt.begin_node(&format!("memory@{:x}", RAM_BASE));
+    fdt.prop_str("device_type", "memory");
+    fdt.prop_u64s("reg", &[RAM_BASE, RAM_SIZE]);
+    fdt.end_node();
+
+    fdt.begin_node("cpus");
+    fdt.prop_u32("#address-cells", 1);
+    fdt.prop_u32("#size-cells", 0);
+    fdt.prop_u32("timebase-frequency", TIMEBASE_FREQ as u32);
+    fdt.begin_node("cpu@0");
+    fdt.prop_str("device_type", "cpu");
+    fdt.prop_u32("reg", 0);
+    fdt.prop_str("status", "okay");
+    fdt.prop_str("compatible", "riscv");
+    fdt.prop_str("riscv,isa", &isa.to_string());
+    fdt.prop_str("mmu-type", "riscv,sv39");
+    if isa.has("zicbom") {
+        fdt.prop_u32("riscv,cbom-block-size", CACHE_BLOCK_SIZE as u32);
+    }
+    if isa.has("zicboz") {
+        fdt.prop_u32("riscv,cboz-block-size", CACHE_BLOCK_SIZE as u32);
+    }
+    fdt.begin_node("interrupt-controller");
+    fdt.prop_u32("#interrupt-cells", 1);
+    fdt.prop_empty("interrupt-controller");
+    fdt.prop_str("compatible", "riscv,cpu-intc");
+    fdt.prop_u32("phandle", CPU_INTC_PHANDLE);
+    fdt.end_node();
+    fdt.end_node();
+    fdt.end_node();
+
+    fdt.begin_node("soc");
+    fdt.prop_u32("#address-cells", 2);
+    fdt.prop_u32("#size-cells", 2);
+    fdt.prop_str("compatible", "simple-bus");
+    fdt.prop_empty("ranges");
+
+    fdt.begin_node(&format!("serial@{:x}", UART_BASE));
+    fdt.prop_str("compatible", "ns16550a");
+    fdt.prop_u64s("reg", &[UART_BASE, UART_SIZE]);
+    fdt.prop_u32("clock-frequency", 3686400);
+    fdt.prop_u32("interrupts", UART_IRQ);
+    fdt.prop_u32("interrupt-parent", PLIC_PHANDLE);
+    fdt.prop_u32("phandle", UART_PHANDLE);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("test@{:x}", SYSCON_BASE));
+    fdt.prop_strs("compatible", &["sifive,test1", "sifive,test0", "syscon"]);
+    fdt.prop_u64s("reg", &[SYSCON_BASE, SYSCON_SIZE]);
+    fdt.prop_u32("phandle", SYSCON_PHANDLE);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("rtc@{:x}", RTC_BASE));
+    fdt.prop_str("compatible", "google,goldfish-rtc");
+    fdt.prop_u64s("reg", &[RTC_BASE, rtc::MMIO_SIZE]);
+    fdt.prop_u32("interrupts", RTC_IRQ);
+    fdt.prop_u32("interrupt-parent", PLIC_PHANDLE);
+    fdt.end_node();
+
+    // machine software and timer interrupts of hart 0
+    fdt.begin_node(&format!("clint@{:x}", CLINT_BASE));
+    fdt.prop_strs("compatible", &["sifive,clint0", "riscv,clint0"]);
+    fdt.prop_u64s("reg", &[CLINT_BASE, clint::MMIO_SIZE]);
+    fdt.prop_u32s("interrupts-extended", &[CPU_INTC_PHANDLE, 3, CPU_INTC_PHANDLE, 7]);
+    fdt.end_node();
+
+    // machine and supervisor external interrupts of hart 0
+    fdt.begin_node(&format!("plic@{:x}", PLIC_BASE));
+    fdt.prop_strs("compatible", &["sifive,plic-1.0.0", "riscv,plic0"]);
+    fdt.prop_u64s("reg", &[PLIC_BASE, plic::MMIO_SIZE]);
+    fdt.prop_u32("#address-cells", 0);
+    fdt.prop_u32("#interrupt-cells", 1);
+    fdt.prop_empty("interrupt-controller");
+    fdt.prop_u32("riscv,ndev", plic::NUM_SOURCES - 1);
+    fdt.prop_u32s("interrupts-extended", &[CPU_INTC_PHANDLE, 11, CPU_INTC_PHANDLE, 9]);
+    fdt.prop_u32("phandle", PLIC_PHANDLE);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("virtio_mmio@{:x}", VIRTIO_RNG_BASE));
+    fdt.prop_str("compatible", "virtio,mmio");
+    fdt.prop_u64s("reg", &[VIRTIO_RNG_BASE, virtio::MMIO_SIZE]);
+    fdt.prop_u32("interrupts", VIRTIO_RNG_IRQ);
+    fdt.prop_u32("interrupt-parent", PLIC_PHANDLE);
+    fdt.end_node();
+
+    fdt.end_node();
+
+    fdt.begin_node("poweroff");
+    fdt.prop_str("compatible", "syscon-poweroff");
+    fdt.prop_u32("regmap", SYSCON_PHANDLE);
+    fdt.prop_u32("offset", 0);
+    fdt.prop_u32("value", 0x5555);
+    fdt.end_node();
+
+    fdt.begin_node("reboot");
+    fdt.prop_str("compatible", "syscon-reboot");
+    fdt.prop_u32("regmap", SYSCON_PHANDLE);
+    fdt.prop_u32("offset", 0);
+    fdt.prop_u32("value", 0x7777);
+    fdt.end_node();
+
+    fdt.end_node();
+    fdt.finish()
+}
+
+pub fn is_dtb(data: &[u8]) -> bool {
+    data.starts_with(&FDT_MAGIC.to_be_bytes())
+}
+
+// The (base, size) ranges of the top-level nodes of device_type "memory" in
+// a blob, per the cell counts of the root node.
+pub fn memory_ranges(blob: &[u8]) -> Result<Vec<(u64, u64)>, String> {
+    let truncated = || "truncated device tree".to_string();
+    let be32 = |off: usize| blob.get(off..off + 4).map(|b| u32::from_be_bytes(b.try_into().unwrap())).ok_or_else(truncated);
+    let cstr = |off: usize| blob.get(off..).and_then(|b| b.split(|c| *c == 0).next()).ok_or_else(truncated);
+    if !is_dtb(blob) {
+        return Err("not a device tree blob".into());
+    }
+    let struct_off = be32(8)? as usize;
+    let strings_off = be32(12)? as usize;
+
+    let (mut addr_cells, mut size_cells) = (2, 1);
+    let mut depth = 0;
+    // device_type and reg of the top-level node being walked
+    let mut memory = false;
+    let mut reg: &[u8] = &[];
+    let mut ranges = Vec::new();
+    let mut off = struct_off;
+    loop {
+        let token = be32(off)?;
+        off += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                off = (off + cstr(off)?.len() + 1).next_multiple_of(4);
+                depth += 1;
+                (memory, reg) = (false, &[]);
+            }
+            FDT_END_NODE if depth == 0 => return Err("unbalanced device tree nodes".into()),
+            FDT_END_NODE => {
+                if depth == 2 && memory {
+                    ranges.extend(parse_reg(reg, addr_cells, size_cells)?);
+                }
+                depth -= 1;
+            }
+            FDT_PROP => {
+                let len = be32(off)? as usize;
+                let name = cstr(strings_off + be32(off + 4)? as usize)?;
+                let value = blob.get(off + 8..off + 8 + len).ok_or_else(truncated)?;
+                off = (off + 8 + len).next_multiple_of(4);
+                match (depth, name) {
+                    (1, b"#address-cells") => addr_cells = cell_count(value)?,
+                    (1, b"#size-cells") => size_cells = cell_count(value)?,
+                    (2, b"device_type") => memory = value == b"memory\0",
+                    (2, b"reg") => reg = value,
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => return Ok(ranges),
+            _ => return Err(format!("bad token {:#x} in device tree", token)),
+        }
+    }
+}
+
+fn cell_count(value: &[u8]) -> Result<u32, String> {
+    value.try_into().map(u32::from_be_bytes).map_err(|_| "bad cell count in device tree".into())
+}
+
+fn parse_reg(reg: &[u8], addr_cells: u32, size_cells: u32) -> Result<Vec<(u64, u64)>, String> {
+    if !(1..=2).contains(&addr_cells) || size_cells > 2 {
+        return Err(format!("unsupported #address-cells {} / #size-cells {}", addr_cells, size_cells));
+    }
+    let cells = |bytes: &[u8]| bytes.chunks(4).fold(0, |acc, c| acc << 32 | u32::from_be_bytes(c.try_into().unwrap()) as u64);
+    let (addr_len, entry_len) = (addr_cells as usize * 4, (addr_cells + size_cells) as usize * 4);
+    if !reg.len().is_multiple_of(entry_len) {
+        return Err("bad reg property in memory node".into());
+    }
+    Ok(reg.chunks(entry_len).map(|e| (cells(&e[..addr_len]), cells(&e[addr_len..]))).collect())
+}
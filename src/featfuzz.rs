@@ -0,0 +1,64 @@
+use std::fmt;
+
+use crate::fault::FaultInjector;
+use crate::isa::Isa;
+
+// Feature fuzzing: implementation choices the specs leave open, drawn from a
+// seed to shake out guests that assume one implementation's behaviour
+// (usually QEMU's): `--fuzz-features <seed>`. The choices are printed at
+// startup and the same seed always draws the same machine.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Choices {
+    pub seed: Option<u64>,
+    // Optional multi-letter extensions left out, the single letters stay so
+    // that rv64g guests still run.
+    pub dropped: Vec<String>,
+    // misa may read as zero, meaning it is not implemented.
+    pub misa_zero: bool,
+    // mtvec.MODE is WARL, vectored mode need not be supported.
+    pub mtvec_direct_only: bool,
+    // mtval may be zero on illegal instruction traps rather than the bits.
+    pub illegal_tval_zero: bool,
+    // time may start anywhere, not only near the host's epoch time.
+    pub time_start: Option<u64>,
+}
+
+impl Choices {
+    pub fn draw(seed: u64, isa: &Isa) -> Self {
+        let mut rng = FaultInjector::new();
+        rng.seed(seed);
+        let dropped = isa.extensions().into_iter()
+            .filter(|ext| ext.len() > 1 && rng.next_u64() & 1 != 0)
+            .collect();
+        Choices {
+            seed: Some(seed),
+            dropped,
+            misa_zero: rng.next_u64() & 1 != 0,
+            mtvec_direct_only: rng.next_u64() & 1 != 0,
+            illegal_tval_zero: rng.next_u64() & 1 != 0,
+            time_start: (rng.next_u64() & 1 != 0).then(|| rng.next_u64() >> 1),
+        }
+    }
+}
+
+impl fmt::Display for Choices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "seed {}", self.seed.unwrap_or(0))?;
+        if !self.dropped.is_empty() {
+            write!(f, ", without {}", self.dropped.join(","))?;
+        }
+        if self.misa_zero {
+            write!(f, ", misa reads zero")?;
+        }
+        if self.mtvec_direct_only {
+            write!(f, ", mtvec direct only")?;
+        }
+        if self.illegal_tval_zero {
+            write!(f, ", no illegal instruction mtval")?;
+        }
+        if let Some(time) = self.time_start {
+            write!(f, ", time starts at {:#x}", time)?;
+        }
+        Ok(())
+    }
+}
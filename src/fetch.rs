@@ -0,0 +1,149 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::fdt;
+use crate::isa::Isa;
+
+// Known-good OpenSBI release, unpacked with the host's curl and tar.
+const OPENSBI_VERSION: &str = "1.5.1";
+const FIRMWARE: &str = "fw_jump.bin";
+const DTB: &str = "virt.dtb";
+
+pub struct Defaults {
+    pub firmware: PathBuf,
+    pub dtb: PathBuf,
+}
+
+// $XDG_CACHE_HOME/nrv64emu, falling back to ~/.cache/nrv64emu.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("nrv64emu"))
+}
+
+fn run(cmd: &mut Command) -> Result<Vec<u8>, String> {
+    let name = cmd.get_program().to_string_lossy().into_owned();
+    let out = cmd.output().map_err(|e| format!("{}: {}", name, e))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(format!("{} failed: {}", name, stderr.trim()));
+    }
+    Ok(out.stdout)
+}
+
+fn download_firmware(cache: &Path) -> Result<Vec<u8>, String> {
+    let release = format!("opensbi-{}-rv-bin", OPENSBI_VERSION);
+    let url = format!("https://github.com/riscv-software-src/opensbi/releases/download/v{}/{}.tar.xz",
+        OPENSBI_VERSION, release);
+    let tarball = cache.join(format!("{}.tar.xz", release));
+    run(Command::new("curl").args(["-fsSL", "-o"]).arg(&tarball).arg(&url))?;
+
+    let member = format!("{}/share/opensbi/lp64/generic/firmware/{}", release, FIRMWARE);
+    let firmware = run(Command::new("tar").arg("-xJOf").arg(&tarball).arg(&member));
+    let _ = std::fs::remove_file(&tarball);
+    let firmware = firmware?;
+    if firmware.is_empty() {
+        return Err(format!("{} is empty in the release", FIRMWARE));
+    }
+    Ok(firmware)
+}
+
+// Makes sure the cache holds the firmware and a device tree for this
+// machine. The firmware's SHA-256 is recorded when it is first downloaded
+// and checked on every later call, a mismatch fetches it again.
+pub fn fetch_defaults(cache: &Path) -> Result<Defaults, String> {
+    std::fs::create_dir_all(cache).map_err(|e| format!("{}: {}", cache.display(), e))?;
+    let write = |path: &Path, data: &[u8]| std::fs::write(path, data)
+        .map_err(|e| format!("{}: {}", path.display(), e));
+
+    let firmware = cache.join(FIRMWARE);
+    let digest_path = cache.join(format!("{}.sha256", FIRMWARE));
+    let recorded = std::fs::read_to_string(&digest_path).ok();
+    let valid = match (std::fs::read(&firmware), &recorded) {
+        (Ok(data), Some(digest)) => hex(&sha256(&data)) == digest.trim(),
+        _ => false,
+    };
+    if !valid {
+        if recorded.is_some() {
+            eprintln!("fetch: cached {} does not match its checksum, fetching it again", FIRMWARE);
+        }
+        let data = download_firmware(cache)?;
+        write(&firmware, &data)?;
+        write(&digest_path, format!("{}\n", hex(&sha256(&data))).as_bytes())?;
+    }
+
+    // Always regenerated, it has to match this build's machine.
+    let dtb = cache.join(DTB);
+    write(&dtb, &fdt::virt_dtb(&Isa::default()))?;
+
+    Ok(Defaults { firmware, dtb })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// FIPS 180-4 SHA-256.
+fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; 32];
+    for (chunk, v) in out.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&v.to_be_bytes());
+    }
+    out
+}
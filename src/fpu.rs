@@ -0,0 +1,522 @@
+// Helpers for the RISC-V floating point semantics that differ from Rust's:
+// canonical NaNs, NaN-boxing of narrower values in the 64-bit registers,
+// min/max with signed zeros, rounding modes, saturating conversions,
+// exception flags and fclass.
+//
+// Arithmetic is done by the host in round-to-nearest-even. The exact error
+// of that result is recovered with error-free transformations and used to
+// step to the neighbouring value for the other rounding modes and to raise
+// the inexact/underflow flags.
+
+use std::cmp::Ordering;
+use std::num::FpCategory;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+const NAN_BOX_S: u64 = 0xffffffff_00000000;
+const NAN_BOX_H: u64 = 0xffffffff_ffff0000;
+const CANONICAL_NAN_H: u16 = 0x7e00;
+const MAX_H: f64 = 65504.0;
+
+// Rounding modes as encoded in the rm field and frm, 0 is round to nearest
+// with ties to even.
+pub const RM_RTZ: u8 = 1;
+pub const RM_RDN: u8 = 2;
+pub const RM_RUP: u8 = 3;
+pub const RM_RMM: u8 = 4;
+
+// Accrued exception flags as in fflags.
+pub const FLAG_NX: u8 = 1 << 0;
+pub const FLAG_UF: u8 = 1 << 1;
+pub const FLAG_OF: u8 = 1 << 2;
+pub const FLAG_DZ: u8 = 1 << 3;
+pub const FLAG_NV: u8 = 1 << 4;
+
+pub trait Float: Copy + PartialOrd
+    + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    const CANONICAL_NAN: Self;
+    const ZERO: Self;
+    const MAX: Self;
+    const MIN_POSITIVE: Self;
+    const INFINITY: Self;
+
+    fn is_nan(self) -> bool;
+    fn is_finite(self) -> bool;
+    fn is_infinite(self) -> bool;
+    fn is_sign_negative(self) -> bool;
+    fn is_signaling(self) -> bool;
+    fn category(self) -> FpCategory;
+    fn abs(self) -> Self;
+    fn next_up(self) -> Self;
+    fn next_down(self) -> Self;
+    fn mul_add(self, a: Self, b: Self) -> Self;
+    fn sqrt(self) -> Self;
+    fn from_i128(v: i128) -> Self;
+    fn to_i128(self) -> i128;
+}
+
+macro_rules! impl_float {
+    ($t:ident, $nan:expr, $quiet:expr) => {
+        impl Float for $t {
+            const CANONICAL_NAN: Self = $t::from_bits($nan);
+            const ZERO: Self = 0.0;
+            const MAX: Self = $t::MAX;
+            const MIN_POSITIVE: Self = $t::MIN_POSITIVE;
+            const INFINITY: Self = $t::INFINITY;
+
+            fn is_nan(self) -> bool { self.is_nan() }
+            fn is_finite(self) -> bool { self.is_finite() }
+            fn is_infinite(self) -> bool { self.is_infinite() }
+            fn is_sign_negative(self) -> bool { self.is_sign_negative() }
+            fn is_signaling(self) -> bool { self.is_nan() && self.to_bits() & $quiet == 0 }
+            fn category(self) -> FpCategory { self.classify() }
+            fn abs(self) -> Self { self.abs() }
+            fn next_up(self) -> Self { self.next_up() }
+            fn next_down(self) -> Self { self.next_down() }
+            fn mul_add(self, a: Self, b: Self) -> Self { self.mul_add(a, b) }
+            fn sqrt(self) -> Self { self.sqrt() }
+            fn from_i128(v: i128) -> Self { v as $t }
+            fn to_i128(self) -> i128 { self as i128 }
+        }
+    };
+}
+
+impl_float!(f32, 0x7fc00000, 1 << 22);
+impl_float!(f64, 0x7ff8000000000000, 1 << 51);
+
+pub fn box_s(f: f32) -> u64 {
+    NAN_BOX_S | f.to_bits() as u64
+}
+
+// Values that are not properly NaN-boxed read as the canonical NaN.
+pub fn unbox_s(v: u64) -> f32 {
+    if v & NAN_BOX_S == NAN_BOX_S {
+        f32::from_bits(v as u32)
+    } else {
+        f32::CANONICAL_NAN
+    }
+}
+
+pub fn box_h(h: u16) -> u64 {
+    NAN_BOX_H | h as u64
+}
+
+pub fn unbox_h(v: u64) -> u16 {
+    if v & NAN_BOX_H == NAN_BOX_H {
+        v as u16
+    } else {
+        CANONICAL_NAN_H
+    }
+}
+
+// Exact, NaN payloads and their quiet bit included.
+pub fn half_to_f64(h: u16) -> f64 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = ((h >> 10) & 0x1f) as i32;
+    let mant = (h & 0x3ff) as u64;
+    match exp {
+        0 => sign * mant as f64 * 2f64.powi(-24),
+        0x1f => f64::from_bits((h as u64 & 0x8000) << 48 | 0x7ff0_0000_0000_0000 | mant << 42),
+        _ => sign * (0x400 | mant) as f64 * 2f64.powi(exp - 25),
+    }
+}
+
+fn sign<F: Float>(err: F) -> Ordering {
+    err.partial_cmp(&F::ZERO).unwrap_or(Ordering::Equal)
+}
+
+// Whether r + err lies exactly halfway between r and its neighbour.
+fn is_tie<F: Float>(r: F, err: F) -> bool {
+    let next = if err > F::ZERO { r.next_up() } else { r.next_down() };
+    (next - r).abs() == err.abs() + err.abs()
+}
+
+fn overflow<F: Float>(negative: bool, rm: u8) -> F {
+    let to_max = match rm {
+        RM_RTZ => true,
+        RM_RDN => !negative,
+        RM_RUP => negative,
+        _ => false,
+    };
+    let mag = if to_max { F::MAX } else { F::INFINITY };
+    if negative { -mag } else { mag }
+}
+
+// Applies rounding mode `rm` to the round-to-nearest-even result `r` of an
+// operation whose exact result compares to r as `err`. Underflow is
+// detected after rounding.
+fn round<F: Float>(r: F, err: Ordering, tie: bool, rm: u8, finite: bool) -> (F, u8) {
+    if r.is_infinite() && finite {
+        return (overflow(r.is_sign_negative(), rm), FLAG_OF | FLAG_NX);
+    }
+    if err == Ordering::Equal {
+        return (r, 0);
+    }
+
+    let up = err == Ordering::Greater;
+    let r = match rm {
+        RM_RUP if up => r.next_up(),
+        RM_RDN if !up => r.next_down(),
+        RM_RTZ if !up && r > F::ZERO => r.next_down(),
+        RM_RTZ if up && r < F::ZERO => r.next_up(),
+        RM_RMM if tie && up && r >= F::ZERO => r.next_up(),
+        RM_RMM if tie && !up && r <= F::ZERO => r.next_down(),
+        _ => r,
+    };
+
+    let mut flags = FLAG_NX;
+    if r.is_infinite() {
+        flags |= FLAG_OF;
+    } else if r.abs() < F::MIN_POSITIVE {
+        flags |= FLAG_UF;
+    }
+    (r, flags)
+}
+
+// NaN results are canonical and invalid unless a quiet NaN was propagated.
+fn finish<F: Float>(r: F, err: F, tie: bool, rm: u8, operands: &[F]) -> (F, u8) {
+    let snan = if operands.iter().any(|x| x.is_signaling()) { FLAG_NV } else { 0 };
+    if r.is_nan() {
+        let invalid = if operands.iter().all(|x| !x.is_nan()) { FLAG_NV } else { 0 };
+        return (F::CANONICAL_NAN, snan | invalid);
+    }
+    let finite = operands.iter().all(|x| x.is_finite());
+    let (r, flags) = round(r, sign(err), tie, rm, finite);
+    (r, snan | flags)
+}
+
+fn two_sum<F: Float>(a: F, b: F) -> (F, F) {
+    let s = a + b;
+    let bb = s - a;
+    (s, (a - (s - bb)) + (b - bb))
+}
+
+pub fn add<F: Float>(a: F, b: F, rm: u8) -> (F, u8) {
+    let (s, err) = two_sum(a, b);
+    // An exact zero sum of opposite signs is -0 only when rounding down.
+    if s == F::ZERO && a.is_sign_negative() != b.is_sign_negative() {
+        let s = if rm == RM_RDN { -F::ZERO } else { F::ZERO };
+        return finish(s, F::ZERO, false, rm, &[a, b]);
+    }
+    finish(s, err, s.is_finite() && is_tie(s, err), rm, &[a, b])
+}
+
+pub fn sub<F: Float>(a: F, b: F, rm: u8) -> (F, u8) {
+    add(a, -b, rm)
+}
+
+pub fn mul<F: Float>(a: F, b: F, rm: u8) -> (F, u8) {
+    let p = a * b;
+    let err = a.mul_add(b, -p);
+    finish(p, err, p.is_finite() && is_tie(p, err), rm, &[a, b])
+}
+
+// Quotients and square roots are never exactly halfway.
+pub fn div<F: Float>(a: F, b: F, rm: u8) -> (F, u8) {
+    if b == F::ZERO && a.is_finite() && a != F::ZERO {
+        let negative = a.is_sign_negative() != b.is_sign_negative();
+        return (if negative { -F::INFINITY } else { F::INFINITY }, FLAG_DZ);
+    }
+    let q = a / b;
+    let rem = (-q).mul_add(b, a);
+    let err = if b.is_sign_negative() { -rem } else { rem };
+    finish(q, err, false, rm, &[a, b])
+}
+
+pub fn sqrt<F: Float>(a: F, rm: u8) -> (F, u8) {
+    let s = a.sqrt();
+    let rem = (-s).mul_add(s, a);
+    finish(s, rem, false, rm, &[a])
+}
+
+// a * b + c. The error of the fused result is recovered with the ErrFma
+// construction and is only accurate enough for its sign, so ties are not
+// detected.
+pub fn fma<F: Float>(a: F, b: F, c: F, rm: u8) -> (F, u8) {
+    let r = a.mul_add(b, c);
+    let p = a * b;
+    let pe = a.mul_add(b, -p);
+    let (s, se) = two_sum(c, pe);
+    let (t, te) = two_sum(p, s);
+    let err = ((t - r) + te) + se;
+
+    let zero_inf = (a == F::ZERO && b.is_infinite()) || (a.is_infinite() && b == F::ZERO);
+    let (r, flags) = finish(r, err, false, rm, &[a, b, c]);
+    (r, flags | if zero_inf { FLAG_NV } else { 0 })
+}
+
+pub fn min<F: Float>(a: F, b: F) -> (F, u8) {
+    let flags = if a.is_signaling() || b.is_signaling() { FLAG_NV } else { 0 };
+    let r = match (a.is_nan(), b.is_nan()) {
+        (true, true) => F::CANONICAL_NAN,
+        (true, false) => b,
+        (false, true) => a,
+        // -0.0 is considered less than +0.0
+        _ if a == b => if a.is_sign_negative() { a } else { b },
+        _ => if a < b { a } else { b },
+    };
+    (r, flags)
+}
+
+pub fn max<F: Float>(a: F, b: F) -> (F, u8) {
+    let flags = if a.is_signaling() || b.is_signaling() { FLAG_NV } else { 0 };
+    let r = match (a.is_nan(), b.is_nan()) {
+        (true, true) => F::CANONICAL_NAN,
+        (true, false) => b,
+        (false, true) => a,
+        _ if a == b => if a.is_sign_negative() { b } else { a },
+        _ => if a > b { a } else { b },
+    };
+    (r, flags)
+}
+
+// FEQ is a quiet comparison, FLT and FLE signal on any NaN.
+pub fn eq<F: Float>(a: F, b: F) -> (bool, u8) {
+    let flags = if a.is_signaling() || b.is_signaling() { FLAG_NV } else { 0 };
+    (a == b, flags)
+}
+
+pub fn lt<F: Float>(a: F, b: F) -> (bool, u8) {
+    let flags = if a.is_nan() || b.is_nan() { FLAG_NV } else { 0 };
+    (a < b, flags)
+}
+
+pub fn le<F: Float>(a: F, b: F) -> (bool, u8) {
+    let flags = if a.is_nan() || b.is_nan() { FLAG_NV } else { 0 };
+    (a <= b, flags)
+}
+
+fn round_int(x: f64, rm: u8) -> f64 {
+    match rm {
+        RM_RTZ => x.trunc(),
+        RM_RDN => x.floor(),
+        RM_RUP => x.ceil(),
+        RM_RMM => x.round(),
+        _ => x.round_ties_even(),
+    }
+}
+
+// Float to integer conversion into [min, max]. Out of range values saturate
+// and NaN converts to max, both are invalid.
+pub fn to_int(x: f64, rm: u8, min: i128, max: i128) -> (i128, u8) {
+    if x.is_nan() {
+        return (max, FLAG_NV);
+    }
+    let r = round_int(x, rm);
+    let v = r as i128;
+    if v < min {
+        (min, FLAG_NV)
+    } else if v > max {
+        (max, FLAG_NV)
+    } else {
+        (v, if r != x { FLAG_NX } else { 0 })
+    }
+}
+
+pub fn from_int<F: Float>(v: i128, rm: u8) -> (F, u8) {
+    let r = F::from_i128(v);
+    let err = v - r.to_i128();
+    let tie = err != 0 && {
+        let next = if err > 0 { r.next_up() } else { r.next_down() };
+        (next.to_i128() - r.to_i128()).abs() == 2 * err.abs()
+    };
+    round(r, err.cmp(&0), tie, rm, true)
+}
+
+// FCVT.S.D
+pub fn narrow(x: f64, rm: u8) -> (f32, u8) {
+    if x.is_nan() {
+        return (f32::CANONICAL_NAN, if x.is_signaling() { FLAG_NV } else { 0 });
+    }
+    let r = x as f32;
+    let err = x - r as f64;
+    let tie = r.is_finite() && err != 0.0 && {
+        let next = if err > 0.0 { r.next_up() } else { r.next_down() };
+        (next as f64 - r as f64).abs() == 2.0 * err.abs()
+    };
+    round(r, sign(err), tie, rm, x.is_finite())
+}
+
+// FCVT.D.S is always exact.
+pub fn widen(x: f32) -> (f64, u8) {
+    if x.is_nan() {
+        return (f64::CANONICAL_NAN, if x.is_signaling() { FLAG_NV } else { 0 });
+    }
+    (x as f64, 0)
+}
+
+// Half precision is computed in f64, where sums and products of halves, fused
+// ones included, are exact. Quotients and square roots are not, their sign of
+// error takes part in rounding so that the result is still rounded once.
+
+// Rounds the non-NaN value x, whose exact value compares to it as `err`.
+fn round_h(x: f64, err: Ordering, rm: u8) -> (u16, u8) {
+    let negative = x.is_sign_negative();
+    let sign = if negative { 0x8000 } else { 0 };
+    if x.is_infinite() {
+        return (sign | 0x7c00, 0);
+    }
+
+    // The exact magnitude lies between n and n + 1 quanta, `half` tells on
+    // which side of the midpoint.
+    let mag = x.abs();
+    let err = if negative { err.reverse() } else { err };
+    let exp = (mag.to_bits() >> 52) as i32 - 1023;
+    let quantum = 2f64.powi(exp.max(-14) - 10);
+    let m = mag / quantum;
+    let mut n = m.trunc();
+    let (half, inexact) = match (m.fract() == 0.0, err) {
+        (true, Ordering::Equal) => (Ordering::Less, false),
+        (true, Ordering::Greater) => (Ordering::Less, true),
+        (true, Ordering::Less) => {
+            n -= 1.0;
+            (Ordering::Greater, true)
+        }
+        _ => (m.fract().partial_cmp(&0.5).unwrap_or(Ordering::Equal).then(err), true),
+    };
+    let up = match rm {
+        RM_RTZ => false,
+        RM_RDN => inexact && negative,
+        RM_RUP => inexact && !negative,
+        RM_RMM => half != Ordering::Less,
+        _ => half == Ordering::Greater || (half == Ordering::Equal && n % 2.0 == 1.0),
+    };
+    let r = (n + if up { 1.0 } else { 0.0 }) * quantum;
+
+    if r > MAX_H {
+        let to_max = match rm {
+            RM_RTZ => true,
+            RM_RDN => !negative,
+            RM_RUP => negative,
+            _ => false,
+        };
+        return (sign | if to_max { 0x7bff } else { 0x7c00 }, FLAG_OF | FLAG_NX);
+    }
+    let tiny = r < 2f64.powi(-14);
+    let bits = if tiny {
+        (r / 2f64.powi(-24)) as u16
+    } else {
+        let exp = (r.to_bits() >> 52) as i32 - 1023;
+        ((exp + 15) as u16) << 10 | ((r / 2f64.powi(exp - 10)) as u16 - 0x400)
+    };
+    let flags = match (inexact, tiny) {
+        (false, _) => 0,
+        (true, false) => FLAG_NX,
+        (true, true) => FLAG_NX | FLAG_UF,
+    };
+    (sign | bits, flags)
+}
+
+fn finish_h(r: f64, err: Ordering, rm: u8, operands: &[f64]) -> (u16, u8) {
+    let snan = if operands.iter().any(|x| x.is_signaling()) { FLAG_NV } else { 0 };
+    if r.is_nan() {
+        let invalid = if operands.iter().all(|x| !x.is_nan()) { FLAG_NV } else { 0 };
+        return (CANONICAL_NAN_H, snan | invalid);
+    }
+    let (r, flags) = round_h(r, err, rm);
+    (r, snan | flags)
+}
+
+// Exact zero results of opposite signed operands follow the rounding mode.
+fn zero_sign(s: f64, a: f64, b: f64, rm: u8) -> f64 {
+    if s == 0.0 && a.is_sign_negative() != b.is_sign_negative() {
+        if rm == RM_RDN { -0.0 } else { 0.0 }
+    } else {
+        s
+    }
+}
+
+pub fn add_h(a: f64, b: f64, rm: u8) -> (u16, u8) {
+    finish_h(zero_sign(a + b, a, b, rm), Ordering::Equal, rm, &[a, b])
+}
+
+pub fn sub_h(a: f64, b: f64, rm: u8) -> (u16, u8) {
+    add_h(a, -b, rm)
+}
+
+pub fn mul_h(a: f64, b: f64, rm: u8) -> (u16, u8) {
+    finish_h(a * b, Ordering::Equal, rm, &[a, b])
+}
+
+pub fn div_h(a: f64, b: f64, rm: u8) -> (u16, u8) {
+    if b == 0.0 && a.is_finite() && a != 0.0 {
+        let negative = a.is_sign_negative() != b.is_sign_negative();
+        return (if negative { 0xfc00 } else { 0x7c00 }, FLAG_DZ);
+    }
+    let q = a / b;
+    let rem = (-q).mul_add(b, a);
+    let err = if b.is_sign_negative() { -rem } else { rem };
+    finish_h(q, sign(err), rm, &[a, b])
+}
+
+pub fn sqrt_h(a: f64, rm: u8) -> (u16, u8) {
+    let s = a.sqrt();
+    let rem = (-s).mul_add(s, a);
+    finish_h(s, sign(rem), rm, &[a])
+}
+
+pub fn fma_h(a: f64, b: f64, c: f64, rm: u8) -> (u16, u8) {
+    let p = a * b;
+    let (s, e) = two_sum(p, c);
+    let err = if s.is_finite() { sign(e) } else { Ordering::Equal };
+    let s = if e == 0.0 { zero_sign(s, p, c, rm) } else { s };
+
+    let zero_inf = (a == 0.0 && b.is_infinite()) || (a.is_infinite() && b == 0.0);
+    let (r, flags) = finish_h(s, err, rm, &[a, b, c]);
+    (r, flags | if zero_inf { FLAG_NV } else { 0 })
+}
+
+// The result is an operand or NaN, finish_h only converts it back.
+pub fn min_h(a: f64, b: f64) -> (u16, u8) {
+    let (r, _) = min(a, b);
+    finish_h(r, Ordering::Equal, 0, &[a, b])
+}
+
+pub fn max_h(a: f64, b: f64) -> (u16, u8) {
+    let (r, _) = max(a, b);
+    finish_h(r, Ordering::Equal, 0, &[a, b])
+}
+
+pub fn from_int_h(v: i128, rm: u8) -> (u16, u8) {
+    let r = v as f64;
+    round_h(r, (v - r as i128).cmp(&0), rm)
+}
+
+// FCVT.H.S and FCVT.H.D, x being the source widened to f64.
+pub fn narrow_h(x: f64, rm: u8) -> (u16, u8) {
+    finish_h(x, Ordering::Equal, rm, &[x])
+}
+
+// FCVT.S.H and FCVT.D.H are always exact.
+pub fn widen_h(h: u16) -> (f64, u8) {
+    let x = half_to_f64(h);
+    if x.is_nan() {
+        return (f64::CANONICAL_NAN, if x.is_signaling() { FLAG_NV } else { 0 });
+    }
+    (x, 0)
+}
+
+// Subnormal halves are normal doubles.
+pub fn class_h(h: u16) -> u64 {
+    let x = half_to_f64(h);
+    if h & 0x7c00 == 0 && h & 0x3ff != 0 {
+        return if x.is_sign_negative() { 1 << 2 } else { 1 << 5 };
+    }
+    class(x)
+}
+
+pub fn class<F: Float>(f: F) -> u64 {
+    let bit = match (f.category(), f.is_sign_negative()) {
+        (FpCategory::Infinite, true) => 0,
+        (FpCategory::Normal, true) => 1,
+        (FpCategory::Subnormal, true) => 2,
+        (FpCategory::Zero, true) => 3,
+        (FpCategory::Zero, false) => 4,
+        (FpCategory::Subnormal, false) => 5,
+        (FpCategory::Normal, false) => 6,
+        (FpCategory::Infinite, false) => 7,
+        (FpCategory::Nan, _) => if f.is_signaling() { 8 } else { 9 },
+    };
+    1 << bit
+}
@@ -0,0 +1,739 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::cpu::{Cpu, WatchKind};
+use crate::registers::{self, ABI_NAMES};
+use crate::hosttime::Account;
+use crate::monitor::Monitor;
+
+#[cfg(test)]
+mod tests;
+
+// How many steps to run between polls of the socket for a Ctrl-C.
+const INTERRUPT_POLL_INTERVAL: u64 = 4096;
+// The qSupported reply.
+pub const SUPPORTED: &str = "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+";
+
+// What a single step may do, set like QEMU's with `maintenance packet
+// Qqemu.sstep=<bits>`. By default a step executes the next instruction with
+// interrupts held off and the timers stopped, a pending interrupt is taken
+// after it once the target runs on. Without NOIRQ a step into a pending
+// interrupt stops at the first instruction of its handler.
+const SSTEP_ENABLE: u8 = 0x1;
+const SSTEP_NOIRQ: u8 = 0x2;
+const SSTEP_NOTIMER: u8 = 0x4;
+const SSTEP_DEFAULT: u8 = SSTEP_ENABLE | SSTEP_NOIRQ | SSTEP_NOTIMER;
+
+const REG_PC: usize = 32;
+const REG_SP: usize = 2;
+
+// Semihosting operations forwarded over the gdb File-I/O protocol.
+const SYS_OPEN: u64 = 0x01;
+const SYS_CLOSE: u64 = 0x02;
+const SYS_WRITEC: u64 = 0x03;
+const SYS_WRITE0: u64 = 0x04;
+const SYS_WRITE: u64 = 0x05;
+const SYS_READ: u64 = 0x06;
+const SYS_ISTTY: u64 = 0x09;
+const SYS_SEEK: u64 = 0x0A;
+const SYS_REMOVE: u64 = 0x0E;
+const SYS_SYSTEM: u64 = 0x12;
+const SYS_ERRNO: u64 = 0x13;
+const SYS_EXIT: u64 = 0x18;
+
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+// File-I/O open flags indexed by the semihosting fopen() mode.
+const FIO_O_RDONLY: u64 = 0x0;
+const FIO_O_WRONLY: u64 = 0x1;
+const FIO_O_RDWR: u64 = 0x2;
+const FIO_O_APPEND: u64 = 0x8;
+const FIO_O_CREAT: u64 = 0x200;
+const FIO_O_TRUNC: u64 = 0x400;
+const OPEN_FLAGS: [u64; 6] = [
+    FIO_O_RDONLY,
+    FIO_O_RDWR,
+    FIO_O_WRONLY | FIO_O_CREAT | FIO_O_TRUNC,
+    FIO_O_RDWR | FIO_O_CREAT | FIO_O_TRUNC,
+    FIO_O_WRONLY | FIO_O_CREAT | FIO_O_APPEND,
+    FIO_O_RDWR | FIO_O_CREAT | FIO_O_APPEND,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum StopReason {
+    Step,
+    Interrupt,
+    Breakpoint { hw: bool },
+    Watchpoint(WatchKind, u64),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Breakpoint {
+    addr: u64,
+    hw: bool,
+}
+
+pub struct Gdb {
+    stream: TcpStream,
+    breakpoints: Vec<Breakpoint>,
+    monitor: Monitor,
+
+    stepping: bool,
+    sstep: u8,
+    just_resumed: bool,
+    detached: bool,
+    steps: u64,
+    // set by kill and a semihosting exit, the run loop ends the run
+    exit: Option<i32>,
+
+    semihost_errno: u64,
+}
+
+fn hex_u64_le(val: u64) -> String {
+    val.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+// A register of the target description. Numbers follow gdb's RISC-V
+// numbering, CSRs at 65 + their number and v0 after the privilege level, and
+// the `g` packet holds every described register in that order. FP and vector
+// registers are only described when the hart has F or V.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Reg {
+    X(usize),
+    Pc,
+    F(usize),
+    Csr(u32),
+    V(usize),
+}
+
+const FEATURE_CORE: &str = "org.gnu.gdb.riscv.core";
+const FEATURE_FPU: &str = "org.gnu.gdb.riscv.fpu";
+const FEATURE_VECTOR: &str = "org.gnu.gdb.riscv.vector";
+const FPU_CSRS: [u32; 3] = [0x001, 0x002, 0x003];
+const VECTOR_CSRS: [u32; 7] = [0x008, 0x009, 0x00A, 0x00F, 0xC20, 0xC21, 0xC22];
+
+impl Reg {
+    fn num(self) -> usize {
+        match self {
+            Reg::X(i) => i,
+            Reg::Pc => REG_PC,
+            Reg::F(i) => 33 + i,
+            Reg::Csr(num) => 65 + num as usize,
+            Reg::V(i) => 4162 + i,
+        }
+    }
+
+    fn feature(self) -> &'static str {
+        match self {
+            Reg::X(_) | Reg::Pc => FEATURE_CORE,
+            Reg::F(_) => FEATURE_FPU,
+            Reg::Csr(num) if FPU_CSRS.contains(&num) => FEATURE_FPU,
+            Reg::Csr(_) | Reg::V(_) => FEATURE_VECTOR,
+        }
+    }
+
+    fn bits(self, cpu: &Cpu) -> usize {
+        match self {
+            Reg::F(_) if cpu.isa().has("d") => 64,
+            Reg::F(_) => 32,
+            Reg::Csr(num) if FPU_CSRS.contains(&num) => 32,
+            Reg::V(_) => cpu.vlen(),
+            _ => 64,
+        }
+    }
+
+    fn read(self, cpu: &Cpu) -> Vec<u8> {
+        let val = match self {
+            Reg::X(i) => cpu.reg(i),
+            Reg::Pc => cpu.pc(),
+            Reg::F(i) => cpu.freg(i),
+            Reg::Csr(num) => cpu.debug_read_csr(num).unwrap_or(0),
+            Reg::V(i) => return cpu.vreg(i).to_vec(),
+        };
+        val.to_le_bytes()[..self.bits(cpu) / 8].to_vec()
+    }
+
+    // False for writes the register does not take, such as to vl.
+    fn write(self, cpu: &mut Cpu, bytes: &[u8]) -> bool {
+        if let Reg::V(i) = self {
+            cpu.set_vreg(i, bytes);
+            return true;
+        }
+        let mut val = [0; 8];
+        val[..bytes.len()].copy_from_slice(bytes);
+        let val = u64::from_le_bytes(val);
+        match self {
+            Reg::X(i) => cpu.set_reg(i, val),
+            Reg::Pc => cpu.set_pc(val),
+            // single precision values are NaN-boxed
+            Reg::F(i) if bytes.len() == 4 => cpu.set_freg(i, val | 0xffff_ffff_0000_0000),
+            Reg::F(i) => cpu.set_freg(i, val),
+            Reg::Csr(num) => return cpu.debug_write_csr(num, val),
+            Reg::V(_) => unreachable!(),
+        }
+        true
+    }
+}
+
+fn registers(cpu: &Cpu) -> Vec<Reg> {
+    let mut regs: Vec<Reg> = (0..32).map(Reg::X).collect();
+    regs.push(Reg::Pc);
+    if cpu.isa().has("f") {
+        regs.extend((0..32).map(Reg::F));
+        regs.extend(FPU_CSRS.map(Reg::Csr));
+    }
+    if cpu.isa().has("v") {
+        regs.extend(VECTOR_CSRS.map(Reg::Csr));
+        regs.extend((0..32).map(Reg::V));
+    }
+    regs
+}
+
+fn find_reg(cpu: &Cpu, num: u64) -> Option<Reg> {
+    registers(cpu).into_iter().find(|reg| reg.num() as u64 == num)
+}
+
+fn reg_xml(cpu: &Cpu, reg: Reg) -> String {
+    let (name, ty) = match reg {
+        Reg::X(2) => ("sp".to_string(), "data_ptr"),
+        Reg::X(i) => (ABI_NAMES[i].to_string(), "int"),
+        Reg::Pc => ("pc".to_string(), "code_ptr"),
+        Reg::F(i) => (format!("f{}", i), if reg.bits(cpu) == 64 { "ieee_double" } else { "ieee_single" }),
+        Reg::Csr(num) => (registers::csr_name(cpu, num), "int"),
+        Reg::V(i) => (format!("v{}", i), "riscv_vector"),
+    };
+    format!("<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>", name, reg.bits(cpu), ty, reg.num())
+}
+
+fn target_xml(cpu: &Cpu) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\"?>",
+        "<!DOCTYPE target SYSTEM \"gdb-target.dtd\">",
+        "<target version=\"1.0\">",
+        "<architecture>riscv:rv64</architecture>",
+    ));
+    let regs = registers(cpu);
+    for feature in [FEATURE_CORE, FEATURE_FPU, FEATURE_VECTOR] {
+        let regs: Vec<Reg> = regs.iter().copied().filter(|reg| reg.feature() == feature).collect();
+        if regs.is_empty() {
+            continue;
+        }
+        xml.push_str(&format!("<feature name=\"{}\">", feature));
+        if feature == FEATURE_VECTOR {
+            let vlenb = cpu.vlen() / 8;
+            for (ty, bytes) in [("uint8", 1), ("uint16", 2), ("uint32", 4), ("uint64", 8)] {
+                xml.push_str(&format!("<vector id=\"v{}\" type=\"{}\" count=\"{}\"/>", ty, ty, vlenb / bytes));
+            }
+            xml.push_str(concat!("<union id=\"riscv_vector\">",
+                "<field name=\"b\" type=\"vuint8\"/><field name=\"s\" type=\"vuint16\"/>",
+                "<field name=\"w\" type=\"vuint32\"/><field name=\"l\" type=\"vuint64\"/>",
+                "</union>"));
+        }
+        for reg in regs {
+            xml.push_str(&reg_xml(cpu, reg));
+        }
+        xml.push_str("</feature>");
+    }
+    xml.push_str("</target>");
+    xml
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
+
+impl Gdb {
+    pub fn listen(port: u16) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        eprintln!("gdb: waiting for connection on port {}", port);
+        let (stream, peer) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        eprintln!("gdb: connected from {}", peer);
+
+        Ok(Gdb {
+            stream,
+            breakpoints: Vec::new(),
+            monitor: Monitor::new(),
+            stepping: false,
+            sstep: SSTEP_DEFAULT,
+            just_resumed: false,
+            detached: false,
+            steps: 0,
+            semihost_errno: 0,
+            exit: None,
+        })
+    }
+
+    // Called before every step. Blocks in the command loop while the target
+    // is halted.
+    pub fn poll(&mut self, cpu: &mut Cpu) {
+        if self.detached {
+            return;
+        }
+        let start = cpu.host_time().start();
+
+        if let Some((op, arg)) = cpu.take_semihost_call() {
+            let ret = self.semihost(cpu, op, arg);
+            cpu.complete_semihost_call(ret as u64);
+            if self.detached {
+                return;
+            }
+        }
+
+        // Logpoints go to gdb's console, this is only valid while running.
+        if !self.just_resumed
+            && let Some(msg) = self.monitor.logpoint(cpu)
+        {
+            let hex: String = format!("{}\n", msg).bytes().map(|b| format!("{:02x}", b)).collect();
+            self.send(&format!("O{}", hex));
+        }
+
+        let reason = self.stop_reason(cpu);
+        self.just_resumed = false;
+        // Time halted in the command loop is spent waiting for gdb.
+        cpu.host_time_mut().stop(Account::Gdb, start);
+
+        if let Some(reason) = reason {
+            self.stepping = false;
+            cpu.set_irqs_masked(false);
+            cpu.set_timers_frozen(false);
+            let reply = self.stop_reply(cpu, reason);
+            self.send(&reply);
+            self.command_loop(cpu);
+        }
+    }
+
+    // The status gdb ended the run with, by kill or a semihosting exit.
+    pub fn take_exit(&mut self) -> Option<i32> {
+        self.exit.take()
+    }
+
+    pub fn take_config_reload(&mut self) -> bool {
+        self.monitor.take_config_reload()
+    }
+
+    // Halts the target right away, used on startup before the first step.
+    pub fn halt(&mut self, cpu: &mut Cpu) {
+        self.command_loop(cpu);
+    }
+
+    fn stop_reason(&mut self, cpu: &mut Cpu) -> Option<StopReason> {
+        if let Some((kind, addr)) = cpu.take_watch_hit() {
+            return Some(StopReason::Watchpoint(kind, addr));
+        }
+        if self.stepping {
+            return Some(StopReason::Step);
+        }
+        if !self.just_resumed
+            && let Some(bp) = self.breakpoints.iter().find(|bp| bp.addr == cpu.pc())
+            && self.monitor.breakpoint_hit(cpu, bp.addr)
+        {
+            return Some(StopReason::Breakpoint { hw: bp.hw });
+        }
+
+        self.steps += 1;
+        if self.steps.is_multiple_of(INTERRUPT_POLL_INTERVAL) && self.interrupt_pending() {
+            return Some(StopReason::Interrupt);
+        }
+
+        None
+    }
+
+    fn stop_reply(&self, cpu: &Cpu, reason: StopReason) -> String {
+        let signal = match reason {
+            StopReason::Interrupt => 0x02, // SIGINT
+            _ => 0x05,                     // SIGTRAP
+        };
+
+        let mut reply = format!("T{:02x}thread:01;{:02x}:{};{:02x}:{};", signal,
+            REG_PC, hex_u64_le(cpu.pc()), REG_SP, hex_u64_le(cpu.reg(REG_SP)));
+        match reason {
+            StopReason::Breakpoint { hw: false } => reply.push_str("swbreak:;"),
+            StopReason::Breakpoint { hw: true } => reply.push_str("hwbreak:;"),
+            StopReason::Watchpoint(kind, addr) => {
+                let name = match kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                reply.push_str(&format!("{}:{:x};", name, addr));
+            }
+            StopReason::Step | StopReason::Interrupt => {}
+        }
+
+        reply
+    }
+
+    fn interrupt_pending(&mut self) -> bool {
+        let mut byte = [0u8; 1];
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let res = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false).unwrap();
+        matches!(res, Ok(1) if byte[0] == 0x03)
+    }
+
+    fn command_loop(&mut self, cpu: &mut Cpu) {
+        loop {
+            let Some(packet) = self.recv() else {
+                eprintln!("gdb: connection closed");
+                self.detached = true;
+                return;
+            };
+
+            match packet.as_bytes().first() {
+                Some(b'c') => {
+                    self.just_resumed = true;
+                    return;
+                }
+                Some(b's') => {
+                    self.stepping = true;
+                    cpu.set_irqs_masked(self.sstep & SSTEP_NOIRQ != 0);
+                    cpu.set_timers_frozen(self.sstep & SSTEP_NOTIMER != 0);
+                    self.just_resumed = true;
+                    return;
+                }
+                Some(b'D') => {
+                    self.send("OK");
+                    self.detached = true;
+                    return;
+                }
+                Some(b'k') => {
+                    self.exit = Some(0);
+                    self.detached = true;
+                    return;
+                }
+                _ => {
+                    let start = cpu.host_time().start();
+                    let reply = self.handle(cpu, &packet);
+                    self.send(&reply);
+                    cpu.host_time_mut().stop(Account::Gdb, start);
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, cpu: &mut Cpu, packet: &str) -> String {
+        if packet.is_empty() {
+            return String::new();
+        }
+        let (cmd, args) = packet.split_at(1);
+        match cmd {
+            "?" => self.stop_reply(cpu, StopReason::Step),
+            "g" => registers(cpu).into_iter().map(|reg| hex(&reg.read(cpu))).collect(),
+            "G" => {
+                let Some(mut bytes) = parse_bytes(args) else {
+                    return "E01".into();
+                };
+                // only changed registers are written, which keeps FS and VS
+                // clean and skips read-only CSRs
+                for reg in registers(cpu) {
+                    let len = reg.bits(cpu) / 8;
+                    if bytes.len() < len {
+                        return "E01".into();
+                    }
+                    let rest = bytes.split_off(len);
+                    if reg.read(cpu) != bytes {
+                        reg.write(cpu, &bytes);
+                    }
+                    bytes = rest;
+                }
+                "OK".into()
+            }
+            "p" => match parse_hex(args).and_then(|n| find_reg(cpu, n)) {
+                Some(reg) => hex(&reg.read(cpu)),
+                None => "E01".into(),
+            },
+            "P" => {
+                let Some((n, val)) = args.split_once('=') else {
+                    return "E01".into();
+                };
+                let (Some(reg), Some(val)) = (parse_hex(n).and_then(|n| find_reg(cpu, n)), parse_bytes(val)) else {
+                    return "E01".into();
+                };
+                if val.len() == reg.bits(cpu) / 8 && reg.write(cpu, &val) {
+                    "OK".into()
+                } else {
+                    "E01".into()
+                }
+            }
+            "m" => {
+                let Some((addr, len)) = args.split_once(',') else {
+                    return "E01".into();
+                };
+                let (Some(addr), Some(len)) = (parse_hex(addr), parse_hex(len)) else {
+                    return "E01".into();
+                };
+                let mut reply = String::new();
+                for a in addr..addr.wrapping_add(len) {
+                    match cpu.debug_read_u8(a) {
+                        Some(b) => reply.push_str(&format!("{:02x}", b)),
+                        None if reply.is_empty() => return "E14".into(),
+                        None => break,
+                    }
+                }
+                reply
+            }
+            "M" => {
+                let Some((range, data)) = args.split_once(':') else {
+                    return "E01".into();
+                };
+                let Some(addr) = range.split_once(',').and_then(|(a, _)| parse_hex(a)) else {
+                    return "E01".into();
+                };
+                let bytes: Option<Vec<u8>> = data.as_bytes().chunks(2)
+                    .map(|c| std::str::from_utf8(c).ok().and_then(|c| u8::from_str_radix(c, 16).ok()))
+                    .collect();
+                let Some(bytes) = bytes else {
+                    return "E01".into();
+                };
+                if !cpu.debug_write_mem(addr, &bytes) {
+                    return "E14".into();
+                }
+                "OK".into()
+            }
+            "Z" | "z" => self.handle_breakpoint(cpu, cmd == "Z", args),
+            "H" => "OK".into(),
+            "T" => "OK".into(),
+            "q" => self.handle_query(cpu, args),
+            "Q" => match args.strip_prefix("qemu.sstep=").and_then(|bits| parse_hex(bits.trim_start_matches("0x"))) {
+                Some(bits) if bits & !(SSTEP_DEFAULT as u64) == 0 && bits as u8 & SSTEP_ENABLE != 0 => {
+                    self.sstep = bits as u8;
+                    "OK".into()
+                }
+                Some(_) => "E22".into(),
+                None => String::new(),
+            },
+            _ => String::new(),
+        }
+    }
+
+    fn handle_breakpoint(&mut self, cpu: &mut Cpu, insert: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+        let (Some(ty), Some(addr), Some(kind)) = (parts.next(), parts.next(), parts.next()) else {
+            return "E01".into();
+        };
+        let (Some(addr), Some(len)) = (parse_hex(addr), parse_hex(kind)) else {
+            return "E01".into();
+        };
+
+        let watch = match ty {
+            "0" | "1" => {
+                let bp = Breakpoint { addr, hw: ty == "1" };
+                if insert {
+                    self.breakpoints.push(bp);
+                } else {
+                    self.breakpoints.retain(|b| *b != bp);
+                }
+                return "OK".into();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+
+        if insert {
+            cpu.add_watchpoint(watch, addr, len);
+        } else {
+            cpu.remove_watchpoint(watch, addr, len);
+        }
+        "OK".into()
+    }
+
+    fn handle_query(&mut self, cpu: &mut Cpu, args: &str) -> String {
+        if let Some(cmd) = args.strip_prefix("Rcmd,") {
+            let cmd: Option<Vec<u8>> = cmd.as_bytes().chunks(2)
+                .map(|c| std::str::from_utf8(c).ok().and_then(|c| u8::from_str_radix(c, 16).ok()))
+                .collect();
+            let Some(cmd) = cmd.and_then(|c| String::from_utf8(c).ok()) else {
+                return "E01".into();
+            };
+            let start = cpu.host_time().start();
+            let output = self.monitor.command(cpu, &cmd);
+            cpu.host_time_mut().stop(Account::Monitor, start);
+            if output.is_empty() {
+                return "OK".into();
+            }
+            return output.bytes().map(|b| format!("{:02x}", b)).collect();
+        }
+        if args.starts_with("Supported") {
+            return SUPPORTED.into();
+        }
+        if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let Some((off, len)) = range.split_once(',') else {
+                return "E01".into();
+            };
+            let (Some(off), Some(len)) = (parse_hex(off), parse_hex(len)) else {
+                return "E01".into();
+            };
+            let xml = target_xml(cpu);
+            let start = (off as usize).min(xml.len());
+            let end = (start + len as usize).min(xml.len());
+            let prefix = if end == xml.len() { "l" } else { "m" };
+            return format!("{}{}", prefix, &xml[start..end]);
+        }
+
+        match args {
+            "Attached" => "1".into(),
+            "C" => "QC01".into(),
+            "qemu.sstepbits" => format!("ENABLE={:x},NOIRQ={:x},NOTIMER={:x}", SSTEP_ENABLE, SSTEP_NOIRQ, SSTEP_NOTIMER),
+            "qemu.sstep" => format!("0x{:x}", self.sstep),
+            "fThreadInfo" => "m01".into(),
+            "sThreadInfo" => "l".into(),
+            _ => String::new(),
+        }
+    }
+
+    fn semihost(&mut self, cpu: &mut Cpu, op: u64, arg: u64) -> i64 {
+        let param = |idx: u64| cpu.debug_read_u64(arg + idx * 8).unwrap_or(0);
+
+        match op {
+            SYS_OPEN => {
+                let (path, mode, len) = (param(0), param(1), param(2));
+                let name: Vec<u8> = (0..len).filter_map(|i| cpu.debug_read_u8(path + i)).collect();
+                if name == b":tt" {
+                    // console: r is stdin, w is stdout, a is stderr
+                    return (mode / 4) as i64;
+                }
+                let Some(flags) = OPEN_FLAGS.get(mode as usize / 2) else {
+                    return -1;
+                };
+                self.file_io(cpu, &format!("Fopen,{:x}/{:x},{:x},1a4", path, len + 1, flags))
+            }
+            SYS_CLOSE => self.file_io(cpu, &format!("Fclose,{:x}", param(0))),
+            SYS_WRITEC => {
+                self.file_io(cpu, &format!("Fwrite,1,{:x},1", arg));
+                0
+            }
+            SYS_WRITE0 => {
+                let len = (0..).take_while(|i| matches!(cpu.debug_read_u8(arg + i), Some(b) if b != 0))
+                    .count();
+                self.file_io(cpu, &format!("Fwrite,1,{:x},{:x}", arg, len));
+                0
+            }
+            SYS_WRITE | SYS_READ => {
+                let (fd, buf, len) = (param(0), param(1), param(2));
+                let name = if op == SYS_WRITE { "write" } else { "read" };
+                let ret = self.file_io(cpu, &format!("F{},{:x},{:x},{:x}", name, fd, buf, len));
+                // semihosting returns the number of bytes not transferred
+                if ret < 0 { len as i64 } else { len as i64 - ret }
+            }
+            SYS_ISTTY => self.file_io(cpu, &format!("Fisatty,{:x}", param(0))),
+            SYS_SEEK => {
+                let ret = self.file_io(cpu, &format!("Flseek,{:x},{:x},0", param(0), param(1)));
+                if ret < 0 { -1 } else { 0 }
+            }
+            SYS_REMOVE => self.file_io(cpu, &format!("Funlink,{:x}/{:x}", param(0), param(1) + 1)),
+            SYS_SYSTEM => self.file_io(cpu, &format!("Fsystem,{:x}/{:x}", param(0), param(1) + 1)),
+            SYS_ERRNO => self.semihost_errno as i64,
+            SYS_EXIT => {
+                let (reason, code) = (param(0), param(1));
+                let code = if reason == ADP_STOPPED_APPLICATION_EXIT { code as i32 } else { 1 };
+                self.send(&format!("W{:02x}", code as u8));
+                self.exit = Some(code);
+                self.detached = true;
+                0
+            }
+            _ => {
+                eprintln!("gdb: unsupported semihosting call {:#x}", op);
+                -1
+            }
+        }
+    }
+
+    // Sends a File-I/O request and serves gdb's memory accesses until the
+    // matching F reply arrives.
+    fn file_io(&mut self, cpu: &mut Cpu, request: &str) -> i64 {
+        self.send(request);
+        loop {
+            let Some(packet) = self.recv() else {
+                self.detached = true;
+                return -1;
+            };
+
+            let Some(reply) = packet.strip_prefix('F') else {
+                let reply = self.handle(cpu, &packet);
+                self.send(&reply);
+                continue;
+            };
+
+            let mut fields = reply.split(',');
+            let ret = fields.next().and_then(|r| match r.strip_prefix('-') {
+                Some(r) => parse_hex(r).map(|r| -(r as i64)),
+                None => parse_hex(r).map(|r| r as i64),
+            }).unwrap_or(-1);
+            if let Some(errno) = fields.next().and_then(parse_hex) {
+                self.semihost_errno = errno;
+            }
+            return ret;
+        }
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0u8; 1];
+        match self.stream.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    fn recv(&mut self) -> Option<String> {
+        loop {
+            // skip acks and stray interrupts until the start of a packet
+            while self.read_byte()? != b'$' {}
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    b => data.push(b),
+                }
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let checksum = std::str::from_utf8(&checksum).ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok());
+
+            let sum = data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+            if checksum == Some(sum) {
+                self.stream.write_all(b"+").ok()?;
+                return String::from_utf8(data).ok();
+            }
+            self.stream.write_all(b"-").ok()?;
+        }
+    }
+
+    fn send(&mut self, data: &str) {
+        let sum = data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, sum);
+        if self.stream.write_all(packet.as_bytes()).is_err() {
+            self.detached = true;
+            return;
+        }
+
+        // wait for the ack, retransmitting on a nack
+        loop {
+            match self.read_byte() {
+                Some(b'+') | None => return,
+                Some(b'-') => {
+                    if self.stream.write_all(packet.as_bytes()).is_err() {
+                        return;
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+    }
+}
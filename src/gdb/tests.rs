@@ -0,0 +1,61 @@
+use super::*;
+use crate::isa::Isa;
+
+fn hart(isa: &str) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.set_isa(Isa::parse(isa).unwrap());
+    cpu
+}
+
+// The bitsize of every register in the description, in regnum order.
+fn described_bits(xml: &str) -> Vec<(usize, usize)> {
+    let attr = |reg: &str, name: &str| -> usize {
+        let start = reg.find(&format!("{}=\"", name)).unwrap() + name.len() + 2;
+        reg[start..].split('"').next().unwrap().parse().unwrap()
+    };
+    let mut regs: Vec<(usize, usize)> = xml.split("<reg ").skip(1)
+        .map(|reg| (attr(reg, "regnum"), attr(reg, "bitsize")))
+        .collect();
+    regs.sort();
+    regs
+}
+
+#[test]
+fn g_packet_follows_the_description() {
+    for isa in ["rv64ima", "rv64imafd", "rv64imafdv"] {
+        let cpu = hart(isa);
+        let regs = described_bits(&target_xml(&cpu));
+        let g: String = registers(&cpu).into_iter().map(|reg| hex(&reg.read(&cpu))).collect();
+        assert_eq!(g.len(), regs.iter().map(|(_, bits)| bits / 4).sum::<usize>(), "{}", isa);
+    }
+    let xml = target_xml(&hart("rv64ima"));
+    assert!(!xml.contains(FEATURE_FPU) && !xml.contains(FEATURE_VECTOR));
+}
+
+#[test]
+fn fp_and_vector_registers() {
+    let mut cpu = hart("rv64imafdv");
+    let xml = target_xml(&cpu);
+    assert!(xml.contains(r#"<reg name="fcsr" bitsize="32" type="int" regnum="68"/>"#));
+    assert!(xml.contains(&format!(r#"<reg name="v31" bitsize="{}" type="riscv_vector" regnum="4193"/>"#, cpu.vlen())));
+
+    let f1 = find_reg(&cpu, 34).unwrap();
+    assert!(f1.write(&mut cpu, &1.5f64.to_le_bytes()));
+    assert_eq!(cpu.freg(1), 1.5f64.to_bits());
+
+    let v2 = find_reg(&cpu, 4164).unwrap();
+    let bytes: Vec<u8> = (0..cpu.vlen() as u8 / 8).collect();
+    assert!(v2.write(&mut cpu, &bytes));
+    assert_eq!(v2.read(&cpu), bytes);
+
+    // vlenb is read-only
+    assert!(!find_reg(&cpu, 65 + 0xC22).unwrap().write(&mut cpu, &[0; 8]));
+
+    // single precision only, NaN-boxed
+    let mut cpu = hart("rv64imaf");
+    let f1 = find_reg(&cpu, 34).unwrap();
+    assert!(f1.write(&mut cpu, &1.5f32.to_le_bytes()));
+    assert_eq!(cpu.freg(1), 0xffff_ffff_0000_0000 | 1.5f32.to_bits() as u64);
+    assert_eq!(f1.read(&cpu), 1.5f32.to_le_bytes());
+    assert_eq!(find_reg(&hart("rv64ima"), 34), None);
+}
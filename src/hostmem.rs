@@ -0,0 +1,93 @@
+use std::ffi::c_void;
+use std::io;
+
+// How guest RAM is backed on the host, for performance studies of big
+// guests: `--host-mem hugepages,node=1`. `hugepages` asks for transparent
+// huge pages, `node` binds RAM to a NUMA node. Both are applied before the
+// guest touches RAM and take effect as its pages are faulted in.
+
+const HUGE_PAGE: usize = 2 << 20;
+const PAGE: usize = 4096;
+
+const MADV_HUGEPAGE: i32 = 14;
+const MPOL_BIND: i64 = 2;
+// The node mask is a single word.
+const MAX_NODE: u32 = 64;
+
+#[cfg(target_arch = "x86_64")]
+const SYS_MBIND: i64 = 237;
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+const SYS_MBIND: i64 = 235;
+
+unsafe extern "C" {
+    fn madvise(addr: *mut c_void, len: usize, advice: i32) -> i32;
+    fn syscall(num: i64, ...) -> i64;
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct HostMem {
+    pub hugepages: bool,
+    pub node: Option<u32>,
+}
+
+// The part of `ram` aligned to `align`, RAM is not allocated aligned.
+fn aligned(ram: &mut [u8], align: usize) -> (*mut c_void, usize) {
+    let start = ram.as_mut_ptr() as usize;
+    let first = start.next_multiple_of(align);
+    let last = (start + ram.len()) / align * align;
+    (first as *mut c_void, last.saturating_sub(first))
+}
+
+impl HostMem {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut mem = HostMem::default();
+        for opt in s.split(',') {
+            match opt.split_once('=') {
+                None if opt == "hugepages" => mem.hugepages = true,
+                Some(("node", node)) => {
+                    let node = node.parse().map_err(|_| format!("bad node {:?}", node))?;
+                    if node >= MAX_NODE {
+                        return Err(format!("node {} is out of range", node));
+                    }
+                    mem.node = Some(node);
+                }
+                _ => return Err(format!("unknown option {:?}", opt)),
+            }
+        }
+        Ok(mem)
+    }
+
+    pub fn apply(&self, ram: &mut [u8]) -> Result<(), String> {
+        if self.hugepages {
+            let (addr, len) = aligned(ram, HUGE_PAGE);
+            // SAFETY: the range lies within `ram`, advice doesn't change its
+            // contents.
+            if unsafe { madvise(addr, len, MADV_HUGEPAGE) } != 0 {
+                return Err(format!("hugepages: {}", io::Error::last_os_error()));
+            }
+        }
+        if let Some(node) = self.node {
+            bind(ram, node)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64"))]
+fn bind(ram: &mut [u8], node: u32) -> Result<(), String> {
+    let (addr, len) = aligned(ram, PAGE);
+    let mask: u64 = 1 << node;
+    // SAFETY: mbind(addr, len, mode, nodemask, maxnode, flags) only reads the
+    // mask, the range lies within `ram`. maxnode counts one bit more than the
+    // kernel reads.
+    let ret = unsafe { syscall(SYS_MBIND, addr, len, MPOL_BIND, &mask as *const u64, MAX_NODE as i64 + 1, 0i64) };
+    if ret != 0 {
+        return Err(format!("node {}: {}", node, io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
+fn bind(_ram: &mut [u8], node: u32) -> Result<(), String> {
+    Err(format!("node {}: NUMA binding is not supported on this host", node))
+}
@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant};
+
+// Where the host spends its time, to tell whether the hart or a device model
+// is the bottleneck. Off by default as timing every step costs two clock
+// reads. Accounts nest: device accesses happen within hart steps and monitor
+// commands within gdb packets, the report shows the self time of each.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Account {
+    Hart,
+    Uart,
+    Syscon,
+    Virtio,
+    Gdb,
+    Monitor,
+    // Host sleeps of a hart waiting in WFI.
+    Idle,
+}
+
+const ACCOUNTS: [(Account, &str); 7] = [
+    (Account::Hart, "hart0"),
+    (Account::Uart, "uart"),
+    (Account::Syscon, "syscon"),
+    (Account::Virtio, "virtio"),
+    (Account::Gdb, "gdb"),
+    (Account::Monitor, "monitor"),
+    (Account::Idle, "idle"),
+];
+
+#[derive(Default)]
+pub struct HostTime {
+    enabled: bool,
+    spent: [(Duration, u64); ACCOUNTS.len()],
+}
+
+impl HostTime {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    // Pass the result to stop() once the accounted work is done.
+    pub fn start(&self) -> Option<Instant> {
+        self.enabled.then(Instant::now)
+    }
+
+    pub fn stop(&mut self, account: Account, start: Option<Instant>) {
+        if let Some(start) = start {
+            let (time, calls) = &mut self.spent[account as usize];
+            *time += start.elapsed();
+            *calls += 1;
+        }
+    }
+
+    fn self_time(&self, account: Account) -> Duration {
+        let spent = |a: Account| self.spent[a as usize].0;
+        match account {
+            Account::Hart => spent(Account::Hart)
+                .saturating_sub(spent(Account::Uart) + spent(Account::Syscon) + spent(Account::Virtio)),
+            Account::Gdb => spent(Account::Gdb).saturating_sub(spent(Account::Monitor)),
+            _ => spent(account),
+        }
+    }
+
+    pub fn report(&self) -> String {
+        if !self.enabled {
+            return "host time accounting is off, run with --host-time\n".into();
+        }
+        let total: Duration = ACCOUNTS.iter().map(|(a, _)| self.self_time(*a)).sum();
+        ACCOUNTS.iter().map(|(account, name)| {
+            let time = self.self_time(*account);
+            let share = match total.is_zero() {
+                true => 0.0,
+                false => 100.0 * time.as_secs_f64() / total.as_secs_f64(),
+            };
+            let calls = self.spent[*account as usize].1;
+            format!("{:<8} {:>10.3}s {:>5.1}% {:>12} calls\n", name, time.as_secs_f64(), share, calls)
+        }).collect()
+    }
+}
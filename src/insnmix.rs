@@ -0,0 +1,53 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::cpu::Observer;
+use crate::decoder::Instruction;
+
+#[cfg(test)]
+mod tests;
+
+// --insn-mix counts the instructions the guest executes by the extension
+// they belong to and reports the mix when the run ends. Instructions that
+// trap count as executed, those that decode to nothing the hart has count
+// as illegal. It watches the hart as an Observer, which keeps fusion off so
+// each instruction of a common pair is counted on its own.
+
+#[derive(Default)]
+pub struct InsnMix {
+    counts: HashMap<&'static str, u64>,
+}
+
+impl InsnMix {
+    fn count(&mut self, insn: &Instruction) {
+        let ext = match insn {
+            Instruction::Invalid(_) => "illegal",
+            _ => insn.extension(),
+        };
+        *self.counts.entry(ext).or_default() += 1;
+    }
+
+    pub fn report(&self) -> String {
+        let total: u64 = self.counts.values().sum();
+        if total == 0 {
+            return "insn-mix: no instruction was executed\n".into();
+        }
+        let mut counts: Vec<(&str, u64)> = self.counts.iter().map(|(ext, n)| (*ext, *n)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        let mut out = format!("{:<10} {:>12} {:>6}\n", "extension", "insns", "%");
+        for (ext, n) in counts {
+            out.push_str(&format!("{:<10} {:>12} {:>5.1}%\n", ext, n, n as f64 * 100.0 / total as f64));
+        }
+        out
+    }
+}
+
+// The observer installed in the hart, sharing the counts with the report.
+pub struct Counter(pub Rc<RefCell<InsnMix>>);
+
+impl Observer for Counter {
+    fn executed(&mut self, _pc: u64, insn: &Instruction, _next_pc: u64) {
+        self.0.borrow_mut().count(insn);
+    }
+}
@@ -0,0 +1,22 @@
+use super::*;
+
+#[test]
+fn counts_by_extension() {
+    let mix = Rc::new(RefCell::new(InsnMix::default()));
+    let mut counter = Counter(Rc::clone(&mix));
+    // addi, addi, mul, an all-zero word
+    for bits in [0x00100093, 0x00108093, 0x02208133, 0] {
+        counter.executed(0, &Instruction::decode(bits), 4);
+    }
+    assert_eq!(mix.borrow().report(), "\
+extension         insns      %
+i                     2  50.0%
+illegal               1  25.0%
+m                     1  25.0%
+");
+}
+
+#[test]
+fn empty_run() {
+    assert_eq!(InsnMix::default().report(), "insn-mix: no instruction was executed\n");
+}
@@ -0,0 +1,181 @@
+use crate::cpu::{Cpu, MmioAccess};
+use crate::expr::{self, Env, Expr};
+use crate::registers;
+
+#[cfg(test)]
+mod tests;
+
+// Invariants are conditions on guest state that are checked as the guest
+// runs, declared with --assert:
+//
+//   --assert '(satp >> 60 == 0) | ((satp << 12 & 0xfffffffffff000) - 0x80000000 < 0x8000000)'
+//   --assert '(sp >= 0x80020000) & (sp < 0x80030000) if pc - 0x80001000 < 0x200'
+//   --assert 'mmio 0x10000000 write: priv == 3'
+//
+// A condition is an expression that must be non-zero, comparisons bind
+// looser than the bit operators combining them. Besides registers and
+// memory it can read CSRs by name and the privilege level as `priv`. Plain
+// invariants are checked before every instruction, `if` limits one to the
+// states where another expression holds. `mmio <addr> [read|write]:` checks
+// after each matching access instead, with `addr` and `value` set. A memory
+// read outside of RAM fails the check.
+//
+// A violation is reported with the instruction and the registers, once when
+// an invariant starts failing and again only after it held in between. With
+// --assert-fatal the first one ends the run with status 1.
+
+#[derive(Debug, Clone)]
+enum When {
+    Always,
+    If(Expr),
+    Mmio { addr: u64, write: Option<bool> },
+}
+
+#[derive(Debug, Clone)]
+struct Invariant {
+    src: String,
+    when: When,
+    cond: Expr,
+    // The CSRs the expressions name, resolved once.
+    csrs: Vec<(String, u32)>,
+    failing: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub src: String,
+    pub instret: u64,
+    pub pc: u64,
+    pub access: Option<MmioAccess>,
+}
+
+pub struct Invariants {
+    invariants: Vec<Invariant>,
+    fatal: bool,
+    // pc of the step being checked, for the reports of accesses
+    pc: u64,
+    privl: u8,
+}
+
+fn parse(src: &str) -> Result<(When, Expr), String> {
+    if let Some(rest) = src.trim().strip_prefix("mmio ") {
+        let (event, cond) = rest.split_once(':').ok_or("missing ':' after mmio address")?;
+        let words: Vec<&str> = event.split_whitespace().collect();
+        let addr = words.first().and_then(|w| expr::parse_number(w))
+            .ok_or_else(|| format!("bad mmio address {:?}", event.trim()))?;
+        let write = match words.get(1..) {
+            Some([]) => None,
+            Some(["write"]) => Some(true),
+            Some(["read"]) => Some(false),
+            _ => return Err(format!("bad mmio access {:?}", event.trim())),
+        };
+        return Ok((When::Mmio { addr, write }, Expr::parse(cond)?));
+    }
+    match src.split_once(" if ") {
+        Some((cond, when)) => Ok((When::If(Expr::parse(when)?), Expr::parse(cond)?)),
+        None => Ok((When::Always, Expr::parse(src)?)),
+    }
+}
+
+impl Invariants {
+    pub fn new(fatal: bool) -> Self {
+        Invariants { invariants: Vec::new(), fatal, pc: 0, privl: 3 }
+    }
+
+    pub fn add(&mut self, src: &str, cpu: &Cpu) -> Result<(), String> {
+        let (when, cond) = parse(src)?;
+        let mut names = cond.vars();
+        if let When::If(e) = &when {
+            names.extend(e.vars());
+        }
+        let mut csrs = Vec::new();
+        for name in names {
+            if let Some(num) = registers::csr_number(cpu, name) {
+                csrs.push((name.to_string(), num));
+            } else if !matches!(name, "priv" | "addr" | "value") {
+                return Err(format!("unknown variable {:?}", name));
+            }
+        }
+        self.invariants.push(Invariant { src: src.to_string(), when, cond, csrs, failing: false });
+        Ok(())
+    }
+
+    // Checks the plain invariants in the state the next instruction runs in.
+    pub fn before_step(&mut self, cpu: &Cpu) -> Vec<Violation> {
+        self.pc = cpu.pc();
+        self.privl = cpu.privilege();
+        let mut violations = Vec::new();
+        for inv in &mut self.invariants {
+            let vars = inv.vars(cpu, self.privl, &[]);
+            let env = Env { cpu, vars: &vars };
+            let holds = match &inv.when {
+                When::Always => inv.cond.eval(&env).is_some_and(|v| v != 0),
+                When::If(when) => when.eval(&env).is_none_or(|w| w == 0)
+                    || inv.cond.eval(&env).is_some_and(|v| v != 0),
+                When::Mmio { .. } => continue,
+            };
+            if !holds && !inv.failing {
+                violations.push(Violation { src: inv.src.clone(), instret: cpu.instret(), pc: self.pc, access: None });
+            }
+            inv.failing = !holds;
+        }
+        violations
+    }
+
+    // Checks the invariants on the access of the step that just executed,
+    // at the level it executed at.
+    pub fn after_step(&mut self, cpu: &Cpu, access: Option<MmioAccess>) -> Vec<Violation> {
+        let Some(access) = access else {
+            return Vec::new();
+        };
+        let mut violations = Vec::new();
+        for inv in &self.invariants {
+            let When::Mmio { addr, write } = inv.when else {
+                continue;
+            };
+            if addr != access.addr || write.is_some_and(|w| w != access.write) {
+                continue;
+            }
+            let vars = inv.vars(cpu, self.privl, &[("addr", access.addr), ("value", access.value)]);
+            if inv.cond.eval(&Env { cpu, vars: &vars }).is_none_or(|v| v == 0) {
+                violations.push(Violation {
+                    src: inv.src.clone(),
+                    instret: cpu.instret().saturating_sub(1),
+                    pc: self.pc,
+                    access: Some(access),
+                });
+            }
+        }
+        violations
+    }
+
+    // True when the run has to end, with --assert-fatal and a violation.
+    pub fn report(&self, violations: &[Violation], cpu: &Cpu) -> bool {
+        for v in violations {
+            eprint!("{}", report(v, cpu));
+        }
+        self.fatal && !violations.is_empty()
+    }
+}
+
+impl Invariant {
+    fn vars<'a>(&'a self, cpu: &Cpu, privl: u8, extra: &[(&'a str, u64)]) -> Vec<(&'a str, u64)> {
+        let mut vars = vec![("priv", privl as u64)];
+        vars.extend(self.csrs.iter().map(|(name, num)| (name.as_str(), cpu.debug_read_csr(*num).unwrap_or(0))));
+        vars.extend_from_slice(extra);
+        vars
+    }
+}
+
+// The violation with the instruction and the registers at the time, those
+// after the access for mmio invariants.
+pub fn report(v: &Violation, cpu: &Cpu) -> String {
+    let insn = cpu.debug_read_u32(v.pc).map_or("?".to_string(), |i| format!("{:#010x}", i));
+    let mut out = format!("assert: violated: {}\n  instret {} pc {:#x} insn {}\n", v.src, v.instret, v.pc, insn);
+    if let Some(access) = v.access {
+        out.push_str(&format!("  mmio {} {:#x} value {:#x}\n",
+            if access.write { "write" } else { "read" }, access.addr, access.value));
+    }
+    out.push_str(&registers::format_xregs(cpu));
+    out
+}
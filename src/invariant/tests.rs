@@ -0,0 +1,49 @@
+use super::*;
+
+use crate::cpu::RAM_BASE;
+
+fn invariants(srcs: &[&str], cpu: &Cpu) -> Invariants {
+    let mut invariants = Invariants::new(false);
+    for src in srcs {
+        invariants.add(src, cpu).unwrap();
+    }
+    invariants
+}
+
+#[test]
+fn reported_when_they_start_failing() {
+    let mut cpu = Cpu::new();
+    cpu.set_pc(RAM_BASE);
+    let mut inv = invariants(&["sp >= 0x1000 if pc == 0x80000000", "mcause == 0"], &cpu);
+    assert_eq!(inv.before_step(&cpu).len(), 1);
+    assert_eq!(inv.before_step(&cpu), []);
+
+    cpu.set_reg(2, 0x1000);
+    cpu.debug_write_csr(0x342, 2);
+    let violations = inv.before_step(&cpu);
+    assert_eq!(violations, [Violation { src: "mcause == 0".into(), instret: 0, pc: RAM_BASE, access: None }]);
+    // out of the range the condition applies to
+    cpu.set_reg(2, 0);
+    cpu.set_pc(RAM_BASE + 4);
+    assert_eq!(inv.before_step(&cpu), []);
+}
+
+#[test]
+fn mmio_invariants_see_the_access() {
+    let cpu = Cpu::new();
+    let mut inv = invariants(&["mmio 0x10000000 write: (priv == 1) | (value < 0x80)"], &cpu);
+    inv.before_step(&cpu);
+    let access = |write, value| Some(MmioAccess { addr: 0x10000000, write, value });
+    assert_eq!(inv.after_step(&cpu, access(true, 0x41)), []);
+    assert_eq!(inv.after_step(&cpu, access(false, 0xff)), []);
+    assert_eq!(inv.after_step(&cpu, access(true, 0xff)).len(), 1);
+}
+
+#[test]
+fn unknown_names_are_refused() {
+    let cpu = Cpu::new();
+    let mut inv = Invariants::new(false);
+    assert!(inv.add("nosuchcsr == 0", &cpu).is_err());
+    assert!(inv.add("mmio 0x10000000 poke: 1", &cpu).is_err());
+    assert!(inv.add("satp == 0", &cpu).is_ok());
+}
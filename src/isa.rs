@@ -0,0 +1,111 @@
+use std::fmt;
+
+#[cfg(test)]
+mod tests;
+
+// Extensions the hart implements, in canonical ISA string order. V is left
+// out of the default, its floating point instructions are not implemented.
+// C is not accepted at all, there is no decoder for 16-bit instructions.
+const LETTERS: &str = "imafdv";
+const DEFAULT_LETTERS: &str = "imafd";
+const MULTI: &[&str] = &["zicbom", "zicboz", "zicond", "zihintntl", "zihintpause", "zabha", "zacas", "zfh", "zba", "zbb", "zbc", "zbs", "zknd", "zkne", "zknh", "sstc"];
+
+// The extensions a hart exposes, configured with an ISA string such as
+// rv64imafd_zba. Single-letter extensions also show up in misa, the
+// multi-letter ones only gate decoding and CSRs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Isa {
+    letters: String,
+    multi: Vec<&'static str>,
+}
+
+impl Default for Isa {
+    fn default() -> Self {
+        Isa { letters: DEFAULT_LETTERS.into(), multi: MULTI.to_vec() }
+    }
+}
+
+impl Isa {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.to_ascii_lowercase();
+        let mut parts = s.split('_');
+        let base = parts.next().unwrap_or("");
+        let letters = base.strip_prefix("rv64")
+            .ok_or_else(|| format!("{:?} is not an rv64 ISA string", s))?;
+        // g is shorthand for imafd, the order is checked on the expansion.
+        let letters = letters.replace('g', "imafd");
+        if !letters.starts_with('i') {
+            return Err("the base integer ISA (i) is required".into());
+        }
+
+        if letters.contains('c') {
+            return Err("compressed instructions (c) are not implemented".into());
+        }
+        let mut last = None;
+        for c in letters.chars() {
+            let pos = LETTERS.find(c).ok_or_else(|| format!("unsupported extension {:?}", c))?;
+            if last.is_some_and(|last| pos <= last) {
+                return Err(format!("extension {:?} is out of order or repeated", c));
+            }
+            last = Some(pos);
+        }
+        if letters.contains('d') && !letters.contains('f') {
+            return Err("d requires f".into());
+        }
+
+        let mut multi = Vec::new();
+        for ext in parts {
+            let known = MULTI.iter().find(|m| **m == ext)
+                .ok_or_else(|| format!("unsupported extension {:?}", ext))?;
+            if multi.contains(known) {
+                return Err(format!("extension {:?} is repeated", ext));
+            }
+            multi.push(*known);
+        }
+        if multi.contains(&"zabha") && !letters.contains('a') {
+            return Err("zabha requires a".into());
+        }
+        if multi.contains(&"zacas") && !letters.contains('a') {
+            return Err("zacas requires a".into());
+        }
+        if multi.contains(&"zfh") && !letters.contains('f') {
+            return Err("zfh requires f".into());
+        }
+
+        Ok(Isa { letters, multi })
+    }
+
+    // The extension letters of misa, without the MXL field and S/U.
+    pub fn misa_extensions(&self) -> u64 {
+        self.letters.bytes().fold(0, |bits, c| bits | 1 << (c - b'a'))
+    }
+
+    // Extension names in ISA string order, single letters first.
+    pub fn extensions(&self) -> Vec<String> {
+        self.letters.chars().map(String::from).chain(self.multi.iter().map(|m| m.to_string())).collect()
+    }
+
+    // Leaves out a multi-letter extension, single letters are kept.
+    pub fn remove(&mut self, ext: &str) {
+        self.multi.retain(|m| *m != ext);
+    }
+
+    // `ext` is a lower-case extension name as returned by
+    // Instruction::extension.
+    pub fn has(&self, ext: &str) -> bool {
+        match ext.len() {
+            1 => self.letters.contains(ext),
+            _ => self.multi.contains(&ext),
+        }
+    }
+}
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rv64{}", self.letters)?;
+        for ext in &self.multi {
+            write!(f, "_{}", ext)?;
+        }
+        Ok(())
+    }
+}
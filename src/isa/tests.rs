@@ -0,0 +1,10 @@
+use super::*;
+
+#[test]
+fn compressed_is_not_offered() {
+    assert_eq!(Isa::default().to_string().split('_').next(), Some("rv64imafd"));
+    assert!(!Isa::default().has("c"));
+    assert!(Isa::parse("rv64gc").is_err());
+    assert!(Isa::parse("rv64imafdc_zba").is_err());
+    assert_eq!(Isa::parse("rv64g_zba").unwrap().misa_extensions(), Isa::parse("rv64imafd").unwrap().misa_extensions());
+}
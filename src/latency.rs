@@ -0,0 +1,122 @@
+use crate::clock::TIMEBASE_FREQ;
+use crate::cpu::Cpu;
+
+#[cfg(test)]
+mod tests;
+
+// --irq-latency measures how long the guest takes to enter the handler of
+// an interrupt: from the step that first sees a source raised to the trap
+// into an interrupt the source drives, in retired instructions and in
+// virtual time. The device sources share MEI and SEI through the PLIC, a
+// trap into either ends the wait of all of them. A source that drops before
+// a handler is entered, as a UART the guest polls does, counts as dropped.
+// Each source is reported when the run ends.
+
+const CAUSE_INTERRUPT: u64 = 1 << 63;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+struct Span {
+    min: u64,
+    max: u64,
+    sum: u64,
+}
+
+impl Span {
+    fn add(&mut self, val: u64, first: bool) {
+        self.min = if first { val } else { self.min.min(val) };
+        self.max = self.max.max(val);
+        self.sum += val;
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Source {
+    name: &'static str,
+    // mip bits of the interrupts entering its handler
+    mask: u64,
+    raised: bool,
+    // (instret, time) it was raised at, while no handler was entered
+    since: Option<(u64, u64)>,
+    count: u64,
+    dropped: u64,
+    insns: Span,
+    ticks: Span,
+}
+
+pub struct Latency {
+    sources: Vec<Source>,
+}
+
+impl Latency {
+    pub fn new() -> Self {
+        Latency { sources: Vec::new() }
+    }
+
+    // Looks for sources raised or dropped since the last step.
+    pub fn before_step(&mut self, cpu: &Cpu) {
+        self.sample(&cpu.irq_sources(), cpu.instret(), cpu.time());
+    }
+
+    // Looks for the step entering a handler, before the tracer takes the
+    // trap.
+    pub fn after_step(&mut self, cpu: &Cpu) {
+        if let Some(trap) = cpu.trap_event() {
+            self.trap(trap.cause, cpu.instret(), cpu.time());
+        }
+    }
+
+    fn sample(&mut self, levels: &[(&'static str, bool, u64)], instret: u64, now: u64) {
+        if self.sources.is_empty() {
+            self.sources = levels.iter().map(|&(name, _, mask)| Source {
+                name,
+                mask,
+                raised: false,
+                since: None,
+                count: 0,
+                dropped: 0,
+                insns: Span::default(),
+                ticks: Span::default(),
+            }).collect();
+        }
+        for (src, &(_, level, _)) in self.sources.iter_mut().zip(levels) {
+            match (src.raised, level) {
+                (false, true) => src.since = Some((instret, now)),
+                (true, false) if src.since.take().is_some() => src.dropped += 1,
+                _ => {}
+            }
+            src.raised = level;
+        }
+    }
+
+    fn trap(&mut self, cause: u64, instret: u64, now: u64) {
+        if cause & CAUSE_INTERRUPT == 0 {
+            return;
+        }
+        let bit = 1u64.checked_shl((cause & !CAUSE_INTERRUPT) as u32).unwrap_or(0);
+        for src in self.sources.iter_mut().filter(|src| src.mask & bit != 0) {
+            if let Some((raised_at, raised_time)) = src.since.take() {
+                let first = src.count == 0;
+                src.count += 1;
+                src.insns.add(instret.saturating_sub(raised_at), first);
+                src.ticks.add(now.saturating_sub(raised_time), first);
+            }
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let seen: Vec<&Source> = self.sources.iter().filter(|src| src.count + src.dropped > 0).collect();
+        if seen.is_empty() {
+            return "irq-latency: no interrupt was raised\n".into();
+        }
+        let us = |ticks: u64| ticks as f64 * 1e6 / TIMEBASE_FREQ as f64;
+        let mut out = format!("{:<10} {:>6} {:>7} {:>10} {:>10} {:>10} {:>9} {:>9} {:>9}\n",
+            "irq", "count", "dropped", "insns min", "avg", "max", "us min", "avg", "max");
+        for src in seen {
+            let n = src.count.max(1) as f64;
+            out.push_str(&format!("{:<10} {:>6} {:>7} {:>10} {:>10.1} {:>10} {:>9.1} {:>9.1} {:>9.1}\n",
+                src.name, src.count, src.dropped, src.insns.min, src.insns.sum as f64 / n, src.insns.max,
+                us(src.ticks.min), us(src.ticks.sum) / n, us(src.ticks.max)));
+        }
+        out
+    }
+}
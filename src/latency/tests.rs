@@ -0,0 +1,45 @@
+use super::*;
+
+const MTIP: u64 = 1 << 7;
+const EXTERNAL: u64 = 1 << 11 | 1 << 9;
+
+fn levels(mtimer: bool, uart: bool, rtc: bool) -> [(&'static str, bool, u64); 3] {
+    [("mtimer", mtimer, MTIP), ("uart", uart, EXTERNAL), ("rtc", rtc, EXTERNAL)]
+}
+
+#[test]
+fn raise_to_handler_per_source() {
+    let mut lat = Latency::new();
+    lat.sample(&levels(false, false, false), 0, 1000);
+    // the timer fires and is taken 3 instructions later
+    lat.sample(&levels(true, false, false), 10, 1010);
+    lat.trap(CAUSE_INTERRUPT | 7, 13, 1012);
+    // an exception enters no interrupt handler
+    lat.sample(&levels(true, true, false), 20, 1020);
+    lat.trap(8, 25, 1025);
+    // SEI serves the uart, the rtc raised since waits along
+    lat.sample(&levels(false, true, true), 30, 1030);
+    lat.trap(CAUSE_INTERRUPT | 9, 40, 1045);
+    // the timer again, lowered before a handler ran
+    lat.sample(&levels(true, false, false), 50, 1050);
+    lat.sample(&levels(false, false, false), 51, 1051);
+    lat.trap(CAUSE_INTERRUPT | 7, 60, 1060);
+    // a second timer interrupt, slower than the first
+    lat.sample(&levels(true, false, false), 70, 1070);
+    lat.trap(CAUSE_INTERRUPT | 7, 77, 1090);
+
+    let stats: Vec<_> = lat.sources.iter().map(|src| (src.name, src.count, src.dropped, src.insns, src.ticks)).collect();
+    assert_eq!(stats, [
+        ("mtimer", 2, 1, Span { min: 3, max: 7, sum: 10 }, Span { min: 2, max: 20, sum: 22 }),
+        ("uart", 1, 0, Span { min: 20, max: 20, sum: 20 }, Span { min: 25, max: 25, sum: 25 }),
+        ("rtc", 1, 0, Span { min: 10, max: 10, sum: 10 }, Span { min: 15, max: 15, sum: 15 }),
+    ]);
+    assert_eq!(lat.report().lines().nth(1), Some("mtimer          2       1          3        5.0          7       0.2       1.1       2.0"));
+}
+
+#[test]
+fn nothing_raised() {
+    let mut lat = Latency::new();
+    lat.sample(&levels(false, false, false), 0, 0);
+    assert_eq!(lat.report(), "irq-latency: no interrupt was raised\n");
+}
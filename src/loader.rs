@@ -0,0 +1,174 @@
+use crate::cpu::Cpu;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+const SYM_SIZE: usize = 24;
+
+// A `--load <file>[@<addr>]` argument. Raw binaries need the address, ELF
+// files are placed by the physical addresses of their PT_LOAD segments.
+#[derive(Debug, Clone)]
+pub struct Payload {
+    pub path: String,
+    pub addr: Option<u64>,
+}
+
+impl Payload {
+    pub fn parse(spec: &str, parse_addr: impl Fn(&str) -> Option<u64>) -> Result<Self, String> {
+        match spec.rsplit_once('@') {
+            Some((path, addr)) => {
+                let addr = parse_addr(addr).ok_or_else(|| format!("bad load address {:?}", addr))?;
+                Ok(Payload { path: path.into(), addr: Some(addr) })
+            }
+            None => Ok(Payload { path: spec.into(), addr: None }),
+        }
+    }
+}
+
+pub struct Segment {
+    pub name: String,
+    pub addr: u64,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    pub fn end(&self) -> u64 {
+        self.addr.saturating_add(self.data.len() as u64)
+    }
+}
+
+fn field<const N: usize>(data: &[u8], off: usize) -> Option<[u8; N]> {
+    data.get(off..off + N)?.try_into().ok()
+}
+
+// PT_LOAD segments of a little-endian RV64 ELF, bss included as zeroes.
+fn elf_segments(path: &str, data: &[u8]) -> Result<Vec<Segment>, String> {
+    let bad = |what: &str| format!("{}: {}", path, what);
+    if data.get(4) != Some(&ELFCLASS64) || data.get(5) != Some(&ELFDATA2LSB) {
+        return Err(bad("not a 64-bit little-endian ELF"));
+    }
+    let u16_at = |off| field::<2>(data, off).map(u16::from_le_bytes).ok_or_else(|| bad("truncated header"));
+    let u32_at = |off| field::<4>(data, off).map(u32::from_le_bytes).ok_or_else(|| bad("truncated header"));
+    let u64_at = |off| field::<8>(data, off).map(u64::from_le_bytes).ok_or_else(|| bad("truncated header"));
+
+    if u16_at(18)? != EM_RISCV {
+        return Err(bad("not a RISC-V ELF"));
+    }
+    let phoff = u64_at(32)? as usize;
+    let phentsize = u16_at(54)? as usize;
+    let phnum = u16_at(56)? as usize;
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        if u32_at(ph)? != PT_LOAD {
+            continue;
+        }
+        let offset = u64_at(ph + 8)? as usize;
+        let paddr = u64_at(ph + 24)?;
+        let filesz = u64_at(ph + 32)? as usize;
+        let memsz = u64_at(ph + 40)? as usize;
+        if memsz == 0 {
+            continue;
+        }
+        let mut seg = data.get(offset..offset + filesz).ok_or_else(|| bad("segment past end of file"))?
+            .to_vec();
+        seg.resize(memsz.max(filesz), 0);
+        segments.push(Segment { name: format!("{} segment {}", path, i), addr: paddr, data: seg });
+    }
+
+    if segments.is_empty() {
+        return Err(bad("no loadable segments"));
+    }
+    Ok(segments)
+}
+
+// Reads every payload into the segments to place in RAM, checked against
+// the machine by preflight::check before they are loaded.
+pub fn read(payloads: &[Payload]) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    for payload in payloads {
+        let data = std::fs::read(&payload.path).map_err(|e| format!("{}: {}", payload.path, e))?;
+        if data.starts_with(ELF_MAGIC) {
+            if payload.addr.is_some() {
+                return Err(format!("{}: ELF files are placed by their program headers", payload.path));
+            }
+            segments.extend(elf_segments(&payload.path, &data)?);
+        } else {
+            let addr = payload.addr
+                .ok_or_else(|| format!("{}: raw binaries need a load address, <file>@<addr>", payload.path))?;
+            segments.push(Segment { name: payload.path.clone(), addr, data });
+        }
+    }
+    Ok(segments)
+}
+
+// A function from an ELF symbol table, at its virtual address, which is
+// where the guest runs it as long as it runs with translation off or
+// identity mapped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u64,
+    pub size: u64,
+}
+
+// The function symbols of an ELF file, none when it is stripped.
+fn elf_symbols(path: &str, data: &[u8]) -> Result<Vec<Symbol>, String> {
+    let bad = |what: &str| format!("{}: {}", path, what);
+    let u16_at = |off| field::<2>(data, off).map(u16::from_le_bytes).ok_or_else(|| bad("truncated section"));
+    let u32_at = |off| field::<4>(data, off).map(u32::from_le_bytes).ok_or_else(|| bad("truncated section"));
+    let u64_at = |off| field::<8>(data, off).map(u64::from_le_bytes).ok_or_else(|| bad("truncated section"));
+
+    let shoff = u64_at(40)? as usize;
+    let shentsize = u16_at(58)? as usize;
+    let shnum = u16_at(60)? as usize;
+    let section = |i: usize| -> Result<(u32, usize, usize, usize), String> {
+        let sh = shoff + i * shentsize;
+        Ok((u32_at(sh + 4)?, u64_at(sh + 24)? as usize, u64_at(sh + 32)? as usize, u32_at(sh + 40)? as usize))
+    };
+
+    let mut symbols = Vec::new();
+    for i in 0..shnum {
+        let (kind, offset, size, link) = section(i)?;
+        if kind != SHT_SYMTAB {
+            continue;
+        }
+        let (_, strtab, strsize, _) = section(link)?;
+        let strings = data.get(strtab..strtab + strsize).ok_or_else(|| bad("string table past end of file"))?;
+        for sym in (offset..offset + size).step_by(SYM_SIZE) {
+            let info = *data.get(sym + 4).ok_or_else(|| bad("truncated symbol"))?;
+            let (addr, size) = (u64_at(sym + 8)?, u64_at(sym + 16)?);
+            if info & 0xf != STT_FUNC || addr == 0 {
+                continue;
+            }
+            let name = strings.get(u32_at(sym)? as usize..).unwrap_or_default();
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            symbols.push(Symbol { name: String::from_utf8_lossy(name).into(), addr, size });
+        }
+    }
+    Ok(symbols)
+}
+
+// The function symbols of the ELF payloads, sorted by address.
+pub fn symbols(payloads: &[Payload]) -> Result<Vec<Symbol>, String> {
+    let mut symbols = Vec::new();
+    for payload in payloads {
+        let data = std::fs::read(&payload.path).map_err(|e| format!("{}: {}", payload.path, e))?;
+        if data.starts_with(ELF_MAGIC) {
+            symbols.extend(elf_symbols(&payload.path, &data)?);
+        }
+    }
+    symbols.sort_by_key(|sym| sym.addr);
+    Ok(symbols)
+}
+
+pub fn load(cpu: &mut Cpu, segments: &[Segment]) {
+    for seg in segments {
+        cpu.load_bytes(seg.addr, &seg.data);
+    }
+}
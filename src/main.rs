@@ -1,13 +1,847 @@
+mod bootrom;
+mod budget;
+mod capabilities;
+mod clint;
+mod clock;
+mod compare;
+mod config;
+mod console;
 mod cpu;
+mod crypto;
 mod decoder;
+mod expr;
+mod fault;
+mod fdt;
+mod featfuzz;
+mod fetch;
+mod fpu;
+mod gdb;
+mod hostmem;
+mod hosttime;
+mod insnmix;
+mod invariant;
+mod isa;
+mod latency;
+mod loader;
+mod memmap;
+mod memuse;
+mod monitor;
+mod pagecache;
+mod plic;
+mod preflight;
+mod probe;
+mod registers;
+mod rtc;
+mod sched;
+mod script;
+mod selftest;
+mod signal;
+mod snapshot;
+mod strace;
+mod syscon;
+mod trace;
+mod uart;
+mod vector;
+mod virtio;
+mod watch;
+mod wx;
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// xv6 fills each page it frees a byte at a time before it turns on paging,
+// the bundled kernel reaches satp about 417M instructions in.
+const DEFAULT_PROBE_TIMEOUT: u64 = 1_000_000_000;
+const XV6_SYS_EXEC: u64 = 7;
+const DEFAULT_WATCH_INTERVAL: u64 = 1_000_000;
+
+fn usage() -> ! {
+    eprintln!("usage: nrv64emu [--expect <milestone>[:<insns>]]... [--exec-syscall <nr>]");
+    eprintln!("                [--serial-input <script>] [--serial-record <file>]");
+    eprintln!("                [--console-filter strip-ansi|safe-ansi|timestamp|hexdump[,...]]");
+    eprintln!("                [--console-encoding utf8|latin1] [--console-log <file>]");
+    eprintln!("                [--gdb <port> [--semihosting]] [--script <file>]");
+    eprintln!("                [--time-scale <factor>] [--idle-skip] [--misaligned trap|emulate]");
+    eprintln!("                [--rtc [base=<date>|now][,clock=host|vm]]");
+    eprintln!("                [--trace-start <addr>] [--trace-stop <addr>] [--trace-insns <n>]");
+    eprintln!("                [--trace-file <file>] [--trace-priv <m|s|u>[,...]] [--trace-color]");
+    eprintln!("                [--wx-monitor] [--strace]");
+    eprintln!("                [--config <file>] [--isa <string>] [--load <file>[@<addr>]]...");
+    eprintln!("                [--watch <expr>]... [--watch-interval <n>] [--watch-file <file>]");
+    eprintln!("                [--assert <invariant>]... [--assert-fatal]");
+    eprintln!("                [--host-time] [--no-fusion] [--pause-yield] [--quantum <n>]");
+    eprintln!("                [--io-latency <n>] [--timer-precision <n>]");
+    eprintln!("                [--host-mem hugepages|node=<n>[,...]] [--max-host-mem <size>[K|M|G]] [--vlen <bits>]");
+    eprintln!("                [--fuzz-features <seed>] [--insn-budget <insns>[,window=<ms>]]");
+    eprintln!("                [--dump-dtb <file>] [--dump-memory-map <file>] [--irq-latency] [--insn-mix]");
+    eprintln!("                [--checkpoint-on-signal] [--checkpoint-dir <dir>] [--resume <file>|latest]");
+    eprintln!("       nrv64emu fetch-defaults [--cache-dir <dir>]");
+    eprintln!("       nrv64emu capabilities [--json]");
+    eprintln!("       nrv64emu selftest [--configs <dir>]");
+    eprintln!("       nrv64emu --version");
+    eprintln!("       nrv64emu trace dump <file> [--color]");
+    eprintln!("       nrv64emu trace filter <file> [--type <kind>[,<kind>]...] [--priv <m|s|u>[,...]]");
+    eprintln!("                [--from <n>] [--to <n>]");
+    eprintln!("       nrv64emu compare-report <log> <log> [--start <pc>] [--context <n>]");
+    eprintln!("milestones: mret-s, satp, user, exec (user and exec need a guest that runs without a disk)");
+    std::process::exit(2);
+}
+
+fn parse_u64(s: &str) -> u64 {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.unwrap_or_else(|_| usage())
+}
+
+fn trace_command(args: &[String]) {
+    match args {
+        [cmd, path] if cmd == "dump" => trace::dump(path, false),
+        [cmd, path, opt] if cmd == "dump" && opt == "--color" => trace::dump(path, true),
+        [cmd, path, opts @ ..] if cmd == "filter" => {
+            let mut kinds = None;
+            let mut privs = trace::ALL_PRIVS;
+            let mut from = 0;
+            let mut to = u64::MAX;
+            let mut opts = opts.iter();
+            while let Some(opt) = opts.next() {
+                let val = opts.next().unwrap_or_else(|| usage());
+                match opt.as_str() {
+                    "--type" => kinds = Some(val.as_str()),
+                    "--priv" => privs = trace::parse_privs(val).unwrap_or_else(|e| {
+                        eprintln!("--priv: {}", e);
+                        std::process::exit(2);
+                    }),
+                    "--from" => from = parse_u64(val),
+                    "--to" => to = parse_u64(val),
+                    _ => usage(),
+                }
+            }
+            trace::filter(path, kinds, privs, from, to);
+        }
+        _ => usage(),
+    }
+}
+
+fn compare_command(args: &[String]) {
+    let [a, b, opts @ ..] = args else {
+        usage();
+    };
+    let mut start = None;
+    let mut context = compare::DEFAULT_CONTEXT;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        let val = opts.next().unwrap_or_else(|| usage());
+        match opt.as_str() {
+            "--start" => start = Some(parse_u64(val)),
+            "--context" => context = parse_u64(val) as usize,
+            _ => usage(),
+        }
+    }
+    compare::run([a, b], start, context);
+}
+
+fn selftest_command(args: &[String]) {
+    let configs = match args {
+        [] => PathBuf::from("./configs"),
+        [opt, dir] if opt == "--configs" => PathBuf::from(dir),
+        _ => usage(),
+    };
+    if !selftest::run(&configs) {
+        std::process::exit(1);
+    }
+}
+
+fn capabilities_command(args: &[String]) {
+    let caps = capabilities::Capabilities::current();
+    match args {
+        [] => print!("{}", caps.to_text()),
+        [opt] if opt == "--json" => println!("{}", caps.to_json()),
+        _ => usage(),
+    }
+}
+
+fn fetch_command(args: &[String]) {
+    let cache = match args {
+        [] => fetch::default_cache_dir().unwrap_or_else(|| {
+            eprintln!("fetch: no cache directory, set HOME or pass --cache-dir");
+            std::process::exit(1);
+        }),
+        [opt, dir] if opt == "--cache-dir" => dir.into(),
+        _ => usage(),
+    };
+    match fetch::fetch_defaults(&cache) {
+        Ok(defaults) => {
+            println!("firmware: {}", defaults.firmware.display());
+            println!("dtb: {}", defaults.dtb.display());
+        }
+        Err(e) => {
+            eprintln!("fetch: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// What a run reports when it ends, however it ends.
+struct Reports {
+    host_time: bool,
+    latency: Option<latency::Latency>,
+    insn_mix: Option<Rc<RefCell<insnmix::InsnMix>>>,
+}
+
+impl Reports {
+    fn finish(&self, cpu: &cpu::Cpu, code: i32) -> ! {
+        if self.host_time {
+            eprint!("{}", cpu.host_time().report());
+        }
+        if let Some(latency) = &self.latency {
+            eprint!("{}", latency.report());
+        }
+        if let Some(mix) = &self.insn_mix {
+            eprint!("{}", mix.borrow().report());
+        }
+        std::process::exit(code);
+    }
+}
 
 fn main() {
-    let kernel_bin = std::fs::read("./configs/xv6/kernel.bin").unwrap();
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    match argv.first().map(String::as_str) {
+        Some("trace") => return trace_command(&argv[1..]),
+        Some("fetch-defaults") => return fetch_command(&argv[1..]),
+        Some("capabilities") => return capabilities_command(&argv[1..]),
+        Some("compare-report") => return compare_command(&argv[1..]),
+        Some("selftest") => return selftest_command(&argv[1..]),
+        Some("--version") => {
+            println!("nrv64emu {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        _ => {}
+    }
+
+    let mut expects = Vec::new();
+    let mut exec_syscall = XV6_SYS_EXEC;
+    let mut serial_input = None;
+    let mut serial_record = None;
+    let mut console_filters = Vec::new();
+    let mut console_encoding = console::Encoding::default();
+    let mut console_log = None;
+    let mut gdb_port = None;
+    let mut semihosting = false;
+    let mut script_path = None;
+    let mut time_scale = 1.0;
+    let mut rtc = clock::RtcSettings::default();
+    let mut idle_skip = false;
+    let mut policy = sched::Policy::default();
+    let mut emulate_misaligned = false;
+    let mut trace_start = None;
+    let mut trace_stop = None;
+    let mut trace_insns = None;
+    let mut trace_file = None;
+    let mut trace_privs = trace::ALL_PRIVS;
+    let mut trace_color = false;
+    let mut wx_monitor = false;
+    let mut strace = None;
+    let mut config_path = None;
+    let mut isa = isa::Isa::default();
+    let mut payloads = Vec::new();
+    let mut checkpoint_on_signal = false;
+    let mut checkpoint_dir = None;
+    let mut resume = None;
+    let mut watch_exprs = Vec::new();
+    let mut watch_interval = DEFAULT_WATCH_INTERVAL;
+    let mut watch_file = None;
+    let mut asserts = Vec::new();
+    let mut assert_fatal = false;
+    let mut host_time = false;
+    let mut fusion = true;
+    let mut pause_yield = false;
+    let mut host_mem = hostmem::HostMem::default();
+    let mut max_host_mem = None;
+    let mut vlen = vector::DEFAULT_VLEN;
+    let mut fuzz_seed = None;
+    let mut insn_budget = None;
+    let mut dump_dtb = None;
+    let mut dump_memory_map = None;
+    let mut irq_latency = false;
+    let mut insn_mix = false;
+
+    let mut args = argv.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--expect" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                let (name, timeout) = match spec.split_once(':') {
+                    Some((name, timeout)) => (name, parse_u64(timeout)),
+                    None => (spec.as_str(), DEFAULT_PROBE_TIMEOUT),
+                };
+                let milestone = probe::Milestone::parse(name).unwrap_or_else(|| usage());
+                expects.push((milestone, timeout));
+            }
+            "--exec-syscall" => {
+                exec_syscall = parse_u64(&args.next().unwrap_or_else(|| usage()));
+            }
+            "--serial-input" => {
+                serial_input = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--serial-record" => {
+                serial_record = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--console-filter" => {
+                console_filters = console::FilterKind::parse_list(&args.next().unwrap_or_else(|| usage()))
+                    .unwrap_or_else(|e| {
+                        eprintln!("--console-filter: {}", e);
+                        std::process::exit(2);
+                    });
+            }
+            "--console-encoding" => {
+                console_encoding = args.next().as_deref().and_then(console::Encoding::parse)
+                    .unwrap_or_else(|| usage());
+            }
+            "--console-log" => {
+                console_log = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--gdb" => {
+                let port = parse_u64(&args.next().unwrap_or_else(|| usage()));
+                gdb_port = Some(u16::try_from(port).unwrap_or_else(|_| usage()));
+            }
+            "--semihosting" => {
+                semihosting = true;
+            }
+            "--script" => {
+                script_path = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--time-scale" => {
+                let scale = args.next().unwrap_or_else(|| usage());
+                time_scale = match scale.parse::<f64>() {
+                    Ok(scale) if scale > 0.0 && scale.is_finite() => scale,
+                    _ => usage(),
+                };
+            }
+            "--rtc" => {
+                rtc = clock::RtcSettings::parse(&args.next().unwrap_or_else(|| usage())).unwrap_or_else(|e| {
+                    eprintln!("--rtc: {}", e);
+                    std::process::exit(1);
+                });
+            }
+            "--idle-skip" => {
+                idle_skip = true;
+            }
+            "--misaligned" => {
+                emulate_misaligned = match args.next().as_deref() {
+                    Some("trap") => false,
+                    Some("emulate") => true,
+                    _ => usage(),
+                };
+            }
+            "--trace-start" => {
+                trace_start = Some(parse_u64(&args.next().unwrap_or_else(|| usage())));
+            }
+            "--trace-stop" => {
+                trace_stop = Some(parse_u64(&args.next().unwrap_or_else(|| usage())));
+            }
+            "--trace-insns" => {
+                trace_insns = Some(parse_u64(&args.next().unwrap_or_else(|| usage())));
+            }
+            "--trace-file" => {
+                trace_file = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--trace-priv" => {
+                trace_privs = trace::parse_privs(&args.next().unwrap_or_else(|| usage())).unwrap_or_else(|e| {
+                    eprintln!("--trace-priv: {}", e);
+                    std::process::exit(2);
+                });
+            }
+            "--trace-color" => {
+                trace_color = true;
+            }
+            "--wx-monitor" => {
+                wx_monitor = true;
+            }
+            "--strace" => {
+                strace = Some(strace::Strace::new());
+            }
+            "--config" => {
+                config_path = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--load" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                payloads.push(loader::Payload::parse(&spec, expr::parse_number).unwrap_or_else(|e| {
+                    eprintln!("--load: {}", e);
+                    std::process::exit(2);
+                }));
+            }
+            "--isa" => {
+                isa = isa::Isa::parse(&args.next().unwrap_or_else(|| usage())).unwrap_or_else(|e| {
+                    eprintln!("--isa: {}", e);
+                    std::process::exit(2);
+                });
+            }
+            "--watch" => {
+                watch_exprs.push(args.next().unwrap_or_else(|| usage()));
+            }
+            "--watch-interval" => {
+                watch_interval = parse_u64(&args.next().unwrap_or_else(|| usage()));
+            }
+            "--assert" => {
+                asserts.push(args.next().unwrap_or_else(|| usage()));
+            }
+            "--assert-fatal" => {
+                assert_fatal = true;
+            }
+            "--watch-file" => {
+                watch_file = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--host-time" => {
+                host_time = true;
+            }
+            "--no-fusion" => {
+                fusion = false;
+            }
+            "--pause-yield" => {
+                pause_yield = true;
+            }
+            "--quantum" => {
+                policy.quantum = parse_u64(&args.next().unwrap_or_else(|| usage())).max(1);
+            }
+            "--io-latency" => {
+                policy.io_latency = parse_u64(&args.next().unwrap_or_else(|| usage())).max(1);
+            }
+            "--timer-precision" => {
+                policy.timer_precision = parse_u64(&args.next().unwrap_or_else(|| usage())).max(1);
+            }
+            "--max-host-mem" => {
+                max_host_mem = Some(memuse::parse_size(&args.next().unwrap_or_else(|| usage())).unwrap_or_else(|e| {
+                    eprintln!("--max-host-mem: {}", e);
+                    std::process::exit(2);
+                }));
+            }
+            "--host-mem" => {
+                host_mem = hostmem::HostMem::parse(&args.next().unwrap_or_else(|| usage())).unwrap_or_else(|e| {
+                    eprintln!("--host-mem: {}", e);
+                    std::process::exit(2);
+                });
+            }
+            "--vlen" => {
+                vlen = parse_u64(&args.next().unwrap_or_else(|| usage())) as usize;
+                if !vlen.is_power_of_two() || !(vector::MIN_VLEN..=vector::MAX_VLEN).contains(&vlen) {
+                    eprintln!("--vlen: must be a power of two from {} to {}", vector::MIN_VLEN, vector::MAX_VLEN);
+                    std::process::exit(2);
+                }
+            }
+            "--fuzz-features" => {
+                fuzz_seed = Some(parse_u64(&args.next().unwrap_or_else(|| usage())));
+            }
+            "--insn-budget" => {
+                insn_budget = Some(budget::Settings::parse(&args.next().unwrap_or_else(|| usage()))
+                    .unwrap_or_else(|e| {
+                        eprintln!("--insn-budget: {}", e);
+                        std::process::exit(2);
+                    }));
+            }
+            "--dump-dtb" => {
+                dump_dtb = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--dump-memory-map" => {
+                dump_memory_map = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--irq-latency" => {
+                irq_latency = true;
+            }
+            "--insn-mix" => {
+                insn_mix = true;
+            }
+            "--checkpoint-on-signal" => {
+                checkpoint_on_signal = true;
+            }
+            "--checkpoint-dir" => {
+                checkpoint_dir = Some(PathBuf::from(args.next().unwrap_or_else(|| usage())));
+            }
+            "--resume" => {
+                resume = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            _ => usage(),
+        }
+    }
+
+    // Semihosting calls are served by gdb's File-I/O protocol.
+    if semihosting && gdb_port.is_none() {
+        usage();
+    }
+
+    let mut probes = probe::Probes::new(exec_syscall);
+    for (milestone, timeout) in expects {
+        probes.expect(milestone, timeout);
+    }
+
+    let mut script = script_path.map(|path| {
+        let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        script::Script::parse(&text).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+
+    let mut trace = trace::Trace::new(trace_start, trace_stop, trace_insns);
+    trace.set_privs(trace_privs);
+    trace.set_color(trace_color);
+    if let Some(path) = trace_file {
+        let file = std::fs::File::create(&path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        trace.set_output(file);
+    }
+
+    let mut watches = (!watch_exprs.is_empty()).then(|| {
+        let out: Box<dyn std::io::Write> = match &watch_file {
+            Some(path) => Box::new(std::fs::File::create(path).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            })),
+            None => Box::new(std::io::stderr()),
+        };
+        let mut watches = watch::Watches::new(watch_interval, out);
+        for src in &watch_exprs {
+            watches.add(src).unwrap_or_else(|e| {
+                eprintln!("--watch {:?}: {}", src, e);
+                std::process::exit(2);
+            });
+        }
+        watches
+    });
+
+    // Checkpoints go to the cache unless a directory is given.
+    let checkpoint_dir = checkpoint_dir
+        .or_else(|| fetch::default_cache_dir().map(|dir| dir.join("checkpoints")))
+        .unwrap_or_else(|| PathBuf::from("checkpoints"));
+    let resume = resume.map(|spec| match spec.as_str() {
+        "latest" => snapshot::latest(&checkpoint_dir).unwrap_or_else(|e| {
+            eprintln!("resume: {}", e);
+            std::process::exit(1);
+        }),
+        _ => PathBuf::from(spec),
+    });
+
+    // Without payloads the xv6 kernel is booted from the start of RAM.
+    if payloads.is_empty() && resume.is_none() {
+        payloads.push(loader::Payload {
+            path: "./configs/xv6/kernel.bin".into(),
+            addr: Some(cpu::RAM_BASE),
+        });
+    }
+
+    let fuzz = fuzz_seed.map(|seed| featfuzz::Choices::draw(seed, &isa)).unwrap_or_default();
+    if fuzz.seed.is_some() {
+        eprintln!("fuzz-features: {}", fuzz);
+    }
+    for ext in &fuzz.dropped {
+        isa.remove(ext);
+    }
 
     let mut cpu = cpu::Cpu::new();
-    cpu.load_bytes(0x80000000, &kernel_bin);
+    cpu.mem_use_mut().set_limit(max_host_mem);
+    if let Err(e) = cpu.mem_use_mut().reserve(memuse::Owner::Ram, cpu::RAM_SIZE as usize) {
+        eprintln!("max-host-mem: {}", e);
+        std::process::exit(1);
+    }
+    // Before anything is loaded, while no page of RAM is populated yet.
+    host_mem.apply(cpu.ram_mut()).unwrap_or_else(|e| {
+        eprintln!("host-mem: {}", e);
+        std::process::exit(1);
+    });
+    let segments = loader::read(&payloads).unwrap_or_else(|e| {
+        eprintln!("load: {}", e);
+        std::process::exit(1);
+    });
+    // A snapshot brings its own RAM and pc.
+    let problems = preflight::check(cpu::RAM_BASE, if resume.is_some() { &[] } else { &segments });
+    for problem in &problems {
+        eprintln!("preflight: {}", problem);
+    }
+    if !problems.is_empty() {
+        std::process::exit(1);
+    }
+    loader::load(&mut cpu, &segments);
+    let mut budget = insn_budget.map(|settings| {
+        let symbols = loader::symbols(&payloads).unwrap_or_else(|e| {
+            eprintln!("load: {}", e);
+            std::process::exit(1);
+        });
+        budget::Budget::new(settings, symbols)
+    });
+    // The boot ROM hands the guest the device tree it was given, or the one
+    // of the machine.
+    let given = segments.iter().find(|seg| fdt::is_dtb(&seg.data));
+    let (dtb, embedded) = match given {
+        Some(seg) => (seg.addr, Vec::new()),
+        None => (bootrom::FDT_ADDR, fdt::virt_dtb(&isa)),
+    };
+    cpu.set_boot_rom(bootrom::image(cpu::RAM_BASE, 0, dtb, &embedded));
+    let dumps = [
+        (dump_dtb, given.map_or(embedded, |seg| seg.data.clone())),
+        (dump_memory_map, memmap::to_json(&segments, cpu::RAM_BASE, 0, dtb).into_bytes()),
+    ];
+    for (path, data) in dumps {
+        if let Some(path) = path {
+            std::fs::write(&path, data).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            });
+        }
+    }
+    cpu.set_semihosting(semihosting);
+    let mut clock = clock::Clock::new(time_scale);
+    clock.set_source(rtc.source);
+    if let Some(base) = rtc.base {
+        clock.set_now(base * clock::TIMEBASE_FREQ, 0);
+    }
+    cpu.set_clock(clock);
+    cpu.set_idle_skip(idle_skip);
+    cpu.set_misaligned_emulation(emulate_misaligned);
+    cpu.set_wx_monitor(wx_monitor);
+    cpu.set_isa(isa);
+    cpu.set_vlen(vlen);
+    cpu.set_fuzz(fuzz);
+    cpu.host_time_mut().set_enabled(host_time);
+    cpu.set_pause_yield(pause_yield);
+
+    // A snapshot replaces RAM and the hart state, including the ISA.
+    if let Some(path) = &resume {
+        snapshot::restore(&mut cpu, path).unwrap_or_else(|e| {
+            eprintln!("resume: {}", e);
+            std::process::exit(1);
+        });
+        eprintln!("resume: continuing from {}", path.display());
+    }
+
+    // CSR names resolve against the hart's final configuration.
+    let mut invariants = (!asserts.is_empty()).then(|| {
+        let mut invariants = invariant::Invariants::new(assert_fatal);
+        for src in &asserts {
+            invariants.add(src, &cpu).unwrap_or_else(|e| {
+                eprintln!("--assert {:?}: {}", src, e);
+                std::process::exit(2);
+            });
+        }
+        invariants
+    });
+    if checkpoint_on_signal {
+        signal::install();
+    }
+
+    // Scripted input replaces the interactive console.
+    if let Some(path) = serial_input {
+        let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        let script = uart::SerialScript::parse(&text).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        let now = cpu.instret();
+        cpu.uart_mut().set_script(script, now);
+    } else {
+        cpu.uart_mut().attach_stdin();
+    }
+
+    if let Some(path) = serial_record {
+        let file = std::fs::File::create(&path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        cpu.uart_mut().set_record(file);
+    }
+    cpu.uart_mut().set_console_filters(&console_filters);
+    cpu.uart_mut().set_console_encoding(console_encoding);
+    if let Some(path) = console_log {
+        let file = std::fs::File::create(&path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        cpu.uart_mut().set_console_log(file);
+    }
+
+    // The config file is applied over the command line.
+    let mut config = config_path.map(config::Reloader::new);
+    if let Some(config) = &mut config {
+        config.reload(&mut cpu, &mut trace).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    }
+
+    let mut gdb = gdb_port.map(|port| {
+        let mut gdb = gdb::Gdb::listen(port).unwrap_or_else(|e| {
+            eprintln!("gdb: {}", e);
+            std::process::exit(1);
+        });
+        gdb.halt(&mut cpu);
+        gdb
+    });
+
+    let mut reports = Reports {
+        host_time,
+        latency: irq_latency.then(latency::Latency::new),
+        insn_mix: insn_mix.then(|| {
+            let mix = Rc::new(RefCell::new(insnmix::InsnMix::default()));
+            cpu.set_observer(Some(Box::new(insnmix::Counter(Rc::clone(&mix)))));
+            mix
+        }),
+    };
+
+    // Breakpoints, single-stepping, pc hooks and the per-step checks and
+    // reports need to see every pc, pairs are fused only without any of them.
+    let needs_every_step = gdb.is_some() || script.is_some() || invariants.is_some() || budget.is_some()
+        || watches.is_some() || strace.is_some() || irq_latency || !probes.is_empty() || wx_monitor
+        || insn_mix;
+    let fusion = fusion && !needs_every_step;
+
+    let mut sched = sched::Scheduler::new(policy);
+    sched.add(sched::Task::Io, 0);
+    sched.add(sched::Task::Timer, 0);
+    sched.add(sched::Task::Hart(0), 0);
+    if config.is_some() {
+        sched.add(sched::Task::Config, policy.config_interval);
+    }
+
+    while let Some((task, mut steps)) = sched.next() {
+        match task {
+            sched::Task::Io => {
+                if checkpoint_on_signal && signal::received() {
+                    let path = snapshot::checkpoint_path(&checkpoint_dir);
+                    match snapshot::save(&mut cpu, &path) {
+                        Ok(()) => {
+                            eprintln!("checkpoint: saved {}", path.display());
+                            reports.finish(&cpu, 0);
+                        }
+                        Err(e) => {
+                            eprintln!("checkpoint: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                cpu.poll_io();
+            }
+            sched::Task::Timer => {
+                steps = cpu.update_timers().unwrap_or(u64::MAX);
+            }
+            sched::Task::Config => {
+                if let Some(config) = &mut config
+                    && config.changed()
+                {
+                    match config.reload(&mut cpu, &mut trace) {
+                        Ok(()) => eprintln!("config: reloaded"),
+                        Err(e) => eprintln!("config: {}", e),
+                    }
+                }
+            }
+            sched::Task::Hart(_) => {
+                // The trace window moves with the guest and config reloads.
+                cpu.set_fusion(fusion && trace.is_idle());
+                for step in 0..steps {
+                    if let Some(gdb) = &mut gdb {
+                        gdb.poll(&mut cpu);
+                        if let Some(code) = gdb.take_exit() {
+                            reports.finish(&cpu, code);
+                        }
+                    }
+
+                    // A hart in WFI sleeps out the rest of its turn.
+                    if cpu.is_waiting() {
+                        cpu.idle(steps - step);
+                        break;
+                    }
+
+                    if !probes.is_empty() {
+                        match probes.check(&cpu) {
+                            probe::Status::Running => {}
+                            probe::Status::Passed => {
+                                probes.report();
+                                reports.finish(&cpu, 0);
+                            }
+                            probe::Status::Failed => {
+                                probes.report();
+                                eprint!("{}", registers::format_xregs(&cpu));
+                                reports.finish(&cpu, 1);
+                            }
+                        }
+                    }
+
+                    if let Some(code) = script.as_mut().and_then(|script| script.before_step(&mut cpu)) {
+                        reports.finish(&cpu, code);
+                    }
+
+                    if let Some(config) = &mut config
+                        && gdb.as_mut().is_some_and(|gdb| gdb.take_config_reload())
+                    {
+                        match config.reload(&mut cpu, &mut trace) {
+                            Ok(()) => eprintln!("config: reloaded"),
+                            Err(e) => eprintln!("config: {}", e),
+                        }
+                    }
+
+                    if let Some(strace) = &mut strace {
+                        strace.before_step(&cpu);
+                    }
+
+                    if let Some(invariants) = &mut invariants {
+                        let violations = invariants.before_step(&cpu);
+                        if invariants.report(&violations, &cpu) {
+                            reports.finish(&cpu, 1);
+                        }
+                    }
+
+                    if let Some(overrun) = budget.as_mut().and_then(|budget| budget.before_step(&cpu)) {
+                        eprint!("{}", budget::report(&overrun));
+                    }
+
+                    if let Some(latency) = &mut reports.latency {
+                        latency.before_step(&cpu);
+                    }
+
+                    trace.before_step(&cpu);
+                    let start = cpu.host_time().start();
+                    cpu.step();
+                    cpu.host_time_mut().stop(hosttime::Account::Hart, start);
+
+                    match cpu.take_power_event() {
+                        Some(syscon::PowerEvent::Reset) => cpu.reset(),
+                        Some(syscon::PowerEvent::PowerOff(code)) => reports.finish(&cpu, code as i32),
+                        None => {}
+                    }
+
+                    if let Some(latency) = &mut reports.latency {
+                        latency.after_step(&cpu);
+                    }
+
+                    let mmio = cpu.take_mmio_access();
+                    let trap = cpu.trap_event();
+                    trace.after_step(&mut cpu, mmio);
+
+                    if let Some(code) = script.as_mut().and_then(|script| script.after_step(&mut cpu, mmio, trap)) {
+                        reports.finish(&cpu, code);
+                    }
+
+                    if let Some(invariants) = &mut invariants {
+                        let violations = invariants.after_step(&cpu, mmio);
+                        if invariants.report(&violations, &cpu) {
+                            reports.finish(&cpu, 1);
+                        }
+                    }
 
-    loop {
-        cpu.step();
+                    if let Some(watches) = &mut watches {
+                        watches.after_step(&cpu);
+                    }
+                }
+            }
+        }
+        sched.done(task, steps);
     }
 }
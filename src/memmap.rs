@@ -0,0 +1,20 @@
+use crate::cpu::DEVICES;
+use crate::loader::Segment;
+use crate::trace;
+
+#[cfg(test)]
+mod tests;
+
+// `--dump-memory-map <file>`: the machine as the guest finds it at boot,
+// its devices and RAM, the payloads placed in RAM and what the boot ROM
+// hands over. Addresses and sizes are hex strings as in capabilities --json.
+pub fn to_json(segments: &[Segment], start: u64, hart_id: u64, dtb: u64) -> String {
+    let devices: Vec<String> = DEVICES.iter().map(|(name, compatible, base, size)| {
+        format!(r#"{{"name":"{}","compatible":"{}","base":"{:#x}","size":"{:#x}"}}"#, name, compatible, base, size)
+    }).collect();
+    let segments: Vec<String> = segments.iter().map(|seg| {
+        format!(r#"{{"name":"{}","base":"{:#x}","size":"{:#x}"}}"#, trace::escape(&seg.name), seg.addr, seg.data.len())
+    }).collect();
+    format!(r#"{{"devices":[{}],"segments":[{}],"boot":{{"start":"{:#x}","hart_id":{},"dtb":"{:#x}"}}}}"#,
+        devices.join(","), segments.join(","), start, hart_id, dtb) + "\n"
+}
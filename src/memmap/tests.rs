@@ -0,0 +1,12 @@
+use super::*;
+
+#[test]
+fn lists_devices_payloads_and_boot_values() {
+    let segments = [Segment { name: "xv6/kernel segment 1".into(), addr: 0x8000_0000, data: vec![0; 0x1234] }];
+    let json = to_json(&segments, 0x8000_0000, 0, 0x1030);
+    assert!(json.starts_with(r#"{"devices":[{"name":"bootrom","compatible":"rom","base":"0x1000","size":"0xf000"},"#));
+    assert!(json.contains(r#"{"name":"ram","compatible":"memory","base":"0x80000000","size":"#));
+    assert!(json.ends_with(concat!(
+        r#""segments":[{"name":"xv6/kernel segment 1","base":"0x80000000","size":"0x1234"}],"#,
+        r#""boot":{"start":"0x80000000","hart_id":0,"dtb":"0x1030"}}"#, "\n")));
+}
@@ -0,0 +1,82 @@
+#[cfg(test)]
+mod tests;
+
+// Host memory held on behalf of the guest, by owner, for `info host-mem` and
+// the --max-host-mem budget of multi-instance deployments. Guest RAM counts
+// at its full size since the guest may touch all of it. Snapshots count
+// while they are built or read and are refused if they would not fit. The
+// page cache is a fixed handful of entries and traces stream to their files
+// through fixed buffers, neither grows with the guest.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Owner {
+    Ram,
+    Snapshot,
+    Monitor,
+}
+
+const OWNERS: [(Owner, &str); 3] = [
+    (Owner::Ram, "ram"),
+    (Owner::Snapshot, "snapshot"),
+    (Owner::Monitor, "monitor"),
+];
+
+#[derive(Debug, Default)]
+pub struct MemUse {
+    used: [usize; OWNERS.len()],
+    limit: Option<usize>,
+}
+
+// Sizes in bytes with an optional K, M or G suffix: 512M.
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let (num, shift) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 30),
+        _ => (s, 0),
+    };
+    num.parse::<usize>().ok().and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("bad size {:?}", s))
+}
+
+impl MemUse {
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    pub fn set(&mut self, owner: Owner, bytes: usize) {
+        self.used[owner as usize] = bytes;
+    }
+
+    pub fn total(&self) -> usize {
+        self.used.iter().sum()
+    }
+
+    // Whether owner may hold `bytes` instead of what it holds now.
+    pub fn fits(&self, owner: Owner, bytes: usize) -> bool {
+        let others = self.total() - self.used[owner as usize];
+        self.limit.is_none_or(|limit| others.saturating_add(bytes) <= limit)
+    }
+
+    // Reserves `bytes` for owner, or says why it is over budget.
+    pub fn reserve(&mut self, owner: Owner, bytes: usize) -> Result<(), String> {
+        if !self.fits(owner, bytes) {
+            return Err(format!("{} bytes of {} exceed --max-host-mem of {} bytes",
+                bytes, OWNERS[owner as usize].1, self.limit.unwrap_or(0)));
+        }
+        self.set(owner, bytes);
+        Ok(())
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for (owner, name) in OWNERS {
+            out.push_str(&format!("{:<10} {:>12}\n", name, self.used[owner as usize]));
+        }
+        out.push_str(&format!("{:<10} {:>12}\n", "total", self.total()));
+        if let Some(limit) = self.limit {
+            out.push_str(&format!("{:<10} {:>12}\n", "limit", limit));
+        }
+        out
+    }
+}
@@ -0,0 +1,23 @@
+use super::*;
+
+#[test]
+fn sizes() {
+    assert_eq!(parse_size("4096"), Ok(4096));
+    assert_eq!(parse_size("512M"), Ok(512 << 20));
+    assert_eq!(parse_size("2g"), Ok(2 << 30));
+    assert!(parse_size("M").is_err());
+    assert!(parse_size("1T").is_err());
+}
+
+#[test]
+fn reserve_stays_within_the_limit() {
+    let mut mem = MemUse::default();
+    mem.set_limit(Some(1000));
+    mem.set(Owner::Ram, 600);
+    assert!(mem.reserve(Owner::Snapshot, 500).is_err());
+    assert_eq!(mem.total(), 600);
+    assert!(mem.reserve(Owner::Snapshot, 400).is_ok());
+    // replacing what an owner holds
+    assert!(mem.reserve(Owner::Snapshot, 300).is_ok());
+    assert_eq!(mem.total(), 900);
+}
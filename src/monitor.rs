@@ -0,0 +1,516 @@
+use std::collections::BTreeMap;
+
+use crate::cpu::{self, Cpu, Exception, Trap};
+use crate::expr::{self, Env, Expr};
+use crate::fault::Fault;
+use crate::memuse;
+use crate::registers;
+
+type DeviceState = Vec<(&'static str, Vec<(&'static str, u64)>)>;
+
+const HELP: &str = "\
+info registers            show pc, privilege and the x registers by ABI name
+info csr [<name|num>]     show all CSRs of the hart or a single one
+info device               list device models
+info device <name>        dump the registers of a device
+device snapshot <tag>     save the register state of all devices
+device diff <tag> [<tag>] compare a snapshot against another or the current state
+fault uart <kind> <rate>  inject drop-tx, drop-rx or corrupt-rx with probability 0..1
+fault uart seed <n>       reseed the fault generator
+fault uart clear          stop injecting faults
+info faults               list active fault injections
+inject <irq>              raise ssip, msip, stip, mtip, seip or meip
+inject clear <irq>        lower an injected interrupt line
+inject exception <cause> [<tval>]
+                          trap the hart with an exception cause
+info guest-maps [<satp>]  list the user mappings of an address space
+break if <addr> <expr>    only stop at the breakpoint at addr when expr is non-zero
+break ignore <addr> <n>   skip the next n hits of the breakpoint at addr
+break clear <addr>        drop the condition, ignore count and hit count of addr
+info breakpoints          list breakpoint conditions and hit counts
+logpoint <addr> <message> log message without stopping whenever addr is reached,
+                          {expr} in the message is replaced by its value
+logpoint clear <addr>     remove the logpoint at addr
+info logpoints            list logpoints
+config reload             re-read the --config file
+info host-time            host time spent per hart, device model and debugger
+info host-mem             host memory held for guest RAM, snapshots and the monitor
+find <start> <end> <pattern>
+                          search physical memory in start..end for \"text\" or
+                          hex bytes, ? matches any nibble: 7f 45 4c 46, 13 0? ??
+memsave <addr> <len> <file>
+                          write len bytes of physical memory at addr to file
+";
+
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_PERMS: u64 = PTE_R | PTE_W | PTE_X;
+
+struct Mapping {
+    start: u64,
+    end: u64,
+    phys: u64,
+    perms: u64,
+}
+
+// Evaluated in the emulator so a stop on the n-th iteration of a loop costs
+// no gdb round-trips. Keyed by address rather than attached to a Z packet as
+// gdb removes and reinserts its breakpoints around every stop.
+#[derive(Default)]
+struct BreakCondition {
+    cond: Option<(String, Expr)>,
+    ignore: u64,
+    hits: u64,
+}
+
+enum LogPart {
+    Text(String),
+    Value(Expr),
+}
+
+struct Logpoint {
+    src: String,
+    parts: Vec<LogPart>,
+}
+
+fn parse_logpoint(src: &str) -> Result<Logpoint, String> {
+    let mut parts = Vec::new();
+    let mut rest = src;
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            parts.push(LogPart::Text(rest[..open].to_string()));
+        }
+        let close = rest[open..].find('}').ok_or("unterminated {")? + open;
+        parts.push(LogPart::Value(Expr::parse(&rest[open + 1..close])?));
+        rest = &rest[close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(LogPart::Text(rest.to_string()));
+    }
+    Ok(Logpoint { src: src.to_string(), parts })
+}
+
+// Commands reachable through gdb's `monitor` (qRcmd).
+pub struct Monitor {
+    snapshots: BTreeMap<String, DeviceState>,
+    conditions: BTreeMap<u64, BreakCondition>,
+    logpoints: BTreeMap<u64, Logpoint>,
+    reload_config: bool,
+}
+
+fn diff_states(old: &DeviceState, new: &DeviceState) -> String {
+    let mut out = String::new();
+    for (dev, regs) in new {
+        let old_regs = old.iter().find(|(d, _)| d == dev).map(|(_, r)| r.as_slice())
+            .unwrap_or(&[]);
+        for (name, val) in regs {
+            match old_regs.iter().find(|(n, _)| n == name) {
+                Some((_, old_val)) if old_val == val => {}
+                Some((_, old_val)) => {
+                    out.push_str(&format!("{}.{}: {:#x} -> {:#x}\n", dev, name, old_val, val));
+                }
+                None => out.push_str(&format!("{}.{}: (new) {:#x}\n", dev, name, val)),
+            }
+        }
+    }
+
+    if out.is_empty() {
+        out.push_str("no differences\n");
+    }
+    out
+}
+
+// Walks an Sv39/Sv48 page table in guest RAM and collects the U-mode leaves.
+fn walk(cpu: &Cpu, table: u64, level: u32, va: u64, vbits: u32, maps: &mut Vec<Mapping>) {
+    for i in 0..512 {
+        let Some(pte) = cpu.debug_read_u64(table + i * 8) else {
+            return;
+        };
+        if pte & PTE_V == 0 {
+            continue;
+        }
+
+        let va = va | i << (12 + 9 * level);
+        let phys = (pte >> 10 & 0xfff_ffff_ffff) << 12;
+        if pte & PTE_PERMS == 0 {
+            if level > 0 {
+                walk(cpu, phys, level - 1, va, vbits, maps);
+            }
+            continue;
+        }
+        if pte & PTE_U == 0 {
+            continue;
+        }
+
+        // Canonical addresses sign-extend the top VA bit.
+        let shift = 64 - vbits;
+        let start = ((va << shift) as i64 >> shift) as u64;
+        let size = 1u64 << (12 + 9 * level);
+        let perms = pte & PTE_PERMS;
+        match maps.last_mut() {
+            Some(last) if last.end == start && last.perms == perms
+                && last.phys + (last.end - last.start) == phys => last.end = last.end.wrapping_add(size),
+            _ => maps.push(Mapping { start, end: start.wrapping_add(size), phys, perms }),
+        }
+    }
+}
+
+// A /proc/<pid>/maps style listing, the offset column holds the physical
+// address backing the start of each range.
+fn guest_maps(cpu: &Cpu, satp: u64) -> String {
+    let (levels, vbits) = match satp >> 60 {
+        8 => (3, 39),
+        9 => (4, 48),
+        0 => return "paging is disabled (bare satp)\n".into(),
+        mode => return format!("unsupported satp mode {}\n", mode),
+    };
+
+    let mut maps = Vec::new();
+    walk(cpu, (satp & 0xfff_ffff_ffff) << 12, levels - 1, 0, vbits, &mut maps);
+    if maps.is_empty() {
+        return "no user mappings\n".into();
+    }
+
+    maps.iter().map(|m| {
+        let flag = |bit, c| if m.perms & bit != 0 { c } else { '-' };
+        format!("{:016x}-{:016x} {}{}{}p {:08x}\n", m.start, m.end,
+            flag(PTE_R, 'r'), flag(PTE_W, 'w'), flag(PTE_X, 'x'), m.phys)
+    }).collect()
+}
+
+// `line` without its first `n` words, for arguments that keep their own
+// spacing.
+fn skip_words(line: &str, n: usize) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        rest = rest.split_once(char::is_whitespace).map_or("", |(_, r)| r).trim_start();
+    }
+    rest
+}
+
+// At most this many matches are listed by `find`, the rest are counted.
+const FIND_MAX_LISTED: usize = 32;
+
+// A search pattern as (value, mask) bytes.
+fn parse_pattern(src: &str) -> Result<Vec<(u8, u8)>, String> {
+    let pattern = match src.strip_prefix('"') {
+        Some(text) => {
+            let text = text.strip_suffix('"').ok_or("unterminated string")?;
+            let mut bytes = Vec::new();
+            let mut chars = text.chars();
+            while let Some(c) = chars.next() {
+                let c = match c {
+                    '\\' => match chars.next() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('0') => '\0',
+                        Some('x') => {
+                            let hex: String = chars.by_ref().take(2).collect();
+                            let b = u8::from_str_radix(&hex, 16).map_err(|_| format!("bad escape \\x{}", hex))?;
+                            bytes.push((b, 0xFF));
+                            continue;
+                        }
+                        Some(c @ ('\\' | '"')) => c,
+                        c => return Err(format!("bad escape \\{}", c.map(String::from).unwrap_or_default())),
+                    },
+                    c => c,
+                };
+                let mut buf = [0; 4];
+                bytes.extend(c.encode_utf8(&mut buf).bytes().map(|b| (b, 0xFF)));
+            }
+            bytes
+        }
+        None => {
+            let digits: Vec<char> = src.chars().filter(|c| !c.is_whitespace()).collect();
+            if !digits.len().is_multiple_of(2) {
+                return Err("hex patterns need two digits per byte".into());
+            }
+            let nibble = |c: char| match c {
+                '?' => Ok((0, 0)),
+                c => c.to_digit(16).map(|d| (d as u8, 0xF)).ok_or_else(|| format!("bad hex digit {:?}", c)),
+            };
+            digits.chunks(2).map(|pair| {
+                let (hi, hi_mask) = nibble(pair[0])?;
+                let (lo, lo_mask) = nibble(pair[1])?;
+                Ok((hi << 4 | lo, hi_mask << 4 | lo_mask))
+            }).collect::<Result<_, String>>()?
+        }
+    };
+    if pattern.is_empty() {
+        return Err("empty pattern".into());
+    }
+    Ok(pattern)
+}
+
+fn find(cpu: &Cpu, start: u64, end: u64, pattern: &[(u8, u8)]) -> String {
+    let Some(mem) = end.checked_sub(start).and_then(|len| cpu.debug_ram(start, len)) else {
+        return format!("{:#x}..{:#x} is not in RAM\n", start, end);
+    };
+    let matches: Vec<u64> = mem.windows(pattern.len()).enumerate()
+        .filter(|(_, window)| window.iter().zip(pattern).all(|(b, (val, mask))| b & mask == *val))
+        .map(|(off, _)| start + off as u64)
+        .collect();
+
+    let mut out: String = matches.iter().take(FIND_MAX_LISTED).map(|addr| format!("{:#010x}\n", addr)).collect();
+    match matches.len() {
+        0 => out.push_str("no matches\n"),
+        n if n > FIND_MAX_LISTED => out.push_str(&format!("... {} matches in total\n", n)),
+        _ => {}
+    }
+    out
+}
+
+fn cond_text(bc: &BreakCondition) -> &str {
+    bc.cond.as_ref().map_or("1", |(src, _)| src.as_str())
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Monitor {
+            snapshots: BTreeMap::new(),
+            conditions: BTreeMap::new(),
+            logpoints: BTreeMap::new(),
+            reload_config: false,
+        }
+    }
+
+    // Set by `config reload`, the config belongs to the main loop.
+    pub fn take_config_reload(&mut self) -> bool {
+        std::mem::take(&mut self.reload_config)
+    }
+
+    // The formatted message of a logpoint at the current pc.
+    pub fn logpoint(&self, cpu: &Cpu) -> Option<String> {
+        let lp = self.logpoints.get(&cpu.pc())?;
+        let env = Env { cpu, vars: &[] };
+        Some(lp.parts.iter().map(|part| match part {
+            LogPart::Text(text) => text.clone(),
+            LogPart::Value(e) => match e.eval(&env) {
+                Some(v) => format!("{:#x}", v),
+                None => "?".into(),
+            },
+        }).collect())
+    }
+
+    // Called when the hart reaches a breakpoint, false to run past it. A
+    // condition that can't be evaluated stops.
+    pub fn breakpoint_hit(&mut self, cpu: &Cpu, addr: u64) -> bool {
+        let Some(bc) = self.conditions.get_mut(&addr) else {
+            return true;
+        };
+        if let Some((_, cond)) = &bc.cond
+            && cond.eval(&Env { cpu, vars: &[] }) == Some(0)
+        {
+            return false;
+        }
+
+        bc.hits += 1;
+        if bc.ignore > 0 {
+            bc.ignore -= 1;
+            return false;
+        }
+        true
+    }
+
+    // Bytes held by the device snapshots.
+    fn held(&self) -> usize {
+        self.snapshots.iter().map(|(tag, devices)| {
+            tag.len() + devices.iter().map(|(_, regs)| size_of_val(regs.as_slice())).sum::<usize>()
+        }).sum()
+    }
+
+    pub fn command(&mut self, cpu: &mut Cpu, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["help"] => HELP.into(),
+            ["info", "registers"] => registers::format_xregs(cpu),
+            ["info", "csr"] => registers::format_csrs(cpu),
+            ["info", "csr", name] => match registers::csr_number(cpu, name) {
+                Some(num) => format!("{} {:#x}\n", registers::csr_name(cpu, num),
+                    cpu.debug_read_csr(num).unwrap_or(0)),
+                None => format!("no csr {:?}\n", name),
+            },
+            ["info", "device"] => cpu.device_registers().iter()
+                .map(|(dev, _)| format!("{}\n", dev))
+                .collect(),
+            ["info", "device", name] => {
+                let devices = cpu.device_registers();
+                let Some((_, regs)) = devices.iter().find(|(dev, _)| dev == name) else {
+                    return format!("no device {:?}\n", name);
+                };
+                regs.iter().map(|(reg, val)| format!("{:<8} {:#x}\n", reg, val)).collect()
+            }
+            ["info", "guest-maps"] => guest_maps(cpu, cpu.satp()),
+            ["info", "guest-maps", satp] => match expr::parse_number(satp) {
+                Some(satp) => guest_maps(cpu, satp),
+                None => format!("bad satp {:?}\n", satp),
+            },
+            ["break", "if", addr, cond @ ..] if !cond.is_empty() => {
+                let Some(addr) = expr::parse_number(addr) else {
+                    return format!("bad address {:?}\n", addr);
+                };
+                let src = cond.join(" ");
+                match Expr::parse(&src) {
+                    Ok(cond) => {
+                        self.conditions.entry(addr).or_default().cond = Some((src, cond));
+                        format!("breakpoint at {:#x} stops if {}\n", addr, cond_text(&self.conditions[&addr]))
+                    }
+                    Err(e) => format!("bad condition: {}\n", e),
+                }
+            }
+            ["break", "ignore", addr, n] => match (expr::parse_number(addr), expr::parse_number(n)) {
+                (Some(addr), Some(n)) => {
+                    self.conditions.entry(addr).or_default().ignore = n;
+                    format!("ignoring the next {} hits of the breakpoint at {:#x}\n", n, addr)
+                }
+                _ => "bad address or count\n".into(),
+            },
+            ["break", "clear", addr] => match expr::parse_number(addr) {
+                Some(addr) => match self.conditions.remove(&addr) {
+                    Some(_) => format!("cleared breakpoint conditions at {:#x}\n", addr),
+                    None => format!("no breakpoint conditions at {:#x}\n", addr),
+                },
+                None => format!("bad address {:?}\n", addr),
+            },
+            ["info", "breakpoints"] => {
+                if self.conditions.is_empty() {
+                    return "no breakpoint conditions\n".into();
+                }
+                self.conditions.iter().map(|(addr, bc)| {
+                    format!("{:#010x} hits={} ignore={} if {}\n", addr, bc.hits, bc.ignore, cond_text(bc))
+                }).collect()
+            }
+            ["logpoint", "clear", addr] => match expr::parse_number(addr) {
+                Some(addr) => match self.logpoints.remove(&addr) {
+                    Some(_) => format!("removed logpoint at {:#x}\n", addr),
+                    None => format!("no logpoint at {:#x}\n", addr),
+                },
+                None => format!("bad address {:?}\n", addr),
+            },
+            ["logpoint", addr, _, ..] => {
+                let Some(addr) = expr::parse_number(addr) else {
+                    return format!("bad address {:?}\n", addr);
+                };
+                match parse_logpoint(skip_words(line, 2).trim_end()) {
+                    Ok(lp) => {
+                        self.logpoints.insert(addr, lp);
+                        format!("logpoint at {:#x}\n", addr)
+                    }
+                    Err(e) => format!("bad logpoint message: {}\n", e),
+                }
+            }
+            ["info", "logpoints"] => {
+                if self.logpoints.is_empty() {
+                    return "no logpoints\n".into();
+                }
+                self.logpoints.iter().map(|(addr, lp)| format!("{:#010x} {}\n", addr, lp.src)).collect()
+            }
+            ["device", "snapshot", tag] => {
+                let old = self.snapshots.insert(tag.to_string(), cpu.device_registers());
+                if let Err(e) = cpu.mem_use_mut().reserve(memuse::Owner::Monitor, self.held()) {
+                    match old {
+                        Some(old) => self.snapshots.insert(tag.to_string(), old),
+                        None => self.snapshots.remove(*tag),
+                    };
+                    return format!("device snapshot: {}\n", e);
+                }
+                format!("saved device snapshot {:?}\n", tag)
+            }
+            ["device", "diff", tag] => match self.snapshots.get(*tag) {
+                Some(old) => diff_states(old, &cpu.device_registers()),
+                None => format!("no snapshot {:?}\n", tag),
+            },
+            ["device", "diff", a, b] => match (self.snapshots.get(*a), self.snapshots.get(*b)) {
+                (Some(old), Some(new)) => diff_states(old, new),
+                _ => "unknown snapshot\n".into(),
+            },
+            ["info", "faults"] => {
+                let rates = cpu.uart_mut().faults_mut().rates();
+                if rates.is_empty() {
+                    return "no faults injected\n".into();
+                }
+                rates.iter().map(|(f, rate)| format!("uart {} {}\n", f.name(), rate)).collect()
+            }
+            ["fault", "uart", "clear"] => {
+                cpu.uart_mut().faults_mut().clear();
+                "cleared uart faults\n".into()
+            }
+            ["fault", "uart", "seed", seed] => match expr::parse_number(seed) {
+                Some(seed) => {
+                    cpu.uart_mut().faults_mut().seed(seed);
+                    format!("uart fault seed {:#x}\n", seed)
+                }
+                None => format!("bad seed {:?}\n", seed),
+            },
+            ["fault", "uart", kind, rate] => {
+                let Some(fault) = Fault::parse(kind) else {
+                    return format!("unknown fault {:?}\n", kind);
+                };
+                match rate.parse::<f64>() {
+                    Ok(rate) if (0.0..=1.0).contains(&rate) => {
+                        cpu.uart_mut().faults_mut().set_rate(fault, rate);
+                        format!("uart {} {}\n", fault.name(), rate)
+                    }
+                    _ => format!("bad rate {:?}\n", rate),
+                }
+            }
+            ["inject", "nmi"] => "nmi is not supported, this hart has no Smrnmi\n".into(),
+            ["inject", "exception", cause, rest @ ..] if rest.len() <= 1 => {
+                let tval = rest.first().map_or(Some(0), |t| expr::parse_number(t));
+                match (expr::parse_number(cause).and_then(Exception::from_cause), tval) {
+                    (Some(exception), Some(tval)) => {
+                        cpu.take_trap(Trap::Exception(exception, tval));
+                        format!("exception {} taken, pc={:#x}\n", exception as u64, cpu.pc())
+                    }
+                    _ => "bad exception cause or tval\n".into(),
+                }
+            }
+            ["inject", "clear", irq] => match cpu::irq_mask(irq) {
+                Some(mask) => {
+                    cpu.inject_irq(mask, false);
+                    format!("lowered {}\n", irq)
+                }
+                None => format!("unknown interrupt {:?}\n", irq),
+            },
+            ["inject", irq] => match cpu::irq_mask(irq) {
+                Some(mask) => {
+                    cpu.inject_irq(mask, true);
+                    format!("raised {}\n", irq)
+                }
+                None => format!("unknown interrupt {:?}\n", irq),
+            },
+            ["find", start, end, _, ..] => {
+                let (Some(start), Some(end)) = (expr::parse_number(start), expr::parse_number(end)) else {
+                    return "bad start or end address\n".into();
+                };
+                match parse_pattern(skip_words(line, 3).trim_end()) {
+                    Ok(pattern) => find(cpu, start, end, &pattern),
+                    Err(e) => format!("bad pattern: {}\n", e),
+                }
+            }
+            ["memsave", addr, len, path] => {
+                let (Some(addr), Some(len)) = (expr::parse_number(addr), expr::parse_number(len)) else {
+                    return "bad address or length\n".into();
+                };
+                let Some(mem) = cpu.debug_ram(addr, len) else {
+                    return format!("{:#x}..{:#x} is not in RAM\n", addr, addr.wrapping_add(len));
+                };
+                match std::fs::write(path, mem) {
+                    Ok(()) => format!("saved {:#x} bytes at {:#x} to {}\n", len, addr, path),
+                    Err(e) => format!("{}: {}\n", path, e),
+                }
+            }
+            ["info", "host-time"] => cpu.host_time().report(),
+            ["info", "host-mem"] => cpu.mem_use().report(),
+            ["config", "reload"] => {
+                self.reload_config = true;
+                "config reload requested, errors are reported on the emulator's stderr\n".into()
+            }
+            _ => format!("unknown command {:?}, try 'help'\n", line),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::cell::Cell;
+
+// The last page translated for each access class, so a run of fetches, loads
+// or stores to one page walks the page table once. Entries are keyed by the
+// virtual page and hold the physical page it maps. Anything that can change a
+// translation or its permissions has to flush: satp and mstatus writes,
+// SFENCE.VMA, privilege changes and PMP updates.
+
+pub const PAGE_SIZE: u64 = 4096;
+const PAGE_MASK: u64 = !(PAGE_SIZE - 1);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Load,
+    Store,
+}
+
+#[derive(Default)]
+pub struct PageCache {
+    // (virtual page, physical page) per access class, cells so that the
+    // cache stays usable through &Cpu.
+    entries: [Cell<Option<(u64, u64)>>; 3],
+}
+
+impl PageCache {
+    pub fn lookup(&self, access: Access, addr: u64) -> Option<u64> {
+        let (page, phys) = self.entries[access as usize].get()?;
+        (addr & PAGE_MASK == page).then_some(phys | (addr & !PAGE_MASK))
+    }
+
+    // `paddr` is the physical address `addr` translated to on the slow path.
+    pub fn fill(&self, access: Access, addr: u64, paddr: u64) {
+        self.entries[access as usize].set(Some((addr & PAGE_MASK, paddr & PAGE_MASK)));
+    }
+
+    pub fn flush(&self) {
+        for entry in &self.entries {
+            entry.set(None);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use crate::snapshot;
+
+#[cfg(test)]
+mod tests;
+
+// SiFive PLIC as found on the QEMU virt machine, for the one hart: context 0
+// is its M-mode external interrupt and context 1 its S-mode one. Sources are
+// level triggered. A claimed source is not pending again before the claim is
+// completed, then its line is looked at anew.
+
+pub const MMIO_SIZE: u64 = 0x400000;
+// Source 0 is reserved, 1..NUM_SOURCES are wired.
+pub const NUM_SOURCES: u32 = 32;
+pub const CONTEXTS: usize = 2;
+const MAX_PRIORITY: u32 = 7;
+
+const PENDING: u64 = 0x1000;
+const ENABLE: u64 = 0x2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT: u64 = 0x200000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+
+pub struct Plic {
+    priority: [u32; NUM_SOURCES as usize],
+    // lines as the devices drive them
+    level: u32,
+    pending: u32,
+    // claimed and not yet completed
+    claimed: u32,
+    enable: [u32; CONTEXTS],
+    threshold: [u32; CONTEXTS],
+}
+
+// The registers, of a source or of a context.
+enum Reg {
+    Priority(usize),
+    Pending,
+    Enable(usize),
+    Threshold(usize),
+    Claim(usize),
+}
+
+fn decode(offset: u64) -> Option<Reg> {
+    let reg = match offset {
+        0..PENDING => Reg::Priority((offset / 4) as usize),
+        PENDING => Reg::Pending,
+        ENABLE..CONTEXT if (offset - ENABLE).is_multiple_of(ENABLE_STRIDE) => {
+            Reg::Enable(((offset - ENABLE) / ENABLE_STRIDE) as usize)
+        }
+        CONTEXT.. => {
+            let ctx = ((offset - CONTEXT) / CONTEXT_STRIDE) as usize;
+            match (offset - CONTEXT) % CONTEXT_STRIDE {
+                0 => Reg::Threshold(ctx),
+                4 => Reg::Claim(ctx),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    match reg {
+        Reg::Priority(source) if source >= NUM_SOURCES as usize => None,
+        Reg::Enable(ctx) | Reg::Threshold(ctx) | Reg::Claim(ctx) if ctx >= CONTEXTS => None,
+        reg => Some(reg),
+    }
+}
+
+impl Plic {
+    pub fn new() -> Self {
+        Plic {
+            priority: [0; NUM_SOURCES as usize],
+            level: 0,
+            pending: 0,
+            claimed: 0,
+            enable: [0; CONTEXTS],
+            threshold: [0; CONTEXTS],
+        }
+    }
+
+    // The lines are driven by the devices and survive a reset of the PLIC.
+    pub fn reset(&mut self) {
+        let level = self.level;
+        *self = Plic::new();
+        self.set_levels(level);
+    }
+
+    // Drives every source line at once, bit n for source n.
+    pub fn set_levels(&mut self, level: u32) {
+        self.level = level & !1;
+        self.pending = self.level & !self.claimed;
+    }
+
+    pub fn set_level(&mut self, source: u32, level: bool) {
+        let bit = 1 << source;
+        self.set_levels(if level { self.level | bit } else { self.level & !bit });
+    }
+
+    // The source context ctx would claim now, 0 for none.
+    fn best(&self, ctx: usize) -> u32 {
+        let candidates = self.pending & self.enable[ctx];
+        let mut best = (0, self.threshold[ctx]);
+        for source in 1..NUM_SOURCES {
+            if candidates & (1 << source) != 0 && self.priority[source as usize] > best.1 {
+                best = (source, self.priority[source as usize]);
+            }
+        }
+        best.0
+    }
+
+    // The external interrupt line of a context.
+    pub fn output(&self, ctx: usize) -> bool {
+        self.best(ctx) != 0
+    }
+
+    // 4-byte accesses only, reading claim/complete claims.
+    pub fn load(&mut self, offset: u64, size: usize) -> Option<u32> {
+        if size != 4 || !offset.is_multiple_of(4) {
+            return None;
+        }
+        Some(match decode(offset) {
+            Some(Reg::Priority(source)) => self.priority[source],
+            Some(Reg::Pending) => self.pending,
+            Some(Reg::Enable(ctx)) => self.enable[ctx],
+            Some(Reg::Threshold(ctx)) => self.threshold[ctx],
+            Some(Reg::Claim(ctx)) => {
+                let source = self.best(ctx);
+                self.claimed |= (1 << source) & !1;
+                self.pending &= !(1 << source);
+                source
+            }
+            None => 0,
+        })
+    }
+
+    pub fn store(&mut self, offset: u64, size: usize, val: u32) -> bool {
+        if size != 4 || !offset.is_multiple_of(4) {
+            return false;
+        }
+        match decode(offset) {
+            Some(Reg::Priority(source)) if source != 0 => self.priority[source] = val.min(MAX_PRIORITY),
+            Some(Reg::Enable(ctx)) => self.enable[ctx] = val & !1,
+            Some(Reg::Threshold(ctx)) => self.threshold[ctx] = val.min(MAX_PRIORITY),
+            // completing a source that is not claimed is ignored
+            Some(Reg::Claim(_)) if val < NUM_SOURCES => {
+                self.claimed &= !(1 << val);
+                self.set_levels(self.level);
+            }
+            _ => {}
+        }
+        true
+    }
+
+    pub fn registers(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("level", self.level as u64),
+            ("pending", self.pending as u64),
+            ("claimed", self.claimed as u64),
+            ("enable_m", self.enable[0] as u64),
+            ("enable_s", self.enable[1] as u64),
+            ("threshold_m", self.threshold[0] as u64),
+            ("threshold_s", self.threshold[1] as u64),
+        ]
+    }
+
+    // The lines are not saved, the devices drive them again after a restore.
+    pub fn save(&self, w: &mut snapshot::Writer) {
+        let state = [self.pending, self.claimed];
+        for reg in self.priority.iter().chain(&state).chain(&self.enable).chain(&self.threshold) {
+            w.u64(*reg as u64);
+        }
+    }
+
+    pub fn restore(&mut self, r: &mut snapshot::Reader) -> Result<(), String> {
+        let regs = self.priority.iter_mut().chain([&mut self.pending, &mut self.claimed])
+            .chain(&mut self.enable).chain(&mut self.threshold);
+        for reg in regs {
+            *reg = r.u64()? as u32;
+        }
+        Ok(())
+    }
+}
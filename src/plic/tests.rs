@@ -0,0 +1,62 @@
+use super::*;
+
+const CLAIM_S: u64 = CONTEXT + CONTEXT_STRIDE + 4;
+
+fn plic_with(sources: &[(u32, u32)]) -> Plic {
+    let mut plic = Plic::new();
+    let mut enable = 0;
+    for &(source, priority) in sources {
+        assert!(plic.store(source as u64 * 4, 4, priority));
+        enable |= 1 << source;
+    }
+    assert!(plic.store(ENABLE + ENABLE_STRIDE, 4, enable));
+    plic
+}
+
+#[test]
+fn claim_and_complete() {
+    let mut plic = plic_with(&[(10, 1)]);
+    assert!(!plic.output(1));
+    plic.set_level(10, true);
+    assert!(plic.output(1));
+    // not enabled for the M-mode context
+    assert!(!plic.output(0));
+
+    assert_eq!(plic.load(CLAIM_S, 4), Some(10));
+    assert!(!plic.output(1));
+    assert_eq!(plic.load(CLAIM_S, 4), Some(0));
+
+    // the line still being high pends the source again on completion
+    assert!(plic.store(CLAIM_S, 4, 10));
+    assert!(plic.output(1));
+    plic.set_level(10, false);
+    assert!(!plic.output(1));
+    assert_eq!(plic.load(PENDING, 4), Some(0));
+}
+
+#[test]
+fn priority_and_threshold() {
+    let mut plic = plic_with(&[(3, 2), (8, 5), (10, 5)]);
+    plic.set_levels(1 << 3 | 1 << 8 | 1 << 10);
+    // lowest source first among equal priorities
+    assert_eq!(plic.load(CLAIM_S, 4), Some(8));
+
+    assert!(plic.store(CONTEXT + CONTEXT_STRIDE, 4, 5));
+    assert!(!plic.output(1));
+    assert!(plic.store(CONTEXT + CONTEXT_STRIDE, 4, 1));
+    assert_eq!(plic.load(CLAIM_S, 4), Some(10));
+    assert_eq!(plic.load(CLAIM_S, 4), Some(3));
+
+    // priority 0 never interrupts
+    assert!(plic.store(3 * 4, 4, 0));
+    assert!(plic.store(CLAIM_S, 4, 3));
+    assert!(!plic.output(1));
+}
+
+#[test]
+fn only_word_accesses() {
+    let mut plic = Plic::new();
+    assert_eq!(plic.load(PENDING, 8), None);
+    assert_eq!(plic.load(PENDING + 2, 4), None);
+    assert!(!plic.store(CLAIM_S, 1, 1));
+}
@@ -0,0 +1,74 @@
+use crate::cpu::{DEVICES, RAM_BASE, RAM_SIZE};
+use crate::fdt;
+use crate::loader::Segment;
+
+#[cfg(test)]
+mod tests;
+
+// Consistency checks of the machine run before the guest starts, so that a
+// configuration that cannot boot is refused with what is wrong instead of
+// the guest faulting somewhere later: the bus map, the payloads against RAM
+// and each other, the address the boot ROM jumps to and the memory nodes of
+// device trees loaded for the firmware.
+
+fn overlaps(a: (u64, u64), b: (u64, u64)) -> bool {
+    a.0 < b.1 && b.0 < a.1
+}
+
+fn in_ram(start: u64, end: u64) -> bool {
+    start >= RAM_BASE && end <= RAM_BASE + RAM_SIZE && start <= end
+}
+
+// Every problem found, empty when the machine can start. Without segments,
+// when resuming a snapshot, only the bus map is checked.
+pub fn check(start: u64, segments: &[Segment]) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (i, (name, _, base, size)) in DEVICES.iter().enumerate() {
+        for (other, _, other_base, other_size) in &DEVICES[i + 1..] {
+            if overlaps((*base, base + size), (*other_base, other_base + other_size)) {
+                problems.push(format!("device {} ({:#x}..{:#x}) collides with {} ({:#x}..{:#x})",
+                    name, base, base + size, other, other_base, other_base + other_size));
+            }
+        }
+    }
+    if segments.is_empty() {
+        return problems;
+    }
+
+    let mut sorted: Vec<&Segment> = segments.iter().collect();
+    sorted.sort_by_key(|seg| seg.addr);
+    for seg in &sorted {
+        if !in_ram(seg.addr, seg.end()) {
+            problems.push(format!("{} ({:#x}..{:#x}) is outside of RAM ({:#x}..{:#x}), load it at an address in RAM",
+                seg.name, seg.addr, seg.end(), RAM_BASE, RAM_BASE + RAM_SIZE));
+        }
+    }
+    for pair in sorted.windows(2) {
+        if pair[1].addr < pair[0].end() {
+            problems.push(format!("{} ({:#x}..{:#x}) overlaps {} ({:#x}..{:#x})",
+                pair[1].name, pair[1].addr, pair[1].end(), pair[0].name, pair[0].addr, pair[0].end()));
+        }
+    }
+
+    if !in_ram(start, start.saturating_add(4)) {
+        problems.push(format!("start address {:#x} is not in RAM ({:#x}..{:#x})",
+            start, RAM_BASE, RAM_BASE + RAM_SIZE));
+    } else if !segments.iter().any(|seg| (seg.addr..seg.end()).contains(&start)) {
+        problems.push(format!("nothing is loaded at the start address {:#x}, the first payload to run belongs there",
+            start));
+    }
+
+    for seg in segments.iter().filter(|seg| fdt::is_dtb(&seg.data)) {
+        match fdt::memory_ranges(&seg.data) {
+            Err(e) => problems.push(format!("{}: {}", seg.name, e)),
+            Ok(ranges) if ranges != [(RAM_BASE, RAM_SIZE)] => {
+                let declared: Vec<String> = ranges.iter().map(|(base, size)| format!("{:#x}+{:#x}", base, size)).collect();
+                problems.push(format!("{}: memory nodes declare [{}], RAM is {:#x}+{:#x}",
+                    seg.name, declared.join(", "), RAM_BASE, RAM_SIZE));
+            }
+            Ok(_) => {}
+        }
+    }
+    problems
+}
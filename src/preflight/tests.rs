@@ -0,0 +1,44 @@
+use super::*;
+use crate::isa::Isa;
+
+fn seg(name: &str, addr: u64, len: usize) -> Segment {
+    Segment { name: name.into(), addr, data: vec![0x13; len] }
+}
+
+#[test]
+fn payload_at_start_address_passes() {
+    assert_eq!(check(RAM_BASE, &[seg("kernel", RAM_BASE, 0x1000)]), Vec::<String>::new());
+    // resuming a snapshot
+    assert_eq!(check(RAM_BASE, &[]), Vec::<String>::new());
+}
+
+#[test]
+fn payload_problems() {
+    let problems = check(RAM_BASE, &[
+        seg("kernel", RAM_BASE + 0x1000, 0x1000),
+        seg("initrd", RAM_BASE + 0x1800, 0x1000),
+        seg("tail", RAM_BASE + RAM_SIZE - 0x10, 0x20),
+    ]);
+    assert_eq!(problems.len(), 3, "{:?}", problems);
+    assert!(problems[0].starts_with("tail (0x87fffff0..0x88000010) is outside of RAM"));
+    assert!(problems[1].starts_with("initrd (0x80001800..0x80002800) overlaps kernel"));
+    assert!(problems[2].starts_with("nothing is loaded at the start address 0x80000000"));
+
+    let problems = check(0, &[seg("kernel", RAM_BASE, 0x1000)]);
+    assert_eq!(problems, ["start address 0x0 is not in RAM (0x80000000..0x88000000)"]);
+}
+
+#[test]
+fn device_tree_memory_must_match_ram() {
+    let dtb = fdt::virt_dtb(&Isa::default());
+    let mut bad = dtb.clone();
+    let reg: Vec<u8> = [RAM_BASE, RAM_SIZE].iter().flat_map(|v| v.to_be_bytes()).collect();
+    let at = bad.windows(reg.len()).position(|w| w == reg).unwrap();
+    bad[at + 8..at + 16].copy_from_slice(&(RAM_SIZE * 2).to_be_bytes());
+
+    let kernel = seg("kernel", RAM_BASE, 0x1000);
+    let dtb = Segment { name: "good.dtb".into(), addr: RAM_BASE + 0x100000, data: dtb };
+    let bad = Segment { name: "bad.dtb".into(), addr: RAM_BASE + 0x200000, data: bad };
+    assert_eq!(check(RAM_BASE, &[kernel, dtb, bad]),
+        ["bad.dtb: memory nodes declare [0x80000000+0x10000000], RAM is 0x80000000+0x8000000"]);
+}
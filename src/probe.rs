@@ -0,0 +1,143 @@
+use crate::cpu::Cpu;
+use crate::decoder::Instruction;
+
+#[cfg(test)]
+mod tests;
+
+// Machine-level boot milestones, checked in order against a running guest.
+// There is no virtio-blk, the bundled xv6 panics looking for its disk after
+// satp, so user and exec only pass with a guest that boots without one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Milestone {
+    MretToSupervisor,
+    SatpEnabled,
+    UserEntry,
+    InitExec,
+}
+
+impl Milestone {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "mret-s" => Some(Milestone::MretToSupervisor),
+            "satp" => Some(Milestone::SatpEnabled),
+            "user" => Some(Milestone::UserEntry),
+            "exec" => Some(Milestone::InitExec),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Milestone::MretToSupervisor => "mret-s",
+            Milestone::SatpEnabled => "satp",
+            Milestone::UserEntry => "user",
+            Milestone::InitExec => "exec",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    Passed,
+    Failed,
+}
+
+struct Expectation {
+    milestone: Milestone,
+    timeout: u64,
+    hit: Option<(u64, u64)>, // (instret, pc)
+}
+
+pub struct Probes {
+    expectations: Vec<Expectation>,
+    next: usize,
+    since: u64,
+    prev_privl: u8,
+    exec_syscall: u64,
+    status: Status,
+}
+
+impl Probes {
+    pub fn new(exec_syscall: u64) -> Self {
+        Probes {
+            expectations: Vec::new(),
+            next: 0,
+            since: 0,
+            prev_privl: 3,
+            exec_syscall,
+            status: Status::Running,
+        }
+    }
+
+    pub fn expect(&mut self, milestone: Milestone, timeout: u64) {
+        self.expectations.push(Expectation {
+            milestone,
+            timeout,
+            hit: None,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expectations.is_empty()
+    }
+
+    // Called before every step, the timeout of each milestone counts retired
+    // instructions since the previous one was reached.
+    pub fn check(&mut self, cpu: &Cpu) -> Status {
+        if self.status != Status::Running {
+            return self.status;
+        }
+
+        let privl = cpu.privilege();
+        let prev_privl = self.prev_privl;
+        self.prev_privl = privl;
+
+        let Some(exp) = self.expectations.get_mut(self.next) else {
+            self.status = Status::Passed;
+            return self.status;
+        };
+
+        let reached = match exp.milestone {
+            Milestone::MretToSupervisor => prev_privl == 3 && privl == 1,
+            Milestone::SatpEnabled => cpu.satp() >> 60 != 0,
+            Milestone::UserEntry => privl == 0,
+            Milestone::InitExec => {
+                privl == 0
+                    && matches!(cpu.peek_insn(), Some(Instruction::Ecall(_)))
+                    && cpu.reg(17) == self.exec_syscall // a7
+            }
+        };
+
+        if reached {
+            exp.hit = Some((cpu.instret(), cpu.pc()));
+            self.since = cpu.instret();
+            self.next += 1;
+            if self.next == self.expectations.len() {
+                self.status = Status::Passed;
+            }
+        } else if cpu.instret() - self.since > exp.timeout {
+            self.status = Status::Failed;
+        }
+
+        self.status
+    }
+
+    pub fn report(&self) {
+        for (idx, exp) in self.expectations.iter().enumerate() {
+            match exp.hit {
+                Some((insn, pc)) => eprintln!("probe={} status=pass insn={} pc={:#010X}",
+                    exp.milestone.name(), insn, pc),
+                None if idx == self.next && self.status == Status::Failed => eprintln!(
+                    "probe={} status=fail timeout={}", exp.milestone.name(), exp.timeout),
+                None => eprintln!("probe={} status=skip", exp.milestone.name()),
+            }
+        }
+
+        let result = match self.status {
+            Status::Passed => "pass",
+            _ => "fail",
+        };
+        eprintln!("result={}", result);
+    }
+}
@@ -0,0 +1,50 @@
+use super::*;
+
+use crate::cpu::RAM_BASE;
+
+const MSTATUS: u32 = 0x300;
+const MEPC: u32 = 0x341;
+
+// A hart in M-mode at an mret to S-mode, which then spins on a jal to itself.
+fn mret_to_supervisor() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.set_pc(RAM_BASE);
+    // mret; j .
+    cpu.load_bytes(RAM_BASE, &0x30200073u32.to_le_bytes());
+    cpu.load_bytes(RAM_BASE + 4, &0x0000006fu32.to_le_bytes());
+    cpu.debug_write_csr(MSTATUS, 1 << 11); // MPP = S
+    cpu.debug_write_csr(MEPC, RAM_BASE + 4);
+    cpu
+}
+
+// Steps until the probes settle, at most limit instructions.
+fn run(cpu: &mut Cpu, probes: &mut Probes, limit: u64) -> Status {
+    for _ in 0..limit {
+        match probes.check(cpu) {
+            Status::Running => cpu.step(),
+            status => return status,
+        }
+    }
+    Status::Running
+}
+
+#[test]
+fn reached_milestones_pass() {
+    let mut cpu = mret_to_supervisor();
+    let mut probes = Probes::new(7);
+    probes.expect(Milestone::MretToSupervisor, 10);
+    assert_eq!(run(&mut cpu, &mut probes, 100), Status::Passed);
+    assert_eq!(probes.expectations[0].hit, Some((1, RAM_BASE + 4)));
+}
+
+#[test]
+fn a_milestone_past_its_timeout_fails() {
+    let mut cpu = mret_to_supervisor();
+    let mut probes = Probes::new(7);
+    probes.expect(Milestone::MretToSupervisor, 10);
+    probes.expect(Milestone::SatpEnabled, 50);
+    assert_eq!(run(&mut cpu, &mut probes, 100), Status::Failed);
+    assert_eq!(probes.next, 1);
+    // the timeout counts from the previous milestone
+    assert_eq!(cpu.instret(), 1 + 51);
+}
@@ -0,0 +1,68 @@
+use crate::cpu::Cpu;
+
+// Names of guest registers for everything that shows them to a person: the
+// monitor (and so gdb's `monitor` commands), the gdb target description,
+// expressions and failure reports. x registers go by their ABI name, CSRs by
+// the name in the hart's CSR file.
+
+pub const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+    "fp", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+    "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+const PRIVILEGES: [&str; 4] = ["U", "S", "?", "M"];
+
+// Accepts ABI names, s0 for fp and x0..x31.
+pub fn index(name: &str) -> Option<usize> {
+    if let Some(idx) = ABI_NAMES.iter().position(|n| *n == name) {
+        return Some(idx);
+    }
+    if name == "s0" {
+        return Some(8);
+    }
+    match name.strip_prefix('x')?.parse::<usize>() {
+        Ok(idx) if idx < 32 => Some(idx),
+        _ => None,
+    }
+}
+
+// CSRs the hart does not have are shown by number.
+pub fn csr_name(cpu: &Cpu, num: u32) -> String {
+    cpu.csr_name(num).unwrap_or_else(|| format!("csr{:#05x}", num))
+}
+
+// A CSR by name or number.
+pub fn csr_number(cpu: &Cpu, name: &str) -> Option<u32> {
+    if let Some(num) = crate::expr::parse_number(name) {
+        return u32::try_from(num).ok().filter(|num| cpu.csr_name(*num).is_some());
+    }
+    (0..4096).find(|num| cpu.csr_name(*num).as_deref() == Some(name))
+}
+
+// pc, privilege and the x registers, four to a line. The privilege of loads
+// and stores follows when MPRV makes it differ.
+pub fn format_xregs(cpu: &Cpu) -> String {
+    let mut out = format!("pc   {:#018x}  priv {}", cpu.pc(), PRIVILEGES[cpu.privilege() as usize & 3]);
+    if cpu.data_privilege() != cpu.privilege() {
+        out.push_str(&format!(" (data {})", PRIVILEGES[cpu.data_privilege() as usize & 3]));
+    }
+    out.push('\n');
+    for (row, names) in ABI_NAMES.chunks(4).enumerate() {
+        let line: Vec<String> = names.iter().enumerate()
+            .map(|(col, name)| format!("{:<4} {:#018x}", name, cpu.reg(row * 4 + col)))
+            .collect();
+        out.push_str(&line.join("  "));
+        out.push('\n');
+    }
+    out
+}
+
+// Every CSR of the hart's configuration, one per line.
+pub fn format_csrs(cpu: &Cpu) -> String {
+    (0..4096).filter_map(|num| {
+        let val = cpu.debug_read_csr(num)?;
+        Some(format!("{:<10} {:#05x} {:#x}\n", csr_name(cpu, num), num, val))
+    }).collect()
+}
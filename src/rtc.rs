@@ -0,0 +1,135 @@
+use crate::snapshot;
+
+#[cfg(test)]
+mod tests;
+
+// Goldfish RTC as found on the QEMU virt machine: the date in nanoseconds
+// since the Unix epoch, which Linux reads at boot, and an alarm. The date
+// is the machine's clock, see --rtc, plus the offset the guest sets by
+// writing it. Writing the high half of a value first and the low half
+// commits it, reading the low half latches the high one.
+
+pub const MMIO_SIZE: u64 = 0x1000;
+
+const TIME_LOW: u64 = 0x00;
+const TIME_HIGH: u64 = 0x04;
+const ALARM_LOW: u64 = 0x08;
+const ALARM_HIGH: u64 = 0x0c;
+const IRQ_ENABLED: u64 = 0x10;
+const CLEAR_ALARM: u64 = 0x14;
+const ALARM_STATUS: u64 = 0x18;
+const CLEAR_INTERRUPT: u64 = 0x1c;
+
+pub struct Rtc {
+    // added to the clock, wrapping
+    offset: u64,
+    time_high: u32,
+    alarm_high: u32,
+    alarm: u64,
+    alarm_running: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Rtc {
+            offset: 0,
+            time_high: 0,
+            alarm_high: 0,
+            alarm: 0,
+            alarm_running: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Rtc::new();
+    }
+
+    // Fires the alarm once `now`, the clock in ns, reaches it.
+    pub fn update(&mut self, now: u64) {
+        if self.alarm_running && now.wrapping_add(self.offset) >= self.alarm {
+            self.alarm_running = false;
+            self.irq_pending = true;
+        }
+    }
+
+    // The level of the interrupt line.
+    pub fn interrupt(&self) -> bool {
+        self.irq_enabled && self.irq_pending
+    }
+
+    // 4-byte accesses only.
+    pub fn load(&mut self, offset: u64, size: usize, now: u64) -> Option<u32> {
+        if size != 4 || !offset.is_multiple_of(4) {
+            return None;
+        }
+        Some(match offset {
+            TIME_LOW => {
+                let time = now.wrapping_add(self.offset);
+                self.time_high = (time >> 32) as u32;
+                time as u32
+            }
+            TIME_HIGH => self.time_high,
+            ALARM_LOW => self.alarm as u32,
+            ALARM_HIGH => (self.alarm >> 32) as u32,
+            IRQ_ENABLED => self.irq_enabled as u32,
+            ALARM_STATUS => self.alarm_running as u32,
+            _ => 0,
+        })
+    }
+
+    pub fn store(&mut self, offset: u64, size: usize, val: u32, now: u64) -> bool {
+        if size != 4 || !offset.is_multiple_of(4) {
+            return false;
+        }
+        match offset {
+            TIME_LOW => {
+                let time = (self.time_high as u64) << 32 | val as u64;
+                self.offset = time.wrapping_sub(now);
+            }
+            TIME_HIGH => self.time_high = val,
+            ALARM_LOW => {
+                self.alarm = (self.alarm_high as u64) << 32 | val as u64;
+                self.alarm_running = true;
+                self.update(now);
+            }
+            ALARM_HIGH => self.alarm_high = val,
+            IRQ_ENABLED => self.irq_enabled = val & 1 != 0,
+            CLEAR_ALARM => self.alarm_running = false,
+            CLEAR_INTERRUPT => self.irq_pending = false,
+            _ => {}
+        }
+        true
+    }
+
+    pub fn registers(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("offset", self.offset),
+            ("alarm", self.alarm),
+            ("alarm_running", self.alarm_running as u64),
+            ("irq_enabled", self.irq_enabled as u64),
+            ("irq_pending", self.irq_pending as u64),
+        ]
+    }
+
+    pub fn save(&self, w: &mut snapshot::Writer) {
+        w.u64s(&[self.offset, self.time_high as u64, self.alarm_high as u64, self.alarm]);
+        for flag in [self.alarm_running, self.irq_enabled, self.irq_pending] {
+            w.u8(flag as u8);
+        }
+    }
+
+    pub fn restore(&mut self, r: &mut snapshot::Reader) -> Result<(), String> {
+        let mut regs = [0; 4];
+        r.u64s(&mut regs)?;
+        let [offset, time_high, alarm_high, alarm] = regs;
+        (self.offset, self.time_high, self.alarm_high, self.alarm) = (offset, time_high as u32, alarm_high as u32, alarm);
+        for flag in [&mut self.alarm_running, &mut self.irq_enabled, &mut self.irq_pending] {
+            *flag = r.u8()? != 0;
+        }
+        Ok(())
+    }
+}
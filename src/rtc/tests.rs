@@ -0,0 +1,26 @@
+use super::*;
+
+#[test]
+fn set_time_and_alarm() {
+    let mut rtc = Rtc::new();
+    assert_eq!(rtc.load(TIME_LOW, 4, 0x1_0000_0005), Some(5));
+    assert_eq!(rtc.load(TIME_HIGH, 4, 0x2_0000_0000), Some(1));
+
+    // the date moves with the clock from where it was set
+    assert!(rtc.store(TIME_HIGH, 4, 0x10, 1000));
+    assert!(rtc.store(TIME_LOW, 4, 0x20, 1000));
+    assert_eq!(rtc.load(TIME_LOW, 4, 1100), Some(0x84));
+    assert_eq!(rtc.load(TIME_HIGH, 4, 1100), Some(0x10));
+
+    assert!(rtc.store(IRQ_ENABLED, 4, 1, 1100));
+    assert!(rtc.store(ALARM_HIGH, 4, 0x10, 1100));
+    assert!(rtc.store(ALARM_LOW, 4, 0x300, 1100));
+    assert_eq!(rtc.load(ALARM_STATUS, 4, 1100), Some(1));
+    rtc.update(1500);
+    assert!(!rtc.interrupt());
+    rtc.update(1800);
+    assert!(rtc.interrupt());
+    assert_eq!(rtc.load(ALARM_STATUS, 4, 1800), Some(0));
+    assert!(rtc.store(CLEAR_INTERRUPT, 4, 1, 1800));
+    assert!(!rtc.interrupt());
+}
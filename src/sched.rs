@@ -0,0 +1,97 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+#[cfg(test)]
+mod tests;
+
+// The main loop's run queue. Harts and periodic host work are ordered by
+// their next deadline in steps, ties in the order they were queued. A hart
+// runs until the earliest other deadline but at most for a quantum, so how
+// long it runs between polls of host input is a policy of the scheduler
+// rather than a constant of the loop.
+
+pub const DEFAULT_QUANTUM: u64 = 10_000;
+pub const DEFAULT_IO_LATENCY: u64 = 1_000;
+pub const DEFAULT_TIMER_PRECISION: u64 = 10_000;
+pub const DEFAULT_CONFIG_INTERVAL: u64 = 1 << 20;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Task {
+    Hart(usize),
+    // Host input for the devices and signals.
+    Io,
+    // Drives the timer interrupt lines, due at the next timer deadline.
+    Timer,
+    // Checks the config file for changes.
+    Config,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Policy {
+    // Most steps a hart runs before the queue is looked at again.
+    pub quantum: u64,
+    // Steps between polls of host input.
+    pub io_latency: u64,
+    // Most steps between timer checks, a closer deadline is checked at
+    // counting a tick as a step.
+    pub timer_precision: u64,
+    pub config_interval: u64,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            quantum: DEFAULT_QUANTUM,
+            io_latency: DEFAULT_IO_LATENCY,
+            timer_precision: DEFAULT_TIMER_PRECISION,
+            config_interval: DEFAULT_CONFIG_INTERVAL,
+        }
+    }
+}
+
+pub struct Scheduler {
+    policy: Policy,
+    now: u64,
+    seq: u64,
+    queue: BinaryHeap<Reverse<(u64, u64, Task)>>,
+}
+
+impl Scheduler {
+    pub fn new(policy: Policy) -> Self {
+        Scheduler { policy, now: 0, seq: 0, queue: BinaryHeap::new() }
+    }
+
+    pub fn add(&mut self, task: Task, at: u64) {
+        self.queue.push(Reverse((at, self.seq, task)));
+        self.seq += 1;
+    }
+
+    // The next task and, for a hart, how many steps it may run.
+    pub fn next(&mut self) -> Option<(Task, u64)> {
+        let Reverse((at, _, task)) = self.queue.pop()?;
+        self.now = self.now.max(at);
+        let steps = match task {
+            Task::Hart(_) => {
+                let until = self.queue.peek().map_or(u64::MAX, |Reverse((at, _, _))| *at);
+                until.saturating_sub(self.now).clamp(1, self.policy.quantum.max(1))
+            }
+            _ => 0,
+        };
+        Some((task, steps))
+    }
+
+    // Requeues a task that ran, a hart after the steps it took and the
+    // timer `steps` before its next deadline.
+    pub fn done(&mut self, task: Task, steps: u64) {
+        let at = match task {
+            Task::Hart(_) => {
+                self.now += steps;
+                self.now
+            }
+            Task::Io => self.now + self.policy.io_latency.max(1),
+            Task::Timer => self.now + steps.clamp(1, self.policy.timer_precision.max(1)),
+            Task::Config => self.now + self.policy.config_interval.max(1),
+        };
+        self.add(task, at);
+    }
+}
@@ -0,0 +1,64 @@
+use super::*;
+
+fn scheduler(quantum: u64, io_latency: u64) -> Scheduler {
+    let mut sched = Scheduler::new(Policy { quantum, io_latency, ..Policy::default() });
+    sched.add(Task::Io, 0);
+    sched.add(Task::Hart(0), 0);
+    sched
+}
+
+#[test]
+fn hart_runs_until_the_next_deadline() {
+    let mut sched = scheduler(100, 30);
+    assert_eq!(sched.next(), Some((Task::Io, 0)));
+    sched.done(Task::Io, 0);
+    assert_eq!(sched.next(), Some((Task::Hart(0), 30)));
+    sched.done(Task::Hart(0), 30);
+    // host input at the same step goes first
+    assert_eq!(sched.next(), Some((Task::Io, 0)));
+}
+
+#[test]
+fn quantum_bounds_a_slice() {
+    let mut sched = scheduler(10, 1000);
+    sched.next();
+    sched.done(Task::Io, 0);
+    for _ in 0..3 {
+        let (task, steps) = sched.next().unwrap();
+        assert_eq!((task, steps), (Task::Hart(0), 10));
+        sched.done(task, steps);
+    }
+    // the remaining steps up to host input
+    assert_eq!(sched.next(), Some((Task::Hart(0), 10)));
+    sched.done(Task::Hart(0), 970);
+    assert_eq!(sched.next(), Some((Task::Io, 0)));
+}
+
+#[test]
+fn harts_share_the_queue() {
+    let mut sched = Scheduler::new(Policy { quantum: 5, ..Policy::default() });
+    sched.add(Task::Hart(0), 0);
+    sched.add(Task::Hart(1), 0);
+    let order: Vec<Task> = (0..4).map(|_| {
+        let (task, steps) = sched.next().unwrap();
+        sched.done(task, steps);
+        task
+    }).collect();
+    assert_eq!(order, [Task::Hart(0), Task::Hart(1), Task::Hart(0), Task::Hart(1)]);
+}
+
+#[test]
+fn timer_is_due_at_its_deadline() {
+    let mut sched = Scheduler::new(Policy { timer_precision: 100, ..Policy::default() });
+    sched.add(Task::Timer, 0);
+    sched.add(Task::Hart(0), 0);
+    sched.next();
+    // 40 ticks to the next deadline
+    sched.done(Task::Timer, 40);
+    assert_eq!(sched.next(), Some((Task::Hart(0), 40)));
+    sched.done(Task::Hart(0), 40);
+    sched.next();
+    // none armed, checked again after the precision
+    sched.done(Task::Timer, u64::MAX);
+    assert_eq!(sched.next(), Some((Task::Hart(0), 100)));
+}
@@ -0,0 +1,246 @@
+use crate::cpu::{Cpu, MmioAccess, TrapEvent};
+use crate::expr::{self, Env, Expr};
+use crate::registers;
+
+#[cfg(test)]
+mod tests;
+
+// Hook scripts are line based, one hook per line:
+//
+//   on pc 0x80000010: print "a0 =", a0; set a0, a0 + 1
+//   on mmio 0x10000000 write: print "tx", value
+//   on insn 1000000: exit 0
+//   on trap 2: print "illegal insn", tval
+//
+// Actions are separated by ';' and their arguments by ','. Available
+// actions are print, set <reg|pc>, write8/16/32/64 <addr>, <value> and
+// exit. Hooks on mmio can use the `addr` and `value` variables. Hooks on
+// trap fire after any trap or those of one mcause value, interrupts with
+// bit 63 set, and can use `cause`, `tval` and `epc`.
+
+#[derive(Debug, Clone)]
+enum Event {
+    Pc(u64),
+    Mmio { addr: u64, write: Option<bool> },
+    Insn(u64),
+    Trap(Option<u64>),
+}
+
+#[derive(Debug, Clone)]
+enum PrintItem {
+    Text(String),
+    Value(Expr),
+}
+
+#[derive(Debug, Clone)]
+enum Action {
+    Print(Vec<PrintItem>),
+    SetReg(usize, Expr),
+    SetPc(Expr),
+    Write(u8, Expr, Expr),
+    Exit(Expr),
+}
+
+#[derive(Debug, Clone)]
+struct Hook {
+    event: Event,
+    actions: Vec<Action>,
+}
+
+pub struct Script {
+    hooks: Vec<Hook>,
+}
+
+// Splits on `sep` outside of double-quoted strings.
+fn split_unquoted(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn parse_event(s: &str) -> Result<Event, String> {
+    let words: Vec<&str> = s.split_whitespace().collect();
+    let number = |w: Option<&&str>| w.and_then(|w| expr::parse_number(w))
+        .ok_or_else(|| format!("bad event {:?}", s));
+
+    match words.first().copied() {
+        Some("pc") if words.len() == 2 => Ok(Event::Pc(number(words.get(1))?)),
+        Some("insn") if words.len() == 2 => Ok(Event::Insn(number(words.get(1))?)),
+        Some("trap") if words.len() == 1 => Ok(Event::Trap(None)),
+        Some("trap") if words.len() == 2 => Ok(Event::Trap(Some(number(words.get(1))?))),
+        Some("mmio") if words.len() <= 3 => {
+            let write = match words.get(2).copied() {
+                None => None,
+                Some("write") => Some(true),
+                Some("read") => Some(false),
+                Some(w) => return Err(format!("bad mmio access kind {:?}", w)),
+            };
+            Ok(Event::Mmio { addr: number(words.get(1))?, write })
+        }
+        _ => Err(format!("bad event {:?}", s)),
+    }
+}
+
+fn parse_action(s: &str) -> Result<Action, String> {
+    let s = s.trim();
+    let (name, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+    let args: Vec<&str> = split_unquoted(rest, ',').into_iter()
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .collect();
+
+    let nargs = |n: usize| if args.len() == n {
+        Ok(())
+    } else {
+        Err(format!("{} takes {} arguments", name, n))
+    };
+
+    match name {
+        "print" => {
+            let items = args.iter().map(|a| match a.strip_prefix('"') {
+                Some(text) => text.strip_suffix('"').map(|t| PrintItem::Text(t.to_string()))
+                    .ok_or_else(|| format!("unterminated string {:?}", a)),
+                None => Expr::parse(a).map(PrintItem::Value),
+            }).collect::<Result<_, _>>()?;
+            Ok(Action::Print(items))
+        }
+        "set" => {
+            nargs(2)?;
+            let val = Expr::parse(args[1])?;
+            if args[0] == "pc" {
+                return Ok(Action::SetPc(val));
+            }
+            match registers::index(args[0]) {
+                Some(idx) => Ok(Action::SetReg(idx, val)),
+                None => Err(format!("unknown register {:?}", args[0])),
+            }
+        }
+        "write8" | "write16" | "write32" | "write64" => {
+            nargs(2)?;
+            let width = name[5..].parse::<u8>().unwrap() / 8;
+            Ok(Action::Write(width, Expr::parse(args[0])?, Expr::parse(args[1])?))
+        }
+        "exit" => {
+            nargs(1)?;
+            Ok(Action::Exit(Expr::parse(args[0])?))
+        }
+        _ => Err(format!("unknown action {:?}", name)),
+    }
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut hooks = Vec::new();
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err = |e: String| format!("line {}: {}", lineno + 1, e);
+            let Some(hook) = line.strip_prefix("on ") else {
+                return Err(err("expected 'on <event>: <actions>'".into()));
+            };
+            let Some((event, actions)) = hook.split_once(':') else {
+                return Err(err("missing ':' after event".into()));
+            };
+            let event = parse_event(event).map_err(err)?;
+            let actions = split_unquoted(actions, ';').into_iter()
+                .filter(|a| !a.trim().is_empty())
+                .map(parse_action)
+                .collect::<Result<_, _>>()
+                .map_err(err)?;
+            hooks.push(Hook { event, actions });
+        }
+
+        Ok(Script { hooks })
+    }
+
+    // Both hook passes return the status of an exit action, the run loop
+    // ends the run with it.
+    pub fn before_step(&mut self, cpu: &mut Cpu) -> Option<i32> {
+        for hook in &self.hooks {
+            let fire = match hook.event {
+                Event::Pc(pc) => cpu.pc() == pc,
+                Event::Insn(n) => cpu.instret() == n,
+                Event::Mmio { .. } | Event::Trap(_) => false,
+            };
+            if fire && let Some(code) = run(&hook.actions, cpu, &[]) {
+                return Some(code);
+            }
+        }
+        None
+    }
+
+    // `trap` is the trap the step took, taken before the tracer consumes it.
+    pub fn after_step(&mut self, cpu: &mut Cpu, access: Option<MmioAccess>, trap: Option<TrapEvent>)
+        -> Option<i32>
+    {
+        for hook in &self.hooks {
+            let exit = match (&hook.event, access, trap) {
+                (Event::Mmio { addr, write }, Some(access), _)
+                    if *addr == access.addr && write.is_none_or(|w| w == access.write) =>
+                {
+                    run(&hook.actions, cpu, &[("addr", access.addr), ("value", access.value)])
+                }
+                (Event::Trap(cause), _, Some(trap)) if cause.is_none_or(|c| c == trap.cause) => {
+                    run(&hook.actions, cpu, &[("cause", trap.cause), ("tval", trap.tval), ("epc", trap.epc)])
+                }
+                _ => None,
+            };
+            if exit.is_some() {
+                return exit;
+            }
+        }
+        None
+    }
+}
+
+// The status of an exit action, which ends the hook.
+fn run(actions: &[Action], cpu: &mut Cpu, vars: &[(&str, u64)]) -> Option<i32> {
+    for action in actions {
+        let env = Env { cpu, vars };
+        match action {
+            Action::Print(items) => {
+                let line: Vec<String> = items.iter().map(|item| match item {
+                    PrintItem::Text(text) => text.clone(),
+                    PrintItem::Value(e) => match e.eval(&env) {
+                        Some(v) => format!("{:#x}", v),
+                        None => "?".into(),
+                    },
+                }).collect();
+                eprintln!("{}", line.join(" "));
+            }
+            Action::SetReg(idx, e) => {
+                if let Some(v) = e.eval(&env) {
+                    cpu.set_reg(*idx, v);
+                }
+            }
+            Action::SetPc(e) => {
+                if let Some(v) = e.eval(&env) {
+                    cpu.set_pc(v);
+                }
+            }
+            Action::Write(width, addr, val) => {
+                let (Some(addr), Some(val)) = (addr.eval(&env), val.eval(&env)) else {
+                    continue;
+                };
+                cpu.debug_write_mem(addr, &val.to_le_bytes()[..*width as usize]);
+            }
+            Action::Exit(e) => return Some(e.eval(&env).unwrap_or(1) as i32),
+        }
+    }
+    None
+}
@@ -0,0 +1,40 @@
+use super::*;
+
+fn trap(cause: u64) -> Option<TrapEvent> {
+    Some(TrapEvent { cause, tval: 0x1234, epc: 0x8000_0000 })
+}
+
+#[test]
+fn trap_hooks_match_the_cause() {
+    let mut script = Script::parse("on trap 2: set a0, tval\non trap: set a1, a1 + 1; set a2, cause").unwrap();
+    let mut cpu = Cpu::new();
+
+    script.after_step(&mut cpu, None, None);
+    assert_eq!(cpu.reg(11), 0);
+
+    script.after_step(&mut cpu, None, trap(2));
+    assert_eq!((cpu.reg(10), cpu.reg(11), cpu.reg(12)), (0x1234, 1, 2));
+
+    cpu.set_reg(10, 0);
+    script.after_step(&mut cpu, None, trap(1 << 63 | 7));
+    assert_eq!((cpu.reg(10), cpu.reg(11), cpu.reg(12)), (0, 2, 1 << 63 | 7));
+}
+
+#[test]
+fn bad_trap_event() {
+    assert!(Script::parse("on trap illegal: exit 1").is_err());
+    assert!(Script::parse("on trap 2 3: exit 1").is_err());
+}
+
+// exit hands its status to the run loop and skips the rest of the hook.
+#[test]
+fn exit_returns_its_status() {
+    let mut script = Script::parse("on insn 0: exit a0 + 2; set a1, 1\non trap: exit 3").unwrap();
+    let mut cpu = Cpu::new();
+    cpu.set_reg(10, 1);
+    assert_eq!(script.before_step(&mut cpu), Some(3));
+    assert_eq!(cpu.reg(11), 0);
+
+    assert_eq!(script.after_step(&mut cpu, None, None), None);
+    assert_eq!(script.after_step(&mut cpu, None, trap(2)), Some(3));
+}
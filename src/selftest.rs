@@ -0,0 +1,141 @@
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+// `nrv64emu selftest` boots reference workloads headless and checks that
+// their console output reaches the expected milestones, in order. Workloads
+// live in <configs>/<name>/, only xv6 ships with the tree so the others are
+// skipped until their files are dropped in. Each one runs as a child of this
+// binary, a guest that brings the emulator down fails only its own run.
+//
+// Nothing here reaches user space: xv6 panics looking for a virtio-blk disk
+// the machine does not have, so its only milestone is the banner. Dhrystone
+// and CoreMark have to be built for this machine's UART, there is no release
+// to fetch. There is no Linux workload.
+
+struct Workload {
+    name: &'static str,
+    files: &'static [&'static str],
+    // Emulator arguments, {} stands for the workload's directory.
+    args: &'static [&'static str],
+    milestones: &'static [&'static str],
+    timeout_secs: u64,
+}
+
+const WORKLOADS: &[Workload] = &[
+    Workload {
+        name: "xv6",
+        files: &["kernel.bin"],
+        args: &["--load", "{}/kernel.bin@0x80000000"],
+        milestones: &["xv6 kernel is booting"],
+        timeout_secs: 60,
+    },
+    // Bare-metal builds for this machine printing through the UART.
+    Workload {
+        name: "dhrystone",
+        files: &["dhrystone.elf"],
+        args: &["--load", "{}/dhrystone.elf"],
+        milestones: &["Dhrystone Benchmark", "Dhrystones per Second"],
+        timeout_secs: 300,
+    },
+    Workload {
+        name: "coremark",
+        files: &["coremark.elf"],
+        args: &["--load", "{}/coremark.elf"],
+        milestones: &["CoreMark Size", "Correct operation validated."],
+        timeout_secs: 600,
+    },
+];
+
+enum Outcome {
+    Passed(Duration),
+    Skipped(String),
+    Failed(String),
+}
+
+// Runs every workload, true when none failed.
+pub fn run(configs: &Path) -> bool {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("selftest: {}", e);
+            return false;
+        }
+    };
+    let mut passed = true;
+    for workload in WORKLOADS {
+        match run_one(&exe, configs, workload) {
+            Outcome::Passed(took) => println!("{:<10} ok ({:.1}s)", workload.name, took.as_secs_f64()),
+            Outcome::Skipped(why) => println!("{:<10} skipped, {}", workload.name, why),
+            Outcome::Failed(why) => {
+                println!("{:<10} FAILED, {}", workload.name, why);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+fn run_one(exe: &Path, configs: &Path, workload: &Workload) -> Outcome {
+    let dir = configs.join(workload.name);
+    if let Some(file) = workload.files.iter().map(|f| dir.join(f)).find(|f| !f.exists()) {
+        return Outcome::Skipped(format!("{} is missing", file.display()));
+    }
+    let dir = dir.to_string_lossy();
+    let args = workload.args.iter().map(|arg| arg.replace("{}", &dir));
+    let child = Command::new(exe)
+        .args(["--serial-input", "/dev/null", "--trace-insns", "0"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let outcome = watch(&mut child, workload);
+    let _ = child.kill();
+    let _ = child.wait();
+    outcome
+}
+
+// Reads the console until every milestone has shown up, the child exits or
+// the timeout passes.
+fn watch(child: &mut Child, workload: &Workload) -> Outcome {
+    let mut stdout = child.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while let Ok(n @ 1..) = stdout.read(&mut buf) {
+            if tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(workload.timeout_secs);
+    let mut console = String::new();
+    let mut searched = 0;
+    let mut milestones = workload.milestones.iter().peekable();
+    while let Some(milestone) = milestones.peek() {
+        if let Some(pos) = console[searched..].find(*milestone) {
+            searched += pos + milestone.len();
+            milestones.next();
+            continue;
+        }
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(bytes) => console.push_str(&String::from_utf8_lossy(&bytes)),
+            Err(RecvTimeoutError::Timeout) => {
+                return Outcome::Failed(format!("timed out waiting for {:?}", milestone));
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Outcome::Failed(format!("exited before {:?}", milestone));
+            }
+        }
+    }
+    Outcome::Passed(start.elapsed())
+}
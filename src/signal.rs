@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// SIGINT/SIGTERM are only recorded here and acted upon between two steps.
+// std already links the C library, so signal() is declared directly.
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+extern "C" fn on_signal(_signum: i32) {
+    RECEIVED.store(true, Ordering::Relaxed);
+}
+
+pub fn install() {
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        signal(SIGINT, on_signal);
+        signal(SIGTERM, on_signal);
+    }
+}
+
+pub fn received() -> bool {
+    RECEIVED.load(Ordering::Relaxed)
+}
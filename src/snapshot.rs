@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+
+use crate::cpu::{self, Cpu};
+use crate::memuse::Owner;
+
+// Machine snapshots hold the guest-visible state: registers, CSRs, RAM and
+// device registers. Host attachments (console, traces, debugger state) are
+// not part of a snapshot. The format is a magic and version followed by the
+// fields in the order they are written, all little-endian.
+
+const MAGIC: &[u8; 8] = b"NRV64SNP";
+pub const VERSION: u32 = 9;
+const EXTENSION: &str = "snap";
+
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        Writer { buf }
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u64s(&mut self, vals: &[u64]) {
+        for val in vals {
+            self.u64(*val);
+        }
+    }
+
+    pub fn opt_u64(&mut self, val: Option<u64>) {
+        self.u8(val.is_some() as u8);
+        self.u64(val.unwrap_or(0));
+    }
+
+    // Length-prefixed.
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.u64(data.len() as u64);
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, String> {
+        let rest = data.strip_prefix(MAGIC).ok_or("not a snapshot")?;
+        let mut reader = Reader { data: rest };
+        let version = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+        if version != VERSION {
+            return Err(format!("unsupported snapshot version {}", version));
+        }
+        Ok(reader)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("truncated snapshot".into());
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn u64s(&mut self, vals: &mut [u64]) -> Result<(), String> {
+        for val in vals {
+            *val = self.u64()?;
+        }
+        Ok(())
+    }
+
+    pub fn opt_u64(&mut self) -> Result<Option<u64>, String> {
+        let some = self.u8()? != 0;
+        let val = self.u64()?;
+        Ok(some.then_some(val))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u64()? as usize;
+        self.take(len)
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        match self.data.is_empty() {
+            true => Ok(()),
+            false => Err("trailing data in snapshot".into()),
+        }
+    }
+}
+
+// A new file name in `dir`, named by the time it was taken so `latest`
+// sorts last.
+pub fn checkpoint_path(dir: &Path) -> PathBuf {
+    let since_epoch = std::time::SystemTime::UNIX_EPOCH.elapsed().unwrap();
+    dir.join(format!("checkpoint-{:020}.{}", since_epoch.as_millis(), EXTENSION))
+}
+
+pub fn latest(dir: &Path) -> Result<PathBuf, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    entries.filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == EXTENSION))
+        .max()
+        .ok_or_else(|| format!("no checkpoints in {}", dir.display()))
+}
+
+// Non-zero RAM pages with their index and length, plus the registers.
+fn estimated_size(cpu: &Cpu) -> usize {
+    cpu.ram_pages_in_use() * (cpu::SNAPSHOT_PAGE + 16) + cpu::SNAPSHOT_PAGE
+}
+
+// Snapshots are built in memory and count against --max-host-mem meanwhile.
+pub fn save(cpu: &mut Cpu, path: &Path) -> Result<(), String> {
+    let size = estimated_size(cpu);
+    cpu.mem_use_mut().reserve(Owner::Snapshot, size)?;
+    let mut w = Writer::new();
+    cpu.save(&mut w);
+    let data = w.finish();
+    let res = write(path, &data);
+    cpu.mem_use_mut().set(Owner::Snapshot, 0);
+    res
+}
+
+fn write(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    // Written aside and renamed, a signal during the write leaves no torn file.
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, data)
+        .and_then(|()| std::fs::rename(&partial, path))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn restore(cpu: &mut Cpu, path: &Path) -> Result<(), String> {
+    let len = std::fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?.len();
+    cpu.mem_use_mut().reserve(Owner::Snapshot, len as usize)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let res = read(cpu, path);
+    cpu.mem_use_mut().set(Owner::Snapshot, 0);
+    res
+}
+
+fn read(cpu: &mut Cpu, path: &Path) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut r = Reader::new(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    cpu.restore(&mut r).and_then(|()| r.finish()).map_err(|e| format!("{}: {}", path.display(), e))
+}
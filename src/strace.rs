@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use crate::cpu::Cpu;
+use crate::decoder::Instruction;
+
+// RISC-V Linux (asm-generic) syscall numbers with their argument counts.
+const SYSCALLS: &[(u64, &str, usize)] = &[
+    (17, "getcwd", 2),
+    (23, "dup", 1),
+    (24, "dup3", 3),
+    (25, "fcntl", 3),
+    (29, "ioctl", 3),
+    (34, "mkdirat", 3),
+    (35, "unlinkat", 3),
+    (37, "linkat", 5),
+    (38, "renameat", 4),
+    (48, "faccessat", 4),
+    (49, "chdir", 1),
+    (56, "openat", 4),
+    (57, "close", 1),
+    (59, "pipe2", 2),
+    (61, "getdents64", 3),
+    (62, "lseek", 3),
+    (63, "read", 3),
+    (64, "write", 3),
+    (65, "readv", 3),
+    (66, "writev", 3),
+    (67, "pread64", 4),
+    (68, "pwrite64", 4),
+    (78, "readlinkat", 4),
+    (79, "newfstatat", 4),
+    (80, "fstat", 2),
+    (93, "exit", 1),
+    (94, "exit_group", 1),
+    (96, "set_tid_address", 1),
+    (98, "futex", 6),
+    (99, "set_robust_list", 2),
+    (101, "nanosleep", 2),
+    (113, "clock_gettime", 2),
+    (124, "sched_yield", 0),
+    (129, "kill", 2),
+    (134, "rt_sigaction", 4),
+    (135, "rt_sigprocmask", 4),
+    (139, "rt_sigreturn", 0),
+    (153, "times", 1),
+    (160, "uname", 1),
+    (172, "getpid", 0),
+    (173, "getppid", 0),
+    (174, "getuid", 0),
+    (175, "geteuid", 0),
+    (176, "getgid", 0),
+    (177, "getegid", 0),
+    (178, "gettid", 0),
+    (214, "brk", 1),
+    (215, "munmap", 2),
+    (220, "clone", 5),
+    (221, "execve", 3),
+    (222, "mmap", 6),
+    (226, "mprotect", 3),
+    (233, "madvise", 3),
+    (260, "wait4", 4),
+    (261, "prlimit64", 4),
+    (278, "getrandom", 3),
+    (291, "statx", 5),
+];
+
+// Syscalls that do not return to the instruction after the ecall.
+const NORETURN: &[u64] = &[93, 94, 139];
+
+struct Pending {
+    call: String,
+}
+
+// Logs U-mode ecalls and their return values, prefixed with the satp of the
+// calling process. A call is matched with its return by the address space,
+// the user stack pointer and the return address, which also tells threads
+// of one process apart.
+pub struct Strace {
+    pending: HashMap<(u64, u64, u64), Pending>,
+}
+
+fn format_call(cpu: &Cpu) -> (u64, String) {
+    let nr = cpu.reg(17); // a7
+    let (name, nargs) = match SYSCALLS.iter().find(|(n, _, _)| *n == nr) {
+        Some((_, name, nargs)) => (name.to_string(), *nargs),
+        None => (format!("syscall_{}", nr), 6),
+    };
+    let args: Vec<String> = (0..nargs).map(|i| format!("{:#x}", cpu.reg(10 + i))).collect();
+    (nr, format!("{}({})", name, args.join(", ")))
+}
+
+fn format_ret(ret: u64) -> String {
+    // -4095..-1 are errno values
+    if ret >= (-4095i64) as u64 {
+        format!("{}", ret as i64)
+    } else {
+        format!("{:#x}", ret)
+    }
+}
+
+impl Strace {
+    pub fn new() -> Self {
+        Strace { pending: HashMap::new() }
+    }
+
+    pub fn before_step(&mut self, cpu: &Cpu) {
+        if cpu.privilege() != 0 {
+            return;
+        }
+
+        let satp = cpu.satp();
+        let sp = cpu.reg(2);
+        if let Some(call) = self.pending.remove(&(satp, sp, cpu.pc())) {
+            eprintln!("[{:#x}] {} = {}", satp, call.call, format_ret(cpu.reg(10)));
+        }
+
+        if !matches!(cpu.peek_insn(), Some(Instruction::Ecall(_))) {
+            return;
+        }
+        let (nr, call) = format_call(cpu);
+        if NORETURN.contains(&nr) {
+            eprintln!("[{:#x}] {} = ?", satp, call);
+        } else {
+            self.pending.insert((satp, sp, cpu.pc() + 4), Pending { call });
+        }
+    }
+}
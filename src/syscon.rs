@@ -0,0 +1,41 @@
+// SiFive test finisher style reset/poweroff controller, as found on the
+// QEMU virt machine and driven by Linux's syscon-reboot/poweroff.
+
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_RESET: u32 = 0x7777;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PowerEvent {
+    Reset,
+    PowerOff(u32),
+}
+
+pub struct Syscon {
+    pending: Option<PowerEvent>,
+}
+
+impl Syscon {
+    pub fn new() -> Self {
+        Syscon { pending: None }
+    }
+
+    pub fn take_event(&mut self) -> Option<PowerEvent> {
+        self.pending.take()
+    }
+
+    pub fn store_u32(&mut self, address: u64, value: u32) -> bool {
+        if address != 0 {
+            return true;
+        }
+
+        self.pending = match value & 0xffff {
+            FINISHER_PASS => Some(PowerEvent::PowerOff(0)),
+            FINISHER_FAIL => Some(PowerEvent::PowerOff(value >> 16)),
+            FINISHER_RESET => Some(PowerEvent::Reset),
+            _ => None,
+        };
+
+        true
+    }
+}
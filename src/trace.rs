@@ -0,0 +1,412 @@
+use std::io::{BufRead, Write};
+
+use crate::cpu::{Cpu, GuestTrace, MmioAccess};
+
+#[cfg(test)]
+mod tests;
+
+// Structured traces are JSON lines, one flat object per event. `n` is the
+// number of retired instructions when the event happened, all other numbers
+// are hex strings so 64-bit values survive tools using doubles:
+//
+//   {"type":"insn","n":12,"pc":"0x80000030","insn":"0x00b50533","priv":"s"}
+//   {"type":"trap","n":13,"cause":"0x2","tval":"0x0","epc":"0x80000034"}
+//   {"type":"mmio","n":20,"addr":"0x10000000","write":true,"value":"0x41"}
+//   {"type":"irq","n":25,"mask":"0x200","level":true}
+//   {"type":"marker","n":30,"text":"enter \u002c exit"}
+//
+// Marker text escapes everything but letters, digits, spaces and ./_-.
+// `priv` is the privilege level the instruction executed at, the events
+// after an instruction happened at its level.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Insn { n: u64, pc: u64, insn: u32, privl: u8 },
+    Trap { n: u64, cause: u64, tval: u64, epc: u64 },
+    Mmio { n: u64, addr: u64, write: bool, value: u64 },
+    Irq { n: u64, mask: u64, level: bool },
+    Marker { n: u64, text: String },
+}
+
+// Privilege levels by their encoding, as used by --trace-priv.
+const PRIV_NAMES: [&str; 4] = ["u", "s", "h", "m"];
+
+pub const ALL_PRIVS: u8 = 0b1011;
+
+// M, S or U, there is no hypervisor mode.
+fn parse_priv(name: &str) -> Option<u8> {
+    PRIV_NAMES.iter().position(|p| *p == name).filter(|level| *level != 2).map(|level| level as u8)
+}
+
+// A set of privilege levels as a mask of 1 << level: "u" or "m,s".
+pub fn parse_privs(s: &str) -> Result<u8, String> {
+    s.split(',').try_fold(0, |mask, name| {
+        parse_priv(name).map(|level| mask | 1 << level).ok_or_else(|| format!("bad privilege level {:?}", name))
+    })
+}
+
+// ANSI colors for the levels: M red, S yellow, U green.
+fn priv_color(privl: u8) -> &'static str {
+    match privl {
+        0 => "\x1b[32m",
+        1 => "\x1b[33m",
+        _ => "\x1b[31m",
+    }
+}
+
+const COLOR_RESET: &str = "\x1b[0m";
+
+pub fn escape(text: &str) -> String {
+    text.chars().map(|c| match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' | ' ' | '.' | '/' | '_' | '-' => c.to_string(),
+        _ => c.encode_utf16(&mut [0; 2]).iter().map(|u| format!("\\u{:04x}", u)).collect(),
+    }).collect()
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut units = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        match rest.strip_prefix("\\u") {
+            Some(hex) => {
+                let unit = hex.get(..4).and_then(|h| u16::from_str_radix(h, 16).ok())
+                    .ok_or("bad escape")?;
+                units.push(unit);
+                rest = &hex[4..];
+            }
+            None => {
+                units.extend(c.encode_utf16(&mut [0; 2]).iter());
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    String::from_utf16(&units).map_err(|_| "bad escape".to_string())
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Insn { .. } => "insn",
+            Event::Trap { .. } => "trap",
+            Event::Mmio { .. } => "mmio",
+            Event::Irq { .. } => "irq",
+            Event::Marker { .. } => "marker",
+        }
+    }
+
+    pub fn n(&self) -> u64 {
+        match *self {
+            Event::Insn { n, .. } | Event::Trap { n, .. } | Event::Mmio { n, .. }
+                | Event::Irq { n, .. } | Event::Marker { n, .. } => n,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let fields = match *self {
+            Event::Insn { pc, insn, privl, .. } => {
+                format!(r#""pc":"{:#x}","insn":"{:#010x}","priv":"{}""#, pc, insn, PRIV_NAMES[privl as usize & 3])
+            }
+            Event::Trap { cause, tval, epc, .. } => {
+                format!(r#""cause":"{:#x}","tval":"{:#x}","epc":"{:#x}""#, cause, tval, epc)
+            }
+            Event::Mmio { addr, write, value, .. } => {
+                format!(r#""addr":"{:#x}","write":{},"value":"{:#x}""#, addr, write, value)
+            }
+            Event::Irq { mask, level, .. } => format!(r#""mask":"{:#x}","level":{}"#, mask, level),
+            Event::Marker { ref text, .. } => format!(r#""text":"{}""#, escape(text)),
+        };
+        format!(r#"{{"type":"{}","n":{},{}}}"#, self.kind(), self.n(), fields)
+    }
+
+    // Only understands the flat objects written by to_json.
+    pub fn from_json(line: &str) -> Result<Self, String> {
+        let body = line.trim().strip_prefix('{').and_then(|l| l.strip_suffix('}'))
+            .ok_or("expected an object")?;
+        let mut fields = Vec::new();
+        for field in body.split(',') {
+            let (key, val) = field.split_once(':').ok_or("expected key:value")?;
+            fields.push((key.trim().trim_matches('"'), val.trim().trim_matches('"')));
+        }
+
+        let get = |key: &str| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+            .ok_or_else(|| format!("missing {:?}", key));
+        let num = |key: &str| {
+            let val = get(key)?;
+            let parsed = match val.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => val.parse(),
+            };
+            parsed.map_err(|_| format!("bad {:?}", key))
+        };
+        let flag = |key: &str| match get(key)? {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(format!("bad {:?}", key)),
+        };
+
+        let n = num("n")?;
+        match get("type")? {
+            "insn" => {
+                let privl = parse_priv(get("priv")?).ok_or("bad \"priv\"")?;
+                Ok(Event::Insn { n, pc: num("pc")?, insn: num("insn")? as u32, privl })
+            }
+            "trap" => Ok(Event::Trap { n, cause: num("cause")?, tval: num("tval")?, epc: num("epc")? }),
+            "mmio" => Ok(Event::Mmio { n, addr: num("addr")?, write: flag("write")?, value: num("value")? }),
+            "irq" => Ok(Event::Irq { n, mask: num("mask")?, level: flag("level")? }),
+            "marker" => Ok(Event::Marker { n, text: unescape(get("text")?)? }),
+            t => Err(format!("unknown event type {:?}", t)),
+        }
+    }
+}
+
+// Iterates over the events of a structured trace.
+pub struct Reader<R: BufRead> {
+    lines: std::io::Lines<R>,
+    lineno: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(input: R) -> Self {
+        Reader { lines: input.lines(), lineno: 0 }
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Event, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.to_string())),
+            };
+            self.lineno += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(Event::from_json(&line).map_err(|e| format!("line {}: {}", self.lineno, e)));
+        }
+    }
+}
+
+// Prints the pc of every executed instruction, or writes structured events
+// to a file. With a start address the trace is armed and begins when the pc
+// reaches it; a window ends before the stop address is executed or after a
+// number of traced instructions, after which the start address arms it
+// again. The guest can also start and stop it through the nrvtrace CSR and
+// record markers through nrvmark. Only instructions at the selected
+// privilege levels are traced and counted, with the events of their steps.
+pub struct Trace {
+    start: Option<u64>,
+    stop: Option<u64>,
+    limit: Option<u64>,
+    privs: u8,
+    color: bool,
+
+    active: bool,
+    count: u64,
+    // level of the step being traced, None when it is not
+    step_priv: Option<u8>,
+
+    out: Option<std::io::LineWriter<std::fs::File>>,
+}
+
+impl Trace {
+    pub fn new(start: Option<u64>, stop: Option<u64>, limit: Option<u64>) -> Self {
+        Trace {
+            start,
+            stop,
+            limit,
+            privs: ALL_PRIVS,
+            color: false,
+            active: start.is_none(),
+            count: 0,
+            step_priv: None,
+            out: None,
+        }
+    }
+
+    pub fn window(&self) -> (Option<u64>, Option<u64>, Option<u64>) {
+        (self.start, self.stop, self.limit)
+    }
+
+    // Starts over as if the trace had just been created with these bounds.
+    pub fn set_window(&mut self, start: Option<u64>, stop: Option<u64>, limit: Option<u64>) {
+        self.start = start;
+        self.stop = stop;
+        self.limit = limit;
+        self.active = start.is_none();
+        self.count = 0;
+    }
+
+    // Off with no start address to arm it, only the guest can start it.
+    pub fn is_idle(&self) -> bool {
+        !self.active && self.start.is_none()
+    }
+
+    pub fn set_privs(&mut self, privs: u8) {
+        self.privs = privs;
+    }
+
+    // Colors the printed pcs by privilege level.
+    pub fn set_color(&mut self, color: bool) {
+        self.color = color;
+    }
+
+    pub fn set_output(&mut self, file: std::fs::File) {
+        self.out = Some(std::io::LineWriter::new(file));
+    }
+
+    pub fn clear_output(&mut self) {
+        self.out = None;
+    }
+
+    fn emit(&mut self, event: Event) {
+        let Some(out) = &mut self.out else {
+            return;
+        };
+        if writeln!(out, "{}", event.to_json()).is_err() {
+            eprintln!("trace: failed to write trace file");
+            self.out = None;
+        }
+    }
+
+    pub fn before_step(&mut self, cpu: &Cpu) {
+        self.step_priv = None;
+        self.insn(cpu, cpu.pc(), cpu.instret(), cpu.privilege());
+    }
+
+    fn insn(&mut self, cpu: &Cpu, pc: u64, n: u64, privl: u8) {
+        if !self.active && self.start == Some(pc) {
+            self.active = true;
+            self.count = 0;
+        }
+        if self.active && (self.stop == Some(pc) || self.limit == Some(self.count)) {
+            self.active = false;
+        }
+        if !self.active || self.privs & 1 << privl == 0 {
+            return;
+        }
+
+        self.count += 1;
+        self.step_priv = Some(privl);
+        if self.out.is_none() {
+            match self.color {
+                true => println!("{}{:#010X}{}", priv_color(privl), pc, COLOR_RESET),
+                false => println!("{:#010X}", pc),
+            }
+            return;
+        }
+        let insn = cpu.debug_read_u32(pc).unwrap_or(0);
+        self.emit(Event::Insn { n, pc, insn, privl });
+    }
+
+    // Side effects of the step that just executed.
+    pub fn after_step(&mut self, cpu: &mut Cpu, mmio: Option<MmioAccess>) {
+        // A fused pair is traced as the two instructions it is.
+        if let Some(pc) = cpu.take_fused_pc() {
+            self.insn(cpu, pc, cpu.instret() - 1, cpu.privilege());
+        }
+        let trap = cpu.take_trap_event();
+        let irq = cpu.take_irq_event();
+        let guest = cpu.take_guest_trace();
+        match guest {
+            Some(GuestTrace::Start) if !self.active => {
+                self.active = true;
+                self.count = 0;
+                let privl = cpu.privilege();
+                self.step_priv = (self.privs & 1 << privl != 0).then_some(privl);
+            }
+            Some(GuestTrace::Stop) => self.active = false,
+            _ => {}
+        }
+        if !self.active || self.step_priv.is_none() {
+            return;
+        }
+
+        let n = cpu.instret();
+        if let Some(access) = mmio {
+            self.emit(Event::Mmio { n, addr: access.addr, write: access.write, value: access.value });
+        }
+        if let Some((mask, level)) = irq {
+            self.emit(Event::Irq { n, mask, level });
+        }
+        if let Some(trap) = trap {
+            self.emit(Event::Trap { n, cause: trap.cause, tval: trap.tval, epc: trap.epc });
+        }
+        if let Some(GuestTrace::Marker(text)) = guest {
+            match self.out {
+                Some(_) => self.emit(Event::Marker { n, text }),
+                None => println!("marker: {}", text),
+            }
+        }
+    }
+}
+
+fn open(path: &str) -> Reader<std::io::BufReader<std::fs::File>> {
+    let file = std::fs::File::open(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    });
+    Reader::new(std::io::BufReader::new(file))
+}
+
+// The events of a trace with the privilege level each happened at, that of
+// the instruction before it for the events of a step.
+fn events(path: &str) -> impl Iterator<Item = (Event, u8)> + '_ {
+    let mut privl = 3;
+    open(path).map(move |event| {
+        let event = event.unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        if let Event::Insn { privl: p, .. } = event {
+            privl = p;
+        }
+        (event, privl)
+    })
+}
+
+// `trace dump <file> [color]`: one human readable line per event, colored
+// by privilege level.
+pub fn dump(path: &str, color: bool) {
+    for (event, privl) in events(path) {
+        let text = match event {
+            Event::Insn { pc, insn, .. } => {
+                format!("pc={:#010x} insn={:#010x} {}", pc, insn, PRIV_NAMES[privl as usize])
+            }
+            Event::Trap { cause, tval, epc, .. } => {
+                format!("cause={:#x} tval={:#x} epc={:#010x}", cause, tval, epc)
+            }
+            Event::Mmio { addr, write, value, .. } => {
+                format!("{} addr={:#010x} value={:#x}", if write { "write" } else { "read" }, addr, value)
+            }
+            Event::Irq { mask, level, .. } => format!("mask={:#x} level={}", mask, level as u8),
+            Event::Marker { ref text, .. } => text.clone(),
+        };
+        let line = format!("{:>12} {:<4} {}", event.n(), event.kind(), text);
+        match color {
+            true => println!("{}{}{}", priv_color(privl), line, COLOR_RESET),
+            false => println!("{}", line),
+        }
+    }
+}
+
+// `trace filter <file> [kinds] [privs] [from] [to]`: re-emits the matching
+// events, `kinds` is a comma separated list, privs a mask of privilege levels
+// and from/to bound the instruction count.
+pub fn filter(path: &str, kinds: Option<&str>, privs: u8, from: u64, to: u64) {
+    let kinds: Option<Vec<&str>> = kinds.map(|k| k.split(',').collect());
+    for (event, privl) in events(path) {
+        if privs & 1 << privl == 0 {
+            continue;
+        }
+        if kinds.as_ref().is_some_and(|k| !k.contains(&event.kind())) {
+            continue;
+        }
+        if event.n() < from || event.n() > to {
+            continue;
+        }
+        println!("{}", event.to_json());
+    }
+}
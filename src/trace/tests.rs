@@ -0,0 +1,32 @@
+use super::*;
+
+#[test]
+fn insn_events_carry_the_privilege_level() {
+    let event = Event::Insn { n: 12, pc: 0x80000030, insn: 0x00b50533, privl: 1 };
+    let json = event.to_json();
+    assert_eq!(json, r#"{"type":"insn","n":12,"pc":"0x80000030","insn":"0x00b50533","priv":"s"}"#);
+    assert_eq!(Event::from_json(&json), Ok(event));
+    assert!(Event::from_json(&json.replace(r#""s""#, r#""h""#)).is_err());
+}
+
+#[test]
+fn privilege_sets() {
+    assert_eq!(parse_privs("u"), Ok(1));
+    assert_eq!(parse_privs("m,s"), Ok(0b1010));
+    assert!(parse_privs("h").is_err());
+    assert!(parse_privs("m,").is_err());
+}
+
+#[test]
+fn idle_once_the_limit_runs_out_without_a_start() {
+    let cpu = Cpu::new();
+    let mut trace = Trace::new(None, None, Some(0));
+    assert!(!trace.is_idle());
+    trace.before_step(&cpu);
+    assert!(trace.is_idle());
+
+    // a start address can arm it again
+    let mut trace = Trace::new(Some(0x80000000), None, Some(0));
+    trace.before_step(&cpu);
+    assert!(!trace.is_idle());
+}
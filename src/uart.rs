@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::console::{Console, Encoding, FilterKind};
+use crate::fault::{Fault, FaultInjector};
+use crate::snapshot;
+
+const LSR_DR: u8 = 0x01;
+const LSR_THRE_TEMT: u8 = 0x60;
+const ISR_NO_INT: u8 = 0x01;
+const ISR_THRI: u8 = 0x02;
+const ISR_RDI: u8 = 0x04;
+const ISR_FIFO_ENABLED: u8 = 0xC0;
+const FCR_FIFO_ENABLE: u8 = 0x01;
+const IER_RDI: u8 = 0x01;
+const IER_THRI: u8 = 0x02;
+
+// Console input scripts are line based: `<delay> <bytes>` where delay is the
+// number of retired instructions since the previous line and bytes may
+// contain \n, \r, \t, \\ and \xNN escapes. Lines starting with '#' are
+// ignored. Recording writes the same format.
+pub struct SerialScript {
+    events: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl SerialScript {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut events = VecDeque::new();
+        for (lineno, line) in text.lines().enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (delay, bytes) = line.split_once(' ').unwrap_or((line, ""));
+            let delay = delay.parse()
+                .map_err(|_| format!("line {}: bad delay {:?}", lineno + 1, delay))?;
+            let bytes = unescape(bytes)
+                .ok_or_else(|| format!("line {}: bad escape", lineno + 1))?;
+            events.push_back((delay, bytes));
+        }
+
+        Ok(SerialScript { events })
+    }
+}
+
+fn unescape(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        match bytes.next()? {
+            b'n' => out.push(b'\n'),
+            b'r' => out.push(b'\r'),
+            b't' => out.push(b'\t'),
+            b'\\' => out.push(b'\\'),
+            b'x' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(out)
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for &b in bytes {
+        match b {
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+
+    out
+}
+
+pub struct Uart {
+    rx: VecDeque<u8>,
+
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    // transmitter empty interrupt, raised by a write to THR or by enabling
+    // it and cleared by reading it from ISR
+    thre_pending: bool,
+
+    script: Option<SerialScript>,
+    script_time: u64,
+
+    stdin: Option<mpsc::Receiver<u8>>,
+    // host input received while waiting, for the next tick
+    held: Vec<u8>,
+    record: Option<std::fs::File>,
+    record_time: u64,
+    console: Console,
+
+    faults: FaultInjector,
+}
+
+impl Uart {
+    pub fn new() -> Self {
+        Uart {
+            rx: VecDeque::new(),
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            thre_pending: false,
+            script: None,
+            script_time: 0,
+            stdin: None,
+            held: Vec::new(),
+            record: None,
+            record_time: 0,
+            console: Console::default(),
+            faults: FaultInjector::new(),
+        }
+    }
+
+    // Clears the guest-visible state, host attachments are kept.
+    pub fn reset(&mut self) {
+        self.rx.clear();
+        self.ier = 0;
+        self.fcr = 0;
+        self.lcr = 0;
+        self.mcr = 0;
+        self.scr = 0;
+        self.thre_pending = false;
+    }
+
+    pub fn save(&self, w: &mut snapshot::Writer) {
+        w.bytes(&self.rx.iter().copied().collect::<Vec<_>>());
+        for reg in [self.ier, self.fcr, self.lcr, self.mcr, self.scr] {
+            w.u8(reg);
+        }
+        w.u8(self.thre_pending as u8);
+    }
+
+    pub fn restore(&mut self, r: &mut snapshot::Reader) -> Result<(), String> {
+        self.rx = r.bytes()?.iter().copied().collect();
+        for reg in [&mut self.ier, &mut self.fcr, &mut self.lcr, &mut self.mcr, &mut self.scr] {
+            *reg = r.u8()?;
+        }
+        self.thre_pending = r.u8()? != 0;
+        Ok(())
+    }
+
+    pub fn faults_mut(&mut self) -> &mut FaultInjector {
+        &mut self.faults
+    }
+
+    fn receive(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if self.faults.fires(Fault::DropRx) {
+                continue;
+            }
+            if self.faults.fires(Fault::CorruptRx) {
+                let bit = self.faults.next_u64() % 8;
+                self.rx.push_back(b ^ (1 << bit));
+            } else {
+                self.rx.push_back(b);
+            }
+        }
+    }
+
+    // Delays of the script count from `now`.
+    pub fn set_script(&mut self, script: SerialScript, now: u64) {
+        self.script = Some(script);
+        self.script_time = now;
+    }
+
+    pub fn attach_stdin(&mut self) {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            loop {
+                let n = match std::io::stdin().read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                if buf[..n].iter().any(|&b| tx.send(b).is_err()) {
+                    break;
+                }
+            }
+        });
+        self.stdin = Some(rx);
+    }
+
+    pub fn set_record(&mut self, file: std::fs::File) {
+        self.record = Some(file);
+    }
+
+    pub fn clear_record(&mut self) {
+        self.record = None;
+    }
+
+    pub fn set_console_filters(&mut self, filters: &[FilterKind]) {
+        self.console.set_filters(filters);
+    }
+
+    pub fn set_console_encoding(&mut self, encoding: Encoding) {
+        self.console.set_encoding(encoding);
+    }
+
+    pub fn set_console_log(&mut self, file: std::fs::File) {
+        self.console.set_log(file);
+    }
+
+    // Pulls due scripted input and pending host input into the receive FIFO.
+    pub fn tick(&mut self, now: u64) {
+        while let Some(script) = &mut self.script {
+            let Some((delay, _)) = script.events.front() else {
+                break;
+            };
+            if now - self.script_time < *delay {
+                break;
+            }
+            let (delay, bytes) = script.events.pop_front().unwrap();
+            self.script_time += delay;
+            self.receive(&bytes);
+        }
+
+        let Some(stdin) = &self.stdin else {
+            return;
+        };
+        let bytes: Vec<u8> = self.held.drain(..).chain(stdin.try_iter()).collect();
+        if bytes.is_empty() {
+            return;
+        }
+
+        if let Some(record) = &mut self.record {
+            let line = format!("{} {}\n", now - self.record_time, escape(&bytes));
+            if record.write_all(line.as_bytes()).is_err() {
+                eprintln!("uart: failed to write serial recording");
+                self.record = None;
+            }
+            self.record_time = now;
+        }
+        self.receive(&bytes);
+    }
+
+    // Blocks until host input arrives, at most for `timeout`.
+    pub fn wait_input(&mut self, timeout: Duration) {
+        match self.stdin.as_ref().map(|stdin| stdin.recv_timeout(timeout)) {
+            Some(Ok(b)) => self.held.push(b),
+            Some(Err(mpsc::RecvTimeoutError::Timeout)) => {}
+            Some(Err(mpsc::RecvTimeoutError::Disconnected)) | None => std::thread::sleep(timeout),
+        }
+    }
+
+    // The highest priority interrupt pending, received data before the
+    // transmitter being empty.
+    fn isr(&self) -> u8 {
+        let fifo = if self.fcr & FCR_FIFO_ENABLE != 0 { ISR_FIFO_ENABLED } else { 0 };
+        let id = if self.ier & IER_RDI != 0 && !self.rx.is_empty() {
+            ISR_RDI
+        } else if self.ier & IER_THRI != 0 && self.thre_pending {
+            ISR_THRI
+        } else {
+            ISR_NO_INT
+        };
+        fifo | id
+    }
+
+    // The level of the interrupt line.
+    pub fn interrupt(&self) -> bool {
+        self.isr() & ISR_NO_INT == 0
+    }
+
+    fn lsr(&self) -> u8 {
+        let dr = if self.rx.is_empty() { 0 } else { LSR_DR };
+        LSR_THRE_TEMT | dr
+    }
+
+    // Register state as seen by the guest, for the monitor's device dumps.
+    pub fn registers(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("ier", self.ier as u64),
+            ("isr", self.isr() as u64),
+            ("fcr", self.fcr as u64),
+            ("lcr", self.lcr as u64),
+            ("mcr", self.mcr as u64),
+            ("lsr", self.lsr() as u64),
+            ("scr", self.scr as u64),
+            ("rx_fifo", self.rx.len() as u64),
+        ]
+    }
+
+    pub fn store_u8(&mut self, address: u64, value: u8) -> bool {
+        match address {
+            0x00 => {
+                let dropped = self.faults.fires(Fault::DropTx);
+                if !dropped {
+                    self.console.write(value);
+                }
+                // transmission is instant
+                self.thre_pending = true;
+            }
+            0x01 => {
+                if value & !self.ier & IER_THRI != 0 {
+                    self.thre_pending = true;
+                }
+                self.ier = value;
+            }
+            0x02 => self.fcr = value,
+            0x03 => self.lcr = value,
+            0x04 => self.mcr = value,
+            0x07 => self.scr = value,
+            _ => {}
+        }
+
+        true
+    }
+
+    pub fn load_u8(&mut self, address: u64) -> Option<u8> {
+        match address {
+            0x00 => Some(self.rx.pop_front().unwrap_or(0)),
+            0x01 => Some(self.ier),
+            0x02 => {
+                let isr = self.isr();
+                if isr & 0x0f == ISR_THRI {
+                    self.thre_pending = false;
+                }
+                Some(isr)
+            }
+            0x03 => Some(self.lcr),
+            0x04 => Some(self.mcr),
+            0x05 => Some(self.lsr()),
+            0x07 => Some(self.scr),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,879 @@
+use crate::cpu::MemFault;
+use crate::decoder::{Instruction, VType};
+use crate::snapshot;
+
+// RVV 1.0 register state and the integer, fixed-point, mask, permutation and
+// load/store instructions. Vector floating point is not implemented and
+// illegal, which is why V is off unless asked for with --isa. Tail and
+// masked-off elements are left undisturbed, agnostic policies allow that too.
+//
+// A register group is contiguous in `regs`: element i of a group starting at
+// v<n> with SEW-byte elements lives at n * vlenb + i * SEW.
+
+pub const DEFAULT_VLEN: usize = 128;
+pub const MIN_VLEN: usize = 64;
+pub const MAX_VLEN: usize = 65536;
+const ELEN: usize = 64;
+
+const VTYPE_VILL: u64 = 1 << 63;
+
+// The rest of the hart, as seen by vector instructions.
+pub trait Hart {
+    fn xreg(&self, idx: u8) -> u64;
+    fn set_xreg(&mut self, idx: u8, val: u64);
+    fn load(&mut self, addr: u64, size: usize) -> Result<u64, MemFault>;
+    fn store(&mut self, addr: u64, size: usize, val: u64) -> Result<(), MemFault>;
+}
+
+#[derive(Debug, Default)]
+pub struct Vector {
+    vlenb: usize,
+    regs: Vec<u8>,
+    vtype: u64,
+    vl: u64,
+    vstart: u64,
+    vxrm: u64,
+    vxsat: bool,
+    // An element access that faulted as (store, addr, fault), vstart holds
+    // its index.
+    fault: Option<(bool, u64, MemFault)>,
+}
+
+// The current vtype and vl, only for a legal vtype. LMUL is in eighths so
+// that fractional LMULs are integers too.
+#[derive(Debug, Copy, Clone)]
+struct Ctx {
+    eb: usize,
+    lmul8: usize,
+    vlmax: usize,
+    vstart: usize,
+    vl: usize,
+}
+
+impl Ctx {
+    fn bits(&self) -> u32 {
+        self.eb as u32 * 8
+    }
+
+    // LMUL of operands with `eb`-byte elements, None when out of range.
+    fn emul8(&self, eb: usize) -> Option<usize> {
+        let emul8 = eb * self.lmul8 / self.eb;
+        (eb <= ELEN / 8 && eb * self.lmul8 >= self.eb && emul8 <= 64).then_some(emul8)
+    }
+
+    fn group_ok(&self, reg: u8, eb: usize) -> bool {
+        self.emul8(eb).is_some_and(|emul8| aligned(reg, emul8))
+    }
+}
+
+fn regs_of(lmul8: usize) -> usize {
+    (lmul8 / 8).max(1)
+}
+
+fn aligned(reg: u8, lmul8: usize) -> bool {
+    let n = regs_of(lmul8);
+    (reg as usize).is_multiple_of(n) && reg as usize + n <= 32
+}
+
+fn sext(val: u64, bits: u32) -> i64 {
+    ((val << (64 - bits)) as i64) >> (64 - bits)
+}
+
+fn trunc(val: u64, bits: u32) -> u64 {
+    if bits == 64 { val } else { val & ((1 << bits) - 1) }
+}
+
+fn lmul8(vtype: u64) -> usize {
+    match vtype & 7 {
+        vlmul @ 0..=3 => 8 << vlmul,
+        5 => 1,
+        6 => 2,
+        7 => 4,
+        _ => 0,
+    }
+}
+
+// Rounding increment of shifting `val` right by `d` bits under `vxrm`, for
+// signed values pass the two's complement.
+fn round_inc(vxrm: u64, val: u128, d: u32) -> u128 {
+    if d == 0 {
+        return 0;
+    }
+    let bit = |n: u32| (val >> n) & 1;
+    let rest = (val & ((1 << (d - 1)) - 1) != 0) as u128;
+    match vxrm {
+        0 => bit(d - 1),                           // rnu
+        1 => bit(d - 1) & (rest | bit(d)),         // rne
+        2 => 0,                                    // rdn
+        _ => (1 - bit(d)) & (bit(d - 1) | rest),   // rod
+    }
+}
+
+impl Vector {
+    pub fn new(vlen: usize) -> Self {
+        Vector { vlenb: vlen / 8, regs: vec![0; 4 * vlen], vtype: VTYPE_VILL, ..Default::default() }
+    }
+
+    pub fn vlen(&self) -> usize {
+        self.vlenb * 8
+    }
+
+    pub fn reg(&self, idx: usize) -> &[u8] {
+        &self.regs[idx * self.vlenb..][..self.vlenb]
+    }
+
+    pub fn reg_mut(&mut self, idx: usize) -> &mut [u8] {
+        &mut self.regs[idx * self.vlenb..][..self.vlenb]
+    }
+
+    pub fn reset(&mut self) {
+        *self = Vector::new(self.vlen());
+    }
+
+    pub fn save(&self, w: &mut snapshot::Writer) {
+        w.u64(self.vlen() as u64);
+        w.bytes(&self.regs);
+        w.u64s(&[self.vtype, self.vl, self.vstart, self.vxrm, self.vxsat as u64]);
+    }
+
+    pub fn restore(&mut self, r: &mut snapshot::Reader) -> Result<(), String> {
+        let vlen = r.u64()? as usize;
+        let regs = r.bytes()?;
+        if !vlen.is_power_of_two() || !(MIN_VLEN..=MAX_VLEN).contains(&vlen) || regs.len() != 4 * vlen {
+            return Err("snapshot vector state is corrupt".into());
+        }
+        *self = Vector::new(vlen);
+        self.regs.copy_from_slice(regs);
+        let mut csrs = [0; 5];
+        r.u64s(&mut csrs)?;
+        let vxsat;
+        [self.vtype, self.vl, self.vstart, self.vxrm, vxsat] = csrs;
+        self.vxsat = vxsat != 0;
+        Ok(())
+    }
+
+    pub fn read_csr(&self, csr: u32) -> Option<u64> {
+        Some(match csr {
+            0x008 => self.vstart,
+            0x009 => self.vxsat as u64,
+            0x00A => self.vxrm,
+            0x00F => self.vxrm << 1 | self.vxsat as u64, // vcsr
+            0xC20 => self.vl,
+            0xC21 => self.vtype,
+            0xC22 => self.vlenb as u64,
+            _ => return None,
+        })
+    }
+
+    // vl, vtype and vlenb are read-only.
+    pub fn write_csr(&mut self, csr: u32, val: u64) -> bool {
+        match csr {
+            0x008 => self.vstart = val & (self.vlen() as u64 - 1),
+            0x009 => self.vxsat = val & 1 != 0,
+            0x00A => self.vxrm = val & 3,
+            0x00F => {
+                self.vxrm = (val >> 1) & 3;
+                self.vxsat = val & 1 != 0;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn ctx(&self) -> Option<Ctx> {
+        if self.vtype & VTYPE_VILL != 0 {
+            return None;
+        }
+        let eb = 1 << ((self.vtype >> 3) & 7);
+        let lmul8 = lmul8(self.vtype);
+        Some(Ctx { eb, lmul8, vlmax: self.vlenb * lmul8 / 8 / eb, vstart: self.vstart as usize, vl: self.vl as usize })
+    }
+
+    fn get(&self, reg: u8, i: usize, eb: usize) -> u64 {
+        let off = reg as usize * self.vlenb + i * eb;
+        let mut bytes = [0; 8];
+        bytes[..eb].copy_from_slice(&self.regs[off..off + eb]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn set(&mut self, reg: u8, i: usize, eb: usize, val: u64) {
+        let off = reg as usize * self.vlenb + i * eb;
+        self.regs[off..off + eb].copy_from_slice(&val.to_le_bytes()[..eb]);
+    }
+
+    fn mask(&self, reg: u8, i: usize) -> bool {
+        self.regs[reg as usize * self.vlenb + i / 8] >> (i % 8) & 1 != 0
+    }
+
+    fn set_mask(&mut self, reg: u8, i: usize, bit: bool) {
+        let byte = &mut self.regs[reg as usize * self.vlenb + i / 8];
+        *byte = (*byte & !(1 << (i % 8))) | (bit as u8) << (i % 8);
+    }
+
+    fn active(&self, vm: bool, i: usize) -> bool {
+        vm || self.mask(0, i)
+    }
+
+    // vsetvl{i}: vl is the AVL capped at VLMAX, an illegal vtype sets vill.
+    fn vset(&mut self, vtype: u64, avl: u64) -> u64 {
+        let eb = 1 << ((vtype >> 3) & 7);
+        let lmul8 = lmul8(vtype);
+        let legal = vtype >> 8 == 0 && (vtype >> 3) & 7 <= 3 && lmul8 != 0 && eb * 64 <= lmul8 * ELEN
+            && self.vlenb * lmul8 / 8 / eb > 0;
+        self.vstart = 0;
+        if !legal {
+            self.vtype = VTYPE_VILL;
+            self.vl = 0;
+            return 0;
+        }
+        self.vtype = vtype;
+        self.vl = avl.min((self.vlenb * lmul8 / 8 / eb) as u64);
+        self.vl
+    }
+
+    // rs1 = x0 asks for VLMAX, unless rd = x0 too which keeps vl.
+    fn avl(&self, rs1: u8, rd: u8, hart: &impl Hart) -> u64 {
+        match (rs1, rd) {
+            (0, 0) => self.vl,
+            (0, _) => u64::MAX,
+            _ => hart.xreg(rs1),
+        }
+    }
+
+    // The access fault of the last exec, taken instead of retiring it.
+    pub fn take_fault(&mut self) -> Option<(bool, u64, MemFault)> {
+        self.fault.take()
+    }
+
+    // Stops at element i, a trap handler can resume from there.
+    fn element_fault(&mut self, store: bool, addr: u64, fault: MemFault, i: usize) -> bool {
+        self.vstart = i as u64;
+        self.fault = Some((store, addr, fault));
+        true
+    }
+
+    // False for reserved encodings and illegal operands, which trap as
+    // illegal instructions.
+    pub fn exec(&mut self, insn: Instruction, hart: &mut impl Hart) -> bool {
+        let ok = match insn {
+            Instruction::Vsetvli(i) => {
+                let vl = self.vset(i.imm as u64 & 0x7ff, self.avl(i.rs1, i.rd, hart));
+                hart.set_xreg(i.rd, vl);
+                true
+            }
+            Instruction::Vsetivli(i) => {
+                let vl = self.vset(i.imm as u64 & 0x3ff, i.rs1 as u64);
+                hart.set_xreg(i.rd, vl);
+                true
+            }
+            Instruction::Vsetvl(r) => {
+                let vl = self.vset(hart.xreg(r.rs2), self.avl(r.rs1, r.rd, hart));
+                hart.set_xreg(r.rd, vl);
+                true
+            }
+            Instruction::Vload(v) => self.memory(v, hart, false),
+            Instruction::Vstore(v) => self.memory(v, hart, true),
+            Instruction::Varith(v) => self.arith(v, hart),
+            _ => false,
+        };
+        if ok && self.fault.is_none() {
+            self.vstart = 0;
+        }
+        ok
+    }
+
+    fn memory(&mut self, v: VType, hart: &mut impl Hart, store: bool) -> bool {
+        let eb = match v.funct3 {
+            0 => 1,
+            5 => 2,
+            6 => 4,
+            _ => 8,
+        };
+        let nf = (v.funct6 >> 3) as usize + 1;
+        let mew = (v.funct6 >> 2) & 1;
+        let base = hart.xreg(v.rs1);
+        if mew != 0 {
+            return false;
+        }
+        match (v.funct6 & 3, v.vs2) {
+            (0, 0x08) => self.whole(v, hart, store, eb, nf, base),
+            (0, 0x0B) => self.mask_memory(v, hart, store, eb, nf, base),
+            (0, lumop @ (0x00 | 0x10)) if !store || lumop == 0 => {
+                let Some(c) = self.ctx() else { return false };
+                self.elements(v, hart, store, &c, eb, nf, lumop == 0x10, |_, i| base.wrapping_add((i * nf * eb) as u64))
+            }
+            (2, rs2) => {
+                let Some(c) = self.ctx() else { return false };
+                let stride = hart.xreg(rs2);
+                self.elements(v, hart, store, &c, eb, nf, false,
+                    |_, i| base.wrapping_add(stride.wrapping_mul(i as u64)))
+            }
+            // indexed, the index EEW is the width and data is SEW wide
+            (1 | 3, vs2) => {
+                let Some(c) = self.ctx() else { return false };
+                if !c.group_ok(vs2, eb) {
+                    return false;
+                }
+                self.elements(v, hart, store, &c, c.eb, nf, false,
+                    |vec, i| base.wrapping_add(vec.get(vs2, i, eb)))
+            }
+            _ => false,
+        }
+    }
+
+    // Unit-stride, strided and indexed accesses of `nf` fields per element,
+    // `addr` gives the address of field 0 of element i.
+    #[allow(clippy::too_many_arguments)]
+    fn elements(&mut self, v: VType, hart: &mut impl Hart, store: bool, c: &Ctx, eb: usize, nf: usize,
+        fault_first: bool, addr: impl Fn(&Self, usize) -> u64) -> bool
+    {
+        let Some(emul8) = c.emul8(eb) else { return false };
+        let regs = regs_of(emul8);
+        if !aligned(v.vd, emul8) || v.vd as usize + nf * regs > 32 || nf * regs > 8 || (!v.vm && v.vd == 0 && !store) {
+            return false;
+        }
+        for i in c.vstart..c.vl {
+            if !self.active(v.vm, i) {
+                continue;
+            }
+            let base = addr(self, i);
+            for f in 0..nf {
+                let a = base.wrapping_add((f * eb) as u64);
+                let reg = v.vd + (f * regs) as u8;
+                if store {
+                    if let Err(fault) = hart.store(a, eb, self.get(reg, i, eb)) {
+                        return self.element_fault(true, a, fault, i);
+                    }
+                } else {
+                    match hart.load(a, eb) {
+                        Ok(val) => self.set(reg, i, eb, val),
+                        // fault-only-first loads trim vl instead of trapping
+                        Err(_) if fault_first && i > 0 => {
+                            self.vl = i as u64;
+                            return true;
+                        }
+                        Err(fault) => return self.element_fault(false, a, fault, i),
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    // vl<nf>r/vs<nf>r move whole registers whatever vtype is.
+    fn whole(&mut self, v: VType, hart: &mut impl Hart, store: bool, eb: usize, nf: usize, base: u64) -> bool {
+        if !v.vm || !nf.is_power_of_two() || !(v.vd as usize).is_multiple_of(nf) || (store && eb != 1) {
+            return false;
+        }
+        for i in self.vstart as usize..nf * self.vlenb / eb {
+            let a = base.wrapping_add((i * eb) as u64);
+            if store {
+                if let Err(fault) = hart.store(a, eb, self.get(v.vd, i, eb)) {
+                    return self.element_fault(true, a, fault, i);
+                }
+            } else {
+                let val = match hart.load(a, eb) {
+                    Ok(val) => val,
+                    Err(fault) => return self.element_fault(false, a, fault, i),
+                };
+                self.set(v.vd, i, eb, val);
+            }
+        }
+        true
+    }
+
+    // vlm.v/vsm.v transfer ceil(vl / 8) bytes of a mask.
+    fn mask_memory(&mut self, v: VType, hart: &mut impl Hart, store: bool, eb: usize, nf: usize, base: u64) -> bool {
+        if self.ctx().is_none() || !v.vm || eb != 1 || nf != 1 {
+            return false;
+        }
+        for i in self.vstart as usize..(self.vl as usize).div_ceil(8) {
+            let a = base.wrapping_add(i as u64);
+            if store {
+                if let Err(fault) = hart.store(a, 1, self.get(v.vd, i, 1)) {
+                    return self.element_fault(true, a, fault, i);
+                }
+            } else {
+                let val = match hart.load(a, 1) {
+                    Ok(val) => val,
+                    Err(fault) => return self.element_fault(false, a, fault, i),
+                };
+                self.set(v.vd, i, 1, val);
+            }
+        }
+        true
+    }
+
+    // vd[i] = f(vs2[i], op[i], vd[i], v0.mask[i]) over the active body
+    // elements, `op` being vs1 or the scalar. Widths are in bytes, of vd,
+    // vs2 and vs1. With `mask_data` v0 is an operand (vadc, vmerge) and all
+    // elements are active.
+    fn map(&mut self, v: &VType, c: &Ctx, (d_eb, a_eb, b_eb): (usize, usize, usize), scalar: Option<u64>,
+        mask_data: bool, mut f: impl FnMut(u64, u64, u64, bool) -> u64) -> bool
+    {
+        if !c.group_ok(v.vd, d_eb) || !c.group_ok(v.vs2, a_eb) || (scalar.is_none() && !c.group_ok(v.rs1, b_eb))
+            || (!v.vm && v.vd == 0)
+        {
+            return false;
+        }
+        for i in c.vstart..c.vl {
+            let m = self.mask(0, i);
+            if !v.vm && !m && !mask_data {
+                continue;
+            }
+            let a = self.get(v.vs2, i, a_eb);
+            let b = scalar.unwrap_or_else(|| self.get(v.rs1, i, b_eb));
+            let d = self.get(v.vd, i, d_eb);
+            let r = f(a, b, d, m);
+            self.set(v.vd, i, d_eb, r);
+        }
+        true
+    }
+
+    // vd.mask[i] = f(vs2[i], op[i], v0.mask[i]), as map.
+    fn compare(&mut self, v: &VType, c: &Ctx, scalar: Option<u64>, mask_data: bool,
+        f: impl Fn(u64, u64, bool) -> bool) -> bool
+    {
+        if !c.group_ok(v.vs2, c.eb) || (scalar.is_none() && !c.group_ok(v.rs1, c.eb)) {
+            return false;
+        }
+        for i in c.vstart..c.vl {
+            let m = self.mask(0, i);
+            if !v.vm && !m && !mask_data {
+                continue;
+            }
+            let a = self.get(v.vs2, i, c.eb);
+            let b = scalar.unwrap_or_else(|| self.get(v.rs1, i, c.eb));
+            self.set_mask(v.vd, i, f(a, b, m));
+        }
+        true
+    }
+
+    // vd[0] = f(...f(vs1[0], vs2[i])...) over the active body elements, vs2
+    // elements being `a_eb` and vs1/vd `d_eb` wide.
+    fn reduce(&mut self, v: &VType, c: &Ctx, a_eb: usize, d_eb: usize, f: impl Fn(u64, u64) -> u64) -> bool {
+        if c.vstart != 0 || !c.group_ok(v.vs2, a_eb) || d_eb > ELEN / 8 {
+            return false;
+        }
+        if c.vl == 0 {
+            return true;
+        }
+        let mut acc = self.get(v.rs1, 0, d_eb);
+        for i in 0..c.vl {
+            if self.active(v.vm, i) {
+                acc = f(acc, self.get(v.vs2, i, a_eb));
+            }
+        }
+        self.set(v.vd, 0, d_eb, acc);
+        true
+    }
+
+    fn arith(&mut self, v: VType, hart: &mut impl Hart) -> bool {
+        // vmv<nr>r.v copies whole registers like the loads and stores.
+        if v.funct3 == 3 && v.funct6 == 0x27 {
+            return self.move_whole(&v);
+        }
+        let Some(c) = self.ctx() else { return false };
+        match v.funct3 {
+            0 | 3 | 4 => self.opi(v, &c, hart),
+            2 | 6 => self.opm(v, &c, hart),
+            _ => false, // OPFVV, OPFVF
+        }
+    }
+
+    fn move_whole(&mut self, v: &VType) -> bool {
+        let nr = v.rs1 as usize + 1;
+        if !v.vm || !nr.is_power_of_two() || nr > 8 || !(v.vd as usize).is_multiple_of(nr) || !(v.vs2 as usize).is_multiple_of(nr) {
+            return false;
+        }
+        let eb = self.ctx().map_or(1, |c| c.eb);
+        for i in self.vstart as usize..nr * self.vlenb / eb {
+            let val = self.get(v.vs2, i, eb);
+            self.set(v.vd, i, eb, val);
+        }
+        true
+    }
+
+    // OPIVV, OPIVI and OPIVX
+    fn opi(&mut self, v: VType, c: &Ctx, hart: &mut impl Hart) -> bool {
+        let (vv, vi) = (v.funct3 == 0, v.funct3 == 3);
+        let bits = c.bits();
+        let eb = c.eb;
+        let max_s = (u64::MAX >> (65 - bits)) as i64;
+        let min_s = -max_s - 1;
+        let max_u = trunc(u64::MAX, bits);
+        // Shifts, slides and vrgather take an unsigned immediate.
+        let uimm = matches!(v.funct6, 0x0C | 0x0E | 0x0F | 0x25 | 0x28..=0x2F);
+        // and slide offsets and gather indices are not truncated to SEW.
+        let index = matches!(v.funct6, 0x0C | 0x0E | 0x0F);
+        let scalar = match v.funct3 {
+            0 => None,
+            3 if uimm => Some(v.rs1 as u64),
+            4 if index => Some(hart.xreg(v.rs1)),
+            3 => Some(trunc(sext(v.rs1 as u64, 5) as u64, bits)),
+            _ => Some(trunc(hart.xreg(v.rs1), bits)),
+        };
+        let s = |x: u64| sext(x, bits);
+        let single = (eb, eb, eb);
+        let vxrm = self.vxrm;
+        let mut sat = false;
+        let ok = match v.funct6 {
+            0x00 => self.map(&v, c, single, scalar, false, |a, b, _, _| a.wrapping_add(b)),
+            0x02 if !vi => self.map(&v, c, single, scalar, false, |a, b, _, _| a.wrapping_sub(b)),
+            0x03 if !vv => self.map(&v, c, single, scalar, false, |a, b, _, _| b.wrapping_sub(a)),
+            0x04 if !vi => self.map(&v, c, single, scalar, false, |a, b, _, _| a.min(b)),
+            0x05 if !vi => self.map(&v, c, single, scalar, false, |a, b, _, _| s(a).min(s(b)) as u64),
+            0x06 if !vi => self.map(&v, c, single, scalar, false, |a, b, _, _| a.max(b)),
+            0x07 if !vi => self.map(&v, c, single, scalar, false, |a, b, _, _| s(a).max(s(b)) as u64),
+            0x09 => self.map(&v, c, single, scalar, false, |a, b, _, _| a & b),
+            0x0A => self.map(&v, c, single, scalar, false, |a, b, _, _| a | b),
+            0x0B => self.map(&v, c, single, scalar, false, |a, b, _, _| a ^ b),
+            0x0C => self.gather(&v, c, scalar, eb),
+            0x0E if vv => self.gather(&v, c, None, 2),
+            0x0E => self.slide_up(&v, c, scalar.unwrap_or(0) as usize, None),
+            0x0F if !vv => self.slide_down(&v, c, scalar.unwrap_or(0) as usize, None),
+            0x10 if !v.vm => self.map(&v, c, single, scalar, true, |a, b, _, m| a.wrapping_add(b).wrapping_add(m as u64)),
+            0x11 => {
+                let carry = !v.vm;
+                self.compare(&v, c, scalar, true,
+                    |a, b, m| (a as u128 + b as u128 + (carry && m) as u128) >> bits != 0)
+            }
+            0x12 if !v.vm && !vi => self.map(&v, c, single, scalar, true, |a, b, _, m| a.wrapping_sub(b).wrapping_sub(m as u64)),
+            0x13 if !vi => {
+                let borrow = !v.vm;
+                self.compare(&v, c, scalar, true, |a, b, m| (a as u128) < b as u128 + (borrow && m) as u128)
+            }
+            0x17 if !v.vm => self.map(&v, c, single, scalar, true, |a, b, _, m| if m { b } else { a }),
+            0x17 if v.vs2 == 0 => self.map(&v, c, single, scalar, false, |_, b, _, _| b),
+            0x18 => self.compare(&v, c, scalar, false, |a, b, _| a == b),
+            0x19 => self.compare(&v, c, scalar, false, |a, b, _| a != b),
+            0x1A if !vi => self.compare(&v, c, scalar, false, |a, b, _| a < b),
+            0x1B if !vi => self.compare(&v, c, scalar, false, |a, b, _| s(a) < s(b)),
+            0x1C => self.compare(&v, c, scalar, false, |a, b, _| a <= b),
+            0x1D => self.compare(&v, c, scalar, false, |a, b, _| s(a) <= s(b)),
+            0x1E if !vv => self.compare(&v, c, scalar, false, |a, b, _| a > b),
+            0x1F if !vv => self.compare(&v, c, scalar, false, |a, b, _| s(a) > s(b)),
+            0x20 => self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                let r = a as u128 + b as u128;
+                sat |= r > max_u as u128;
+                r.min(max_u as u128) as u64
+            }),
+            0x21 => self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                let r = s(a) as i128 + s(b) as i128;
+                sat |= r > max_s as i128 || r < min_s as i128;
+                r.clamp(min_s as i128, max_s as i128) as u64
+            }),
+            0x22 if !vi => self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                sat |= b > a;
+                a.saturating_sub(b)
+            }),
+            0x23 if !vi => self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                let r = s(a) as i128 - s(b) as i128;
+                sat |= r > max_s as i128 || r < min_s as i128;
+                r.clamp(min_s as i128, max_s as i128) as u64
+            }),
+            0x25 => self.map(&v, c, single, scalar, false, |a, b, _, _| a << (b & (bits as u64 - 1))),
+            0x27 => {
+                self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                    let p = s(a) as i128 * s(b) as i128;
+                    let r = (p >> (bits - 1)) + round_inc(vxrm, p as u128, bits - 1) as i128;
+                    sat |= r > max_s as i128;
+                    r.min(max_s as i128) as u64
+                })
+            }
+            0x28 => self.map(&v, c, single, scalar, false, |a, b, _, _| a >> (b & (bits as u64 - 1))),
+            0x29 => self.map(&v, c, single, scalar, false, |a, b, _, _| (s(a) >> (b & (bits as u64 - 1))) as u64),
+            0x2A => {
+                self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                    let d = (b & (bits as u64 - 1)) as u32;
+                    ((a as u128 >> d) + round_inc(vxrm, a as u128, d)) as u64
+                })
+            }
+            0x2B => {
+                self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                    let d = (b & (bits as u64 - 1)) as u32;
+                    let a = s(a) as i128;
+                    ((a >> d) + round_inc(vxrm, a as u128, d) as i128) as u64
+                })
+            }
+            0x2C => self.map(&v, c, (eb, 2 * eb, eb), scalar, false,
+                |a, b, _, _| a >> (b & (2 * bits as u64 - 1))),
+            0x2D => self.map(&v, c, (eb, 2 * eb, eb), scalar, false,
+                |a, b, _, _| (sext(a, 2 * bits) >> (b & (2 * bits as u64 - 1))) as u64),
+            0x2E => {
+                self.map(&v, c, (eb, 2 * eb, eb), scalar, false, |a, b, _, _| {
+                    let d = (b & (2 * bits as u64 - 1)) as u32;
+                    let r = (a as u128 >> d) + round_inc(vxrm, a as u128, d);
+                    sat |= r > max_u as u128;
+                    r.min(max_u as u128) as u64
+                })
+            }
+            0x2F => {
+                self.map(&v, c, (eb, 2 * eb, eb), scalar, false, |a, b, _, _| {
+                    let d = (b & (2 * bits as u64 - 1)) as u32;
+                    let a = sext(a, 2 * bits) as i128;
+                    let r = (a >> d) + round_inc(vxrm, a as u128, d) as i128;
+                    sat |= r > max_s as i128 || r < min_s as i128;
+                    r.clamp(min_s as i128, max_s as i128) as u64
+                })
+            }
+            0x30 if vv => self.reduce(&v, c, eb, 2 * eb, |acc, a| acc.wrapping_add(a)),
+            0x31 if vv => self.reduce(&v, c, eb, 2 * eb, |acc, a| acc.wrapping_add(s(a) as u64)),
+            _ => false,
+        };
+        self.vxsat |= sat;
+        ok
+    }
+
+    // OPMVV and OPMVX
+    fn opm(&mut self, v: VType, c: &Ctx, hart: &mut impl Hart) -> bool {
+        let vv = v.funct3 == 2;
+        let bits = c.bits();
+        let eb = c.eb;
+        let scalar = (!vv).then(|| trunc(hart.xreg(v.rs1), bits));
+        let s = |x: u64| sext(x, bits);
+        let w = 2 * bits;
+        let single = (eb, eb, eb);
+        let widen = (2 * eb, eb, eb);
+        let wide_a = (2 * eb, 2 * eb, eb);
+        let vxrm = self.vxrm;
+        match v.funct6 {
+            0x00 if vv => self.reduce(&v, c, eb, eb, |acc, a| acc.wrapping_add(a)),
+            0x01 if vv => self.reduce(&v, c, eb, eb, |acc, a| acc & a),
+            0x02 if vv => self.reduce(&v, c, eb, eb, |acc, a| acc | a),
+            0x03 if vv => self.reduce(&v, c, eb, eb, |acc, a| acc ^ a),
+            0x04 if vv => self.reduce(&v, c, eb, eb, |acc, a| acc.min(a)),
+            0x05 if vv => self.reduce(&v, c, eb, eb, |acc, a| s(acc).min(s(a)) as u64),
+            0x06 if vv => self.reduce(&v, c, eb, eb, |acc, a| acc.max(a)),
+            0x07 if vv => self.reduce(&v, c, eb, eb, |acc, a| s(acc).max(s(a)) as u64),
+            0x08 => self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                let r = a as u128 + b as u128;
+                ((r >> 1) + round_inc(vxrm, r, 1)) as u64
+            }),
+            0x09 => self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                let r = s(a) as i128 + s(b) as i128;
+                ((r >> 1) + round_inc(vxrm, r as u128, 1) as i128) as u64
+            }),
+            0x0A => self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                let r = a as i128 - b as i128;
+                ((r >> 1) + round_inc(vxrm, r as u128, 1) as i128) as u64
+            }),
+            0x0B => self.map(&v, c, single, scalar, false, |a, b, _, _| {
+                let r = s(a) as i128 - s(b) as i128;
+                ((r >> 1) + round_inc(vxrm, r as u128, 1) as i128) as u64
+            }),
+            0x0E if !vv => self.slide_up(&v, c, 1, scalar),
+            0x0F if !vv => self.slide_down(&v, c, 1, scalar),
+            0x10 if vv => match v.rs1 {
+                0x00 if v.vm => {
+                    hart.set_xreg(v.vd, s(self.get(v.vs2, 0, eb)) as u64);
+                    true
+                }
+                0x10 if c.vstart == 0 => {
+                    let n = (0..c.vl).filter(|&i| self.active(v.vm, i) && self.mask(v.vs2, i)).count();
+                    hart.set_xreg(v.vd, n as u64);
+                    true
+                }
+                0x11 if c.vstart == 0 => {
+                    let first = (0..c.vl).find(|&i| self.active(v.vm, i) && self.mask(v.vs2, i));
+                    hart.set_xreg(v.vd, first.map_or(u64::MAX, |i| i as u64));
+                    true
+                }
+                _ => false,
+            },
+            0x10 if v.vs2 == 0 && v.vm => {
+                if c.vstart < c.vl {
+                    self.set(v.vd, 0, eb, scalar.unwrap_or(0));
+                }
+                true
+            }
+            0x12 if vv => {
+                let (n, signed) = match v.rs1 {
+                    2..=7 => (8 >> ((v.rs1 - 2) / 2), v.rs1 & 1 != 0),
+                    _ => return false,
+                };
+                let a_eb = eb / n;
+                if a_eb == 0 {
+                    return false;
+                }
+                let a_bits = a_eb as u32 * 8;
+                self.map(&v, c, (eb, a_eb, eb), Some(0), false,
+                    |a, _, _, _| if signed { sext(a, a_bits) as u64 } else { a })
+            }
+            0x14 if vv => self.mask_unary(&v, c),
+            0x17 if vv && v.vm => self.compress(&v, c),
+            0x18..=0x1F if vv && v.vm => {
+                for i in c.vstart..c.vl {
+                    let (a, b) = (self.mask(v.vs2, i), self.mask(v.rs1, i));
+                    let r = match v.funct6 {
+                        0x18 => a & !b,
+                        0x19 => a & b,
+                        0x1A => a | b,
+                        0x1B => a ^ b,
+                        0x1C => a | !b,
+                        0x1D => !(a & b),
+                        0x1E => !(a | b),
+                        _ => !(a ^ b),
+                    };
+                    self.set_mask(v.vd, i, r);
+                }
+                true
+            }
+            0x20 => self.map(&v, c, single, scalar, false, |a, b, _, _| a.checked_div(b).unwrap_or(u64::MAX)),
+            0x21 => self.map(&v, c, single, scalar, false, |a, b, _, _| match s(b) {
+                0 => u64::MAX,
+                b => s(a).wrapping_div(b) as u64,
+            }),
+            0x22 => self.map(&v, c, single, scalar, false, |a, b, _, _| a.checked_rem(b).unwrap_or(a)),
+            0x23 => self.map(&v, c, single, scalar, false, |a, b, _, _| match s(b) {
+                0 => a,
+                b => s(a).wrapping_rem(b) as u64,
+            }),
+            0x24 => self.map(&v, c, single, scalar, false, |a, b, _, _| ((a as u128 * b as u128) >> bits) as u64),
+            0x25 => self.map(&v, c, single, scalar, false, |a, b, _, _| a.wrapping_mul(b)),
+            0x26 => self.map(&v, c, single, scalar, false,
+                |a, b, _, _| ((s(a) as i128 * b as i128) >> bits) as u64),
+            0x27 => self.map(&v, c, single, scalar, false,
+                |a, b, _, _| ((s(a) as i128 * s(b) as i128) >> bits) as u64),
+            0x29 => self.map(&v, c, single, scalar, false, |a, b, d, _| b.wrapping_mul(d).wrapping_add(a)),
+            0x2B => self.map(&v, c, single, scalar, false, |a, b, d, _| a.wrapping_sub(b.wrapping_mul(d))),
+            0x2D => self.map(&v, c, single, scalar, false, |a, b, d, _| b.wrapping_mul(a).wrapping_add(d)),
+            0x2F => self.map(&v, c, single, scalar, false, |a, b, d, _| d.wrapping_sub(b.wrapping_mul(a))),
+            // Widening, 2 * SEW is at most 64 bits.
+            0x30 => self.map(&v, c, widen, scalar, false, |a, b, _, _| a.wrapping_add(b)),
+            0x31 => self.map(&v, c, widen, scalar, false, |a, b, _, _| s(a).wrapping_add(s(b)) as u64),
+            0x32 => self.map(&v, c, widen, scalar, false, |a, b, _, _| a.wrapping_sub(b)),
+            0x33 => self.map(&v, c, widen, scalar, false, |a, b, _, _| s(a).wrapping_sub(s(b)) as u64),
+            0x34 => self.map(&v, c, wide_a, scalar, false, |a, b, _, _| a.wrapping_add(b)),
+            0x35 => self.map(&v, c, wide_a, scalar, false, |a, b, _, _| sext(a, w).wrapping_add(s(b)) as u64),
+            0x36 => self.map(&v, c, wide_a, scalar, false, |a, b, _, _| a.wrapping_sub(b)),
+            0x37 => self.map(&v, c, wide_a, scalar, false, |a, b, _, _| sext(a, w).wrapping_sub(s(b)) as u64),
+            0x38 => self.map(&v, c, widen, scalar, false, |a, b, _, _| a.wrapping_mul(b)),
+            0x3A => self.map(&v, c, widen, scalar, false, |a, b, _, _| s(a).wrapping_mul(b as i64) as u64),
+            0x3B => self.map(&v, c, widen, scalar, false, |a, b, _, _| s(a).wrapping_mul(s(b)) as u64),
+            0x3C => self.map(&v, c, widen, scalar, false, |a, b, d, _| d.wrapping_add(a.wrapping_mul(b))),
+            0x3D => self.map(&v, c, widen, scalar, false, |a, b, d, _| d.wrapping_add(s(a).wrapping_mul(s(b)) as u64)),
+            0x3E if !vv => self.map(&v, c, widen, scalar, false,
+                |a, b, d, _| d.wrapping_add((b as i64).wrapping_mul(s(a)) as u64)),
+            0x3F => self.map(&v, c, widen, scalar, false, |a, b, d, _| d.wrapping_add(s(b).wrapping_mul(a as i64) as u64)),
+            _ => false,
+        }
+    }
+
+    // vrgather.vv/vx/vi and vrgatherei16.vv (idx_eb 2)
+    fn gather(&mut self, v: &VType, c: &Ctx, scalar: Option<u64>, idx_eb: usize) -> bool {
+        if !c.group_ok(v.vd, c.eb) || !c.group_ok(v.vs2, c.eb) || (scalar.is_none() && !c.group_ok(v.rs1, idx_eb))
+            || v.vd == v.vs2 || (!v.vm && v.vd == 0)
+        {
+            return false;
+        }
+        for i in c.vstart..c.vl {
+            if !self.active(v.vm, i) {
+                continue;
+            }
+            let idx = scalar.unwrap_or_else(|| self.get(v.rs1, i, idx_eb));
+            let val = if idx < c.vlmax as u64 { self.get(v.vs2, idx as usize, c.eb) } else { 0 };
+            self.set(v.vd, i, c.eb, val);
+        }
+        true
+    }
+
+    // vslideup and vslide1up, the latter with `fill` for element 0.
+    fn slide_up(&mut self, v: &VType, c: &Ctx, offset: usize, fill: Option<u64>) -> bool {
+        if !c.group_ok(v.vd, c.eb) || !c.group_ok(v.vs2, c.eb) || v.vd == v.vs2 || (!v.vm && v.vd == 0) {
+            return false;
+        }
+        for i in c.vstart.max(if fill.is_some() { 0 } else { offset })..c.vl {
+            if !self.active(v.vm, i) {
+                continue;
+            }
+            let val = match fill {
+                Some(x) if i == 0 => x,
+                _ => self.get(v.vs2, i - offset, c.eb),
+            };
+            self.set(v.vd, i, c.eb, val);
+        }
+        true
+    }
+
+    // vslidedown and vslide1down, the latter with `fill` for element vl - 1.
+    fn slide_down(&mut self, v: &VType, c: &Ctx, offset: usize, fill: Option<u64>) -> bool {
+        if !c.group_ok(v.vd, c.eb) || !c.group_ok(v.vs2, c.eb) || (!v.vm && v.vd == 0) {
+            return false;
+        }
+        for i in c.vstart..c.vl {
+            if !self.active(v.vm, i) {
+                continue;
+            }
+            let val = match fill {
+                Some(x) if i + 1 == c.vl => x,
+                _ => match i.checked_add(offset) {
+                    Some(j) if j < c.vlmax => self.get(v.vs2, j, c.eb),
+                    _ => 0,
+                },
+            };
+            self.set(v.vd, i, c.eb, val);
+        }
+        true
+    }
+
+    // vmsbf, vmsof, vmsif, viota and vid
+    fn mask_unary(&mut self, v: &VType, c: &Ctx) -> bool {
+        if c.vstart != 0 || (!v.vm && v.vd == 0) {
+            return false;
+        }
+        match v.rs1 {
+            0x01..=0x03 if v.vd != v.vs2 => {
+                let mut before = true;
+                for i in 0..c.vl {
+                    if !self.active(v.vm, i) {
+                        continue;
+                    }
+                    let bit = self.mask(v.vs2, i);
+                    let r = match v.rs1 {
+                        0x01 => before && !bit,     // vmsbf
+                        0x02 => before && bit,      // vmsof
+                        _ => before,                // vmsif
+                    };
+                    before &= !bit;
+                    self.set_mask(v.vd, i, r);
+                }
+                true
+            }
+            0x10 if c.group_ok(v.vd, c.eb) => {
+                let mut sum = 0;
+                for i in 0..c.vl {
+                    if self.active(v.vm, i) {
+                        self.set(v.vd, i, c.eb, sum);
+                        sum += self.mask(v.vs2, i) as u64;
+                    }
+                }
+                true
+            }
+            0x11 if v.vs2 == 0 && c.group_ok(v.vd, c.eb) => {
+                for i in 0..c.vl {
+                    if self.active(v.vm, i) {
+                        self.set(v.vd, i, c.eb, i as u64);
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn compress(&mut self, v: &VType, c: &Ctx) -> bool {
+        if c.vstart != 0 || !c.group_ok(v.vd, c.eb) || !c.group_ok(v.vs2, c.eb) || v.vd == v.vs2 {
+            return false;
+        }
+        let mut j = 0;
+        for i in 0..c.vl {
+            if self.mask(v.rs1, i) {
+                let val = self.get(v.vs2, i, c.eb);
+                self.set(v.vd, j, c.eb, val);
+                j += 1;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,563 @@
+// Virtio over MMIO, version 2 of the register layout (virtio 1.x). The
+// transport registers, feature negotiation, config space and virtqueues in
+// both the split and the packed (virtio 1.1) layout are handled here once;
+// a device only describes itself and consumes the descriptor chains the
+// driver makes available.
+
+use crate::snapshot;
+
+pub mod rng;
+#[cfg(test)]
+mod tests;
+
+pub const MMIO_SIZE: u64 = 0x1000;
+
+const MAGIC: u32 = 0x74726976; // "virt"
+const VERSION: u32 = 2;
+const VENDOR_ID: u32 = 0x554d4551; // "QEMU", as drivers expect on virt
+
+// Offered by every device, legacy drivers are not supported.
+pub const F_VERSION_1: u64 = 1 << 32;
+// Offered by every device, the driver picks the ring layout.
+pub const F_RING_PACKED: u64 = 1 << 34;
+
+const STATUS_FEATURES_OK: u32 = 8;
+
+const INT_USED_BUFFER: u32 = 1;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+const DESC_F_INDIRECT: u16 = 4;
+const DESC_F_AVAIL: u16 = 1 << 7;
+const DESC_F_USED: u16 = 1 << 15;
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+const EVENT_F_DISABLE: u16 = 1;
+
+const CONFIG_OFFSET: u64 = 0x100;
+
+// Guest memory as a device doing DMA sees it.
+pub trait Memory {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> bool;
+    fn write(&mut self, addr: u64, data: &[u8]) -> bool;
+}
+
+fn read_u16(mem: &dyn Memory, addr: u64) -> Option<u16> {
+    let mut buf = [0; 2];
+    mem.read(addr, &mut buf).then(|| u16::from_le_bytes(buf))
+}
+
+pub trait Device {
+    fn device_id(&self) -> u32;
+    // Device-specific feature bits, the transport adds F_VERSION_1.
+    fn features(&self) -> u64;
+    fn num_queues(&self) -> usize;
+    fn queue_max_size(&self) -> u16 {
+        256
+    }
+    // The device-specific configuration space.
+    fn config(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn write_config(&mut self, _offset: usize, _data: &[u8]) {}
+    // The driver made buffers available on queue `index`.
+    fn process(&mut self, index: usize, queue: &mut Queue, mem: &mut dyn Memory);
+    fn reset(&mut self) {}
+    // Device state for snapshots, the transport saves the queues.
+    fn save(&self, w: &mut snapshot::Writer);
+    fn restore(&mut self, r: &mut snapshot::Reader) -> Result<(), String>;
+}
+
+// A descriptor chain as (guest address, length) buffers, the device-readable
+// ones first as the spec requires.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Chain {
+    pub head: u16,
+    pub readable: Vec<(u64, u32)>,
+    pub writable: Vec<(u64, u32)>,
+    // Ring slots the chain takes, for packed rings.
+    descs: u16,
+}
+
+impl Chain {
+    // False if a readable buffer follows a writable one.
+    fn add(&mut self, addr: u64, len: u32, writable: bool) -> bool {
+        match writable {
+            true => self.writable.push((addr, len)),
+            false if self.writable.is_empty() => self.readable.push((addr, len)),
+            false => return false,
+        }
+        true
+    }
+
+    // Everything the driver wrote, None if a buffer is outside RAM.
+    #[allow(dead_code)]
+    pub fn read(&self, mem: &dyn Memory) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        for &(addr, len) in &self.readable {
+            let start = data.len();
+            data.resize(start + len as usize, 0);
+            if !mem.read(addr, &mut data[start..]) {
+                return None;
+            }
+        }
+        Some(data)
+    }
+
+    pub fn writable_len(&self) -> u64 {
+        self.writable.iter().map(|&(_, len)| len as u64).sum()
+    }
+
+    // Fills the writable buffers in order, returns the bytes written.
+    pub fn write(&self, mem: &mut dyn Memory, mut data: &[u8]) -> u32 {
+        let mut written = 0;
+        for &(addr, len) in &self.writable {
+            let n = data.len().min(len as usize);
+            if n == 0 || !mem.write(addr, &data[..n]) {
+                break;
+            }
+            written += n as u32;
+            data = &data[n..];
+        }
+        written
+    }
+}
+
+// A virtqueue in guest memory. In the split layout `desc` is the descriptor
+// table, `driver` the available ring and `device` the used ring. In the
+// packed layout `desc` is the one descriptor ring both sides write, `driver`
+// and `device` their event suppression structures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Queue {
+    pub size: u16,
+    pub ready: bool,
+    pub packed: bool,
+    pub desc: u64,
+    pub driver: u64,
+    pub device: u64,
+    next_avail: u16,
+    next_used: u16,
+    // Packed ring wrap counters, flipped each time the index wraps.
+    avail_wrap: bool,
+    used_wrap: bool,
+    // Buffers were used since the last interrupt.
+    used: bool,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Queue {
+            size: 0,
+            ready: false,
+            packed: false,
+            desc: 0,
+            driver: 0,
+            device: 0,
+            next_avail: 0,
+            next_used: 0,
+            avail_wrap: true,
+            used_wrap: true,
+            used: false,
+        }
+    }
+}
+
+// A descriptor as (addr, len) and the two 16-bit fields after it, which are
+// (flags, next) in a split table and (id, flags) in a packed ring.
+fn read_desc(mem: &dyn Memory, addr: u64) -> Option<(u64, u32, u16, u16)> {
+    let mut desc = [0; 16];
+    if !mem.read(addr, &mut desc) {
+        return None;
+    }
+    Some((
+        u64::from_le_bytes(desc[0..8].try_into().unwrap()),
+        u32::from_le_bytes(desc[8..12].try_into().unwrap()),
+        u16::from_le_bytes(desc[12..14].try_into().unwrap()),
+        u16::from_le_bytes(desc[14..16].try_into().unwrap()),
+    ))
+}
+
+impl Queue {
+    fn reset(&mut self) {
+        *self = Queue::default();
+    }
+
+    // The next available chain, None when the driver has made none
+    // available or the chain is malformed.
+    pub fn pop(&mut self, mem: &dyn Memory) -> Option<Chain> {
+        if !self.ready || self.size == 0 {
+            return None;
+        }
+        let chain = match self.packed {
+            false => self.pop_split(mem)?,
+            true => self.pop_packed(mem)?,
+        };
+        if chain.is_none() {
+            eprintln!("virtio: malformed descriptor chain");
+        }
+        chain
+    }
+
+    // The outer None means nothing is available.
+    fn pop_split(&mut self, mem: &dyn Memory) -> Option<Option<Chain>> {
+        let avail_idx = read_u16(mem, self.driver + 2)?;
+        if avail_idx == self.next_avail {
+            return None;
+        }
+        let slot = (self.next_avail % self.size) as u64;
+        let head = read_u16(mem, self.driver + 4 + 2 * slot)?;
+        self.next_avail = self.next_avail.wrapping_add(1);
+        Some(self.walk_split(mem, head))
+    }
+
+    fn walk_split(&self, mem: &dyn Memory, head: u16) -> Option<Chain> {
+        let mut chain = Chain { head, descs: 1, ..Chain::default() };
+        let (mut table, mut size, mut idx) = (self.desc, self.size as u32, head);
+        let mut indirect = false;
+        // A chain visits every descriptor at most once.
+        for _ in 0..(self.size as u32 + u16::MAX as u32) {
+            if idx as u32 >= size {
+                return None;
+            }
+            let (addr, len, flags, next) = read_desc(mem, table + 16 * idx as u64)?;
+            if flags & DESC_F_INDIRECT != 0 {
+                // one level of indirection, in place of the rest of the chain
+                if indirect || flags & DESC_F_NEXT != 0 || !len.is_multiple_of(16) {
+                    return None;
+                }
+                (table, size, idx, indirect) = (addr, len / 16, 0, true);
+                continue;
+            }
+            if !chain.add(addr, len, flags & DESC_F_WRITE != 0) {
+                return None;
+            }
+            if flags & DESC_F_NEXT == 0 {
+                return Some(chain);
+            }
+            idx = next;
+        }
+        None
+    }
+
+    // A packed descriptor is available when its AVAIL bit matches the
+    // driver's wrap counter and its USED bit does not.
+    fn pop_packed(&mut self, mem: &dyn Memory) -> Option<Option<Chain>> {
+        let flags = read_u16(mem, self.desc + 16 * self.next_avail as u64 + 14)?;
+        let wrap = self.avail_wrap;
+        if (flags & DESC_F_AVAIL != 0) != wrap || (flags & DESC_F_USED != 0) == wrap {
+            return None;
+        }
+
+        let mut chain = Chain::default();
+        // The chain's descriptors are consecutive in the ring, the buffer id
+        // is taken from the last one.
+        while chain.descs < self.size {
+            let Some((addr, len, id, flags)) = read_desc(mem, self.desc + 16 * self.next_avail as u64) else {
+                return Some(None);
+            };
+            chain.descs += 1;
+            self.next_avail += 1;
+            if self.next_avail == self.size {
+                self.next_avail = 0;
+                self.avail_wrap = !self.avail_wrap;
+            }
+            let ok = match flags & DESC_F_INDIRECT != 0 {
+                // an indirect table is a plain array and stands alone
+                true => chain.descs == 1 && flags & DESC_F_NEXT == 0 && len.is_multiple_of(16)
+                    && (0..len as u64 / 16).all(|i| {
+                        read_desc(mem, addr + 16 * i).is_some_and(|(addr, len, _, flags)| {
+                            chain.add(addr, len, flags & DESC_F_WRITE != 0)
+                        })
+                    }),
+                false => chain.add(addr, len, flags & DESC_F_WRITE != 0),
+            };
+            if !ok || flags & DESC_F_NEXT == 0 {
+                chain.head = id;
+                return Some(ok.then_some(chain));
+            }
+        }
+        Some(None)
+    }
+
+    // Returns a chain to the driver with `len` bytes written into it.
+    pub fn push(&mut self, mem: &mut dyn Memory, chain: &Chain, len: u32) {
+        if self.packed {
+            self.push_packed(mem, chain, len);
+        } else {
+            let slot = (self.next_used % self.size) as u64;
+            let mut elem = [0; 8];
+            elem[..4].copy_from_slice(&(chain.head as u32).to_le_bytes());
+            elem[4..].copy_from_slice(&len.to_le_bytes());
+            self.next_used = self.next_used.wrapping_add(1);
+            mem.write(self.device + 4 + 8 * slot, &elem);
+            mem.write(self.device + 2, &self.next_used.to_le_bytes());
+        }
+        self.used = true;
+    }
+
+    // The used descriptor goes where the chain started, its flags are
+    // written last as they hand it back to the driver.
+    fn push_packed(&mut self, mem: &mut dyn Memory, chain: &Chain, len: u32) {
+        let desc = self.desc + 16 * self.next_used as u64;
+        let flags = match self.used_wrap {
+            true => DESC_F_AVAIL | DESC_F_USED,
+            false => 0,
+        };
+        mem.write(desc + 8, &len.to_le_bytes());
+        mem.write(desc + 12, &chain.head.to_le_bytes());
+        mem.write(desc + 14, &flags.to_le_bytes());
+        self.next_used += chain.descs;
+        if self.next_used >= self.size {
+            self.next_used -= self.size;
+            self.used_wrap = !self.used_wrap;
+        }
+    }
+
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.u64s(&[self.size as u64, self.desc, self.driver, self.device, self.next_avail as u64,
+            self.next_used as u64]);
+        for flag in [self.ready, self.packed, self.avail_wrap, self.used_wrap, self.used] {
+            w.u8(flag as u8);
+        }
+    }
+
+    fn restore(&mut self, r: &mut snapshot::Reader) -> Result<(), String> {
+        let mut vals = [0; 6];
+        r.u64s(&mut vals)?;
+        let [size, desc, driver, device, next_avail, next_used] = vals;
+        (self.size, self.desc, self.driver, self.device) = (size as u16, desc, driver, device);
+        (self.next_avail, self.next_used) = (next_avail as u16, next_used as u16);
+        for flag in [&mut self.ready, &mut self.packed, &mut self.avail_wrap, &mut self.used_wrap, &mut self.used] {
+            *flag = r.u8()? != 0;
+        }
+        Ok(())
+    }
+
+    // Whether used buffers should raise an interrupt, clearing the request.
+    fn take_interrupt(&mut self, mem: &dyn Memory) -> bool {
+        let suppressed = match self.packed {
+            false => read_u16(mem, self.driver).is_some_and(|flags| flags & AVAIL_F_NO_INTERRUPT != 0),
+            true => read_u16(mem, self.driver + 2).is_some_and(|flags| flags == EVENT_F_DISABLE),
+        };
+        std::mem::take(&mut self.used) && !suppressed
+    }
+}
+
+// The MMIO register file in front of a device.
+pub struct Transport<D> {
+    pub device: D,
+    status: u32,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queues: Vec<Queue>,
+    interrupt_status: u32,
+}
+
+impl<D: Device + Default> Default for Transport<D> {
+    fn default() -> Self {
+        Transport::new(D::default())
+    }
+}
+
+impl<D: Device> Transport<D> {
+    pub fn new(device: D) -> Self {
+        let queues = vec![Queue::default(); device.num_queues()];
+        Transport {
+            device,
+            status: 0,
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            queue_sel: 0,
+            queues,
+            interrupt_status: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.status = 0;
+        self.device_features_sel = 0;
+        self.driver_features = 0;
+        self.driver_features_sel = 0;
+        self.queue_sel = 0;
+        self.queues.iter_mut().for_each(Queue::reset);
+        self.interrupt_status = 0;
+        self.device.reset();
+    }
+
+    // The level of the device's interrupt line.
+    pub fn interrupt(&self) -> bool {
+        self.interrupt_status != 0
+    }
+
+    fn offered_features(&self) -> u64 {
+        self.device.features() | F_VERSION_1 | F_RING_PACKED
+    }
+
+    fn queue(&mut self) -> Option<&mut Queue> {
+        self.queues.get_mut(self.queue_sel as usize)
+    }
+
+    // Registers are 32 bits wide, the config space takes any access size.
+    pub fn load(&mut self, offset: u64, size: usize) -> Option<u32> {
+        if offset >= CONFIG_OFFSET {
+            let config = self.device.config();
+            let start = (offset - CONFIG_OFFSET) as usize;
+            let bytes = config.get(start..start + size)?;
+            let mut val = [0; 4];
+            val[..size].copy_from_slice(bytes);
+            return Some(u32::from_le_bytes(val));
+        }
+        if size != 4 {
+            return None;
+        }
+        let features = self.offered_features();
+        let queue = self.queues.get(self.queue_sel as usize);
+        let val = match offset {
+            0x000 => MAGIC,
+            0x004 => VERSION,
+            0x008 => self.device.device_id(),
+            0x00c => VENDOR_ID,
+            0x010 => match self.device_features_sel {
+                0 => features as u32,
+                1 => (features >> 32) as u32,
+                _ => 0,
+            },
+            0x034 => queue.map_or(0, |_| self.device.queue_max_size() as u32),
+            0x044 => queue.is_some_and(|q| q.ready) as u32,
+            0x060 => self.interrupt_status,
+            0x070 => self.status,
+            0x0fc => 0, // config generation, the config never changes
+            _ => 0,
+        };
+        Some(val)
+    }
+
+    pub fn store(&mut self, offset: u64, size: usize, val: u32, mem: &mut dyn Memory) -> bool {
+        if offset >= CONFIG_OFFSET {
+            let start = (offset - CONFIG_OFFSET) as usize;
+            if start + size > self.device.config().len() {
+                return false;
+            }
+            self.device.write_config(start, &val.to_le_bytes()[..size]);
+            return true;
+        }
+        if size != 4 {
+            return false;
+        }
+        let lo = |old: u64| (old & !0xffff_ffff) | val as u64;
+        let hi = |old: u64| (old & 0xffff_ffff) | (val as u64) << 32;
+        match offset {
+            0x014 => self.device_features_sel = val,
+            0x020 => match self.driver_features_sel {
+                0 => self.driver_features = lo(self.driver_features),
+                1 => self.driver_features = hi(self.driver_features),
+                _ => {}
+            },
+            0x024 => self.driver_features_sel = val,
+            0x030 => self.queue_sel = val,
+            0x050 => self.notify(val as usize, mem),
+            0x064 => self.interrupt_status &= !val,
+            0x070 => self.set_status(val),
+            // The queue layout can only change while the queue is not ready.
+            0x038 | 0x044 | 0x080 | 0x084 | 0x090 | 0x094 | 0x0a0 | 0x0a4 => {
+                let max = self.device.queue_max_size() as u32;
+                let packed = self.driver_features & F_RING_PACKED != 0;
+                let Some(queue) = self.queue() else {
+                    return true;
+                };
+                match offset {
+                    0x044 => (queue.ready, queue.packed) = (val & 1 != 0, packed),
+                    _ if queue.ready => {}
+                    0x038 if val.is_power_of_two() && val <= max => queue.size = val as u16,
+                    0x038 => {}
+                    0x080 => queue.desc = lo(queue.desc),
+                    0x084 => queue.desc = hi(queue.desc),
+                    0x090 => queue.driver = lo(queue.driver),
+                    0x094 => queue.driver = hi(queue.driver),
+                    0x0a0 => queue.device = lo(queue.device),
+                    _ => queue.device = hi(queue.device),
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    // Writing 0 resets the device. FEATURES_OK only sticks when the driver
+    // accepted a subset of the offered features including VERSION_1.
+    fn set_status(&mut self, val: u32) {
+        if val == 0 {
+            self.reset();
+            return;
+        }
+        let mut val = val;
+        if val & STATUS_FEATURES_OK != 0 && self.status & STATUS_FEATURES_OK == 0 {
+            let offered = self.offered_features();
+            if self.driver_features & !offered != 0 || self.driver_features & F_VERSION_1 == 0 {
+                val &= !STATUS_FEATURES_OK;
+            }
+        }
+        self.status = val;
+    }
+
+    fn notify(&mut self, index: usize, mem: &mut dyn Memory) {
+        let Some(queue) = self.queues.get_mut(index) else {
+            return;
+        };
+        if !queue.ready {
+            return;
+        }
+        self.device.process(index, queue, mem);
+        if queue.take_interrupt(mem) {
+            self.interrupt_status |= INT_USED_BUFFER;
+        }
+    }
+
+    // Requests complete within the notify that submits them, so the
+    // negotiated state and the ring positions are all there is in flight.
+    pub fn save(&self, w: &mut snapshot::Writer) {
+        w.u64s(&[self.status as u64, self.device_features_sel as u64, self.driver_features,
+            self.driver_features_sel as u64, self.queue_sel as u64, self.interrupt_status as u64]);
+        w.u64(self.queues.len() as u64);
+        for queue in &self.queues {
+            queue.save(w);
+        }
+        self.device.save(w);
+    }
+
+    pub fn restore(&mut self, r: &mut snapshot::Reader) -> Result<(), String> {
+        let mut regs = [0; 6];
+        r.u64s(&mut regs)?;
+        let [status, device_features_sel, driver_features, driver_features_sel, queue_sel, interrupt_status] = regs;
+        self.status = status as u32;
+        self.device_features_sel = device_features_sel as u32;
+        self.driver_features = driver_features;
+        self.driver_features_sel = driver_features_sel as u32;
+        self.queue_sel = queue_sel as u32;
+        self.interrupt_status = interrupt_status as u32;
+        if r.u64()? != self.queues.len() as u64 {
+            return Err("snapshot virtio queues do not match this device".into());
+        }
+        for queue in &mut self.queues {
+            queue.restore(r)?;
+        }
+        self.device.restore(r)
+    }
+
+    // Register state for the monitor's device dumps.
+    pub fn registers(&self) -> Vec<(&'static str, u64)> {
+        let mut regs = vec![
+            ("device_id", self.device.device_id() as u64),
+            ("status", self.status as u64),
+            ("driver_features", self.driver_features),
+            ("interrupt_status", self.interrupt_status as u64),
+        ];
+        for queue in &self.queues {
+            regs.push(("queue_size", queue.size as u64));
+            regs.push(("queue_next_avail", queue.next_avail as u64));
+        }
+        regs
+    }
+}
@@ -0,0 +1,54 @@
+use super::{Device, Memory, Queue};
+use crate::fault::FaultInjector;
+use crate::snapshot;
+
+// virtio-rng: one queue of writable buffers the device fills with entropy.
+// The stream is a seeded PRNG so runs stay reproducible.
+pub struct Rng {
+    source: FaultInjector,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Rng { source: FaultInjector::new() }
+    }
+}
+
+impl Device for Rng {
+    fn device_id(&self) -> u32 {
+        4
+    }
+
+    fn features(&self) -> u64 {
+        0
+    }
+
+    fn num_queues(&self) -> usize {
+        1
+    }
+
+    fn process(&mut self, _index: usize, queue: &mut Queue, mem: &mut dyn Memory) {
+        while let Some(chain) = queue.pop(mem) {
+            let len = chain.writable_len().min(1 << 16) as usize;
+            let mut data = Vec::with_capacity(len + 8);
+            while data.len() < len {
+                data.extend(self.source.next_u64().to_le_bytes());
+            }
+            let written = chain.write(mem, &data[..len]);
+            queue.push(mem, &chain, written);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.source = FaultInjector::new();
+    }
+
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.u64(self.source.state());
+    }
+
+    fn restore(&mut self, r: &mut snapshot::Reader) -> Result<(), String> {
+        self.source.seed(r.u64()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use super::rng::Rng;
+use super::*;
+
+const DESC: u64 = 0x1000;
+const DRIVER: u64 = 0x2000;
+const DEVICE: u64 = 0x3000;
+const INDIRECT: u64 = 0x5000;
+const QUEUE_SIZE: u16 = 4;
+
+struct Ram(Vec<u8>);
+
+impl Memory for Ram {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> bool {
+        let Some(bytes) = self.0.get(addr as usize..addr as usize + buf.len()) else {
+            return false;
+        };
+        buf.copy_from_slice(bytes);
+        true
+    }
+
+    fn write(&mut self, addr: u64, data: &[u8]) -> bool {
+        let Some(bytes) = self.0.get_mut(addr as usize..addr as usize + data.len()) else {
+            return false;
+        };
+        bytes.copy_from_slice(data);
+        true
+    }
+}
+
+impl Ram {
+    fn u16(&self, addr: u64) -> u16 {
+        read_u16(self, addr).unwrap()
+    }
+
+    fn put_desc(&mut self, addr: u64, buf: u64, len: u32, a: u16, b: u16) {
+        let mut desc = Vec::new();
+        desc.extend(buf.to_le_bytes());
+        desc.extend(len.to_le_bytes());
+        desc.extend(a.to_le_bytes());
+        desc.extend(b.to_le_bytes());
+        self.write(addr, &desc);
+    }
+}
+
+// The driver side of either ring layout, handing out ring slots in order.
+struct Driver {
+    packed: bool,
+    next: u16,
+    wrap: bool,
+    avail_idx: u16,
+    used_next: u16,
+    used_wrap: bool,
+    used_idx: u16,
+    // Ring slots of the chains not yet used, in order.
+    pending: Vec<u16>,
+}
+
+impl Driver {
+    fn new(packed: bool) -> Self {
+        Driver { packed, next: 0, wrap: true, avail_idx: 0, used_next: 0, used_wrap: true, used_idx: 0, pending: Vec::new() }
+    }
+
+    fn slot(&mut self) -> u16 {
+        let slot = self.next;
+        self.next += 1;
+        if self.next == QUEUE_SIZE {
+            self.next = 0;
+            self.wrap = !self.wrap;
+        }
+        slot
+    }
+
+    // Makes the buffers, as (addr, len, writable), available as one chain
+    // or through an indirect table.
+    fn offer(&mut self, ram: &mut Ram, bufs: &[(u64, u32, bool)], indirect: bool) {
+        let write = |w: bool| if w { DESC_F_WRITE } else { 0 };
+        let avail = match self.wrap {
+            true => DESC_F_AVAIL,
+            false => DESC_F_USED,
+        };
+        let head = self.next;
+        let descs: Vec<(u64, u32, u16)> = match indirect {
+            true => {
+                for (i, &(addr, len, w)) in bufs.iter().enumerate() {
+                    let last = i + 1 == bufs.len();
+                    match self.packed {
+                        true => ram.put_desc(INDIRECT + 16 * i as u64, addr, len, 0, write(w)),
+                        false => {
+                            let next = if last { 0 } else { DESC_F_NEXT };
+                            ram.put_desc(INDIRECT + 16 * i as u64, addr, len, write(w) | next, i as u16 + 1);
+                        }
+                    }
+                }
+                vec![(INDIRECT, 16 * bufs.len() as u32, DESC_F_INDIRECT)]
+            }
+            false => bufs.iter().map(|&(addr, len, w)| (addr, len, write(w))).collect(),
+        };
+        for (i, &(addr, len, flags)) in descs.iter().enumerate() {
+            let next = if i + 1 == descs.len() { 0 } else { DESC_F_NEXT };
+            let slot = self.slot();
+            match self.packed {
+                // the id matches the split head so both layouts report alike
+                true => ram.put_desc(DESC + 16 * slot as u64, addr, len, head, flags | next | avail),
+                false => ram.put_desc(DESC + 16 * slot as u64, addr, len, flags | next, self.next % QUEUE_SIZE),
+            }
+        }
+        self.pending.push(descs.len() as u16);
+        if !self.packed {
+            ram.write(DRIVER + 4 + 2 * (self.avail_idx % QUEUE_SIZE) as u64, &head.to_le_bytes());
+            self.avail_idx += 1;
+            ram.write(DRIVER + 2, &self.avail_idx.to_le_bytes());
+        }
+    }
+
+    // The used (id, len) pairs since the last call.
+    fn used(&mut self, ram: &Ram) -> Vec<(u16, u32)> {
+        let mut used = Vec::new();
+        if self.packed {
+            loop {
+                let desc = DESC + 16 * self.used_next as u64;
+                let flags = ram.u16(desc + 14);
+                let wrap = self.used_wrap;
+                if (flags & DESC_F_AVAIL != 0) != wrap || (flags & DESC_F_USED != 0) != wrap {
+                    break;
+                }
+                let mut len = [0; 4];
+                ram.read(desc + 8, &mut len);
+                used.push((ram.u16(desc + 12), u32::from_le_bytes(len)));
+                self.used_next += self.pending.remove(0);
+                if self.used_next >= QUEUE_SIZE {
+                    self.used_next -= QUEUE_SIZE;
+                    self.used_wrap = !self.used_wrap;
+                }
+            }
+        } else {
+            while self.used_idx != ram.u16(DEVICE + 2) {
+                let mut elem = [0; 8];
+                ram.read(DEVICE + 4 + 8 * (self.used_idx % QUEUE_SIZE) as u64, &mut elem);
+                let id = u32::from_le_bytes(elem[..4].try_into().unwrap());
+                used.push((id as u16, u32::from_le_bytes(elem[4..].try_into().unwrap())));
+                self.used_idx += 1;
+            }
+        }
+        used
+    }
+}
+
+// A virtio-rng with queue 0 set up and the ring layout negotiated.
+fn transport(ram: &mut Ram, packed: bool) -> Transport<Rng> {
+    let features = F_VERSION_1 | if packed { F_RING_PACKED } else { 0 };
+    let mut dev = Transport::new(Rng::default());
+    for (offset, val) in [
+        (0x070, 3), (0x024, 0), (0x020, features as u32), (0x024, 1), (0x020, (features >> 32) as u32), (0x070, 11),
+        (0x030, 0), (0x038, QUEUE_SIZE as u32), (0x080, DESC as u32), (0x090, DRIVER as u32), (0x0a0, DEVICE as u32),
+        (0x044, 1), (0x070, 15),
+    ] {
+        assert!(dev.store(offset, 4, val, ram));
+    }
+    assert_eq!(dev.load(0x070, 4), Some(15));
+    dev
+}
+
+// Feeds the same buffers through a ring, the second round wrapping it.
+fn run(packed: bool) -> (Vec<(u16, u32)>, Vec<u8>) {
+    let mut ram = Ram(vec![0; 0x6000]);
+    let mut dev = transport(&mut ram, packed);
+    let mut driver = Driver::new(packed);
+
+    driver.offer(&mut ram, &[(0x4000, 16, true)], false);
+    driver.offer(&mut ram, &[(0x4100, 4, false), (0x4200, 8, true), (0x4300, 8, true)], false);
+    assert!(dev.store(0x050, 4, 0, &mut ram));
+    assert!(dev.interrupt());
+    assert!(dev.store(0x064, 4, INT_USED_BUFFER, &mut ram));
+    let mut used = driver.used(&ram);
+
+    driver.offer(&mut ram, &[(0x4400, 16, true), (0x4500, 8, true)], true);
+    assert!(dev.store(0x050, 4, 0, &mut ram));
+    assert!(dev.interrupt());
+    used.extend(driver.used(&ram));
+    (used, ram.0[0x4000..0x4600].to_vec())
+}
+
+#[test]
+fn split_and_packed_rings_agree() {
+    let (split_used, split_data) = run(false);
+    let (packed_used, packed_data) = run(true);
+    assert_eq!(split_used, [(0, 16), (1, 16), (0, 24)]);
+    assert_eq!(packed_used, split_used);
+    assert_eq!(packed_data, split_data);
+    // the readable buffer is left alone
+    assert_eq!(&split_data[0x100..0x104], &[0; 4]);
+}
+
+#[test]
+fn packed_ring_honours_event_suppression() {
+    let mut ram = Ram(vec![0; 0x6000]);
+    let mut dev = transport(&mut ram, true);
+    let mut driver = Driver::new(true);
+    ram.write(DRIVER + 2, &EVENT_F_DISABLE.to_le_bytes());
+    driver.offer(&mut ram, &[(0x4000, 16, true)], false);
+    assert!(dev.store(0x050, 4, 0, &mut ram));
+    assert_eq!(driver.used(&ram), [(0, 16)]);
+    assert!(!dev.interrupt());
+}
+
+#[test]
+fn packed_ring_needs_version_1() {
+    let mut ram = Ram(vec![0; 0x1000]);
+    let mut dev = Transport::new(Rng::default());
+    for (offset, val) in [(0x070, 3), (0x024, 1), (0x020, (F_RING_PACKED >> 32) as u32), (0x070, 11)] {
+        assert!(dev.store(offset, 4, val, &mut ram));
+    }
+    assert_eq!(dev.load(0x070, 4), Some(3));
+}
+
+#[test]
+fn restored_transport_continues_the_ring() {
+    for packed in [false, true] {
+        let mut ram = Ram(vec![0; 0x6000]);
+        let mut dev = transport(&mut ram, packed);
+        let mut driver = Driver::new(packed);
+        driver.offer(&mut ram, &[(0x4000, 16, true)], false);
+        driver.offer(&mut ram, &[(0x4100, 8, true)], false);
+        driver.offer(&mut ram, &[(0x4200, 8, true)], false);
+        assert!(dev.store(0x050, 4, 0, &mut ram));
+        driver.used(&ram);
+
+        let mut w = snapshot::Writer::new();
+        dev.save(&mut w);
+        let data = w.finish();
+        let mut restored = Transport::new(Rng::default());
+        let mut r = snapshot::Reader::new(&data).unwrap();
+        restored.restore(&mut r).unwrap();
+        r.finish().unwrap();
+
+        // the next chain wraps the ring, both must use it alike
+        let mut copy = Ram(ram.0.clone());
+        let mut copy_driver = Driver { pending: driver.pending.clone(), ..driver };
+        driver.offer(&mut ram, &[(0x4300, 16, true)], false);
+        copy_driver.offer(&mut copy, &[(0x4300, 16, true)], false);
+        assert!(dev.store(0x050, 4, 0, &mut ram));
+        assert!(restored.store(0x050, 4, 0, &mut copy));
+        assert_eq!(copy_driver.used(&copy), driver.used(&ram));
+        assert_eq!(copy.0, ram.0);
+        assert_eq!(restored.load(0x060, 4), dev.load(0x060, 4));
+    }
+}
@@ -0,0 +1,59 @@
+use std::io::Write;
+
+use crate::cpu::Cpu;
+use crate::expr::{Env, Expr};
+
+// Watch expressions are sampled every `interval` retired instructions and
+// written as CSV, ready for a spreadsheet or gnuplot:
+//
+//   instret,a0,u64[0x80001000]
+//   1000000,42,2147500032
+//   2000000,43,
+//
+// Values are decimal, an expression that can't be evaluated (memory outside
+// of RAM) leaves its column empty.
+pub struct Watches {
+    exprs: Vec<(String, Expr)>,
+    interval: u64,
+    out: Box<dyn Write>,
+    next: Option<u64>,
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+impl Watches {
+    pub fn new(interval: u64, out: Box<dyn Write>) -> Self {
+        Watches { exprs: Vec::new(), interval: interval.max(1), out, next: None }
+    }
+
+    pub fn add(&mut self, src: &str) -> Result<(), String> {
+        let expr = Expr::parse(src)?;
+        self.exprs.push((src.to_string(), expr));
+        Ok(())
+    }
+
+    pub fn after_step(&mut self, cpu: &Cpu) {
+        // Fused steps retire two instructions and can step over a multiple.
+        let n = cpu.instret();
+        if n < self.next.unwrap_or(self.interval) {
+            return;
+        }
+        if self.next.is_none() {
+            let names: Vec<String> = self.exprs.iter().map(|(src, _)| csv_field(src)).collect();
+            let _ = writeln!(self.out, "instret,{}", names.join(","));
+        }
+        self.next = Some((n / self.interval + 1) * self.interval);
+
+        let env = Env { cpu, vars: &[] };
+        let values: Vec<String> = self.exprs.iter()
+            .map(|(_, e)| e.eval(&env).map_or(String::new(), |v| v.to_string()))
+            .collect();
+        let _ = writeln!(self.out, "{},{}", n, values.join(","));
+    }
+}
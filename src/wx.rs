@@ -0,0 +1,49 @@
+use std::collections::HashSet;
+
+const PAGE_SHIFT: u64 = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Violation {
+    ExecWritten,
+    WriteExecuted,
+}
+
+impl Violation {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Violation::ExecWritten => "execute from written page",
+            Violation::WriteExecuted => "write to executed page",
+        }
+    }
+}
+
+// Flags guest W^X violations on physical pages: executing a page that was
+// written since it last executed, and writing a page that has executed
+// since it was last written. Each transition is flagged once.
+pub struct WxMonitor {
+    written: HashSet<u64>,
+    executed: HashSet<u64>,
+}
+
+impl WxMonitor {
+    pub fn new() -> Self {
+        WxMonitor {
+            written: HashSet::new(),
+            executed: HashSet::new(),
+        }
+    }
+
+    pub fn on_write(&mut self, addr: u64) -> Option<Violation> {
+        let page = addr >> PAGE_SHIFT;
+        self.written.insert(page);
+        self.executed.remove(&page).then_some(Violation::WriteExecuted)
+    }
+
+    pub fn on_exec(&mut self, pc: u64) -> Option<Violation> {
+        let page = pc >> PAGE_SHIFT;
+        if !self.executed.insert(page) {
+            return None;
+        }
+        self.written.remove(&page).then_some(Violation::ExecWritten)
+    }
+}
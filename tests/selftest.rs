@@ -0,0 +1,14 @@
+use std::process::Command;
+
+// The bundled workloads must pass, missing ones are skipped. Only xv6 is
+// bundled, and only up to its banner.
+#[test]
+fn selftest_passes() {
+    let out = Command::new(env!("CARGO_BIN_EXE_nrv64emu"))
+        .args(["selftest", "--configs", concat!(env!("CARGO_MANIFEST_DIR"), "/configs")])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(stdout.contains("xv6        ok"), "{}", stdout);
+}